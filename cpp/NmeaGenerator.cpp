@@ -1,12 +1,35 @@
 // NmeaGenerator.cpp
 #include "NmeaGenerator.hpp"
 
+#include <cmath>
+#include <cstdint>
 #include <iomanip>
 #include <sstream>
 
+namespace {
+
+// Absolute angle as ddmm.mmmm (dddmm.mmmm), rounded before splitting
+std::string formatDegreesMinutes(double angle, int degreeDigits, int decimals)
+{
+    uint64_t scale = 1;
+    for (int i = 0; i < decimals; ++i) {
+        scale *= 10;
+    }
+    uint64_t units   = static_cast<uint64_t>(std::llround(angle * 60.0 * scale));
+    uint64_t minutes = units % (60 * scale);
+
+    std::ostringstream ss;
+    ss << std::setfill('0') << std::setw(degreeDigits) << units / (60 * scale) << std::setw(2)
+       << minutes / scale << '.' << std::setw(decimals) << minutes % scale;
+    return ss.str();
+}
+
+} // namespace
+
 // Constructor
-NmeaGenerator::NmeaGenerator()
-    : rng_(std::random_device {}())
+NmeaGenerator::NmeaGenerator(int minuteDecimals)
+    : minuteDecimals_(minuteDecimals)
+    , rng_(std::random_device {}())
 {
 }
 
@@ -66,24 +89,12 @@ LocationData NmeaGenerator::generateLocation()
     // Latitude: -90 to 90
     double latitude = randomUniform(-90.0, 90.0);
     loc.ns          = (latitude >= 0) ? 'N' : 'S';
-    latitude        = std::abs(latitude);
-    int lat_deg     = static_cast<int>(latitude);
-    double lat_min  = (latitude - lat_deg) * 60.0;
-    std::ostringstream lat_ss;
-    lat_ss << std::setw(2) << std::setfill('0') << lat_deg << std::fixed << std::setprecision(4)
-           << lat_min;
-    loc.latitude = lat_ss.str();
+    loc.latitude    = formatDegreesMinutes(std::abs(latitude), 2, minuteDecimals_);
 
     // Longitude: -180 to 180
     double longitude = randomUniform(-180.0, 180.0);
     loc.ew           = (longitude >= 0) ? 'E' : 'W';
-    longitude        = std::abs(longitude);
-    int lon_deg      = static_cast<int>(longitude);
-    double lon_min   = (longitude - lon_deg) * 60.0;
-    std::ostringstream lon_ss;
-    lon_ss << std::setw(3) << std::setfill('0') << lon_deg << std::fixed << std::setprecision(4)
-           << lon_min;
-    loc.longitude = lon_ss.str();
+    loc.longitude    = formatDegreesMinutes(std::abs(longitude), 3, minuteDecimals_);
 
     return loc;
 }
@@ -125,7 +136,7 @@ std::vector<SatelliteInfo> NmeaGenerator::generateSatellites()
 std::string NmeaGenerator::generateGPGGA(const LocationData& loc, int numSatellites)
 {
     std::string utc_time    = getUTCTime();
-    std::string fix_quality = std::to_string(randomInt(0, 5));
+    int fix_quality         = randomInt(0, 5);
     double horizontal_dil   = randomUniform(0.5, 2.5);
     double altitude         = randomUniform(10.0, 100.0);
     double geoid_sep        = randomUniform(-50.0, 50.0);
@@ -134,7 +145,14 @@ std::string NmeaGenerator::generateGPGGA(const LocationData& loc, int numSatelli
     gpgga_body << "GPGGA," << utc_time << "," << loc.latitude << "," << loc.ns << ","
                << loc.longitude << "," << loc.ew << "," << fix_quality << "," << numSatellites
                << "," << std::fixed << std::setprecision(1) << horizontal_dil << "," << altitude
-               << ",M," << geoid_sep << ",M,,,";
+               << ",M," << geoid_sep << ",M,";
+    // DGPS and RTK fixes carry the age of the corrections and their station
+    if (fix_quality == 2 || fix_quality == 4 || fix_quality == 5) {
+        gpgga_body << randomUniform(1.0, 10.0) << "," << std::setfill('0') << std::setw(4)
+                   << randomInt(0, 1023);
+    } else {
+        gpgga_body << ",";
+    }
     std::string checksum = calculateChecksum(gpgga_body.str());
     return "$" + gpgga_body.str() + "*" + checksum + "\r\n";
 }
@@ -35,7 +35,8 @@ struct LocationData {
 
 class NmeaGenerator {
 public:
-    NmeaGenerator();
+    // Digits of the minutes in coordinates, 4 to 8
+    explicit NmeaGenerator(int minuteDecimals = 4);
     ~NmeaGenerator() = default;
 
     // Generate all NMEA sentences
@@ -70,6 +71,8 @@ private:
     // Generate multiple GSV sentences for all constellations
     std::string generateGPGSV(const std::vector<SatelliteInfo>& satellites);
 
+    int minuteDecimals_;
+
     // Random device and generator
     std::mt19937 rng_;
 };
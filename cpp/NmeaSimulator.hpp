@@ -12,7 +12,8 @@ public:
                   const std::string& serial_port,
                   const std::string& file_path, // New parameter
                   double interval,
-                  const std::string& symlink_path);
+                  const std::string& symlink_path,
+                  int minute_decimals = 4);
     ~NmeaSimulator();
 
     // Start the simulator
@@ -10,6 +10,7 @@ int main(int argc, char* argv[])
     std::string file_path    = ""; // New variable for the NMEA log file
     double interval          = 1.0; // Default interval in seconds
     std::string symlink_path = "/tmp/ttySIMULATOR"; // Default symlink path
+    int minute_decimals      = 4;
 
     // Simple command-line argument parsing
     for (int i = 1; i < argc; ++i) {
@@ -24,6 +25,8 @@ int main(int argc, char* argv[])
             interval = std::stod(argv[++i]);
         } else if ((arg == "-l" || arg == "--link") && i + 1 < argc) {
             symlink_path = argv[++i];
+        } else if ((arg == "-m" || arg == "--minute-decimals") && i + 1 < argc) {
+            minute_decimals = std::stoi(argv[++i]);
         } else if (arg == "-h" || arg == "--help") {
             std::cout << "Usage: " << argv[0] << " [options]\n"
                       << "Options:\n"
@@ -32,6 +35,7 @@ int main(int argc, char* argv[])
                       << "  -f, --file <path>       Specify NMEA log file path\n" // Help for new option
                       << "  -i, --interval <sec>    Specify interval between sentences (default: 1.0)\n"
                       << "  -l, --link <symlink>    Specify symbolic link path for PTY (default: /tmp/ttySIMULATOR)\n"
+                      << "  -m, --minute-decimals <n>  Digits of the minutes in coordinates, 4 to 8 (default: 4)\n"
                       << "  -h, --help              Show this help message\n";
             return 0;
         }
@@ -43,8 +47,13 @@ int main(int argc, char* argv[])
         return 1;
     }
 
+    if (minute_decimals < 4 || minute_decimals > 8) {
+        std::cerr << "Error: --minute-decimals must be 4 to 8.\n";
+        return 1;
+    }
+
     // Initialize the simulator with the provided arguments
-    NmeaSimulator simulator(pipe_path, serial_port, file_path, interval, symlink_path);
+    NmeaSimulator simulator(pipe_path, serial_port, file_path, interval, symlink_path, minute_decimals);
     simulator.start();
 
     return 0;
@@ -0,0 +1,62 @@
+// benches/encoding.rs
+
+// Encoding cost per epoch. At 100 Hz with 50 simulated devices one tick
+// of all of them has to stay well under the 10 ms between epochs.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use nmea_simulator::nmea_generator::Constellation;
+use nmea_simulator::position::Position;
+use nmea_simulator::sentences::SentenceBuffer;
+use nmea_simulator::NmeaGenerator;
+use std::hint::black_box;
+
+const DEVICES: usize = 50;
+
+// A busy receiver: every sentence, every system and many satellites
+fn generator(seed: u64) -> NmeaGenerator {
+    let mut generator = NmeaGenerator::with_seed(seed);
+    generator.position = Some(Position::new(48.1173, 11.5167, 545.4));
+    generator.satellites = Some(24);
+    generator.constellations = Some(Constellation::ALL.to_vec());
+    generator
+}
+
+fn epoch(c: &mut Criterion) {
+    let mut generator = generator(0);
+    let mut out = SentenceBuffer::new();
+    let fix = generator.generate_fix();
+    c.bench_function("encode_epoch", |b| {
+        b.iter(|| {
+            out.clear();
+            generator.encode_epoch(black_box(&fix), &mut out);
+            black_box(out.as_str().len())
+        })
+    });
+    c.bench_function("encode_sentences", |b| {
+        b.iter(|| generator.encode_sentences(black_box(&fix)))
+    });
+}
+
+// One tick: a fix and its sentences for every device. The buffers live
+// as long as the devices, as in the simulator.
+fn devices(c: &mut Criterion) {
+    let mut devices: Vec<_> = (0..DEVICES as u64)
+        .map(|seed| (generator(seed), SentenceBuffer::new()))
+        .collect();
+    let mut group = c.benchmark_group("100hz");
+    group.throughput(Throughput::Elements(DEVICES as u64));
+    group.bench_function("50_devices", |b| {
+        b.iter(|| {
+            for (generator, out) in devices.iter_mut() {
+                let fix = generator.generate_fix();
+                out.clear();
+                generator.encode_epoch(&fix, out);
+                black_box(out.as_str().len());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, epoch, devices);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+/* Generated with cbindgen from src/ffi.rs, do not edit */
+
+#ifndef NMEA_SIMULATOR_H
+#define NMEA_SIMULATOR_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct NmeaSim NmeaSim;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+NmeaSim *nmea_sim_new(uint64_t seed);
+
+void nmea_sim_free(NmeaSim *sim);
+
+int32_t nmea_sim_set_position(NmeaSim *sim, double latitude, double longitude, double altitude);
+
+void nmea_sim_release_position(NmeaSim *sim);
+
+size_t nmea_sim_next(NmeaSim *sim, uint8_t *buf, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NMEA_SIMULATOR_H */
@@ -0,0 +1,99 @@
+// src/ais.rs
+
+use crate::position::Position;
+
+// AIS messages as AIVDM carries them: the bits of the message in 6-bit
+// ASCII armoring, padded to a whole character with fill bits. Only what
+// the simulated vessels send is here.
+
+// Navigation status "under way using engine", and rate of turn "not
+// available"
+const STATUS_UNDER_WAY: u64 = 0;
+const ROT_NOT_AVAILABLE: i64 = -128;
+const SPEED_NOT_AVAILABLE: u64 = 1023;
+const HEADING_NOT_AVAILABLE: u64 = 511;
+
+// Message 1, the scheduled position report of a class A transponder
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionReport {
+    pub mmsi: u32,
+    pub position: Position,
+    pub speed_knots: f64,
+    pub course: f64,
+    // True heading, not available when unset
+    pub heading: Option<f64>,
+    // UTC second of the report
+    pub second: u32,
+}
+
+impl PositionReport {
+    // The armored payload and its fill bits
+    pub fn encode(&self) -> (String, u8) {
+        let mut bits = Bits::default();
+        bits.put(1, 6);
+        bits.put(0, 2); // repeat indicator
+        bits.put(self.mmsi as u64, 30);
+        bits.put(STATUS_UNDER_WAY, 4);
+        bits.put_signed(ROT_NOT_AVAILABLE, 8);
+        // Tenths of a knot; 102.2 means 102.2 or more
+        let speed = match self.speed_knots {
+            speed if speed.is_finite() && speed >= 0.0 => ((speed * 10.0).round() as u64).min(1022),
+            _ => SPEED_NOT_AVAILABLE,
+        };
+        bits.put(speed, 10);
+        // Position accuracy above 10 m, then the coordinates in ten
+        // thousandths of a minute
+        bits.put(0, 1);
+        bits.put_signed((self.position.lon_deg * 600_000.0).round() as i64, 28);
+        bits.put_signed((self.position.lat_deg * 600_000.0).round() as i64, 27);
+        bits.put(
+            ((self.course.rem_euclid(360.0) * 10.0).round() as u64) % 3600,
+            12,
+        );
+        let heading = self.heading.map_or(HEADING_NOT_AVAILABLE, |heading| {
+            heading.rem_euclid(360.0).round() as u64 % 360
+        });
+        bits.put(heading, 9);
+        bits.put(self.second.min(59) as u64, 6);
+        bits.put(0, 2); // no special manoeuvre
+        bits.put(0, 3); // spare
+        bits.put(0, 1); // RAIM not in use
+        bits.put(0, 19); // SOTDMA radio status
+        bits.armor()
+    }
+}
+
+#[derive(Default)]
+struct Bits {
+    bits: Vec<bool>,
+}
+
+impl Bits {
+    fn put(&mut self, value: u64, width: usize) {
+        self.bits
+            .extend((0..width).rev().map(|i| (value >> i) & 1 == 1));
+    }
+
+    fn put_signed(&mut self, value: i64, width: usize) {
+        self.put(value as u64 & ((1u64 << width) - 1), width);
+    }
+
+    // Six bits to a character: 0 to 39 as '0' to 'W', 40 to 63 as '`'
+    // to 'w'
+    fn armor(self) -> (String, u8) {
+        let fill = (6 - self.bits.len() % 6) % 6;
+        let payload = self
+            .bits
+            .chunks(6)
+            .map(|chunk| {
+                let value = chunk
+                    .iter()
+                    .chain(std::iter::repeat(&false))
+                    .take(6)
+                    .fold(0u8, |value, &bit| value << 1 | bit as u8);
+                (if value < 40 { value + 48 } else { value + 56 }) as char
+            })
+            .collect();
+        (payload, fill as u8)
+    }
+}
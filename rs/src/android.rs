@@ -0,0 +1,183 @@
+// src/android.rs
+
+use crate::nmea_generator::Fix;
+use crate::state::SharedState;
+use std::error::Error;
+use std::fmt;
+use std::process::{Command, Stdio};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const METERS_PER_SECOND_PER_KNOT: f64 = 1852.0 / 3600.0;
+
+// The mock location provider on devices, from the Appium project
+// (github.com/appium/io.appium.settings); installed with any Appium setup
+// or from its APK
+const SETTINGS_PACKAGE: &str = "io.appium.settings";
+const LOCATION_SERVICE: &str = "io.appium.settings/.LocationService";
+
+// Android device or emulator the fixes go to, by its adb serial as in
+// `adb devices`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AndroidTarget {
+    // The emulator console's geo fix, through adb emu
+    Emulator(String),
+    // A device running the Appium settings app as its mock location
+    // provider
+    Device(String),
+}
+
+impl AndroidTarget {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value.split_once(':') {
+            Some(("emulator", serial)) if !serial.is_empty() => {
+                Ok(AndroidTarget::Emulator(serial.to_string()))
+            }
+            Some(("device", serial)) if !serial.is_empty() => {
+                Ok(AndroidTarget::Device(serial.to_string()))
+            }
+            _ => Err(format!(
+                "Invalid Android target '{}', expected emulator:<serial> or device:<serial>",
+                value
+            )
+            .into()),
+        }
+    }
+
+    pub fn serial(&self) -> &str {
+        match self {
+            AndroidTarget::Emulator(serial) | AndroidTarget::Device(serial) => serial,
+        }
+    }
+
+    // The adb arguments that set the location to the fix
+    pub fn location_args(&self, fix: &Fix) -> Vec<String> {
+        let mut args = vec!["-s".to_string(), self.serial().to_string()];
+        match self {
+            // geo fix <longitude> <latitude> [<altitude> [<satellites> [<knots>]]]
+            AndroidTarget::Emulator(_) => {
+                args.extend(["emu", "geo", "fix"].map(String::from));
+                args.extend([
+                    format!("{:.7}", fix.longitude),
+                    format!("{:.7}", fix.latitude),
+                    format!("{:.1}", fix.altitude),
+                    fix.satellites_used().to_string(),
+                    format!("{:.1}", fix.speed_knots),
+                ]);
+            }
+            AndroidTarget::Device(_) => {
+                args.extend(
+                    [
+                        "shell",
+                        "am",
+                        "start-foreground-service",
+                        "--user",
+                        "0",
+                        "-n",
+                        LOCATION_SERVICE,
+                    ]
+                    .map(String::from),
+                );
+                let extras = [
+                    ("latitude", format!("{:.7}", fix.latitude)),
+                    ("longitude", format!("{:.7}", fix.longitude)),
+                    ("altitude", format!("{:.1}", fix.altitude)),
+                    (
+                        "speed",
+                        format!("{:.2}", fix.speed_knots * METERS_PER_SECOND_PER_KNOT),
+                    ),
+                    ("bearing", format!("{:.1}", fix.course)),
+                ];
+                for (name, value) in extras {
+                    args.extend(["--es".to_string(), name.to_string(), value]);
+                }
+            }
+        }
+        args
+    }
+}
+
+impl fmt::Display for AndroidTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AndroidTarget::Emulator(serial) => write!(f, "emulator:{}", serial),
+            AndroidTarget::Device(serial) => write!(f, "device:{}", serial),
+        }
+    }
+}
+
+// Runs adb, failing with what it printed when it does
+fn adb(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let output = Command::new("adb")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("adb {}: {}", args.join(" "), message.trim()).into());
+    }
+    Ok(())
+}
+
+// Moves the location of an Android device or emulator along with the fixes
+// of the first port. adb takes a while for each, so a fix that comes in
+// meanwhile replaces the one before instead of queueing up.
+pub fn spawn_android_output(
+    target: AndroidTarget,
+    state: SharedState,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    adb(&["version".to_string()]).map_err(|e| format!("adb is needed for --android: {}", e))?;
+    if let AndroidTarget::Device(serial) = &target {
+        // Lets the settings app provide mock locations
+        let args = ["-s", serial, "shell", "appops", "set", SETTINGS_PACKAGE]
+            .into_iter()
+            .chain(["android:mock_location", "allow"])
+            .map(String::from)
+            .collect::<Vec<_>>();
+        if let Err(e) = adb(&args) {
+            warn!("Failed to allow mock locations on {}: {}", serial, e);
+        }
+    }
+    info!("Sending fixes to Android {}", target);
+
+    Ok(thread::spawn(move || {
+        let mut last_epoch = None;
+        let mut failing = false;
+        while !shutdown_event.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+            let (fix, no_fix) = {
+                let state = state.lock().unwrap();
+                (state.truth.clone(), state.no_fix)
+            };
+            let Some(fix) = fix.filter(|fix| last_epoch != Some(fix.time)) else {
+                continue;
+            };
+            last_epoch = Some(fix.time);
+            // Without a fix the device keeps its last location
+            if no_fix || fix.fix_quality == 0 {
+                continue;
+            }
+
+            // Reported once until the device is back, e.g. after a reboot
+            match adb(&target.location_args(&fix)) {
+                Ok(()) if failing => {
+                    info!("Android {} takes fixes again", target);
+                    failing = false;
+                }
+                Ok(()) => {}
+                Err(e) if !failing => {
+                    warn!("Failed to send a fix to Android: {}", e);
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }))
+}
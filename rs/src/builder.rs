@@ -0,0 +1,257 @@
+// src/builder.rs
+
+use crate::control::ControlCommand;
+use crate::error::SimError;
+use crate::faults::Fault;
+use crate::nmea_generator::{DgpsNetwork, EncodingMode, TalkerPolicy, FORMATTERS};
+use crate::options::Options;
+use crate::output::{OutputSink, OutputSpec, PtyMode, MAX_LATENCY};
+use crate::position::Position;
+use crate::simulator::Simulator;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::time::Duration;
+
+// Typical motion of the simulated receiver. A profile pins the speed, which
+// walks and routes move at and the receiver reports.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionProfile {
+    Static,
+    Pedestrian,
+    Car,
+    Boat,
+    Aircraft,
+}
+
+impl MotionProfile {
+    pub fn speed_knots(&self) -> f64 {
+        match self {
+            MotionProfile::Static => 0.0,
+            MotionProfile::Pedestrian => 2.7,
+            MotionProfile::Car => 27.0,
+            MotionProfile::Boat => 12.0,
+            MotionProfile::Aircraft => 250.0,
+        }
+    }
+}
+
+// Sets up a Simulator for embedding without going through Options and the
+// controller by hand, e.g.
+//
+//   let simulator = Simulator::builder()
+//       .rate_hz(5.0)
+//       .profile(MotionProfile::Car)
+//       .output(OutputSpec::File("/tmp/gps.nmea".into()))
+//       .sentence("GGA")
+//       .sentence("RMC")
+//       .stop_on_signals()
+//       .build()?;
+//   simulator.run()?;
+#[derive(Default)]
+pub struct SimulatorBuilder {
+    options: Options,
+    sinks: Vec<Box<dyn OutputSink>>,
+    rate_hz: Option<f64>,
+    // Formatters to send, all of them when empty
+    sentences: Vec<String>,
+    position: Option<Position>,
+    profile: Option<MotionProfile>,
+    stop_on_signals: bool,
+}
+
+impl SimulatorBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Epochs per second, 0.1 to 10
+    pub fn rate_hz(mut self, hz: f64) -> Self {
+        self.rate_hz = Some(hz);
+        self
+    }
+
+    pub fn profile(mut self, profile: MotionProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    pub fn position(mut self, position: Position) -> Self {
+        self.position = Some(position);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = Some(seed);
+        self
+    }
+
+    // Ends run() after this many epochs per port
+    pub fn count(mut self, epochs: u64) -> Self {
+        self.options.count = Some(epochs);
+        self
+    }
+
+    // Ends run() after this long
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.options.duration = Some(duration);
+        self
+    }
+
+    // Serves a linked PTY pair, as the command line does with two paths
+    pub fn linked_ptys(mut self, gps_input_path: &str, gps_output_path: &str) -> Self {
+        self.options.gps_input_path = Some(gps_input_path.to_string());
+        self.options.gps_output_path = Some(gps_output_path.to_string());
+        if self.options.pty_mode == PtyMode::SinglePort {
+            self.options.pty_mode = PtyMode::LinkedPair;
+        }
+        self
+    }
+
+    // Serves a single PTY that clients open, as --mode single-port does
+    pub fn single_pty(mut self, path: &str) -> Self {
+        self.options.gps_input_path = Some(path.to_string());
+        self.options.gps_output_path = None;
+        self.options.pty_mode = PtyMode::SinglePort;
+        self
+    }
+
+    // Makes the linked pair a loopback, forwarding what clients write to
+    // the input PTY too
+    pub fn loopback(mut self) -> Self {
+        self.options.pty_mode = PtyMode::Loopback;
+        self
+    }
+
+    // Holds <gps_input_path>.lock, so that a second simulator on the same
+    // paths fails instead of taking them over
+    pub fn lock_links(mut self) -> Self {
+        self.options.lock_links = true;
+        self
+    }
+
+    pub fn output(mut self, spec: OutputSpec) -> Self {
+        self.options.outputs.push(spec);
+        self
+    }
+
+    // Where the fix behind every epoch goes as a JSON line
+    pub fn truth_output(mut self, spec: OutputSpec) -> Self {
+        self.options.truth_outputs.push(spec);
+        self
+    }
+
+    // Damages the sentences of every port, e.g. Fault::Checksum(0.02)
+    pub fn fault(mut self, fault: Fault) -> Self {
+        self.options.faults.push(fault);
+        self
+    }
+
+    // Sends every epoch this long after its fix
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.options.latency = latency;
+        self
+    }
+
+    // Talker ID of the position sentences, GN for several systems by default
+    pub fn talker(mut self, policy: TalkerPolicy) -> Self {
+        self.options.talker_policy = policy;
+        self
+    }
+
+    // Strict NMEA 0183 4.11 sentences, or permissive ones by default
+    pub fn encoding(mut self, encoding: EncodingMode) -> Self {
+        self.options.encoding = encoding;
+        self
+    }
+
+    // Reference stations a differential fix reports in turn
+    pub fn dgps_network(mut self, network: DgpsNetwork) -> Self {
+        self.options.dgps_network = Some(network);
+        self
+    }
+
+    // A sink of the embedding program, e.g. a channel into the test
+    pub fn sink(mut self, sink: Box<dyn OutputSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    // Sends this sentence, e.g. "GGA"; once one is chosen the others are
+    // left out
+    pub fn sentence(mut self, formatter: &str) -> Self {
+        self.sentences.push(formatter.to_ascii_uppercase());
+        self
+    }
+
+    // Ends the run on SIGINT and SIGTERM, with the PTY symlinks removed
+    pub fn stop_on_signals(mut self) -> Self {
+        self.stop_on_signals = true;
+        self
+    }
+
+    // Everything else the command line can set
+    pub fn options(mut self, f: impl FnOnce(&mut Options)) -> Self {
+        f(&mut self.options);
+        self
+    }
+
+    pub fn build(self) -> Result<Simulator, SimError> {
+        if self.options.latency > MAX_LATENCY {
+            return Err(SimError::Usage(format!(
+                "Invalid latency {:?}, expected up to {:?}",
+                self.options.latency, MAX_LATENCY
+            )));
+        }
+        let mut simulator = Simulator::new(self.options);
+        for sink in self.sinks {
+            simulator.add_output(sink);
+        }
+
+        let mut commands = Vec::new();
+        if let Some(hz) = self.rate_hz {
+            commands.push(ControlCommand::SetRate { hz, port: None });
+        }
+        if !self.sentences.is_empty() {
+            for formatter in FORMATTERS {
+                commands.push(ControlCommand::SetSentence {
+                    sentence: formatter.to_string(),
+                    enabled: false,
+                    port: None,
+                });
+            }
+            for formatter in self.sentences {
+                commands.push(ControlCommand::SetSentence {
+                    sentence: formatter,
+                    enabled: true,
+                    port: None,
+                });
+            }
+        }
+        if let Some(position) = self.position {
+            commands.push(ControlCommand::SetPosition {
+                latitude: position.lat_deg,
+                longitude: position.lon_deg,
+                altitude: position.alt_m,
+            });
+        }
+        if let Some(profile) = self.profile {
+            commands.push(ControlCommand::SetSpeed {
+                knots: profile.speed_knots(),
+                course: None,
+            });
+        }
+        let controller = simulator.controller();
+        for command in commands {
+            controller
+                .apply(command)
+                .map_err(|e| SimError::Usage(e.to_string()))?;
+        }
+
+        if self.stop_on_signals {
+            for signal in [SIGINT, SIGTERM] {
+                signal_hook::flag::register(signal, simulator.shutdown_handle())
+                    .map_err(|e| SimError::io("Failed to install the signal handlers", e))?;
+            }
+        }
+        Ok(simulator)
+    }
+}
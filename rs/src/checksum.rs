@@ -0,0 +1,41 @@
+// src/checksum.rs
+
+use crate::parser::ParseError;
+use crate::tag_block;
+
+// The NMEA 0183 checksum of sentences, for encoding them and for checking
+// what comes back. UBX frames have their own, see ubx::checksum.
+
+// XOR of all characters between '$' or '!' and '*'
+pub fn checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |checksum, c| checksum ^ c)
+}
+
+// The sentence with its body, e.g. "GPTXT,01,01,02,hello": a leading '$'
+// unless it starts with '!' already, the checksum and the line ending
+pub fn wrap_sentence(body: &str) -> String {
+    let (start, body) = match body.strip_prefix(['$', '!']) {
+        Some(rest) => (&body[..1], rest),
+        None => ("$", body),
+    };
+    format!("{}{}*{:02X}\r\n", start, body, checksum(body))
+}
+
+// The body of a complete sentence, e.g. "GPTXT,01,01,02,hello" of
+// "$GPTXT,01,01,02,hello*59\r\n", if its checksum is there and right. A
+// TAG block in front of it must be right too.
+pub fn verify(line: &str) -> Result<&str, ParseError> {
+    let line = tag_block::strip(line.trim_end())?;
+    let line = line.strip_prefix(['$', '!']).unwrap_or(line);
+    let (body, sum) = line.split_once('*').unwrap_or((line, ""));
+    let expected = checksum(body);
+    // Uppercase hex only, as the standard has it
+    let valid = sum.len() == 2 && !sum.bytes().any(|c| c.is_ascii_lowercase());
+    if !valid || u8::from_str_radix(sum, 16) != Ok(expected) {
+        return Err(ParseError::Checksum {
+            expected,
+            found: sum.to_string(),
+        });
+    }
+    Ok(body)
+}
@@ -0,0 +1,1139 @@
+// src/cli.rs
+
+use crate::android::AndroidTarget;
+use crate::error::SimError;
+use crate::faults::Fault;
+use crate::mavlink::GpsMessage;
+use crate::mux::Instrument;
+use crate::nmea_generator::{
+    Crab, DgpsNetwork, EncodingMode, Environment, NmeaGenerator, TalkerPolicy,
+};
+use crate::ntrip::NtripConfig;
+use crate::options::Options;
+use crate::output::{Framing, MultiSink, OutputSink, OutputSpec, PtyMode, StdoutSink, MAX_LATENCY};
+use crate::pps::PpsSpec;
+use crate::replay::{self, Replay};
+use crate::rtcm::RtcmBase;
+use crate::sentences::MINUTE_DECIMALS;
+#[cfg(unix)]
+use crate::simulator::Simulator;
+use crate::tag_block::TagBlocks;
+use crate::trajectory::TrajectorySource;
+use crate::truth_input::TruthInput;
+use crate::ubx::Protocol;
+use crate::validate::{Validator, KINEMATICS_TOLERANCE};
+use chrono::TimeDelta;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Read};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+#[cfg(unix)]
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// How long selftest waits for the simulator to create its PTYs
+#[cfg(unix)]
+const SELFTEST_STARTUP: Duration = Duration::from_secs(5);
+
+// The command line of the binary. Without a subcommand the arguments are
+// those of serve, as before there were subcommands.
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Simulated GNSS receiver writing NMEA 0183 to PTYs, pipes and sockets",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub serve: ServeArgs,
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(about = "Serve simulated receivers on linked PTYs or a FIFO")]
+    Serve(Box<ServeArgs>),
+    #[command(about = "Write generated sentences to stdout or outputs")]
+    Generate(GenerateArgs),
+    #[command(about = "Play a recorded NMEA log back at its recorded pace")]
+    Replay(ReplayArgs),
+    #[command(about = "Check the checksums, fields, ranges and times of NMEA logs")]
+    Validate(ValidateArgs),
+    #[command(about = "Serve a PTY, read it as a client would and check what comes out")]
+    Selftest(SelftestArgs),
+}
+
+#[derive(Args)]
+pub struct LogArgs {
+    #[arg(
+        long,
+        global = true,
+        value_name = "FILTER",
+        value_parser = |filter: &str| crate::logging::parse_filter(filter).map(|_| filter.to_string()),
+        env = "NMEA_SIM_LOG_LEVEL",
+        help = "Log level or filter, e.g. debug or warn,nmea_simulator::output=info [default: RUST_LOG or info]"
+    )]
+    pub log_level: Option<String>,
+    #[arg(
+        short,
+        long,
+        global = true,
+        conflicts_with = "log_level",
+        env = "NMEA_SIM_QUIET",
+        help = "Log warnings and errors only"
+    )]
+    pub quiet: bool,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        default_value_t = LogFormat::Text,
+        env = "NMEA_SIM_LOG_FORMAT",
+        help = "Log lines as text or JSON objects"
+    )]
+    pub log_format: LogFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl LogArgs {
+    pub fn filter(&self) -> Option<&str> {
+        if self.quiet {
+            Some("warn")
+        } else {
+            self.log_level.as_deref()
+        }
+    }
+
+    pub fn json(&self) -> bool {
+        matches!(self.log_format, LogFormat::Json)
+    }
+}
+
+// Option values use the parsers of the types they configure
+fn parsed<T>(result: Result<T, Box<dyn Error>>) -> Result<T, String> {
+    result.map_err(|e| e.to_string())
+}
+
+// Longest a time option may be, well short of overflowing an Instant
+const MAX_SECONDS: f64 = 365.0 * 24.0 * 3600.0;
+
+fn seconds(value: &str) -> Result<Duration, String> {
+    match value.parse::<f64>() {
+        Ok(secs) if secs > 0.0 && secs <= MAX_SECONDS => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(format!(
+            "expected a number of seconds greater than zero, up to {}",
+            MAX_SECONDS
+        )),
+    }
+}
+
+fn latency(value: &str) -> Result<Duration, String> {
+    match seconds(value) {
+        Ok(latency) if latency <= MAX_LATENCY => Ok(latency),
+        _ => Err(format!(
+            "expected a latency greater than zero, up to {} seconds",
+            MAX_LATENCY.as_secs()
+        )),
+    }
+}
+
+fn tolerance(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(tolerance) if tolerance.is_finite() && tolerance >= 0.0 => Ok(tolerance),
+        _ => Err("expected a tolerance of zero or more".to_string()),
+    }
+}
+
+fn speed(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(speed) if speed.is_finite() && speed > 0.0 => Ok(speed),
+        _ => Err("expected a speed factor greater than zero".to_string()),
+    }
+}
+
+// As set-rate has it
+fn rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(hz) if (0.1..=10.0).contains(&hz) => Ok(hz),
+        _ => Err("expected a rate of 0.1 to 10 Hz".to_string()),
+    }
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[arg(
+        env = "NMEA_SIM_GPS_INPUT_PATH",
+        help = "Symlink to the PTY the simulator writes to, or that clients open in single-port mode"
+    )]
+    pub gps_input_path: Option<String>,
+    #[arg(
+        env = "NMEA_SIM_GPS_OUTPUT_PATH",
+        help = "Symlink to the PTY clients read from; not used in single-port mode"
+    )]
+    pub gps_output_path: Option<String>,
+    #[arg(
+        long,
+        requires = "gps_input_path",
+        value_parser = |mode: &str| parsed(PtyMode::parse(mode)),
+        env = "NMEA_SIM_MODE",
+        help = "PTY topology: single-port, linked-pair (default) or loopback, which also forwards what clients write to the input PTY"
+    )]
+    pub mode: Option<PtyMode>,
+    #[arg(
+        long,
+        requires = "gps_input_path",
+        env = "NMEA_SIM_LOCK",
+        help = "Hold <gps_input_path>.lock while serving, so a second simulator on the same paths fails instead of taking them over"
+    )]
+    pub lock: bool,
+    #[arg(
+        long,
+        conflicts_with = "gps_input_path",
+        env = "NMEA_SIM_FIFO",
+        help = "Serve a FIFO instead of linked PTYs; on Windows a named pipe, e.g. nmea_sim for \\\\.\\pipe\\nmea_sim"
+    )]
+    pub fifo: Option<String>,
+    #[arg(
+        long,
+        conflicts_with_all = ["gps_input_path", "fifo", "tui"],
+        env = "NMEA_SIM_HEADLESS",
+        help = "Serve only the --output targets, without PTYs or a FIFO, e.g. in a container"
+    )]
+    pub headless: bool,
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(1..),
+        env = "NMEA_SIM_PORTS",
+        help = "Create N ports; paths mark the port number with n in braces"
+    )]
+    pub ports: u64,
+    #[arg(
+        short,
+        long = "output",
+        value_name = "KIND:TARGET",
+        value_parser = |spec: &str| parsed(OutputSpec::parse(spec)),
+        env = "NMEA_SIM_OUTPUT",
+        value_delimiter = ',',
+        help = "Additional output: pty:<path>, serial:<port>, file:<path>, tcp:<addr:port>, udp:<host:port>, ws:<addr:port>, fifo:<path>; tcp, udp and ws need the features of the same names"
+    )]
+    pub outputs: Vec<OutputSpec>,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u32).range(1..),
+        env = "NMEA_SIM_BAUD",
+        help = "Set the PTY line speed and pace the primary output"
+    )]
+    pub baud: Option<u32>,
+    #[arg(
+        long,
+        value_parser = |spec: &str| parsed(Framing::parse(spec)),
+        env = "NMEA_SIM_FRAMING",
+        help = "Character framing for --baud, e.g. 8N1"
+    )]
+    pub framing: Option<Framing>,
+    #[arg(
+        long,
+        value_name = "rts:PORT|dtr:PORT|chrony:SOCKET",
+        value_parser = |value: &str| parsed(PpsSpec::parse(value)),
+        env = "NMEA_SIM_PPS",
+        help = "Pulse per second at the top of each second: a modem line of a serial port, or a chrony SOCK refclock"
+    )]
+    pub pps: Option<PpsSpec>,
+    #[arg(
+        long,
+        env = "NMEA_SIM_SEED",
+        help = "Seed the random values to repeat a run"
+    )]
+    pub seed: Option<u64>,
+    #[arg(
+        short = 'n',
+        long,
+        env = "NMEA_SIM_COUNT",
+        help = "Stop after this many epochs per port and clean up [default: never]"
+    )]
+    pub count: Option<u64>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = seconds,
+        env = "NMEA_SIM_DURATION",
+        help = "Stop after this long and clean up [default: never]"
+    )]
+    pub duration: Option<Duration>,
+    #[arg(
+        long,
+        value_name = "nmea|ubx|both",
+        value_parser = |value: &str| parsed(Protocol::parse(value)),
+        env = "NMEA_SIM_PROTOCOL",
+        help = "Encode epochs as NMEA, UBX NAV-PVT/SAT/DOP or both"
+    )]
+    pub protocol: Option<Protocol>,
+    #[arg(
+        long,
+        value_name = "URL",
+        value_parser = |url: &str| parsed(NtripConfig::parse(url)),
+        env = "NMEA_SIM_NTRIP",
+        help = "Take the fix quality from an NTRIP caster, [user:pass@]host[:port]/mount"
+    )]
+    pub ntrip: Option<NtripConfig>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = seconds,
+        requires = "ntrip",
+        env = "NMEA_SIM_NTRIP_TIMEOUT",
+        help = "How long the caster's fix quality stays valid"
+    )]
+    pub ntrip_timeout: Option<Duration>,
+    #[arg(
+        long = "rtcm-output",
+        value_name = "KIND:TARGET",
+        value_parser = |spec: &str| parsed(OutputSpec::parse(spec)),
+        requires = "rtcm_base",
+        env = "NMEA_SIM_RTCM_OUTPUT",
+        value_delimiter = ',',
+        help = "Where the RTCM3 stream of the simulated base station goes"
+    )]
+    pub rtcm_outputs: Vec<OutputSpec>,
+    #[arg(
+        long,
+        value_name = "LAT,LON,ALT",
+        value_parser = |spec: &str| parsed(RtcmBase::parse(spec)),
+        requires = "rtcm_outputs",
+        env = "NMEA_SIM_RTCM_BASE",
+        help = "Position of the simulated base station"
+    )]
+    pub rtcm_base: Option<RtcmBase>,
+    #[arg(
+        long,
+        value_name = "ID",
+        value_parser = clap::value_parser!(u16).range(0..4096),
+        requires = "rtcm_outputs",
+        env = "NMEA_SIM_RTCM_STATION_ID",
+        help = "Reference station ID in the RTCM3 messages [default: 0]"
+    )]
+    pub rtcm_station_id: Option<u16>,
+    #[arg(
+        long = "signalk-output",
+        value_name = "KIND:TARGET",
+        value_parser = |spec: &str| parsed(OutputSpec::parse(spec)),
+        env = "NMEA_SIM_SIGNALK_OUTPUT",
+        value_delimiter = ',',
+        help = "Send Signal K deltas, e.g. ws:0.0.0.0:3000"
+    )]
+    pub signalk_outputs: Vec<OutputSpec>,
+    #[arg(
+        long = "truth-output",
+        value_name = "KIND:TARGET",
+        value_parser = |spec: &str| parsed(OutputSpec::parse(spec)),
+        env = "NMEA_SIM_TRUTH_OUTPUT",
+        value_delimiter = ',',
+        help = "Write the fix behind every epoch as a JSON line, e.g. file:truth.jsonl"
+    )]
+    pub truth_outputs: Vec<OutputSpec>,
+    #[arg(
+        long = "fault",
+        value_name = "KIND=RATE",
+        value_parser = |spec: &str| parsed(Fault::parse(spec)),
+        env = "NMEA_SIM_FAULT",
+        value_delimiter = ',',
+        help = "Damage a fraction of the sentences, e.g. checksum=0.02: checksum (wrong, lowercase or missing), truncate (cut mid-field), merge (no line ending), garbage=RATE[:LEN] (up to LEN non-ASCII bytes after it, default 16), drop, drop-epoch, repeat (a sentence of the epoch before after it), jitter=FRACTION (of the interval late), stall=RATE[:SECS] (hold epochs back, then burst, default 5), freeze=RATE[:SECS] (repeat a fix while the truth moves, default 10), outlier=RATE[:METRES] (a position up to METRES off, default 1000), lf (bare LF line ending), no-dollar, split (epoch written in pieces cut anywhere), seq-gap=RATE[:LEN] (template {seq} numbers skip up to LEN, default 1)"
+    )]
+    pub faults: Vec<Fault>,
+    #[arg(
+        long = "mux",
+        value_name = "INSTRUMENT[=HZ]",
+        value_parser = |spec: &str| parsed(Instrument::parse(spec)),
+        env = "NMEA_SIM_MUX",
+        value_delimiter = ',',
+        help = "Send as through an NMEA multiplexer, with other instruments talking in between the receiver's sentences on their own clocks: gyro (HEHDT, default 10 Hz), compass (HCHDG, 5 Hz), depth (SDDPT and SDDBT, 1 Hz) or ais (!AIVDM of vessels nearby, 2 Hz)"
+    )]
+    pub mux: Vec<Instrument>,
+    #[arg(
+        long = "tag-block",
+        value_name = "PARAMS",
+        value_parser = |spec: &str| parsed(TagBlocks::parse(spec)),
+        env = "NMEA_SIM_TAG_BLOCK",
+        help = "Frame every sentence in an NMEA 4.0 TAG block with these parameters, e.g. s:gps1,c,n: s:SOURCE (up to 15 characters), c (UNIX time in seconds) and n (line count)"
+    )]
+    pub tag_blocks: Option<TagBlocks>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = latency,
+        env = "NMEA_SIM_LATENCY",
+        help = "Send every epoch this long after its fix, so the output lags the truth"
+    )]
+    pub latency: Option<Duration>,
+    #[arg(
+        long,
+        requires = "latency",
+        env = "NMEA_SIM_REPORT_AGE",
+        help = "Report the latency as the age of the data in GGA field 13"
+    )]
+    pub report_age: bool,
+    #[arg(
+        long,
+        value_name = "DIGITS",
+        value_parser = clap::value_parser!(u8).range(4..=8),
+        env = "NMEA_SIM_MINUTE_DECIMALS",
+        help = "Digits of the minutes in coordinates, 4 to 8 [default: 4]"
+    )]
+    pub minute_decimals: Option<u8>,
+    #[arg(
+        long = "talker",
+        value_name = "POLICY",
+        value_parser = |value: &str| parsed(TalkerPolicy::parse(value)),
+        env = "NMEA_SIM_TALKER",
+        help = "Talker ID of GGA, RMC and GLL: gp (with every system in one GSA unless strict), gn (GN with more than one system) or dominant (system with the most satellites) [default: gn]"
+    )]
+    pub talker_policy: Option<TalkerPolicy>,
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_parser = |value: &str| parsed(EncodingMode::parse(value)),
+        env = "NMEA_SIM_ENCODING",
+        help = "How closely sentences follow the standard: strict (NMEA 0183 4.11: hundredths of seconds, GLL mode, RMC navigational status, GSA system and GSV signal IDs, GSV per system, at most 82 characters) or permissive (as older receivers send them) [default: permissive]"
+    )]
+    pub encoding: Option<EncodingMode>,
+    #[arg(
+        long,
+        env = "NMEA_SIM_SBAS",
+        help = "Receive SBAS corrections: SBAS satellites (PRN 120-158) in view and a DGPS fix with the SBAS PRN as station ID"
+    )]
+    pub sbas: bool,
+    #[arg(
+        long,
+        value_name = "ENVIRONMENT",
+        value_parser = |value: &str| parsed(Environment::parse(value)),
+        env = "NMEA_SIM_ENVIRONMENT",
+        help = "Surroundings the satellite signal strengths follow: open-sky, suburban, urban or indoor [default: open-sky]"
+    )]
+    pub environment: Option<Environment>,
+    #[arg(
+        long,
+        value_name = "MODEL",
+        value_parser = |value: &str| parsed(Crab::parse(value)),
+        env = "NMEA_SIM_CRAB",
+        help = "Heading off the course over the ground for HDT and HDG: none, offset:<degrees> (clockwise) or current:<knots>@<set> (heading through the water against a current or leeway) [default: none]"
+    )]
+    pub crab: Option<Crab>,
+    #[arg(
+        long,
+        value_name = "STATIONS",
+        value_parser = |value: &str| parsed(DgpsNetwork::parse(value)),
+        env = "NMEA_SIM_DGPS_NETWORK",
+        help = "Reference station IDs a differential GGA reports in turn, e.g. 101,102,103@120 for two minutes each [default: 60 s each]; the age of the corrections rises from 0 to 10 s and drops back"
+    )]
+    pub dgps_network: Option<DgpsNetwork>,
+    #[arg(
+        long,
+        value_name = "SOURCE",
+        value_parser = |value: &str| parsed(TrajectorySource::parse(value)),
+        env = "NMEA_SIM_TRAJECTORY",
+        help = "Where the positions come from: external (pinned by the control API or --truth-input, random otherwise), walk (wandering at the pinned speed) or route:<file> (along latitude,longitude[,altitude] lines, looping) [default: external]"
+    )]
+    pub trajectory: Option<TrajectorySource>,
+    #[arg(
+        long,
+        env = "NMEA_SIM_STRICT",
+        help = "Stop with an error at an epoch whose speeds, courses or headings disagree with its fix, instead of logging it"
+    )]
+    pub strict: bool,
+    #[arg(
+        long,
+        value_name = "TOLERANCE",
+        value_parser = tolerance,
+        env = "NMEA_SIM_KINEMATICS_TOLERANCE",
+        help = "How far speeds in knots and km/h and angles in degrees may be off the fix [default: 0.1]"
+    )]
+    pub kinematics_tolerance: Option<f64>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "gps_input_path",
+        env = "NMEA_SIM_RECORD_RECEIVED",
+        help = "Log data received from clients as hex and ASCII"
+    )]
+    pub record_received: Option<String>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "NMEA_SIM_READY_FILE",
+        help = "Create this file once the links exist and every port is sending or waiting for a client; removed on exit"
+    )]
+    pub ready_file: Option<String>,
+    #[arg(
+        long,
+        env = "NMEA_SIM_CONFIG",
+        help = "TOML settings (rate, sentences, position, ...), reloaded on change or SIGHUP"
+    )]
+    pub config: Option<String>,
+    #[arg(
+        long,
+        env = "NMEA_SIM_SCENARIO",
+        help = "Play a TOML timeline of control commands"
+    )]
+    pub scenario: Option<String>,
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "NMEA_SIM_RECORD",
+        help = "Record the truth of every epoch and the commands applied as a scenario that replays the run with --scenario"
+    )]
+    pub record: Option<String>,
+    #[arg(
+        long,
+        value_name = "udp:ADDR:PORT|mavlink:ADDR:PORT|control",
+        value_parser = |value: &str| parsed(TruthInput::parse(value)),
+        env = "NMEA_SIM_TRUTH_INPUT",
+        help = "Take the truth from an external simulator"
+    )]
+    pub truth_input: Option<TruthInput>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = seconds,
+        requires = "truth_input",
+        env = "NMEA_SIM_TRUTH_TIMEOUT",
+        help = "How long the truth stays valid without an update [default: 2]"
+    )]
+    pub truth_timeout: Option<Duration>,
+    #[arg(
+        long,
+        value_name = "udp:HOST:PORT",
+        value_parser = |value: &str| {
+            value
+                .strip_prefix("udp:")
+                .map(str::to_string)
+                .ok_or("expected udp:<host:port>")
+        },
+        env = "NMEA_SIM_MAVLINK_OUTPUT",
+        help = "Feed fixes to an autopilot over MAVLink"
+    )]
+    pub mavlink_output: Option<String>,
+    #[arg(
+        long,
+        value_name = "gps_input|hil_gps",
+        value_parser = |value: &str| parsed(GpsMessage::parse(value)),
+        requires = "mavlink_output",
+        env = "NMEA_SIM_MAVLINK_MESSAGE",
+        help = "MAVLink message carrying the fixes [default: gps_input]"
+    )]
+    pub mavlink_message: Option<GpsMessage>,
+    #[arg(
+        long = "android",
+        value_name = "emulator:SERIAL|device:SERIAL",
+        value_parser = |value: &str| parsed(AndroidTarget::parse(value)),
+        env = "NMEA_SIM_ANDROID",
+        help = "Move the location of an Android emulator (geo fix) or device (Appium settings app) over adb"
+    )]
+    pub android_output: Option<AndroidTarget>,
+    #[arg(
+        long,
+        env = "NMEA_SIM_ROS2",
+        help = "Publish NavSatFix on fix and the sentences on nmea_sentence to ROS 2 through zenoh-bridge-ros2dds"
+    )]
+    pub ros2: bool,
+    #[arg(
+        long,
+        value_name = "ENDPOINT",
+        requires = "ros2",
+        env = "NMEA_SIM_ROS2_CONNECT",
+        value_delimiter = ',',
+        help = "zenoh endpoint of the bridge, e.g. tcp/192.168.1.10:7447; found by scouting when not given"
+    )]
+    pub ros2_connect: Vec<String>,
+    #[arg(
+        long,
+        value_name = "ID",
+        requires = "ros2",
+        env = "NMEA_SIM_ROS2_FRAME_ID",
+        help = "frame_id of the published messages [default: gps]"
+    )]
+    pub ros2_frame_id: Option<String>,
+    #[arg(
+        long,
+        env = "NMEA_SIM_SCRIPT",
+        help = "Run a Rhai scenario script (at, every, on_epoch, set_*, emit, ...)"
+    )]
+    pub script: Option<String>,
+    #[arg(
+        long,
+        env = "NMEA_SIM_CONTROL",
+        help = "Take JSON-line commands on a Unix socket, e.g. /tmp/nmea_sim.ctl"
+    )]
+    pub control: Option<String>,
+    #[arg(
+        long,
+        value_name = "ADDR:PORT",
+        env = "NMEA_SIM_API",
+        help = "REST control API (GET /state, PUT /position, PUT /rate, POST /inject, ...)"
+    )]
+    pub api: Option<String>,
+    #[arg(
+        long,
+        conflicts_with = "repl",
+        env = "NMEA_SIM_TUI",
+        help = "Interactive terminal UI with live controls"
+    )]
+    pub tui: bool,
+    #[arg(
+        long,
+        env = "NMEA_SIM_REPL",
+        help = "Read commands like 'pos 37.77 -122.41' or 'speed 12' from stdin"
+    )]
+    pub repl: bool,
+    #[arg(
+        long,
+        env = "NMEA_SIM_STEP",
+        help = "Start paused and send one epoch per SIGUSR2, advance command or 's' in the terminal UI"
+    )]
+    pub step: bool,
+    #[arg(
+        long,
+        value_name = "ADDR:PORT",
+        env = "NMEA_SIM_HTTP",
+        help = "Serve /events (SSE), /state (JSON), /health and /ready"
+    )]
+    pub http: Option<String>,
+    #[arg(
+        long,
+        value_name = "ADDR:PORT",
+        env = "NMEA_SIM_METRICS",
+        help = "Serve Prometheus metrics on /metrics: I/O stats and the simulated position, speed, satellites and fix"
+    )]
+    pub metrics: Option<String>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = seconds,
+        env = "NMEA_SIM_STATS_INTERVAL",
+        help = "Log sentences and bytes sent, client connects and write errors this often"
+    )]
+    pub stats_interval: Option<Duration>,
+}
+
+impl ServeArgs {
+    // The checks clap can't express; errors are usage errors
+    pub fn into_options(self) -> Result<Options, SimError> {
+        let usage = |message: &str| Err(SimError::Usage(message.to_string()));
+
+        let mode = self.mode.unwrap_or_default();
+        match (&self.gps_input_path, &self.gps_output_path, &self.fifo) {
+            (None, None, Some(_)) => {}
+            (None, None, None) if self.headless => {
+                if self.outputs.is_empty() {
+                    return usage("--headless needs at least one --output");
+                }
+                if self.ports > 1 {
+                    return usage("--headless serves one port; run one simulator per port");
+                }
+            }
+            (Some(_), output, None) if output.is_some() == (mode.paths() == 2) => {
+                if !cfg!(unix) {
+                    return usage(
+                        "PTYs need a Unix system; use --fifo <pipe name> or --output serial:<port>",
+                    );
+                }
+            }
+            _ if mode == PtyMode::SinglePort => {
+                return usage("Expected one PTY path for --mode single-port, or --fifo")
+            }
+            _ => return usage("Expected two PTY paths, --fifo or --headless"),
+        }
+        if self.script.is_some() && !cfg!(feature = "scripting") {
+            return usage("--script needs a build with the scripting feature");
+        }
+        if self.ros2 && !cfg!(feature = "ros2") {
+            return usage("--ros2 needs a build with the ros2 feature");
+        }
+        if self.pps.is_some() && !cfg!(unix) {
+            return usage("--pps needs a Unix system");
+        }
+        if self.control.is_some() && !cfg!(unix) {
+            return usage("--control needs a Unix system");
+        }
+        if self.tui && !cfg!(all(unix, feature = "tui")) {
+            return usage("--tui needs a Unix build with the tui feature");
+        }
+        if self.truth_input == Some(TruthInput::Control)
+            && self.control.is_none()
+            && self.api.is_none()
+        {
+            return usage("--truth-input control needs --control or --api");
+        }
+        if self.ports > 1 {
+            let patterns = [&self.gps_input_path, &self.gps_output_path, &self.fifo];
+            for pattern in patterns.into_iter().flatten() {
+                if !pattern.contains("{n}") {
+                    return Err(SimError::Usage(format!(
+                        "Path '{}' needs a {{n}} placeholder when using --ports",
+                        pattern
+                    )));
+                }
+            }
+        }
+
+        let ntrip = self.ntrip.map(|mut ntrip| {
+            if let Some(timeout) = self.ntrip_timeout {
+                ntrip.timeout = timeout;
+            }
+            ntrip
+        });
+        let rtcm_base = self.rtcm_base.map(|mut base| {
+            base.station_id = self.rtcm_station_id.unwrap_or(0);
+            base
+        });
+
+        Ok(Options {
+            gps_input_path: self.gps_input_path,
+            gps_output_path: self.gps_output_path,
+            pty_mode: mode,
+            lock_links: self.lock,
+            fifo_path: self.fifo,
+            ports: self.ports as usize,
+            outputs: self.outputs,
+            baud: self.baud,
+            framing: self.framing.unwrap_or_default(),
+            pps: self.pps,
+            protocol: self.protocol.unwrap_or_default(),
+            ntrip,
+            rtcm_base,
+            rtcm_outputs: self.rtcm_outputs,
+            signalk_outputs: self.signalk_outputs,
+            truth_outputs: self.truth_outputs,
+            faults: self.faults,
+            mux: self.mux,
+            tag_blocks: self.tag_blocks,
+            latency: self.latency.unwrap_or_default(),
+            report_age: self.report_age,
+            minute_decimals: self
+                .minute_decimals
+                .map_or(MINUTE_DECIMALS, |digits| digits as usize),
+            talker_policy: self.talker_policy.unwrap_or_default(),
+            encoding: self.encoding.unwrap_or_default(),
+            sbas: self.sbas,
+            environment: self.environment,
+            crab: self.crab,
+            dgps_network: self.dgps_network,
+            trajectory: self.trajectory.clone(),
+            kinematics_tolerance: self.kinematics_tolerance.unwrap_or(KINEMATICS_TOLERANCE),
+            strict: self.strict,
+            received_log_path: self.record_received,
+            ready_file: self.ready_file,
+            config_path: self.config,
+            scenario_path: self.scenario,
+            scenario_record_path: self.record,
+            truth_input: self.truth_input,
+            truth_timeout: self.truth_timeout.unwrap_or(Duration::from_secs(2)),
+            mavlink_output: self.mavlink_output,
+            mavlink_message: self.mavlink_message.unwrap_or_default(),
+            android_output: self.android_output,
+            ros2: self.ros2,
+            ros2_connect: self.ros2_connect,
+            ros2_frame_id: self.ros2_frame_id.unwrap_or_else(|| "gps".to_string()),
+            script_path: self.script,
+            control_path: self.control,
+            api_addr: self.api,
+            tui: self.tui,
+            repl: self.repl,
+            step: self.step,
+            count: self.count,
+            duration: self.duration,
+            seed: self.seed,
+            http_addr: self.http,
+            metrics_addr: self.metrics,
+            stats_interval: self.stats_interval,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct GenerateArgs {
+    #[arg(
+        short,
+        long = "output",
+        value_name = "KIND:TARGET",
+        value_parser = |spec: &str| parsed(OutputSpec::parse(spec)),
+        help = "Where sentences go, as in serve [default: stdout]"
+    )]
+    pub outputs: Vec<OutputSpec>,
+    #[arg(
+        short = 'n',
+        long,
+        help = "Stop after this many epochs [default: never]"
+    )]
+    pub count: Option<u64>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        value_parser = seconds,
+        help = "Stop after this long [default: never]"
+    )]
+    pub duration: Option<Duration>,
+    #[arg(long, default_value_t = 1.0, value_parser = rate, help = "Epochs per second")]
+    pub rate: f64,
+    #[arg(long, help = "Write epochs as fast as possible instead of at --rate")]
+    pub fast: bool,
+    #[arg(long, help = "Seed the random values to repeat a run")]
+    pub seed: Option<u64>,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    #[arg(help = "NMEA log to play")]
+    pub path: String,
+    #[arg(
+        short,
+        long = "output",
+        value_name = "KIND:TARGET",
+        value_parser = |spec: &str| parsed(OutputSpec::parse(spec)),
+        help = "Where sentences go, as in serve [default: stdout]"
+    )]
+    pub outputs: Vec<OutputSpec>,
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        value_parser = speed,
+        help = "Play this many times faster than recorded, e.g. 48 for an 8 hour drive in 10 minutes or 0.5 for half speed"
+    )]
+    pub speed: f64,
+    #[arg(
+        long = "loop",
+        help = "Start over at the end of the log, with the times and dates moved on by its length"
+    )]
+    pub looping: bool,
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    #[arg(help = "NMEA logs to check [default: stdin]")]
+    pub paths: Vec<String>,
+    #[arg(long, help = "Count sentences the parser doesn't know as invalid")]
+    pub strict: bool,
+}
+
+#[derive(Args)]
+pub struct SelftestArgs {
+    #[arg(
+        long,
+        value_parser = |mode: &str| parsed(PtyMode::parse(mode)),
+        help = "PTY topology to test, as in serve [default: linked-pair]"
+    )]
+    pub mode: Option<PtyMode>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        default_value = "3",
+        value_parser = seconds,
+        help = "How long to read"
+    )]
+    pub duration: Duration,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Where to create the symlinks, e.g. where clients will find them [default: a temporary directory]"
+    )]
+    pub dir: Option<String>,
+}
+
+// Writes endless epochs of the generator, or until --count or --duration
+pub fn generate(args: GenerateArgs, shutdown_event: &AtomicBool) -> Result<(), SimError> {
+    let seed = args.seed.unwrap_or_else(rand::random);
+    info!(
+        "Random seed {}, pass --seed {} to repeat this run",
+        seed, seed
+    );
+    let mut generator = NmeaGenerator::with_seed(seed);
+    let mut outputs = open_outputs(&args.outputs)?;
+
+    let interval = Duration::from_secs_f64(1.0 / args.rate);
+    let mut next_epoch = Instant::now();
+    let deadline = args.duration.map(|duration| next_epoch + duration);
+    let epochs = generator
+        .iter()
+        .take(args.count.unwrap_or(u64::MAX) as usize);
+    for sentences in epochs {
+        if !args.fast && !sleep_until(next_epoch, shutdown_event) {
+            break;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            break;
+        }
+        if shutdown_event.load(Ordering::SeqCst) || !write(&mut outputs, &sentences) {
+            break;
+        }
+        next_epoch += interval;
+    }
+    Ok(())
+}
+
+pub fn replay(args: ReplayArgs, shutdown_event: &AtomicBool) -> Result<(), SimError> {
+    let replay = Replay::load(&args.path)?;
+    if replay.epochs.is_empty() {
+        return Err(SimError::Other(format!("No sentences in {}", args.path)));
+    }
+    info!(
+        "Replaying {} epochs from {}",
+        replay.epochs.len(),
+        args.path
+    );
+    let mut outputs = open_outputs(&args.outputs)?;
+
+    // Every loop goes on from the times the one before ended at
+    let length = TimeDelta::from_std(replay.duration())
+        .map_err(|e| SimError::Other(format!("{} is too long to loop: {}", args.path, e)))?;
+    let mut offset = TimeDelta::zero();
+    let mut next_epoch = Instant::now();
+    loop {
+        for (i, epoch) in replay.epochs.iter().enumerate() {
+            let sentences = if offset.is_zero() {
+                Cow::Borrowed(epoch.sentences.as_str())
+            } else {
+                Cow::Owned(replay::rebase(&epoch.sentences, offset))
+            };
+            if !sleep_until(next_epoch, shutdown_event) || !write(&mut outputs, &sentences) {
+                return Ok(());
+            }
+            next_epoch += replay.interval_after(i).div_f64(args.speed);
+        }
+        if !args.looping {
+            return Ok(());
+        }
+        offset += length;
+    }
+}
+
+// Reports every sentence that fails to parse, has values out of range or
+// goes back in time; unknown sentences only count with --strict
+pub fn validate(args: ValidateArgs) -> Result<(), SimError> {
+    let paths = if args.paths.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        args.paths
+    };
+    let (mut total, mut invalid) = (0, 0);
+    for path in &paths {
+        let text = if path == "-" {
+            let mut text = String::new();
+            io::stdin()
+                .read_to_string(&mut text)
+                .map_err(|e| SimError::io("Failed to read stdin", e))?;
+            text
+        } else {
+            fs::read_to_string(path)
+                .map_err(|e| SimError::io(format!("Failed to read {}", path), e))?
+        };
+
+        let mut validator = Validator::new(args.strict);
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            total += 1;
+            if let Err(e) = validator.check(line) {
+                invalid += 1;
+                println!("{}:{}: {}: {}", path, number + 1, e, line);
+            }
+        }
+    }
+
+    println!("{} sentences, {} invalid", total, invalid);
+    if invalid > 0 {
+        return Err(SimError::Validation { invalid, total });
+    }
+    Ok(())
+}
+
+// Serves a port on PTYs of its own and reads it for a while like a client,
+// checking every sentence as validate does. Passes if sentences came and
+// all of them are valid; failing to create or open the PTYs is an error.
+#[cfg(unix)]
+pub fn selftest(args: SelftestArgs, shutdown_event: &AtomicBool) -> Result<(), SimError> {
+    let (dir, temporary) = match args.dir {
+        Some(dir) => (PathBuf::from(dir), false),
+        None => (
+            std::env::temp_dir().join(format!("nmea_simulator_selftest_{}", std::process::id())),
+            true,
+        ),
+    };
+    fs::create_dir_all(&dir)
+        .map_err(|e| SimError::io(format!("Failed to create {}", dir.display()), e))?;
+    let result = run_selftest(
+        &dir,
+        args.mode.unwrap_or_default(),
+        args.duration,
+        shutdown_event,
+    );
+    if temporary {
+        let _ = fs::remove_dir_all(&dir);
+    }
+    match &result {
+        Ok(()) => println!("PASS"),
+        Err(_) => println!("FAIL"),
+    }
+    result
+}
+
+#[cfg(not(unix))]
+pub fn selftest(_args: SelftestArgs, _shutdown_event: &AtomicBool) -> Result<(), SimError> {
+    Err(SimError::Usage("selftest needs a Unix system".to_string()))
+}
+
+#[cfg(unix)]
+fn run_selftest(
+    dir: &Path,
+    mode: PtyMode,
+    duration: Duration,
+    shutdown_event: &AtomicBool,
+) -> Result<(), SimError> {
+    let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+    let gps_input_path = path("gps_input");
+    let gps_output_path = (mode.paths() == 2).then(|| path("gps_output"));
+    let client_path = gps_output_path.clone().unwrap_or(gps_input_path.clone());
+
+    let simulator = Simulator::new(Options {
+        gps_input_path: Some(gps_input_path),
+        gps_output_path,
+        pty_mode: mode,
+        ..Options::default()
+    });
+    let stop = simulator.shutdown_handle();
+    let server = thread::spawn(move || simulator.run());
+    let read = read_as_client(&client_path, duration, shutdown_event, &server);
+    stop.store(true, Ordering::SeqCst);
+    // Why serving failed explains why reading did
+    server
+        .join()
+        .unwrap_or_else(|_| Err(SimError::Other("The simulator panicked".to_string())))?;
+
+    let (total, invalid) = read?;
+    println!(
+        "{} sentences in {:.1} s, {} invalid",
+        total,
+        duration.as_secs_f64(),
+        invalid
+    );
+    if total == 0 {
+        return Err(SimError::Other(format!(
+            "No sentences from {}",
+            client_path
+        )));
+    }
+    if invalid > 0 {
+        return Err(SimError::Validation { invalid, total });
+    }
+    Ok(())
+}
+
+// Opens the PTY like a client once the simulator has linked it and checks
+// the lines it reads; returns how many there were and how many are invalid
+#[cfg(unix)]
+fn read_as_client(
+    path: &str,
+    duration: Duration,
+    shutdown_event: &AtomicBool,
+    server: &JoinHandle<Result<(), SimError>>,
+) -> Result<(usize, usize), SimError> {
+    let startup = Instant::now() + SELFTEST_STARTUP;
+    while fs::symlink_metadata(path).is_err() {
+        if server.is_finished() || Instant::now() >= startup {
+            return Err(SimError::Other(format!("{} was not created", path)));
+        }
+        if !sleep_until(Instant::now() + POLL_INTERVAL, shutdown_event) {
+            return Ok((0, 0));
+        }
+    }
+    let mut client = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+        .open(path)
+        .map_err(|e| SimError::io(format!("Failed to open {} as a client", path), e))?;
+    println!("Opened {} as a client", path);
+
+    let mut validator = Validator::new(false);
+    let (mut total, mut invalid) = (0, 0);
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 1024];
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline && !shutdown_event.load(Ordering::SeqCst) {
+        match client.read(&mut buf) {
+            Ok(n) => pending.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(SimError::io(format!("Failed to read {}", path), e)),
+        }
+        while let Some(end) = pending.iter().position(|&c| c == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            total += 1;
+            if let Err(e) = validator.check(line) {
+                invalid += 1;
+                println!("{}: {}: {}", path, e, line);
+            }
+        }
+    }
+    Ok((total, invalid))
+}
+
+fn open_outputs(specs: &[OutputSpec]) -> Result<MultiSink, SimError> {
+    let mut outputs = MultiSink::new();
+    if specs.is_empty() {
+        outputs.add(Box::new(StdoutSink));
+    }
+    for spec in specs {
+        let sink: Box<dyn OutputSink> = spec
+            .open()
+            .map_err(|e| SimError::Other(format!("Failed to open {:?}: {}", spec, e)))?;
+        outputs.add(sink);
+    }
+    Ok(outputs)
+}
+
+// False once every output has failed
+fn write(outputs: &mut MultiSink, sentences: &str) -> bool {
+    outputs.write_all(sentences.as_bytes());
+    if outputs.is_empty() {
+        warn!("All outputs failed, stopping");
+        return false;
+    }
+    true
+}
+
+// False if shutdown was requested while waiting
+fn sleep_until(deadline: Instant, shutdown_event: &AtomicBool) -> bool {
+    loop {
+        if shutdown_event.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
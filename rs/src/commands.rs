@@ -0,0 +1,319 @@
+// src/commands.rs
+
+use crate::checksum::wrap_sentence;
+use crate::nmea_generator::SentenceRates;
+use crate::state::{PortControl, PortState};
+use crate::{parser, ubx};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+const CLASS_ACK: u8 = 0x05;
+const ACK_NAK: u8 = 0x00;
+const ACK_ACK: u8 = 0x01;
+const CLASS_CFG: u8 = 0x06;
+const CFG_MSG: u8 = 0x01;
+const CFG_RATE: u8 = 0x08;
+// Class of the standard NMEA messages in CFG-MSG
+const CLASS_NMEA: u8 = 0xF0;
+
+// PMTK001 result flags
+const PMTK_INVALID: u8 = 1;
+const PMTK_FAILED: u8 = 2;
+const PMTK_SUCCEEDED: u8 = 3;
+
+// Fastest navigation rate a client may ask for
+const MIN_INTERVAL: Duration = Duration::from_millis(100);
+
+// Longest sentence or frame kept while waiting for the rest of it
+const MAX_PENDING: usize = 4096;
+
+#[derive(Debug)]
+pub enum Command {
+    // Sentence without '$', checksum and line ending, e.g. "PMTK220,1000"
+    Nmea(String),
+    Ubx { class: u8, id: u8, payload: Vec<u8> },
+}
+
+// Splits the bytes a client writes into NMEA sentences and UBX frames.
+// Anything that is neither, or fails its checksum, is skipped.
+#[derive(Default)]
+pub struct CommandParser {
+    buf: Vec<u8>,
+}
+
+impl CommandParser {
+    pub fn new() -> Self {
+        CommandParser { buf: Vec::new() }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Vec<Command> {
+        self.buf.extend_from_slice(data);
+        let mut commands = Vec::new();
+
+        loop {
+            // Resynchronise on the start of a sentence or frame
+            let start = self
+                .buf
+                .iter()
+                .enumerate()
+                .position(|(i, &b)| b == b'$' || self.buf[i..].starts_with(&[0xB5, 0x62]));
+            match start {
+                Some(start) => {
+                    self.buf.drain(..start);
+                }
+                None => {
+                    // Keep a trailing sync char, the rest of the frame may follow
+                    let keep = self.buf.last() == Some(&0xB5);
+                    self.buf.clear();
+                    if keep {
+                        self.buf.push(0xB5);
+                    }
+                    break;
+                }
+            }
+
+            let parsed = if self.buf[0] == b'$' {
+                self.take_sentence()
+            } else {
+                self.take_frame()
+            };
+            match parsed {
+                Some(Some(command)) => commands.push(command),
+                Some(None) => {}
+                None if self.buf.len() > MAX_PENDING => {
+                    self.buf.drain(..1);
+                }
+                None => break,
+            }
+        }
+
+        commands
+    }
+
+    // None while incomplete, Some(None) for a malformed sentence
+    fn take_sentence(&mut self) -> Option<Option<Command>> {
+        let end = self.buf.iter().position(|&b| b == b'\n')?;
+        let line: Vec<u8> = self.buf.drain(..=end).collect();
+        let line = String::from_utf8_lossy(&line[1..]);
+        let line = line.trim_end();
+
+        let sentence = match parser::strip_checksum(line) {
+            Ok(sentence) => sentence,
+            Err(e) => {
+                warn!("Ignoring ${}: {}", line, e);
+                return Some(None);
+            }
+        };
+        Some(Some(Command::Nmea(sentence.to_string())))
+    }
+
+    fn take_frame(&mut self) -> Option<Option<Command>> {
+        if self.buf.len() < 6 {
+            return None;
+        }
+        let length = u16::from_le_bytes([self.buf[4], self.buf[5]]) as usize;
+        if length > MAX_PENDING {
+            self.buf.drain(..1);
+            return Some(None);
+        }
+        let frame_len = 6 + length + 2;
+        if self.buf.len() < frame_len {
+            return None;
+        }
+
+        let (ck_a, ck_b) = ubx::checksum(&self.buf[2..6 + length]);
+        if (ck_a, ck_b) != (self.buf[6 + length], self.buf[7 + length]) {
+            // Not a real frame start, skip the sync char
+            self.buf.drain(..1);
+            return Some(None);
+        }
+
+        let frame: Vec<u8> = self.buf.drain(..frame_len).collect();
+        Some(Some(Command::Ubx {
+            class: frame[2],
+            id: frame[3],
+            payload: frame[6..6 + length].to_vec(),
+        }))
+    }
+}
+
+// Parses what a client sends to a port and applies it to the port's
+// settings. Returns a tap to be fed with the received bytes.
+pub fn receive_handler(control: Arc<PortControl>) -> impl FnMut(&[u8]) + Send {
+    let mut parser = CommandParser::new();
+    move |data| {
+        for command in parser.push(data) {
+            control.update(|state| {
+                let reply = apply(&command, state);
+                state.replies.extend(reply);
+            });
+        }
+    }
+}
+
+// Applies one command and returns the reply a real receiver would send
+pub fn apply(command: &Command, state: &mut PortState) -> Vec<u8> {
+    match command {
+        Command::Nmea(sentence) => apply_sentence(sentence, state).into_bytes(),
+        Command::Ubx { class, id, payload } => apply_ubx(*class, *id, payload, state),
+    }
+}
+
+fn apply_sentence(sentence: &str, state: &mut PortState) -> String {
+    let fields: Vec<&str> = sentence.split(',').collect();
+    match fields[0] {
+        "PMTK220" => {
+            let flag = match fields.get(1).and_then(|ms| ms.parse::<u64>().ok()) {
+                Some(ms) if Duration::from_millis(ms) >= MIN_INTERVAL && ms <= 10_000 => {
+                    state.interval = Duration::from_millis(ms);
+                    info!("Client set fix interval to {} ms", ms);
+                    PMTK_SUCCEEDED
+                }
+                Some(_) => PMTK_FAILED,
+                None => PMTK_INVALID,
+            };
+            pmtk_ack(220, flag)
+        }
+        "PMTK314" => {
+            let flag = match set_pmtk314(&fields[1..], &mut state.sentence_rates) {
+                Ok(()) => {
+                    info!("Client set sentence rates to {:?}", state.sentence_rates);
+                    PMTK_SUCCEEDED
+                }
+                Err(()) => PMTK_INVALID,
+            };
+            pmtk_ack(314, flag)
+        }
+        command if command.starts_with("PMTK") => match command[4..].parse::<u16>() {
+            Ok(number) => pmtk_ack(number, PMTK_INVALID),
+            Err(_) => String::new(),
+        },
+        // PUBX,40,msgId,rddc,rus1,rus2,rusb,rspi,reserved; u-blox does not
+        // acknowledge it. The rate for the first UART applies to our port.
+        "PUBX" if fields.get(1) == Some(&"40") => {
+            let formatter = fields.get(2).copied().unwrap_or_default();
+            let rate = fields.get(4).and_then(|rate| rate.parse::<u32>().ok());
+            match (state.sentence_rates.get_mut(formatter), rate) {
+                (Some(current), Some(rate)) => {
+                    *current = rate;
+                    info!("Client set {} rate to {}", formatter, rate);
+                }
+                _ => warn!("Ignoring unsupported PUBX,40 request: {}", sentence),
+            }
+            String::new()
+        }
+        // Query, e.g. "CCGPQ,GGA": requester and target talker, then 'Q'
+        address if address.len() == 5 && address.ends_with('Q') => {
+            match fields.get(1) {
+                Some(formatter) if !formatter.is_empty() => {
+                    state.queries.push(formatter.to_string());
+                }
+                _ => warn!("Ignoring query without formatter: {}", sentence),
+            }
+            String::new()
+        }
+        _ => String::new(),
+    }
+}
+
+// PMTK314 takes the rates of GLL, RMC, VTG, GGA, GSA, GSV, GRS, GST and
+// further sentences we do not generate; "-1" restores the defaults
+fn set_pmtk314(fields: &[&str], rates: &mut SentenceRates) -> Result<(), ()> {
+    if fields == ["-1"] {
+        *rates = SentenceRates::default();
+        return Ok(());
+    }
+
+    let values = fields
+        .iter()
+        .map(|field| field.parse::<u32>().map_err(|_| ()))
+        .collect::<Result<Vec<u32>, ()>>()?;
+    if values.len() < 6 {
+        return Err(());
+    }
+    *rates = SentenceRates {
+        gll: values[0],
+        rmc: values[1],
+        vtg: values[2],
+        gga: values[3],
+        gsa: values[4],
+        gsv: values[5],
+        gst: values.get(7).copied().unwrap_or(rates.gst),
+        ..*rates
+    };
+    Ok(())
+}
+
+fn pmtk_ack(command: u16, flag: u8) -> String {
+    wrap_sentence(&format!("PMTK001,{},{}", command, flag))
+}
+
+fn apply_ubx(class: u8, id: u8, payload: &[u8], state: &mut PortState) -> Vec<u8> {
+    if class != CLASS_CFG {
+        return Vec::new();
+    }
+
+    let accepted = match (id, payload.len()) {
+        // Poll of the current rate, answered before the acknowledgement
+        (CFG_RATE, 0) => {
+            let mut reply = ubx::frame(CLASS_CFG, CFG_RATE, &cfg_rate_payload(state.interval));
+            reply.extend(ubx_ack(ACK_ACK, class, id));
+            return reply;
+        }
+        (CFG_RATE, 6) => {
+            let meas_rate = u16::from_le_bytes([payload[0], payload[1]]) as u64;
+            let nav_rate = u16::from_le_bytes([payload[2], payload[3]]) as u64;
+            let interval = Duration::from_millis(meas_rate * nav_rate);
+            if (MIN_INTERVAL..=Duration::from_secs(10)).contains(&interval) {
+                state.interval = interval;
+                info!("Client set fix interval to {} ms", interval.as_millis());
+                true
+            } else {
+                false
+            }
+        }
+        // Message rate for the current port, or per port with the first
+        // UART at index 1
+        (CFG_MSG, 3) | (CFG_MSG, 8) if payload[0] == CLASS_NMEA => {
+            let rate = if payload.len() == 3 {
+                payload[2]
+            } else {
+                payload[3]
+            } as u32;
+            let formatter = match payload[1] {
+                0x00 => "GGA",
+                0x01 => "GLL",
+                0x02 => "GSA",
+                0x03 => "GSV",
+                0x04 => "RMC",
+                0x05 => "VTG",
+                0x07 => "GST",
+                _ => "",
+            };
+            match state.sentence_rates.get_mut(formatter) {
+                Some(current) => {
+                    *current = rate;
+                    info!("Client set {} rate to {}", formatter, rate);
+                    true
+                }
+                None => false,
+            }
+        }
+        _ => false,
+    };
+
+    ubx_ack(if accepted { ACK_ACK } else { ACK_NAK }, class, id)
+}
+
+fn cfg_rate_payload(interval: Duration) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(6);
+    payload.extend_from_slice(&(interval.as_millis() as u16).to_le_bytes());
+    payload.extend_from_slice(&1u16.to_le_bytes()); // Navigation rate, cycles
+    payload.extend_from_slice(&1u16.to_le_bytes()); // Aligned to GPS time
+    payload
+}
+
+fn ubx_ack(id: u8, class: u8, message_id: u8) -> Vec<u8> {
+    ubx::frame(CLASS_ACK, id, &[class, message_id])
+}
@@ -0,0 +1,318 @@
+// src/config.rs
+
+use crate::checksum::wrap_sentence;
+use crate::control;
+use crate::error::SimError;
+use crate::nmea_generator::{EncodingMode, SentenceRates};
+use crate::output::SentenceFilter;
+use crate::sentences::check_length;
+use crate::state::{Injection, PortControl, SharedState};
+use crate::template::SentenceTemplate;
+use crate::trajectory::TrajectorySource;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Settings read from the TOML file given with --config, e.g.
+//
+//   rate = 5.0
+//   position = [37.77, -122.41, 10.0]
+//   speed_knots = 12.0
+//
+//   [sentences]
+//   gsv = 5
+//   gll = 0
+//
+//   [trajectory]
+//   source = "route"
+//   path = "drive.csv"
+//
+//   [filters]
+//   udp = ["RMC", "GGA"]
+//   "file:ais.nmea" = ["VDM"]
+//
+//   [[template]]
+//   sentence = "PXYZ,{time},{lat},{lon},{rand:0..100:.1},{seq}"
+//   every = 5
+//
+//   [[inject]]
+//   sentence = "PXYZ,STATUS,1"
+//   every = 5.0
+//
+//   [[inject]]
+//   sentence = "GPTXT,01,01,02,warm start"
+//   at = 30.0
+//   port = 1
+//
+// The file describes the complete setup: whatever it leaves out goes back
+// to its default when the file is reloaded, apart from the trajectory.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // Epochs per second on every port
+    #[serde(default = "default_rate")]
+    pub rate: f64,
+    // Every n-th epoch per sentence, 0 disables it
+    #[serde(default)]
+    pub sentences: SentenceRates,
+    // Pinned values as in the control commands; random when unset
+    pub position: Option<(f64, f64, f64)>,
+    pub speed_knots: Option<f64>,
+    pub course: Option<f64>,
+    pub fix_quality: Option<u8>,
+    // Where the positions come from, as in the control command
+    // "set-trajectory"; unset leaves it to --trajectory and the controller
+    pub trajectory: Option<TrajectorySource>,
+    // Sentences some outputs get, by output kind or name; the others get
+    // everything
+    #[serde(default)]
+    pub filters: BTreeMap<String, Vec<String>>,
+    // Sentences of our own the generator fills each epoch, see
+    // template.rs for the placeholders
+    #[serde(default)]
+    pub template: Vec<TemplateConfig>,
+    // Sentences of our own, checksummed and sent as the control command
+    // "inject-sentence" does
+    #[serde(default)]
+    pub inject: Vec<InjectConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    pub sentence: String,
+    // Every n-th epoch, as in [sentences]
+    #[serde(default = "one")]
+    pub every: u32,
+}
+
+impl TemplateConfig {
+    fn parse(&self) -> Result<SentenceTemplate, Box<dyn Error>> {
+        let mut template = SentenceTemplate::parse(&self.sentence)?;
+        template.every = self.every;
+        Ok(template)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InjectConfig {
+    pub sentence: String,
+    // Seconds after the start of the run, and between repeats
+    pub at: Option<f64>,
+    pub every: Option<f64>,
+    // Every port when unset
+    pub port: Option<usize>,
+}
+
+fn default_rate() -> f64 {
+    1.0
+}
+
+fn one() -> u32 {
+    1
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Self, SimError> {
+        Self::read(path).map_err(|e| SimError::Config {
+            path: path.to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if !(0.1..=10.0).contains(&self.rate) {
+            return Err(format!("Invalid rate {} Hz, expected 0.1 to 10", self.rate).into());
+        }
+        if let Some((latitude, longitude, _)) = self.position {
+            if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                return Err(format!("Invalid position {},{}", latitude, longitude).into());
+            }
+        }
+        if self
+            .speed_knots
+            .is_some_and(|knots| !knots.is_finite() || knots < 0.0)
+        {
+            return Err(format!("Invalid speed {:?}", self.speed_knots).into());
+        }
+        if self.fix_quality.is_some_and(|q| q > 8) {
+            return Err(format!("Invalid fix quality {:?}", self.fix_quality).into());
+        }
+        for (output, sentences) in &self.filters {
+            // A formatter or a talker and formatter
+            let invalid = sentences.iter().find(|sentence| {
+                !matches!(sentence.len(), 3 | 5)
+                    || !sentence
+                        .bytes()
+                        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+            });
+            if let Some(sentence) = invalid {
+                return Err(
+                    format!("Invalid sentence '{}' in filter of {}", sentence, output).into(),
+                );
+            }
+        }
+        if let Some(trajectory) = &self.trajectory {
+            trajectory.waypoints()?;
+        }
+        for template in &self.template {
+            template.parse()?;
+        }
+        for inject in &self.inject {
+            if inject.at.is_none() && inject.every.is_none() {
+                return Err(format!("Injection of '{}' needs at or every", inject.sentence).into());
+            }
+            control::injection_interval(inject.at, inject.every)?;
+        }
+        Ok(())
+    }
+
+    // Takes effect at the next epoch of each port; outputs stay open.
+    // Injections replace those of the previous load, apart from single
+    // ones whose time has passed.
+    pub fn apply(&self, state: &SharedState, ports: &[Arc<PortControl>]) {
+        let now = Instant::now();
+        let mut injections = vec![Vec::new(); ports.len()];
+        {
+            let mut state = state.lock().unwrap();
+            let start = *state.started.get_or_insert(now);
+            for inject in &self.inject {
+                let sentence = match control::injected_sentence(&inject.sentence, state.encoding) {
+                    Ok(sentence) => sentence,
+                    Err(e) => {
+                        warn!("Not injecting '{}': {}", inject.sentence, e);
+                        continue;
+                    }
+                };
+                let every = inject.every.map(Duration::from_secs_f64);
+                let Some(injection) = Injection::schedule(sentence, inject.at, every, start, now)
+                else {
+                    continue;
+                };
+                match inject.port {
+                    Some(port) if port >= ports.len() => {
+                        warn!("Not injecting '{}': no port {}", inject.sentence, port)
+                    }
+                    Some(port) => injections[port].push(injection),
+                    None => injections
+                        .iter_mut()
+                        .for_each(|i| i.push(injection.clone())),
+                }
+            }
+            state.position = self.position;
+            state.speed_knots = self.speed_knots;
+            state.course = self.course.map(|course| course.rem_euclid(360.0));
+            state.fix_quality = self.fix_quality;
+            // Only another source restarts the trajectory, so a reload
+            // leaves a route where it got to
+            match &self.trajectory {
+                Some(trajectory) if state.trajectory.source != *trajectory => {
+                    match trajectory.waypoints() {
+                        Ok(waypoints) => {
+                            state.switch_trajectory(trajectory.clone(), waypoints, false)
+                        }
+                        Err(e) => warn!("Not switching to trajectory {}: {}", trajectory, e),
+                    }
+                }
+                _ => {}
+            }
+            state.output_filters = Arc::new(
+                self.filters
+                    .iter()
+                    .map(|(output, sentences)| SentenceFilter {
+                        output: output.clone(),
+                        sentences: sentences.clone(),
+                    })
+                    .collect(),
+            );
+            // Checked by validate. One too long even without its
+            // placeholders is too long every epoch, which strict encoding
+            // refuses as it does injections.
+            let mut templates = Vec::new();
+            for template in self.template.iter().filter_map(|t| t.parse().ok()) {
+                if let Err(e) = check_length(&wrap_sentence(&template.literal())) {
+                    if state.encoding == EncodingMode::Strict {
+                        warn!("Not sending template '{}': {}", template.text, e);
+                        continue;
+                    }
+                    warn!("Template '{}' is too long: {}", template.text, e);
+                }
+                templates.push(template);
+            }
+            state.templates = Arc::new(templates);
+        }
+        let interval = Duration::from_secs_f64(1.0 / self.rate);
+        for (port, injections) in ports.iter().zip(injections) {
+            port.update(|port| {
+                port.interval = interval;
+                port.sentence_rates = self.sentences;
+                port.injections.retain(|injection| !injection.from_config);
+                port.injections
+                    .extend(injections.into_iter().map(|injection| Injection {
+                        from_config: true,
+                        ..injection
+                    }));
+            });
+        }
+    }
+}
+
+// Reloads the config file when it changes on disk or, on Unix, when the
+// process gets SIGHUP. A file that fails to load leaves the settings as
+// they were.
+pub fn spawn_config_watcher(
+    path: &str,
+    state: SharedState,
+    ports: Vec<Arc<PortControl>>,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let reload_requested = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, reload_requested.clone())?;
+
+    let path = path.to_string();
+    let mut modified = modified_time(&path);
+    Ok(thread::spawn(move || {
+        while !shutdown_event.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+
+            let current = modified_time(&path);
+            let changed = current.is_some() && current != modified;
+            let signalled = reload_requested.swap(false, Ordering::SeqCst);
+            if !changed && !signalled {
+                continue;
+            }
+            modified = current;
+
+            match Config::load(&path) {
+                Ok(config) => {
+                    config.apply(&state, &ports);
+                    info!("Reloaded config from {}", path);
+                }
+                Err(e) => warn!("{}, keeping previous settings", e),
+            }
+        }
+    }))
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
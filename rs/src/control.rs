@@ -0,0 +1,667 @@
+// src/control.rs
+
+use crate::checksum::wrap_sentence;
+use crate::http;
+use crate::nmea_generator::{Constellation, Crab, EncodingMode, Environment};
+use crate::scenario::ScenarioRecorder;
+use crate::sentences::check_length;
+use crate::state::{Injection, PortControl, RtkOscillation, SharedState};
+use crate::trajectory::TrajectorySource;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::error::Error;
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+// Commands an external controller can send, as JSON objects tagged by
+// "command", e.g. {"command": "set-speed", "knots": 12.5}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum ControlCommand {
+    SetPosition {
+        latitude: f64,
+        longitude: f64,
+        #[serde(default)]
+        altitude: f64,
+    },
+    SetSpeed {
+        knots: f64,
+        course: Option<f64>,
+    },
+    // Heading relative to the course, e.g. {"model": "offset", "degrees":
+    // -8.0}; HDT and HDG report the heading, RMC and VTG the course
+    SetCrab {
+        crab: Crab,
+    },
+    // Switches where the positions come from, e.g. {"trajectory":
+    // {"source": "route", "path": "drive.csv"}}, gliding over from the last
+    // position unless jump is set
+    SetTrajectory {
+        trajectory: TrajectorySource,
+        #[serde(default)]
+        jump: bool,
+    },
+    // Null hands the fix quality back to the generator
+    SetFixQuality {
+        fix_quality: Option<u8>,
+    },
+    // RTK fixed for `fixed` seconds, then float for `float` seconds, and
+    // over again, in place of the fix quality; both null stops it
+    SetRtkOscillation {
+        fixed: Option<f64>,
+        float: Option<f64>,
+    },
+    // Complete truth from an external vehicle simulator; values left out
+    // keep their current setting
+    SetTruth {
+        latitude: f64,
+        longitude: f64,
+        #[serde(default)]
+        altitude: f64,
+        #[serde(default)]
+        speed_knots: f64,
+        #[serde(default)]
+        course: f64,
+        fix_quality: Option<u8>,
+        hdop: Option<f64>,
+        satellites: Option<usize>,
+    },
+    // Satellites in view, at most 12; null goes back to random
+    SetSatellites {
+        count: Option<usize>,
+    },
+    // Null goes back to random
+    SetHdop {
+        hdop: Option<f64>,
+    },
+    // Constellations satellites are drawn from, e.g. ["GPS", "NAVIC"];
+    // null goes back to the default mix, which leaves out NavIC
+    SetConstellations {
+        constellations: Option<Vec<Constellation>>,
+    },
+    // Receives SBAS corrections or not, e.g. to script losing WAAS in a
+    // canyon
+    SetSbas {
+        enabled: bool,
+    },
+    // Surroundings of the antenna: "open-sky", "suburban", "urban" or
+    // "indoor"; the satellites' signal strengths follow at the next epoch
+    SetEnvironment {
+        environment: Environment,
+    },
+    // Goes back to random values for everything pinned
+    Release,
+    // Epoch rate of one port or of all of them
+    SetRate {
+        hz: f64,
+        port: Option<usize>,
+    },
+    // Turns a sentence, e.g. "GGA", on or off on one port or on all of them
+    SetSentence {
+        sentence: String,
+        enabled: bool,
+        port: Option<usize>,
+    },
+    Pause,
+    Resume,
+    // Reports no fix, with status V and empty coordinates, until regained
+    LoseFix,
+    RegainFix,
+    // Marks the epochs as passing Galileo OSNMA authentication or not,
+    // reported in a TXT sentence and the truth; null stops reporting it
+    SetAuthentication {
+        authenticated: Option<bool>,
+    },
+    // Sends the next epochs right away, also while paused
+    Advance {
+        #[serde(default = "one")]
+        epochs: u32,
+    },
+    // The next sentences go out with a wrong checksum
+    CorruptSentences {
+        count: u32,
+        port: Option<usize>,
+    },
+    // The next sentences that templates number skip this many numbers
+    SkipSequence {
+        #[serde(default = "one")]
+        count: u32,
+        port: Option<usize>,
+    },
+    // Sent on one port or on all of them: right away, or "time" seconds
+    // after the start of the run, and again "every" so many seconds when
+    // given. Not "at", which scenario events have for themselves.
+    // The checksum is added when the sentence has none. Sentences longer
+    // than NMEA 0183 allows are refused with strict encoding, and sent with
+    // a warning otherwise.
+    InjectSentence {
+        sentence: String,
+        port: Option<usize>,
+        #[serde(default)]
+        time: Option<f64>,
+        #[serde(default)]
+        every: Option<f64>,
+    },
+    // Drops the injected sentences still waiting for their time
+    ClearInjections {
+        port: Option<usize>,
+    },
+    GetState,
+}
+
+// Applies control commands to the running simulation
+#[derive(Clone)]
+pub struct Controller {
+    state: SharedState,
+    ports: Vec<Arc<PortControl>>,
+    // Where the commands applied go while the run is recorded, shared by
+    // every clone
+    recorder: Arc<Mutex<Option<ScenarioRecorder>>>,
+}
+
+impl Controller {
+    pub fn new(state: SharedState, ports: Vec<Arc<PortControl>>) -> Self {
+        Controller {
+            state,
+            ports,
+            recorder: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Records every command applied from now on
+    pub fn record_to(&self, recorder: ScenarioRecorder) {
+        *self.recorder.lock().unwrap() = Some(recorder);
+    }
+
+    // Parses one JSON command and applies it; the reply is always JSON
+    pub fn handle_line(&self, line: &str) -> serde_json::Value {
+        let result = serde_json::from_str::<ControlCommand>(line)
+            .map_err(|e| e.into())
+            .and_then(|command| self.apply(command));
+        match result {
+            Ok(reply) => reply,
+            Err(e) => json!({ "ok": false, "error": e.to_string() }),
+        }
+    }
+
+    pub fn apply(&self, command: ControlCommand) -> Result<serde_json::Value, Box<dyn Error>> {
+        // Only what took effect is recorded
+        let recorder = self.recorder.lock().unwrap().clone();
+        match recorder {
+            Some(recorder) => {
+                let reply = self.execute(command.clone())?;
+                recorder.record_command(&command);
+                Ok(reply)
+            }
+            None => self.execute(command),
+        }
+    }
+
+    fn execute(&self, command: ControlCommand) -> Result<serde_json::Value, Box<dyn Error>> {
+        match command {
+            ControlCommand::SetPosition {
+                latitude,
+                longitude,
+                altitude,
+            } => {
+                if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                    return Err(format!("Invalid position {},{}", latitude, longitude).into());
+                }
+                self.state.lock().unwrap().position = Some((latitude, longitude, altitude));
+            }
+            ControlCommand::SetSpeed { knots, course } => {
+                if !knots.is_finite() || knots < 0.0 {
+                    return Err(format!("Invalid speed {}", knots).into());
+                }
+                let mut state = self.state.lock().unwrap();
+                state.speed_knots = Some(knots);
+                if let Some(course) = course {
+                    state.course = Some(course.rem_euclid(360.0));
+                }
+            }
+            ControlCommand::SetFixQuality { fix_quality } => {
+                if fix_quality.is_some_and(|q| q > 8) {
+                    return Err(format!("Invalid fix quality {:?}", fix_quality).into());
+                }
+                self.state.lock().unwrap().fix_quality = fix_quality;
+            }
+            ControlCommand::SetRtkOscillation { fixed, float } => {
+                let dwell = |secs: f64| match Duration::try_from_secs_f64(secs) {
+                    Ok(dwell) if !dwell.is_zero() => Ok(dwell),
+                    _ => Err(format!("Invalid dwell time {} s", secs)),
+                };
+                let oscillation = match (fixed, float) {
+                    (Some(fixed), Some(float)) => Some(RtkOscillation {
+                        fixed: dwell(fixed)?,
+                        float: dwell(float)?,
+                        since: Instant::now(),
+                    }),
+                    (None, None) => None,
+                    _ => return Err("Expected both fixed and float dwell times, or neither".into()),
+                };
+                self.state.lock().unwrap().rtk_oscillation = oscillation;
+            }
+            ControlCommand::SetTruth {
+                latitude,
+                longitude,
+                altitude,
+                speed_knots,
+                course,
+                fix_quality,
+                hdop,
+                satellites,
+            } => {
+                if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+                    return Err(format!("Invalid position {},{}", latitude, longitude).into());
+                }
+                if !speed_knots.is_finite() || speed_knots < 0.0 || !course.is_finite() {
+                    return Err(
+                        format!("Invalid speed {} or course {}", speed_knots, course).into(),
+                    );
+                }
+                if fix_quality.is_some_and(|q| q > 8) {
+                    return Err(format!("Invalid fix quality {:?}", fix_quality).into());
+                }
+                if hdop.is_some_and(|hdop| !(hdop > 0.0 && hdop < 100.0)) {
+                    return Err(format!("Invalid HDOP {:?}", hdop).into());
+                }
+                if satellites.is_some_and(|count| count > 12) {
+                    return Err(
+                        format!("Invalid satellite count {:?}, at most 12", satellites).into(),
+                    );
+                }
+                let mut state = self.state.lock().unwrap();
+                state.position = Some((latitude, longitude, altitude));
+                state.speed_knots = Some(speed_knots);
+                state.course = Some(course.rem_euclid(360.0));
+                if fix_quality.is_some() {
+                    state.fix_quality = fix_quality;
+                }
+                if hdop.is_some() {
+                    state.hdop = hdop;
+                }
+                if satellites.is_some() {
+                    state.satellites = satellites;
+                }
+                state.truth_received = Some(Instant::now());
+            }
+            ControlCommand::SetSatellites { count } => {
+                if count.is_some_and(|count| count > 12) {
+                    return Err(format!("Invalid satellite count {:?}, at most 12", count).into());
+                }
+                self.state.lock().unwrap().satellites = count;
+            }
+            ControlCommand::SetHdop { hdop } => {
+                if hdop.is_some_and(|hdop| !(hdop > 0.0 && hdop < 100.0)) {
+                    return Err(format!("Invalid HDOP {:?}", hdop).into());
+                }
+                self.state.lock().unwrap().hdop = hdop;
+            }
+            ControlCommand::SetConstellations { constellations } => {
+                if constellations.as_ref().is_some_and(|c| c.is_empty()) {
+                    return Err("At least one constellation is needed".into());
+                }
+                self.state.lock().unwrap().constellations = constellations;
+            }
+            ControlCommand::SetCrab { crab } => self.state.lock().unwrap().crab = crab,
+            ControlCommand::SetTrajectory { trajectory, jump } => {
+                let waypoints = trajectory.waypoints()?;
+                self.state
+                    .lock()
+                    .unwrap()
+                    .switch_trajectory(trajectory, waypoints, jump);
+            }
+            ControlCommand::SetSbas { enabled } => self.state.lock().unwrap().sbas = enabled,
+            ControlCommand::SetEnvironment { environment } => {
+                self.state.lock().unwrap().environment = environment;
+            }
+            ControlCommand::Release => {
+                let mut state = self.state.lock().unwrap();
+                state.position = None;
+                state.speed_knots = None;
+                state.course = None;
+                state.fix_quality = None;
+                state.rtk_oscillation = None;
+                state.satellites = None;
+                state.hdop = None;
+                state.constellations = None;
+            }
+            ControlCommand::SetRate { hz, port } => {
+                if !(0.1..=10.0).contains(&hz) {
+                    return Err(format!("Invalid rate {} Hz, expected 0.1 to 10", hz).into());
+                }
+                let interval = Duration::from_secs_f64(1.0 / hz);
+                for port in self.ports(port)? {
+                    port.update(|state| state.interval = interval);
+                }
+            }
+            ControlCommand::SetSentence {
+                sentence,
+                enabled,
+                port,
+            } => {
+                let formatter = sentence.to_ascii_uppercase();
+                for port in self.ports(port)? {
+                    let mut state = port.lock();
+                    let rate = state
+                        .sentence_rates
+                        .get_mut(&formatter)
+                        .ok_or_else(|| format!("Unsupported sentence {}", sentence))?;
+                    *rate = enabled as u32;
+                }
+            }
+            ControlCommand::Pause => self.state.lock().unwrap().paused = true,
+            ControlCommand::Resume => self.state.lock().unwrap().paused = false,
+            ControlCommand::LoseFix => self.state.lock().unwrap().no_fix = true,
+            ControlCommand::RegainFix => self.state.lock().unwrap().no_fix = false,
+            ControlCommand::SetAuthentication { authenticated } => {
+                self.state.lock().unwrap().authenticated = authenticated;
+            }
+            ControlCommand::Advance { epochs } => {
+                for port in &self.ports {
+                    port.update(|state| state.steps += epochs);
+                }
+            }
+            ControlCommand::CorruptSentences { count, port } => {
+                for port in self.ports(port)? {
+                    port.update(|state| state.corrupt += count);
+                }
+            }
+            ControlCommand::SkipSequence { count, port } => {
+                for port in self.ports(port)? {
+                    port.update(|state| state.sequence_gap += count as u64);
+                }
+            }
+            ControlCommand::InjectSentence {
+                sentence,
+                port,
+                time,
+                every,
+            } => {
+                let every = injection_interval(time, every)?;
+                let (sentence, start) = {
+                    let mut state = self.state.lock().unwrap();
+                    let sentence = injected_sentence(&sentence, state.encoding)?;
+                    (sentence, *state.started.get_or_insert_with(Instant::now))
+                };
+                let ports = self.ports(port)?;
+                if time.is_none() && every.is_none() {
+                    for port in ports {
+                        port.update(|state| state.replies.extend(sentence.as_bytes()));
+                    }
+                } else {
+                    let injection =
+                        Injection::schedule(sentence, time, every, start, Instant::now())
+                            .ok_or_else(|| {
+                                format!("Injection time {} s has passed", time.unwrap_or_default())
+                            })?;
+                    for port in ports {
+                        port.update(|state| state.injections.push(injection.clone()));
+                    }
+                }
+            }
+            ControlCommand::ClearInjections { port } => {
+                for port in self.ports(port)? {
+                    port.update(|state| state.injections.clear());
+                }
+            }
+            ControlCommand::GetState => {
+                let state = self.state.lock().unwrap();
+                return Ok(json!({
+                    "ok": true,
+                    "paused": state.paused,
+                    "no_fix": state.no_fix,
+                    "authenticated": state.authenticated,
+                    "fix_quality": state.fix_quality,
+                    "rtk_oscillation": state.rtk_oscillation.map(|oscillation| {
+                        [oscillation.fixed.as_secs_f64(), oscillation.float.as_secs_f64()]
+                    }),
+                    "position": state.position,
+                    "speed_knots": state.speed_knots,
+                    "course": state.course,
+                    "crab": state.crab,
+                    "trajectory": state.trajectory.source,
+                    "satellites": state.satellites,
+                    "hdop": state.hdop,
+                    "constellations": state.constellations,
+                    "sbas": state.sbas,
+                    "environment": state.environment,
+                    "truth": state.truth,
+                }));
+            }
+        }
+        Ok(json!({ "ok": true }))
+    }
+
+    // The given port, or all of them
+    fn ports(&self, port: Option<usize>) -> Result<&[Arc<PortControl>], Box<dyn Error>> {
+        match port {
+            Some(port) if port < self.ports.len() => Ok(&self.ports[port..=port]),
+            Some(port) => Err(format!("No port {}", port).into()),
+            None => Ok(&self.ports),
+        }
+    }
+}
+
+fn one() -> u32 {
+    1
+}
+
+// A year, in seconds
+pub const MAX_INJECTION_TIME: f64 = 365.0 * 24.0 * 3600.0;
+
+// Checks the time and period of a sentence injected later, both within a
+// year so the time it is due stays one the clock can have
+pub fn injection_interval(
+    time: Option<f64>,
+    every: Option<f64>,
+) -> Result<Option<Duration>, Box<dyn Error>> {
+    if let Some(time) = time.filter(|time| !(0.0..=MAX_INJECTION_TIME).contains(time)) {
+        return Err(format!(
+            "Invalid injection time {} s, expected 0 to {}",
+            time, MAX_INJECTION_TIME
+        )
+        .into());
+    }
+    match every {
+        Some(every) if !(0.1..=MAX_INJECTION_TIME).contains(&every) => Err(format!(
+            "Invalid injection interval {} s, expected 0.1 to {}",
+            every, MAX_INJECTION_TIME
+        )
+        .into()),
+        every => Ok(every.map(Duration::from_secs_f64)),
+    }
+}
+
+// The sentence as it goes out, or why it does not under the encoding
+pub fn injected_sentence(sentence: &str, encoding: EncodingMode) -> Result<String, String> {
+    let sentence = normalize_sentence(sentence);
+    if let Err(e) = check_length(&sentence) {
+        if encoding == EncodingMode::Strict {
+            return Err(e);
+        }
+        tracing::warn!("Injecting anyway: {}", e);
+    }
+    Ok(sentence)
+}
+
+// Accepts "GPTXT,...", "$GPTXT,..." or a complete sentence with checksum
+fn normalize_sentence(sentence: &str) -> String {
+    let sentence = sentence.trim_end();
+    let body = sentence.strip_prefix('$').unwrap_or(sentence);
+    if body.contains('*') {
+        format!("${}\r\n", body)
+    } else {
+        wrap_sentence(body)
+    }
+}
+
+// REST flavour of the control commands for harnesses that would rather
+// speak HTTP. Bodies are the JSON of the matching command, without the
+// "command" field.
+pub fn spawn_api_server(
+    addr: &str,
+    controller: Controller,
+    runtime: &Handle,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    http::spawn_server(addr, runtime, shutdown_event, move |request, mut stream| {
+        let command = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/state") => Some("get-state"),
+            ("PUT", "/position") => Some("set-position"),
+            ("PUT", "/truth") => Some("set-truth"),
+            ("PUT", "/speed") => Some("set-speed"),
+            ("PUT", "/crab") => Some("set-crab"),
+            ("PUT", "/trajectory") => Some("set-trajectory"),
+            ("PUT", "/fix-quality") => Some("set-fix-quality"),
+            ("PUT", "/rtk-oscillation") => Some("set-rtk-oscillation"),
+            ("PUT", "/satellites") => Some("set-satellites"),
+            ("PUT", "/hdop") => Some("set-hdop"),
+            ("PUT", "/constellations") => Some("set-constellations"),
+            ("PUT", "/sbas") => Some("set-sbas"),
+            ("PUT", "/environment") => Some("set-environment"),
+            ("PUT", "/authentication") => Some("set-authentication"),
+            ("PUT", "/rate") => Some("set-rate"),
+            ("PUT", "/sentence") => Some("set-sentence"),
+            ("POST", "/pause") => Some("pause"),
+            ("POST", "/resume") => Some("resume"),
+            ("POST", "/lose-fix") => Some("lose-fix"),
+            ("POST", "/regain-fix") => Some("regain-fix"),
+            ("POST", "/release") => Some("release"),
+            ("POST", "/scenario/advance") => Some("advance"),
+            ("POST", "/inject") => Some("inject-sentence"),
+            ("DELETE", "/inject") => Some("clear-injections"),
+            ("POST", "/corrupt") => Some("corrupt-sentences"),
+            ("POST", "/sequence-gap") => Some("skip-sequence"),
+            _ => None,
+        };
+        let Some(command) = command else {
+            return http::write_json(
+                &mut stream,
+                "404 Not Found",
+                &json!({ "error": "not found" }),
+            );
+        };
+
+        let result = command_from_body(command, &request.body).and_then(|c| controller.apply(c));
+        match result {
+            Ok(reply) => http::write_json(&mut stream, "200 OK", &reply),
+            Err(e) => http::write_json(
+                &mut stream,
+                "400 Bad Request",
+                &json!({ "ok": false, "error": e.to_string() }),
+            ),
+        }
+    })
+}
+
+// Parses a JSON object holding the arguments of the given command
+pub fn command_from_body(command: &str, body: &[u8]) -> Result<ControlCommand, Box<dyn Error>> {
+    let mut value = if body.iter().all(u8::is_ascii_whitespace) {
+        json!({})
+    } else {
+        serde_json::from_slice(body)?
+    };
+    value
+        .as_object_mut()
+        .ok_or("Request body must be a JSON object")?
+        .insert("command".to_string(), command.into());
+    Ok(serde_json::from_value(value)?)
+}
+
+#[cfg(unix)]
+pub use self::socket::spawn_control_socket;
+
+#[cfg(unix)]
+mod socket {
+    use super::Controller;
+    use crate::runtime::shutdown_requested;
+    use std::error::Error;
+    use std::fs;
+    use std::os::unix::fs::FileTypeExt;
+    use std::sync::{atomic::AtomicBool, Arc};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio::runtime::Handle;
+    use tokio::task::JoinHandle;
+    use tracing::{error, info, warn};
+
+    const ACCEPT_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+    // Serves JSON-line commands on a Unix socket, one task per client.
+    // The socket file is removed again on shutdown.
+    pub fn spawn_control_socket(
+        path: &str,
+        controller: Controller,
+        runtime: &Handle,
+        shutdown_event: Arc<AtomicBool>,
+    ) -> Result<JoinHandle<()>, Box<dyn Error>> {
+        // A socket left behind by an earlier run would make bind fail
+        if let Ok(meta) = fs::symlink_metadata(path) {
+            if !meta.file_type().is_socket() {
+                return Err(format!("{} exists and is not a socket", path).into());
+            }
+            fs::remove_file(path)?;
+        }
+
+        let listener = {
+            let _runtime = runtime.enter();
+            UnixListener::bind(path)
+                .inspect_err(|e| error!("Failed to listen on {}: {}", path, e))?
+        };
+        info!("Control socket listening on {}", path);
+
+        let path = path.to_string();
+        Ok(runtime.spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    _ = shutdown_requested(&shutdown_event) => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            error!("Error accepting control connection on {}: {}", path, e);
+                            tokio::time::sleep(ACCEPT_RETRY_INTERVAL).await;
+                            continue;
+                        }
+                    },
+                };
+                let controller = controller.clone();
+                let shutdown_event = shutdown_event.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_client(stream, &controller, &shutdown_event).await {
+                        warn!("Control client failed: {}", e);
+                    }
+                });
+            }
+            let _ = fs::remove_file(&path);
+            info!("Control socket {} exiting.", path);
+        }))
+    }
+
+    async fn serve_client(
+        stream: UnixStream,
+        controller: &Controller,
+        shutdown_event: &AtomicBool,
+    ) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        loop {
+            // Partial lines stay buffered until the rest arrives
+            let line = tokio::select! {
+                _ = shutdown_requested(shutdown_event) => break,
+                line = lines.next_line() => line?,
+            };
+            let Some(line) = line else {
+                break;
+            };
+            if !line.trim().is_empty() {
+                let reply = controller.handle_line(line.trim());
+                writer.write_all(format!("{}\n", reply).as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+// src/error.rs
+
+use std::error::Error;
+use std::io;
+use thiserror::Error;
+
+// Why a run could not start or finish, by class, so library users can
+// match on it and the binary can pick an exit code. Failures of single
+// outputs and services once running are only reported, not returned.
+#[derive(Debug, Error)]
+pub enum SimError {
+    // Bad command line arguments or builder settings
+    #[error("{0}")]
+    Usage(String),
+    #[cfg(unix)]
+    #[error("{context}: {source}")]
+    Pty {
+        context: String,
+        #[source]
+        source: nix::Error,
+    },
+    #[error("Failed to link {link} to {target}: {source}")]
+    Symlink {
+        link: String,
+        target: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{context}: {source}")]
+    Io {
+        context: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to load config {path}: {message}")]
+    Config { path: String, message: String },
+    #[error("Failed to load scenario {path}: {message}")]
+    Scenario { path: String, message: String },
+    // Sentences that failed validation
+    #[error("{invalid} of {total} sentences are invalid")]
+    Validation { invalid: usize, total: usize },
+    // A service that failed to start, e.g. a server whose address is taken
+    #[error("{0}")]
+    Other(String),
+    // Sentences of an epoch that disagree with the fix they were encoded
+    // from, which stops a strict run
+    #[error("Epoch {epoch}: {message}")]
+    Inconsistent { epoch: u64, message: String },
+}
+
+impl SimError {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        SimError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    // As in sysexits.h
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            SimError::Usage(_) => 64,
+            SimError::Scenario { .. } | SimError::Validation { .. } => 65,
+            SimError::Other(_) | SimError::Inconsistent { .. } => 70,
+            #[cfg(unix)]
+            SimError::Pty { .. } => 71,
+            SimError::Symlink { .. } => 73,
+            SimError::Io { .. } => 74,
+            SimError::Config { .. } => 78,
+        }
+    }
+}
+
+// The services still report their own errors as strings
+impl From<Box<dyn Error>> for SimError {
+    fn from(e: Box<dyn Error>) -> Self {
+        SimError::Other(e.to_string())
+    }
+}
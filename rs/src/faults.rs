@@ -0,0 +1,412 @@
+// src/faults.rs
+
+use crate::checksum::checksum;
+use crate::nmea_generator::Fix;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::error::Error;
+use std::f64::consts::TAU;
+use std::time::{Duration, Instant};
+
+// Damage done to the stream on purpose, from --fault kind=rate[:length], so
+// clients can be tested against what real receivers and links get wrong
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    // Fraction of sentences whose checksum is wrong, in lowercase hex or
+    // missing
+    Checksum(f64),
+    // Fraction of sentences cut off mid-field without their line ending, as
+    // after a UART overrun, so the next one starts on the same line
+    Truncate(f64),
+    // Fraction of sentences that lose their line ending, gluing the next
+    // one on
+    Merge(f64),
+    // Fraction of sentences followed by a burst of up to max_length bytes
+    // that are not ASCII, like a mismatched baud rate or a UBX frame
+    // leaking into the stream produces
+    Garbage { rate: f64, max_length: usize },
+    // Fraction of sentences left out
+    Drop(f64),
+    // Fraction of epochs left out entirely
+    DropEpoch(f64),
+    // Fraction of sentences followed by a verbatim copy of a sentence of
+    // the epoch before, a stale repeat that trips up "latest fix" logic
+    Repeat(f64),
+    // Epochs go out late by up to this fraction of the interval
+    Jitter(f64),
+    // Fraction of epochs starting a stall of this long, after which the
+    // epochs held back go out in one burst, like buffered Bluetooth
+    // receivers do
+    Stall { rate: f64, duration: Duration },
+    // Fraction of epochs starting a freeze of this long, during which the
+    // receiver keeps reporting their fix, time and all, while the truth
+    // moves on
+    Freeze { rate: f64, duration: Duration },
+    // Fraction of epochs whose reported position is off by up to this many
+    // metres in any direction, with the DOP of the true fix, for testing
+    // outlier rejection
+    Outlier { rate: f64, max_distance_m: f64 },
+    // Framing seen in the wild: fractions of sentences ending in a bare LF
+    // or without their leading '$', and of epochs written in pieces cut
+    // anywhere instead of at sentence ends
+    LineFeed(f64),
+    NoDollar(f64),
+    Split(f64),
+    // Fraction of epochs in which the sentences that templates number skip
+    // up to max_length numbers, as if some were lost on the way
+    SequenceGap { rate: f64, max_length: u64 },
+}
+
+// Between the pieces of a split epoch, so readers get them one at a time
+pub const PIECE_GAP: Duration = Duration::from_millis(5);
+
+// Longest stall or freeze in seconds
+const MAX_FAULT_DURATION: f64 = 3600.0;
+
+impl Fault {
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let (kind, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid fault '{}', expected kind=rate", spec))?;
+        let (rate, length) = match value.split_once(':') {
+            Some((rate, length)) => (rate, Some(length)),
+            None => (value, None),
+        };
+        let rate = rate
+            .parse::<f64>()
+            .ok()
+            .filter(|rate| (0.0..=1.0).contains(rate))
+            .ok_or_else(|| format!("Invalid fault rate '{}', expected 0 to 1", rate))?;
+        let duration = |default: f64| -> Result<Duration, String> {
+            length
+                .map_or(Some(default), |secs| {
+                    secs.parse::<f64>()
+                        .ok()
+                        .filter(|&secs| secs > 0.0 && secs <= MAX_FAULT_DURATION)
+                })
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .ok_or_else(|| format!("Invalid {} duration in '{}'", kind, spec))
+        };
+        match kind {
+            "garbage" => {
+                let max_length = length
+                    .map_or(Some(16), |length| {
+                        length.parse::<usize>().ok().filter(|&length| length > 0)
+                    })
+                    .ok_or_else(|| format!("Invalid garbage length in '{}'", spec))?;
+                Ok(Fault::Garbage { rate, max_length })
+            }
+            "seq-gap" => {
+                let max_length = length
+                    .map_or(Some(1), |length| {
+                        length.parse::<u64>().ok().filter(|&length| length > 0)
+                    })
+                    .ok_or_else(|| format!("Invalid seq-gap length in '{}'", spec))?;
+                Ok(Fault::SequenceGap { rate, max_length })
+            }
+            "stall" => Ok(Fault::Stall {
+                rate,
+                duration: duration(5.0)?,
+            }),
+            "freeze" => Ok(Fault::Freeze {
+                rate,
+                duration: duration(10.0)?,
+            }),
+            "outlier" => {
+                let max_distance_m = length
+                    .map_or(Some(1000.0), |metres| {
+                        metres
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|&metres| metres > 0.0 && metres.is_finite())
+                    })
+                    .ok_or_else(|| format!("Invalid outlier distance in '{}'", spec))?;
+                Ok(Fault::Outlier {
+                    rate,
+                    max_distance_m,
+                })
+            }
+            _ if length.is_some() => Err(format!(
+                "Invalid fault '{}', only garbage, stall, freeze, outlier and seq-gap take a length",
+                spec
+            )
+            .into()),
+            "checksum" => Ok(Fault::Checksum(rate)),
+            "truncate" => Ok(Fault::Truncate(rate)),
+            "merge" => Ok(Fault::Merge(rate)),
+            "drop" => Ok(Fault::Drop(rate)),
+            "drop-epoch" => Ok(Fault::DropEpoch(rate)),
+            "repeat" => Ok(Fault::Repeat(rate)),
+            "jitter" => Ok(Fault::Jitter(rate)),
+            "lf" => Ok(Fault::LineFeed(rate)),
+            "no-dollar" => Ok(Fault::NoDollar(rate)),
+            "split" => Ok(Fault::Split(rate)),
+            _ => Err(format!(
+                "Unknown fault '{}', expected checksum, truncate, merge, garbage, drop, drop-epoch, repeat, jitter, stall, freeze, outlier, lf, no-dollar, split or seq-gap",
+                kind
+            )
+            .into()),
+        }
+    }
+}
+
+// Applies the faults of one port to its epochs, with random values of its
+// own so faults don't change the fixes of a seeded run
+pub struct FaultInjector {
+    faults: Vec<Fault>,
+    rng: StdRng,
+    // Sentences of the epoch before, as generated, for stale repeats
+    previous: Vec<String>,
+    // Epochs held back until the end of a stall
+    held: Vec<u8>,
+    stalled_until: Option<Instant>,
+    // The fix reported until the end of a freeze
+    frozen: Option<(Fix, Instant)>,
+}
+
+impl FaultInjector {
+    pub fn new(faults: Vec<Fault>, seed: u64) -> Self {
+        FaultInjector {
+            faults,
+            rng: StdRng::seed_from_u64(seed),
+            previous: Vec::new(),
+            held: Vec::new(),
+            stalled_until: None,
+            frozen: None,
+        }
+    }
+
+    // The fix the receiver reports for the true one
+    pub fn report(&mut self, truth: &Fix) -> Fix {
+        let now = Instant::now();
+        match &self.frozen {
+            Some((fix, until)) if now < *until => return fix.clone(),
+            _ => self.frozen = None,
+        }
+        let mut fix = truth.clone();
+        for fault in &self.faults {
+            match *fault {
+                Fault::Freeze { rate, duration } if self.rng.gen_bool(rate) => {
+                    self.frozen = Some((truth.clone(), now + duration));
+                }
+                Fault::Outlier {
+                    rate,
+                    max_distance_m,
+                } if self.rng.gen_bool(rate) => {
+                    // Hundreds of metres to kilometres for the default
+                    let distance = max_distance_m * self.rng.gen_range(0.1..=1.0);
+                    let bearing = self.rng.gen_range(0.0..TAU);
+                    let position = fix
+                        .position()
+                        .offset(distance * bearing.cos(), distance * bearing.sin());
+                    fix.latitude = position.lat_deg;
+                    fix.longitude = position.lon_deg;
+                }
+                _ => {}
+            }
+        }
+        fix
+    }
+
+    // The writes an epoch goes out in, each flushed on its own
+    pub fn pieces<'a>(&mut self, epoch: &'a [u8]) -> Vec<&'a [u8]> {
+        let split = self.faults.iter().any(|fault| match *fault {
+            Fault::Split(rate) => epoch.len() > 1 && self.rng.gen_bool(rate),
+            _ => false,
+        });
+        if !split {
+            return vec![epoch];
+        }
+        let mut cuts: Vec<usize> = (0..self.rng.gen_range(1..=3))
+            .map(|_| self.rng.gen_range(1..epoch.len()))
+            .collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        for cut in cuts {
+            pieces.push(&epoch[start..cut]);
+            start = cut;
+        }
+        pieces.push(&epoch[start..]);
+        pieces
+    }
+
+    // How many numbers the sentences templates number skip this epoch
+    pub fn sequence_gap(&mut self) -> u64 {
+        let mut gap = 0;
+        for fault in &self.faults {
+            if let Fault::SequenceGap { rate, max_length } = *fault {
+                if self.rng.gen_bool(rate) {
+                    gap += self.rng.gen_range(1..=max_length);
+                }
+            }
+        }
+        gap
+    }
+
+    // How late the next epoch goes out
+    pub fn jitter(&mut self, interval: Duration) -> Duration {
+        let mut delay = Duration::ZERO;
+        for fault in &self.faults {
+            if let Fault::Jitter(fraction) = *fault {
+                delay += interval.mul_f64(fraction * self.rng.gen::<f64>());
+            }
+        }
+        delay
+    }
+
+    // What to write of an epoch now: nothing during a stall, and at its
+    // end everything held back at once
+    pub fn release(&mut self, epoch: Vec<u8>) -> Vec<u8> {
+        let now = Instant::now();
+        if self.stalled_until.is_some_and(|until| now < until) {
+            self.held.extend(epoch);
+            return Vec::new();
+        }
+        if self.stalled_until.take().is_some() {
+            let mut burst = std::mem::take(&mut self.held);
+            burst.extend(epoch);
+            return burst;
+        }
+        for fault in &self.faults {
+            if let Fault::Stall { rate, duration } = *fault {
+                if self.rng.gen_bool(rate) {
+                    self.stalled_until = Some(now + duration);
+                    self.held = epoch;
+                    return Vec::new();
+                }
+            }
+        }
+        epoch
+    }
+
+    // The bytes to send for the sentences of an epoch, which need not be
+    // text any more
+    pub fn apply(&mut self, sentences: &str) -> Vec<u8> {
+        if self.faults.is_empty() {
+            return sentences.as_bytes().to_vec();
+        }
+        let previous = std::mem::replace(
+            &mut self.previous,
+            sentences
+                .split_inclusive("\r\n")
+                .map(str::to_string)
+                .collect(),
+        );
+        let mut out = Vec::with_capacity(sentences.len());
+        for fault in &self.faults {
+            if let Fault::DropEpoch(rate) = *fault {
+                if self.rng.gen_bool(rate) {
+                    return out;
+                }
+            }
+        }
+
+        for line in sentences.split_inclusive("\r\n") {
+            let mut line = line.to_string();
+            let mut dropped = false;
+            let mut after = Vec::new();
+            for fault in &self.faults {
+                match *fault {
+                    Fault::Checksum(rate) => {
+                        if self.rng.gen_bool(rate) {
+                            line = damage_checksum(&line, &mut self.rng);
+                        }
+                    }
+                    Fault::Truncate(rate) => {
+                        if self.rng.gen_bool(rate) {
+                            line = truncate(&line, &mut self.rng);
+                        }
+                    }
+                    Fault::Merge(rate) => {
+                        if self.rng.gen_bool(rate) {
+                            line.truncate(line.trim_end_matches(['\r', '\n']).len());
+                        }
+                    }
+                    Fault::LineFeed(rate) => {
+                        if line.ends_with("\r\n") && self.rng.gen_bool(rate) {
+                            line.truncate(line.len() - 2);
+                            line.push('\n');
+                        }
+                    }
+                    Fault::NoDollar(rate) => {
+                        if line.starts_with('$') && self.rng.gen_bool(rate) {
+                            line.remove(0);
+                        }
+                    }
+                    Fault::Garbage { rate, max_length } => {
+                        if self.rng.gen_bool(rate) {
+                            let length = self.rng.gen_range(1..=max_length);
+                            after.extend((0..length).map(|_| self.rng.gen_range(0x80..=0xFF)));
+                        }
+                    }
+                    Fault::Drop(rate) => dropped |= self.rng.gen_bool(rate),
+                    Fault::DropEpoch(_)
+                    | Fault::Jitter(_)
+                    | Fault::Stall { .. }
+                    | Fault::Freeze { .. }
+                    | Fault::Outlier { .. }
+                    | Fault::Split(_)
+                    | Fault::SequenceGap { .. } => {}
+                    Fault::Repeat(rate) => {
+                        if !previous.is_empty() && self.rng.gen_bool(rate) {
+                            let stale = &previous[self.rng.gen_range(0..previous.len())];
+                            after.extend(stale.as_bytes());
+                        }
+                    }
+                }
+            }
+            if !dropped {
+                out.extend(line.into_bytes());
+            }
+            out.extend(after);
+        }
+        out
+    }
+}
+
+// A wrong checksum, the right one in lowercase hex, which strict parsers
+// reject, or none at all
+fn damage_checksum(line: &str, rng: &mut StdRng) -> String {
+    let Some((body, sum)) = line.trim_end().split_once('*') else {
+        return line.to_string();
+    };
+    let lowercase = sum.to_ascii_lowercase();
+    match rng.gen_range(0..3) {
+        1 if lowercase != sum => format!("{}*{}\r\n", body, lowercase),
+        2 => format!("{}\r\n", body),
+        _ => {
+            let wrong = checksum(body.trim_start_matches('$')) ^ rng.gen_range(1..=0xFF);
+            format!("{}*{:02X}\r\n", body, wrong)
+        }
+    }
+}
+
+// Cuts the line somewhere after its formatter and before its checksum
+fn truncate(line: &str, rng: &mut StdRng) -> String {
+    let end = line.find('*').unwrap_or(line.trim_end().len());
+    let start = line.find(',').map_or(end, |comma| comma + 1);
+    if start >= end {
+        return line.trim_end().to_string();
+    }
+    line[..rng.gen_range(start..end)].to_string()
+}
+
+// Gives up to `count` of the sentences a checksum that does not match, so
+// clients have to reject them. Returns the sentences and how many were
+// corrupted.
+pub fn corrupt_checksums(sentences: &str, count: u32) -> (String, u32) {
+    let mut corrupted = 0;
+    let mut out = String::with_capacity(sentences.len());
+    for line in sentences.split_inclusive("\r\n") {
+        let body = line.trim_end().strip_prefix('$');
+        match body.and_then(|body| body.split_once('*')) {
+            Some((body, _)) if corrupted < count => {
+                out.push_str(&format!("${}*{:02X}\r\n", body, checksum(body) ^ 0xFF));
+                corrupted += 1;
+            }
+            _ => out.push_str(line),
+        }
+    }
+    (out, corrupted)
+}
@@ -0,0 +1,91 @@
+// src/ffi.rs
+
+// C API over the generator, for test benches in C and C++ that link the
+// cdylib and pull sentences themselves instead of reading a PTY. The
+// header is include/nmea_simulator.h, generated with cbindgen.
+//
+// Every function takes a simulator from nmea_sim_new that was not freed
+// yet, or null, which is ignored.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::nmea_generator::NmeaGenerator;
+use crate::position::Position;
+use std::ptr;
+
+// Opaque to C
+pub struct NmeaSim {
+    generator: NmeaGenerator,
+    // The epoch a too small buffer could not take, handed out next time
+    pending: Option<String>,
+}
+
+// A simulator with random values drawn from the seed; free it with
+// nmea_sim_free
+#[no_mangle]
+pub extern "C" fn nmea_sim_new(seed: u64) -> *mut NmeaSim {
+    Box::into_raw(Box::new(NmeaSim {
+        generator: NmeaGenerator::with_seed(seed),
+        pending: None,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nmea_sim_free(sim: *mut NmeaSim) {
+    if !sim.is_null() {
+        drop(Box::from_raw(sim));
+    }
+}
+
+// Pins the reported position; 0 on success, -1 for a null simulator or
+// coordinates out of range
+#[no_mangle]
+pub unsafe extern "C" fn nmea_sim_set_position(
+    sim: *mut NmeaSim,
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+) -> i32 {
+    let Some(sim) = sim.as_mut() else {
+        return -1;
+    };
+    if !(-90.0..=90.0).contains(&latitude)
+        || !(-180.0..=180.0).contains(&longitude)
+        || !altitude.is_finite()
+    {
+        return -1;
+    }
+    sim.generator.position = Some(Position::new(latitude, longitude, altitude));
+    0
+}
+
+// Goes back to random positions
+#[no_mangle]
+pub unsafe extern "C" fn nmea_sim_release_position(sim: *mut NmeaSim) {
+    if let Some(sim) = sim.as_mut() {
+        sim.generator.position = None;
+    }
+}
+
+// Copies the sentences of the next epoch into buf, which must have room
+// for len bytes, as a NUL terminated string and returns their length
+// without the NUL, like snprintf. When that is len or more nothing was
+// copied and the next call returns the same epoch, so it can be retried
+// with a larger buffer; buf may be null to only ask for the size.
+#[no_mangle]
+pub unsafe extern "C" fn nmea_sim_next(sim: *mut NmeaSim, buf: *mut u8, len: usize) -> usize {
+    let Some(sim) = sim.as_mut() else {
+        return 0;
+    };
+    let generator = &mut sim.generator;
+    let epoch = sim
+        .pending
+        .get_or_insert_with(|| generator.iter().next().unwrap_or_default());
+    let size = epoch.len();
+    if buf.is_null() || size >= len {
+        return size;
+    }
+    ptr::copy_nonoverlapping(epoch.as_ptr(), buf, size);
+    *buf.add(size) = 0;
+    sim.pending = None;
+    size
+}
@@ -0,0 +1,117 @@
+// src/fifo.rs
+
+use crate::output::{Backlog, OutputSink};
+use nix::sys::stat::Mode;
+use nix::unistd::mkfifo;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::ErrorKind;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::Path;
+use tracing::{error, info};
+
+// Writes to a named pipe. The pipe is created if missing and removed again
+// on drop if we created it. While no reader is attached the data is
+// discarded; a reader that goes away is picked up again on the next write.
+pub struct FifoSink {
+    path: String,
+    created: bool,
+    writer: Option<File>,
+    backlog: Backlog,
+}
+
+impl FifoSink {
+    pub fn create(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(FifoSink {
+            path: path.to_string(),
+            created: Self::make_fifo(path)?,
+            writer: None,
+            backlog: Backlog::new(path),
+        })
+    }
+
+    // Returns whether the FIFO had to be created
+    fn make_fifo(path: &str) -> Result<bool, Box<dyn Error>> {
+        let mut created = false;
+        match fs::metadata(path) {
+            Ok(meta) if meta.file_type().is_fifo() => {
+                info!("Using existing FIFO: {}", path);
+            }
+            Ok(_) => return Err(format!("{} exists and is not a FIFO", path).into()),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("Creating FIFO: {}", path);
+                mkfifo(path, Mode::from_bits_truncate(0o666))
+                    .inspect_err(|e| error!("Failed to create FIFO {}: {}", path, e))?;
+                created = true;
+            }
+            Err(e) => return Err(Box::new(e)),
+        }
+        Ok(created)
+    }
+
+    // Try to attach to a reader. Returns Ok(false) if nobody has the FIFO
+    // open for reading yet.
+    fn try_open(&mut self) -> Result<bool, Box<dyn Error>> {
+        // O_NONBLOCK makes open() fail with ENXIO instead of waiting for a
+        // reader, and keeps a reader that stops reading from stalling us
+        let file = match OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)
+        {
+            Ok(file) => file,
+            Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(false),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        info!("FIFO reader connected: {}", self.path);
+        self.writer = Some(file);
+        Ok(true)
+    }
+}
+
+impl OutputSink for FifoSink {
+    fn name(&self) -> String {
+        format!("fifo:{}", self.path)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.writer.is_none() && !self.try_open()? {
+            return Ok(());
+        }
+
+        if let Some(writer) = self.writer.as_mut() {
+            if let Err(e) = self.backlog.write(writer, data) {
+                if e.kind() != ErrorKind::BrokenPipe {
+                    return Err(Box::new(e));
+                }
+                info!("FIFO reader disconnected: {}", self.path);
+                self.writer = None;
+                self.backlog.clear();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reconnects(&self) -> bool {
+        true
+    }
+
+    // Creates the FIFO again if someone removed it
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer = None;
+        self.backlog.clear();
+        self.created |= Self::make_fifo(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for FifoSink {
+    fn drop(&mut self) {
+        if self.created && Path::new(&self.path).exists() {
+            let _ = fs::remove_file(&self.path);
+            info!("Removed FIFO: {}", self.path);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+// src/geoid.rs
+
+// Height of the EGM96 geoid above the WGS84 ellipsoid in whole metres,
+// sampled every 10 degrees from 90S and 180W, as the tables receivers and
+// gpsd bundle. Interpolated it is a few metres off the full model, more
+// where the geoid is steep, but follows the position as a receiver's does.
+const GRID_STEP: f64 = 10.0;
+const ROWS: usize = 19;
+const COLUMNS: usize = 37;
+
+#[rustfmt::skip]
+const GRID: [[i8; COLUMNS]; ROWS] = [
+    /* 90S */ [-30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30, -30],
+    /* 80S */ [-53, -54, -55, -52, -48, -42, -38, -38, -29, -26, -26, -24, -23, -21, -19, -16, -12, -8, -4, -1, 1, 4, 4, 6, 5, 4, 2, -6, -15, -24, -33, -40, -48, -50, -53, -52, -53],
+    /* 70S */ [-61, -60, -61, -55, -49, -44, -38, -31, -25, -16, -6, 1, 4, 5, 4, 2, 6, 12, 16, 16, 17, 21, 20, 26, 26, 22, 16, 10, -1, -16, -29, -36, -46, -55, -54, -59, -61],
+    /* 60S */ [-45, -43, -37, -32, -30, -26, -23, -22, -16, -10, -2, 10, 20, 20, 21, 24, 22, 17, 16, 19, 25, 30, 35, 35, 33, 30, 27, 10, -2, -14, -23, -30, -33, -29, -35, -43, -45],
+    /* 50S */ [-15, -18, -18, -16, -17, -15, -10, -10, -8, -2, 6, 14, 13, 3, 3, 10, 20, 27, 25, 26, 34, 39, 45, 45, 38, 39, 28, 13, -1, -15, -22, -22, -18, -15, -14, -10, -15],
+    /* 40S */ [21, 6, 1, -7, -12, -12, -12, -10, -7, -1, 8, 23, 15, -2, -6, 6, 21, 24, 18, 26, 31, 33, 39, 41, 30, 24, 13, -2, -20, -32, -33, -27, -14, -2, 5, 20, 21],
+    /* 30S */ [46, 22, 5, -2, -8, -13, -10, -7, -4, 1, 9, 32, 16, 4, -8, 4, 12, 15, 22, 27, 34, 29, 14, 15, 15, 7, -9, -25, -37, -39, -23, -14, 15, 33, 34, 45, 46],
+    /* 20S */ [51, 27, 10, 0, -9, -11, -5, -2, -3, -1, 9, 35, 20, -5, -6, -5, 0, 13, 17, 23, 21, 8, -9, -10, -11, -20, -40, -47, -45, -25, 5, 23, 45, 58, 57, 63, 51],
+    /* 10S */ [36, 22, 11, 6, -1, -8, -10, -8, -11, -9, 1, 32, 4, -18, -13, -9, 4, 14, 12, 13, -2, -14, -25, -32, -38, -60, -75, -63, -26, 0, 35, 52, 68, 76, 64, 52, 36],
+    /* 00N */ [22, 16, 17, 13, 1, -12, -23, -20, -14, -3, 14, 10, -15, -27, -18, 3, 12, 20, 18, 12, -13, -9, -28, -49, -62, -89, -102, -75, -19, 6, 41, 64, 63, 48, 32, 21, 22],
+    /* 10N */ [13, 12, 11, 2, -11, -28, -38, -29, -10, 3, 1, -11, -41, -42, -16, 3, 17, 33, 22, 23, 2, -3, -7, -36, -59, -90, -95, -63, -24, 12, 53, 60, 58, 46, 36, 26, 13],
+    /* 20N */ [5, 10, 7, -7, -23, -39, -47, -34, -9, 10, 20, 1, -29, -35, -26, -3, 8, 30, 37, 35, 22, 13, 8, -8, -40, -58, -61, -52, -23, 13, 31, 47, 38, 22, 12, 3, 5],
+    /* 30N */ [-7, -5, -8, -15, -28, -40, -42, -29, -22, -2, 4, -15, -30, -36, -27, -12, 10, 21, 36, 26, 33, 20, 19, 9, -2, -20, -15, -27, -31, -19, 4, 22, 39, 44, 28, 6, -7],
+    /* 40N */ [-12, -10, -13, -20, -31, -34, -21, -16, -26, -34, -33, -35, -26, 2, 33, 59, 52, 51, 52, 48, 35, 40, 33, -9, -28, -39, -48, -59, -50, -28, 3, 23, 37, 18, -1, -11, -12],
+    /* 50N */ [-8, 8, 8, 1, -11, -19, -16, -18, -22, -35, -40, -26, -12, 24, 45, 63, 62, 59, 47, 48, 42, 28, 12, -10, -19, -33, -43, -42, -43, -29, -2, 17, 23, 22, 6, 2, -8],
+    /* 60N */ [2, 9, 17, 10, 13, 1, -14, -30, -39, -46, -42, -21, 6, 29, 49, 65, 60, 57, 47, 41, 21, 18, 14, 7, -3, -22, -29, -32, -32, -26, -15, -2, 13, 17, 19, 6, 2],
+    /* 70N */ [3, 9, 17, 27, 30, 33, 22, 10, -3, -14, -22, -24, -28, -11, 4, 16, 28, 35, 39, 37, 29, 21, 13, 5, -7, -17, -25, -31, -29, -21, -9, 2, 11, 19, 25, 16, 3],
+    /* 80N */ [13, 13, 13, 18, 19, 22, 26, 20, 14, 4, -6, -12, -16, -17, -15, -10, -6, 2, 4, 13, 15, 16, 21, 24, 25, 23, 21, 18, 14, 5, -5, -14, -22, -28, -22, -5, 13],
+    /* 90N */ [13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13, 13],
+];
+
+// Geoid separation at a position, bilinear between the four grid points
+// around it: what GGA reports, and what the altitude above mean sea level
+// is short of the height above the ellipsoid
+pub fn separation(latitude: f64, longitude: f64) -> f64 {
+    let row = (latitude.clamp(-90.0, 90.0) + 90.0) / GRID_STEP;
+    let column = (longitude + 180.0).rem_euclid(360.0) / GRID_STEP;
+    // The last row and column only ever bound a cell
+    let r = (row as usize).min(ROWS - 2);
+    let c = (column as usize).min(COLUMNS - 2);
+    let (dr, dc) = (row - r as f64, column - c as f64);
+    let at = |r: usize, c: usize| GRID[r][c] as f64;
+    let south = at(r, c) + (at(r, c + 1) - at(r, c)) * dc;
+    let north = at(r + 1, c) + (at(r + 1, c + 1) - at(r + 1, c)) * dc;
+    south + (north - south) * dr
+}
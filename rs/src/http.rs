@@ -0,0 +1,328 @@
+// src/http.rs
+
+use crate::output::{self, Client, OutputSink};
+use crate::runtime::shutdown_requested;
+use crate::state::SharedState;
+use crate::stats::{self, SharedStats};
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+const ACCEPT_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Request bodies are small JSON documents
+const MAX_BODY: usize = 64 * 1024;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    // Header names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// Reads the request line, headers and body (if it has a Content-Length) of
+// an HTTP/1.x request
+pub fn read_request(stream: &TcpStream) -> Result<Request, Box<dyn Error>> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut request = parse_request_line(&request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        request.headers.extend(parse_header(&line));
+    }
+
+    request.body.resize(body_length(&request)?, 0);
+    reader.read_exact(&mut request.body)?;
+    Ok(request)
+}
+
+// The same for connections served on the runtime
+pub async fn read_request_async<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> Result<Request, Box<dyn Error + Send + Sync>> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut request = parse_request_line(&request_line)?;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+        request.headers.extend(parse_header(&line));
+    }
+
+    request.body.resize(body_length(&request)?, 0);
+    reader.read_exact(&mut request.body).await?;
+    Ok(request)
+}
+
+fn parse_request_line(line: &str) -> Result<Request, String> {
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or("Empty request")?.to_string();
+    let path = parts.next().ok_or("Missing request path")?.to_string();
+    Ok(Request {
+        method,
+        path,
+        headers: Vec::new(),
+        body: Vec::new(),
+    })
+}
+
+fn parse_header(line: &str) -> Option<(String, String)> {
+    let (key, value) = line.split_once(':')?;
+    Some((key.trim().to_ascii_lowercase(), value.trim().to_string()))
+}
+
+fn body_length(request: &Request) -> Result<usize, String> {
+    let Some(length) = request.header("content-length") else {
+        return Ok(0);
+    };
+    let length: usize = length
+        .parse()
+        .map_err(|_| format!("Invalid Content-Length: {}", length))?;
+    if length > MAX_BODY {
+        return Err(format!("Request body too large: {} bytes", length));
+    }
+    Ok(length)
+}
+
+pub fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    Ok(())
+}
+
+pub fn write_json(
+    stream: &mut TcpStream,
+    status: &str,
+    value: &serde_json::Value,
+) -> Result<(), Box<dyn Error>> {
+    write_response(
+        stream,
+        status,
+        "application/json",
+        value.to_string().as_bytes(),
+    )
+}
+
+// Accepts connections until shutdown and hands each request to `handle`.
+// Every connection is a task on the runtime; the handler gets a blocking
+// stream, as responses are small and subscribers keep the stream.
+pub fn spawn_server<F>(
+    addr: &str,
+    runtime: &Handle,
+    shutdown_event: Arc<AtomicBool>,
+    handle: F,
+) -> Result<JoinHandle<()>, Box<dyn Error>>
+where
+    F: Fn(Request, TcpStream) -> Result<(), Box<dyn Error>> + Send + Sync + 'static,
+{
+    let listener = {
+        let _runtime = runtime.enter();
+        tokio::net::TcpListener::from_std(output::listen(addr)?)?
+    };
+    info!("HTTP server listening on {}", addr);
+
+    let handle = Arc::new(handle);
+    let addr = addr.to_string();
+    Ok(runtime.spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                _ = shutdown_requested(&shutdown_event) => break,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("Error accepting HTTP connection on {}: {}", addr, e);
+                        tokio::time::sleep(ACCEPT_RETRY_INTERVAL).await;
+                        continue;
+                    }
+                },
+            };
+            let handle = handle.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_connection(stream, &*handle).await {
+                    warn!("HTTP request from {} failed: {}", peer, e);
+                }
+            });
+        }
+        info!("HTTP server on {} exiting.", addr);
+    }))
+}
+
+async fn serve_connection<F>(
+    stream: tokio::net::TcpStream,
+    handle: &F,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    F: Fn(Request, TcpStream) -> Result<(), Box<dyn Error>>,
+{
+    let mut reader = tokio::io::BufReader::new(stream);
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request_async(&mut reader))
+        .await
+        .map_err(|_| "Timed out reading the request")??;
+
+    let stream = reader.into_inner().into_std()?;
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    handle(request, stream).map_err(|e| e.to_string().into())
+}
+
+// Server-Sent Events subscribers. As an output sink it publishes every
+// sentence as its own event.
+#[derive(Clone, Default)]
+pub struct SseSink {
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+impl SseSink {
+    pub fn new() -> Self {
+        SseSink::default()
+    }
+
+    pub fn subscribe(&self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+        )?;
+        self.clients.lock().unwrap().push(Client::new(stream)?);
+        Ok(())
+    }
+}
+
+impl OutputSink for SseSink {
+    fn name(&self) -> String {
+        "sse".to_string()
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut events = String::new();
+        for line in String::from_utf8_lossy(data).lines() {
+            if !line.is_empty() {
+                events.push_str(&format!("data: {}\n\n", line));
+            }
+        }
+
+        // Subscribers that went away are simply dropped
+        self.clients
+            .lock()
+            .unwrap()
+            .retain_mut(|client| client.send(events.as_bytes()).is_ok());
+        Ok(())
+    }
+}
+
+// Live view for demos: GET /events streams sentences, GET /state returns the
+// current truth as JSON. GET /health answers while the simulator runs and
+// GET /ready once it is ready, for orchestrators and test harnesses.
+pub fn spawn_status_server(
+    addr: &str,
+    state: SharedState,
+    events: SseSink,
+    runtime: &Handle,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    spawn_server(
+        addr,
+        runtime,
+        shutdown_event,
+        move |request, mut stream| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/events") => events.subscribe(stream),
+            ("GET", "/health") => write_json(
+                &mut stream,
+                "200 OK",
+                &serde_json::json!({ "status": "ok" }),
+            ),
+            ("GET", "/ready") => {
+                if state.lock().unwrap().ready {
+                    write_json(&mut stream, "200 OK", &serde_json::json!({ "ready": true }))
+                } else {
+                    write_json(
+                        &mut stream,
+                        "503 Service Unavailable",
+                        &serde_json::json!({ "ready": false }),
+                    )
+                }
+            }
+            ("GET", "/state") => {
+                let truth = state.lock().unwrap().truth.clone();
+                match truth {
+                    Some(fix) => write_json(&mut stream, "200 OK", &serde_json::to_value(fix)?),
+                    None => write_json(
+                        &mut stream,
+                        "503 Service Unavailable",
+                        &serde_json::json!({ "error": "no fix generated yet" }),
+                    ),
+                }
+            }
+            _ => write_json(
+                &mut stream,
+                "404 Not Found",
+                &serde_json::json!({ "error": "not found" }),
+            ),
+        },
+    )
+}
+
+// Scrape target for Prometheus: GET /metrics returns the stats and the
+// simulated truth
+pub fn spawn_metrics_server(
+    addr: &str,
+    stats: SharedStats,
+    state: SharedState,
+    runtime: &Handle,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, Box<dyn Error>> {
+    spawn_server(
+        addr,
+        runtime,
+        shutdown_event,
+        move |request, mut stream| match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => {
+                let mut metrics = stats.snapshot().prometheus();
+                metrics.push_str(&stats::truth_gauges(&state.lock().unwrap()));
+                write_response(
+                    &mut stream,
+                    "200 OK",
+                    "text/plain; version=0.0.4",
+                    metrics.as_bytes(),
+                )
+            }
+            _ => write_json(
+                &mut stream,
+                "404 Not Found",
+                &serde_json::json!({ "error": "not found" }),
+            ),
+        },
+    )
+}
@@ -0,0 +1,70 @@
+// src/lib.rs
+
+// The simulator as a library, so other projects can run it in-process
+// from their integration tests; the binary only runs the command line.
+// Simulator runs it all, the modules below are usable on their own too.
+
+pub mod ais;
+pub mod android;
+pub mod builder;
+pub mod checksum;
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod control;
+pub mod error;
+pub mod faults;
+pub mod ffi;
+#[cfg(unix)]
+pub mod fifo;
+pub mod geoid;
+pub mod http;
+pub mod logging;
+pub mod mavlink;
+pub mod mux;
+#[cfg(windows)]
+pub mod named_pipe;
+pub mod nmea_generator;
+pub mod ntrip;
+pub mod options;
+pub mod output;
+pub mod parser;
+pub mod position;
+pub mod pps;
+#[cfg(unix)]
+pub mod pty_handler;
+#[cfg(unix)]
+pub mod recorder;
+pub mod repl;
+pub mod replay;
+#[cfg(feature = "ros2")]
+pub mod ros2;
+pub mod rtcm;
+pub mod runtime;
+pub mod scenario;
+#[cfg(feature = "scripting")]
+pub mod scripting;
+pub mod sentences;
+pub mod signalk;
+pub mod simulator;
+pub mod state;
+pub mod stats;
+pub mod tag_block;
+pub mod template;
+pub mod trajectory;
+pub mod truth_input;
+#[cfg(all(unix, feature = "tui"))]
+pub mod tui;
+pub mod ubx;
+pub mod validate;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use builder::{MotionProfile, SimulatorBuilder};
+pub use checksum::{checksum, verify, wrap_sentence};
+pub use control::{ControlCommand, Controller};
+pub use error::SimError;
+pub use nmea_generator::{EncodingMode, Environment, NmeaGenerator, TalkerPolicy};
+pub use options::Options;
+pub use output::OutputSink;
+pub use simulator::Simulator;
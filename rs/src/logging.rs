@@ -0,0 +1,36 @@
+// src/logging.rs
+
+use crate::error::SimError;
+use std::io::{self, IsTerminal};
+use tracing_subscriber::EnvFilter;
+
+// Level used without --log-level, -q or RUST_LOG
+const DEFAULT_FILTER: &str = "info";
+
+// Log lines go to stderr, so they never mix with NMEA written to stdout.
+// Programs embedding the simulator install a subscriber of their own.
+pub fn init(filter: Option<&str>, json: bool) -> Result<(), SimError> {
+    let filter = match filter {
+        Some(filter) => parse_filter(filter)?,
+        None => {
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))
+        }
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal());
+    let result = if json {
+        builder.json().try_init()
+    } else {
+        builder.try_init()
+    };
+    result.map_err(|e| SimError::Other(format!("Failed to set up logging: {}", e)))
+}
+
+// A level like "warn", or per module targets like
+// "info,nmea_simulator::output=debug"
+pub fn parse_filter(filter: &str) -> Result<EnvFilter, SimError> {
+    EnvFilter::try_new(filter)
+        .map_err(|e| SimError::Usage(format!("Invalid log level {}: {}", filter, e)))
+}
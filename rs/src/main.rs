@@ -1,97 +1,172 @@
 // src/main.rs
 
-mod nmea_generator;
-mod pty_handler;
-
-use nmea_generator::NmeaGenerator;
-use pty_handler::PtyHandler;
-use signal_hook::consts::SIGINT;
-use signal_hook::iterator::Signals;
-use std::error::Error;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use nmea_simulator::cli::{self, Cli, Command, ServeArgs};
+#[cfg(unix)]
+use nmea_simulator::ControlCommand;
+use nmea_simulator::{logging, Controller, SimError, Simulator};
+#[cfg(unix)]
+use signal_hook::consts::{SIGHUP, SIGUSR1, SIGUSR2};
+use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook::{iterator::Signals, low_level::signal_name};
+use std::process::ExitCode;
+#[cfg(unix)]
+use std::sync::atomic::Ordering;
+use std::sync::{atomic::AtomicBool, Arc};
+#[cfg(unix)]
 use std::thread;
-use std::time::Duration;
-
-fn main() -> Result<(), Box<dyn Error>> {
-    let shutdown_event = Arc::new(AtomicBool::new(false));
-
-    // Set up signal handler
-    let shutdown_event_clone = shutdown_event.clone();
-    let mut signals = Signals::new(&[SIGINT])?;
+use tracing::error;
+#[cfg(unix)]
+use tracing::{info, warn};
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => return usage_error(e),
+    };
+    if let Err(e) = logging::init(cli.log.filter(), cli.log.json()) {
+        eprintln!("{}", e);
+        return ExitCode::from(e.exit_code());
+    }
 
-    thread::spawn(move || {
-        for _ in signals.forever() {
-            println!("\nKeyboardInterrupt received. Shutting down...");
-            shutdown_event_clone.store(true, Ordering::SeqCst);
+    let result = match cli.command {
+        None => serve(cli.serve),
+        Some(Command::Serve(args)) => serve(*args),
+        Some(Command::Generate(args)) => {
+            let shutdown_event = Arc::new(AtomicBool::new(false));
+            install_signal_handler(shutdown_event.clone(), true)
+                .and_then(|_| cli::generate(args, &shutdown_event))
         }
-    });
+        Some(Command::Replay(args)) => {
+            let shutdown_event = Arc::new(AtomicBool::new(false));
+            install_signal_handler(shutdown_event.clone(), true)
+                .and_then(|_| cli::replay(args, &shutdown_event))
+        }
+        Some(Command::Validate(args)) => cli::validate(args),
+        Some(Command::Selftest(args)) => {
+            let shutdown_event = Arc::new(AtomicBool::new(false));
+            install_signal_handler(shutdown_event.clone(), true)
+                .and_then(|_| cli::selftest(args, &shutdown_event))
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // Reported like the errors clap finds itself
+        Err(SimError::Usage(message)) => {
+            usage_error(Cli::command().error(ErrorKind::ArgumentConflict, message))
+        }
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    // Ensure correct number of arguments
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <gps_input_path> <gps_output_path>", args[0]);
-        std::process::exit(1);
+// Help and version go to stdout with success, mistakes exit with
+// EX_USAGE like every other usage error
+fn usage_error(e: clap::Error) -> ExitCode {
+    let _ = e.print();
+    if e.use_stderr() {
+        ExitCode::from(SimError::Usage(String::new()).exit_code())
+    } else {
+        ExitCode::SUCCESS
     }
+}
 
-    let gps_input_path = &args[1];
-    let gps_output_path = &args[2];
+fn serve(args: ServeArgs) -> Result<(), SimError> {
+    let options = args.into_options()?;
 
-    // Initialize PTY handler
-    let mut pty_handler = PtyHandler::new(shutdown_event.clone());
-    pty_handler.setup_linked_ptys(gps_input_path, gps_output_path)?;
-    pty_handler.start_forwarding()?;
+    // SIGHUP reloads the config file if there is one
+    let stop_on_hangup = options.config_path.is_none();
+    let step = options.step;
+    let simulator = Simulator::new(options);
 
-    // Initialize NMEA generator
-    let mut nmea_generator = NmeaGenerator::new();
+    // Set up signal handlers, then run until shutdown
+    install_signal_handler(simulator.shutdown_handle(), stop_on_hangup)
+        .and_then(|_| install_pause_handler(simulator.controller(), step))
+        .and_then(|_| simulator.run())
+}
 
-    // Write NMEA messages to /tmp/gps_input
-    if let Err(e) = write_nmea_messages(gps_input_path, &mut nmea_generator, shutdown_event.clone())
-    {
-        eprintln!("Error writing NMEA messages: {}", e);
+// Ctrl+C, systemd stop and a closed terminal all end the run the same
+// way, so the PTY symlinks are always removed
+#[cfg(unix)]
+fn install_signal_handler(
+    shutdown_event: Arc<AtomicBool>,
+    stop_on_hangup: bool,
+) -> Result<(), SimError> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])
+        .map_err(|e| SimError::io("Failed to install the signal handlers", e))?;
+    if stop_on_hangup {
+        signals
+            .add_signal(SIGHUP)
+            .map_err(|e| SimError::io("Failed to install the SIGHUP handler", e))?;
     }
 
-    // Perform cleanup
-    pty_handler.cleanup(gps_input_path, gps_output_path)?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGINT => info!("KeyboardInterrupt received. Shutting down..."),
+                _ => info!(
+                    "{} received. Shutting down...",
+                    signal_name(signal).unwrap_or("Signal")
+                ),
+            }
+            shutdown_event.store(true, Ordering::SeqCst);
+        }
+    });
 
     Ok(())
 }
 
-fn write_nmea_messages(
-    gps_input_path: &str,
-    nmea_generator: &mut NmeaGenerator,
-    shutdown_event: Arc<AtomicBool>,
-) -> Result<(), Box<dyn Error>> {
-    // Open the GPS input PTY for writing
-    println!("Opening GPS input path: {}", gps_input_path);
-    let gps_input = OpenOptions::new()
-        .write(true)
-        .open(gps_input_path)
-        .map_err(|e| {
-            eprintln!("Failed to open {}: {}", gps_input_path, e);
-            e
-        })?;
-
-    let mut writer = std::io::BufWriter::new(gps_input);
+// SIGUSR1 pauses and SIGUSR2 resumes, or sends a single epoch with --step,
+// so a client held in a debugger can be fed from its shell with
+// kill -USR2 <pid>
+#[cfg(unix)]
+fn install_pause_handler(controller: Controller, step: bool) -> Result<(), SimError> {
+    let mut signals = Signals::new([SIGUSR1, SIGUSR2])
+        .map_err(|e| SimError::io("Failed to install the SIGUSR1 and SIGUSR2 handlers", e))?;
 
-    // Main loop to write NMEA messages
-    while !shutdown_event.load(Ordering::SeqCst) {
-        let sentence = nmea_generator.generate_sentences();
-        if let Err(e) = writer.write_all(sentence.as_bytes()) {
-            eprintln!("Error writing to {}: {}", gps_input_path, e);
-            break;
-        }
-        if let Err(e) = writer.flush() {
-            eprintln!("Error flushing to {}: {}", gps_input_path, e);
-            break;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let command = match signal {
+                SIGUSR1 => ControlCommand::Pause,
+                _ if step => ControlCommand::Advance { epochs: 1 },
+                _ => ControlCommand::Resume,
+            };
+            info!(
+                "{} received: {:?}",
+                signal_name(signal).unwrap_or("Signal"),
+                command
+            );
+            if let Err(e) = controller.apply(command) {
+                warn!(
+                    "Failed to apply {}: {}",
+                    signal_name(signal).unwrap_or("signal"),
+                    e
+                );
+            }
         }
-        println!("Sent to {}: {}", gps_input_path, sentence.trim());
-        thread::sleep(Duration::from_secs(1));
-    }
+    });
 
     Ok(())
 }
+
+#[cfg(not(unix))]
+fn install_pause_handler(_controller: Controller, _step: bool) -> Result<(), SimError> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn install_signal_handler(
+    shutdown_event: Arc<AtomicBool>,
+    _stop_on_hangup: bool,
+) -> Result<(), SimError> {
+    // Ctrl+C just raises the flag; the loops report the shutdown themselves
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, shutdown_event.clone())
+            .map_err(|e| SimError::io("Failed to install the signal handlers", e))?;
+    }
+    Ok(())
+}
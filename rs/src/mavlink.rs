@@ -0,0 +1,383 @@
+// src/mavlink.rs
+
+use crate::control::{ControlCommand, Controller};
+use crate::nmea_generator::Fix;
+use crate::rtcm::{gps_time_of_week_ms, gps_week};
+use crate::state::SharedState;
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const STX_V1: u8 = 0xFE;
+const STX_V2: u8 = 0xFD;
+const MSG_HIL_GPS: u32 = 113;
+const MSG_GPS_INPUT: u32 = 232;
+const CRC_EXTRA_HIL_GPS: u8 = 124;
+const CRC_EXTRA_GPS_INPUT: u8 = 151;
+// Sent as the GPS component of vehicle 1
+const SYSTEM_ID: u8 = 1;
+const COMPONENT_ID: u8 = 220;
+
+// GPS_INPUT_IGNORE_FLAGS
+const IGNORE_HDOP: u16 = 0x02;
+const IGNORE_VEL_HORIZ: u16 = 0x08;
+
+const KNOTS_PER_METER_PER_SECOND: f64 = 3600.0 / 1852.0;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Which message carries the simulated fix to the autopilot: HIL_GPS for
+// PX4 HITL/SITL, GPS_INPUT for ArduPilot's MAVLink GPS driver
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GpsMessage {
+    HilGps,
+    #[default]
+    GpsInput,
+}
+
+impl GpsMessage {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "hil_gps" | "HIL_GPS" => Ok(GpsMessage::HilGps),
+            "gps_input" | "GPS_INPUT" => Ok(GpsMessage::GpsInput),
+            _ => Err(format!(
+                "Unknown MAVLink message '{}', expected hil_gps or gps_input",
+                value
+            )
+            .into()),
+        }
+    }
+}
+
+// MAVLink 2 frame; trailing zero bytes of the payload are left out
+pub fn frame(seq: u8, msgid: u32, crc_extra: u8, payload: &[u8]) -> Vec<u8> {
+    let len = payload.iter().rposition(|&b| b != 0).map_or(1, |i| i + 1);
+    let mut frame = Vec::with_capacity(len + 12);
+    frame.extend_from_slice(&[STX_V2, len as u8, 0, 0, seq, SYSTEM_ID, COMPONENT_ID]);
+    frame.extend_from_slice(&msgid.to_le_bytes()[..3]);
+    frame.extend_from_slice(&payload[..len]);
+    let crc = crc_x25(&frame[1..], crc_extra);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+// CRC-16/MCRF4XX over the frame after the start byte, then the message's
+// CRC_EXTRA seed byte
+fn crc_x25(data: &[u8], crc_extra: u8) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data.iter().chain(std::iter::once(&crc_extra)) {
+        let mut tmp = byte ^ (crc & 0xFF) as u8;
+        tmp ^= tmp << 4;
+        crc = (crc >> 8) ^ ((tmp as u16) << 8) ^ ((tmp as u16) << 3) ^ ((tmp as u16) >> 4);
+    }
+    crc
+}
+
+fn crc_extra(msgid: u32) -> Option<u8> {
+    match msgid {
+        MSG_HIL_GPS => Some(CRC_EXTRA_HIL_GPS),
+        MSG_GPS_INPUT => Some(CRC_EXTRA_GPS_INPUT),
+        _ => None,
+    }
+}
+
+// Splits a datagram into the frames we understand, as message ID and
+// payload. Unknown messages and frames failing their CRC are skipped.
+pub fn parse_frames(mut data: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut messages = Vec::new();
+    while let Some(start) = data.iter().position(|&b| b == STX_V1 || b == STX_V2) {
+        data = &data[start..];
+        // Signed MAVLink 2 frames carry 13 more bytes after the CRC
+        let (header_len, msgid, signature) = match data {
+            [STX_V2, _, incompat, _, _, _, _, a, b, c, ..] => (
+                10,
+                u32::from_le_bytes([*a, *b, *c, 0]),
+                if incompat & 0x01 != 0 { 13 } else { 0 },
+            ),
+            [STX_V1, _, _, _, _, id, ..] => (6, *id as u32, 0),
+            _ => break,
+        };
+        let len = data[1] as usize;
+        let frame_len = header_len + len + 2;
+        if data.len() < frame_len {
+            break;
+        }
+
+        let valid = crc_extra(msgid).is_some_and(|extra| {
+            let crc = u16::from_le_bytes([data[frame_len - 2], data[frame_len - 1]]);
+            crc_x25(&data[1..frame_len - 2], extra) == crc
+        });
+        if valid {
+            messages.push((msgid, data[header_len..header_len + len].to_vec()));
+            data = &data[(frame_len + signature).min(data.len())..];
+        } else {
+            data = &data[1..];
+        }
+    }
+    messages
+}
+
+// Little-endian fields of a payload that may have been truncated
+struct Reader<'a> {
+    payload: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes = [0u8; N];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.payload.get(self.offset + i).copied().unwrap_or(0);
+        }
+        self.offset += N;
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.bytes::<1>()[0]
+    }
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.bytes())
+    }
+    fn i16(&mut self) -> i16 {
+        i16::from_le_bytes(self.bytes())
+    }
+    fn i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.bytes())
+    }
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.bytes())
+    }
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.bytes())
+    }
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.bytes())
+    }
+}
+
+// The truth a HIL_GPS or GPS_INPUT message describes
+pub fn decode_truth(msgid: u32, payload: &[u8]) -> Option<ControlCommand> {
+    let mut r = Reader { payload, offset: 0 };
+    let _time_usec = r.u64();
+    let (latitude, longitude, altitude, hdop, vn, ve, fix_type, satellites) = match msgid {
+        MSG_HIL_GPS => {
+            let lat = r.i32();
+            let lon = r.i32();
+            let alt = r.i32();
+            let eph = r.u16();
+            let _epv = r.u16();
+            let _vel = r.u16();
+            let vn = r.i16();
+            let ve = r.i16();
+            let _vd = r.i16();
+            let _cog = r.u16();
+            let fix_type = r.u8();
+            let satellites = r.u8();
+            (
+                lat,
+                lon,
+                alt as f64 / 1000.0,
+                (eph != u16::MAX).then_some(eph as f64 / 100.0),
+                vn as f64 / 100.0,
+                ve as f64 / 100.0,
+                fix_type,
+                satellites,
+            )
+        }
+        MSG_GPS_INPUT => {
+            let _time_week_ms = r.u32();
+            let lat = r.i32();
+            let lon = r.i32();
+            let alt = r.f32();
+            let hdop = r.f32();
+            let _vdop = r.f32();
+            let vn = r.f32();
+            let ve = r.f32();
+            let _vd = r.f32();
+            let _accuracies = (r.f32(), r.f32(), r.f32());
+            let ignore_flags = r.u16();
+            let _time_week = r.u16();
+            let _gps_id = r.u8();
+            let fix_type = r.u8();
+            let satellites = r.u8();
+            let velocity = ignore_flags & IGNORE_VEL_HORIZ == 0;
+            (
+                lat,
+                lon,
+                alt as f64,
+                (ignore_flags & IGNORE_HDOP == 0 && hdop > 0.0).then_some(hdop as f64),
+                if velocity { vn as f64 } else { 0.0 },
+                if velocity { ve as f64 } else { 0.0 },
+                fix_type,
+                satellites,
+            )
+        }
+        _ => return None,
+    };
+
+    let speed = (vn * vn + ve * ve).sqrt();
+    Some(ControlCommand::SetTruth {
+        latitude: latitude as f64 / 1e7,
+        longitude: longitude as f64 / 1e7,
+        altitude,
+        speed_knots: speed * KNOTS_PER_METER_PER_SECOND,
+        course: ve.atan2(vn).to_degrees().rem_euclid(360.0),
+        fix_quality: Some(fix_quality(fix_type)),
+        hdop,
+        satellites: (satellites != u8::MAX).then_some(satellites.min(12) as usize),
+    })
+}
+
+// GPS_FIX_TYPE to GGA fix quality and back
+fn fix_quality(fix_type: u8) -> u8 {
+    match fix_type {
+        0..=1 => 0,
+        4 => 2,
+        5 => 5,
+        6 => 4,
+        7 => 6,
+        _ => 1,
+    }
+}
+
+fn fix_type(fix_quality: u8) -> u8 {
+    match fix_quality {
+        0 => 1,
+        2 | 3 => 4,
+        4 => 6,
+        5 => 5,
+        6 => 7,
+        _ => 3,
+    }
+}
+
+pub fn encode(message: GpsMessage, seq: u8, fix: &Fix) -> Vec<u8> {
+    let speed = fix.speed_knots / KNOTS_PER_METER_PER_SECOND;
+    let course = fix.course.to_radians();
+    let (vn, ve) = (speed * course.cos(), speed * course.sin());
+    let time_usec = fix.time.timestamp_micros() as u64;
+
+    let mut p = Vec::with_capacity(64);
+    match message {
+        GpsMessage::HilGps => {
+            p.extend_from_slice(&time_usec.to_le_bytes());
+            p.extend_from_slice(&((fix.latitude * 1e7).round() as i32).to_le_bytes());
+            p.extend_from_slice(&((fix.longitude * 1e7).round() as i32).to_le_bytes());
+            p.extend_from_slice(&((fix.altitude * 1000.0).round() as i32).to_le_bytes());
+            p.extend_from_slice(&((fix.hdop * 100.0).round() as u16).to_le_bytes()); // eph
+            p.extend_from_slice(&u16::MAX.to_le_bytes()); // epv, unknown
+            p.extend_from_slice(&((speed * 100.0).round() as u16).to_le_bytes());
+            p.extend_from_slice(&((vn * 100.0).round() as i16).to_le_bytes());
+            p.extend_from_slice(&((ve * 100.0).round() as i16).to_le_bytes());
+            p.extend_from_slice(&0i16.to_le_bytes()); // vd
+            p.extend_from_slice(&((fix.course * 100.0).round() as u16).to_le_bytes());
+            p.push(fix_type(fix.fix_quality));
+            p.push(fix.satellites_used() as u8);
+            frame(seq, MSG_HIL_GPS, CRC_EXTRA_HIL_GPS, &p)
+        }
+        GpsMessage::GpsInput => {
+            p.extend_from_slice(&time_usec.to_le_bytes());
+            p.extend_from_slice(&(gps_time_of_week_ms(fix.time) as u32).to_le_bytes());
+            p.extend_from_slice(&((fix.latitude * 1e7).round() as i32).to_le_bytes());
+            p.extend_from_slice(&((fix.longitude * 1e7).round() as i32).to_le_bytes());
+            for value in [
+                fix.altitude,
+                fix.hdop,
+                fix.hdop * 1.5, // vdop
+                vn,
+                ve,
+                0.0,             // vd
+                0.2,             // Speed accuracy, m/s
+                fix.hdop * 2.5,  // Horizontal accuracy, m
+                fix.hdop * 3.75, // Vertical accuracy, m
+            ] {
+                p.extend_from_slice(&(value as f32).to_le_bytes());
+            }
+            p.extend_from_slice(&0u16.to_le_bytes()); // Nothing to ignore
+            p.extend_from_slice(&(gps_week(fix.time) as u16).to_le_bytes());
+            p.push(0); // gps_id
+            p.push(fix_type(fix.fix_quality));
+            p.push(fix.satellites_used() as u8);
+            frame(seq, MSG_GPS_INPUT, CRC_EXTRA_GPS_INPUT, &p)
+        }
+    }
+}
+
+// Takes the truth from HIL_GPS or GPS_INPUT messages sent by autopilot
+// SITL or a vehicle simulator
+pub fn spawn_mavlink_listener(
+    addr: &str,
+    controller: Controller,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let socket = UdpSocket::bind(addr).inspect_err(|e| error!("Failed to bind {}: {}", addr, e))?;
+    socket.set_read_timeout(Some(READ_TIMEOUT))?;
+    info!("Receiving MAVLink HIL_GPS/GPS_INPUT on udp:{}", addr);
+
+    Ok(thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        while !shutdown_event.load(Ordering::SeqCst) {
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    for (msgid, payload) in parse_frames(&buf[..len]) {
+                        let Some(command) = decode_truth(msgid, &payload) else {
+                            continue;
+                        };
+                        if let Err(e) = controller.apply(command) {
+                            warn!("Ignoring MAVLink message {} from {}: {}", msgid, from, e);
+                        }
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => {
+                    error!("Error receiving MAVLink: {}", e);
+                    thread::sleep(READ_TIMEOUT);
+                }
+            }
+        }
+    }))
+}
+
+// Feeds each fix of the first port to an autopilot as a GPS message
+pub fn spawn_mavlink_output(
+    addr: &str,
+    message: GpsMessage,
+    state: SharedState,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket
+        .connect(addr)
+        .inspect_err(|e| error!("Failed to resolve {}: {}", addr, e))?;
+    info!("Sending MAVLink {:?} to udp:{}", message, addr);
+    let addr = addr.to_string();
+
+    Ok(thread::spawn(move || {
+        let mut seq: u8 = 0;
+        let mut last_epoch = None;
+        while !shutdown_event.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+            let fix = state.lock().unwrap().truth.clone();
+            let Some(fix) = fix.filter(|fix| last_epoch != Some(fix.time)) else {
+                continue;
+            };
+            last_epoch = Some(fix.time);
+
+            // Nobody listening yet is not an error worth stopping for
+            if let Err(e) = socket.send(&encode(message, seq, &fix)) {
+                if e.kind() != ErrorKind::ConnectionRefused {
+                    error!("Error sending MAVLink to {}: {}", addr, e);
+                }
+            }
+            seq = seq.wrapping_add(1);
+        }
+    }))
+}
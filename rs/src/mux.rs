@@ -0,0 +1,283 @@
+// src/mux.rs
+
+use crate::ais::PositionReport;
+use crate::nmea_generator::Fix;
+use crate::position::Position;
+use crate::sentences::{Dbt, Dpt, Hdg, Hdt, Sentence, Vdm};
+use chrono::Timelike;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// An NMEA multiplexer in front of the port, as boats combine their
+// instruments into one stream: the receiver's epochs come in over a line of
+// their own, every instrument talks on its own clock, and the mux passes
+// whole sentences on in the order they finished coming in.
+
+// Input line of the receiver, NMEA 0183-HS with 10 bits to a character
+const INPUT_CHAR_TIME: Duration = Duration::from_nanos(10_000_000_000 / 38_400);
+// No two instrument clocks tick quite alike
+const CLOCK_JITTER: f64 = 0.02;
+// Vessels around whose reports the AIS receiver takes turns passing on
+const VESSELS: usize = 3;
+
+// An instrument on the mux and how often it talks, e.g. gyro=10
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instrument {
+    // Gyrocompass, HEHDT
+    Gyro(f64),
+    // Magnetic compass, HCHDG
+    Compass(f64),
+    // Echosounder, SDDPT and SDDBT
+    Depth(f64),
+    // AIS receiver, !AIVDM position reports of the vessels around
+    Ais(f64),
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instrument::Gyro(hz) => write!(f, "gyro={}", hz),
+            Instrument::Compass(hz) => write!(f, "compass={}", hz),
+            Instrument::Depth(hz) => write!(f, "depth={}", hz),
+            Instrument::Ais(hz) => write!(f, "ais={}", hz),
+        }
+    }
+}
+
+impl Instrument {
+    // "gyro", "compass", "depth" or "ais", each optionally with "=<hz>"
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let (kind, hz) = match spec.split_once('=') {
+            Some((kind, hz)) => {
+                let hz: f64 = hz
+                    .parse()
+                    .map_err(|_| format!("Invalid rate in instrument '{}'", spec))?;
+                if !(0.01..=50.0).contains(&hz) {
+                    return Err(format!("Invalid rate {} Hz, expected 0.01 to 50", hz).into());
+                }
+                (kind, Some(hz))
+            }
+            None => (spec, None),
+        };
+        match kind {
+            "gyro" => Ok(Instrument::Gyro(hz.unwrap_or(10.0))),
+            "compass" => Ok(Instrument::Compass(hz.unwrap_or(5.0))),
+            "depth" => Ok(Instrument::Depth(hz.unwrap_or(1.0))),
+            "ais" => Ok(Instrument::Ais(hz.unwrap_or(2.0))),
+            _ => Err(format!(
+                "Unknown instrument '{}', expected gyro, compass, depth or ais",
+                kind
+            )
+            .into()),
+        }
+    }
+
+    pub fn hz(&self) -> f64 {
+        match *self {
+            Instrument::Gyro(hz)
+            | Instrument::Compass(hz)
+            | Instrument::Depth(hz)
+            | Instrument::Ais(hz) => hz,
+        }
+    }
+}
+
+// A vessel steaming on a straight course from where it was first seen
+struct Vessel {
+    mmsi: u32,
+    origin: Position,
+    speed_knots: f64,
+    course: f64,
+}
+
+pub struct Multiplexer {
+    // Every instrument and when it talks next
+    instruments: Vec<(Instrument, Instant)>,
+    // Sentences in, by when they finished coming in
+    queue: Vec<(Instant, Vec<u8>)>,
+    // When the receiver's line is done with the epoch before
+    input_free: Instant,
+    // Latest truth, which the instruments measure
+    truth: Option<Fix>,
+    depth_m: f64,
+    vessels: Vec<Vessel>,
+    next_vessel: usize,
+    started: Instant,
+    rng: StdRng,
+}
+
+impl Multiplexer {
+    pub fn new(instruments: &[Instrument], seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let now = Instant::now();
+        // Instruments are switched on whenever, not in step
+        let instruments = instruments
+            .iter()
+            .map(|&instrument| {
+                let phase = rng.gen_range(0.0..1.0) / instrument.hz();
+                (instrument, now + Duration::from_secs_f64(phase))
+            })
+            .collect();
+        let depth_m = rng.gen_range(5.0..50.0);
+        Multiplexer {
+            instruments,
+            queue: Vec::new(),
+            input_free: now,
+            truth: None,
+            depth_m,
+            vessels: Vec::new(),
+            next_vessel: 0,
+            started: now,
+            rng,
+        }
+    }
+
+    // The truth the instruments report from now on
+    pub fn update(&mut self, truth: &Fix) {
+        self.truth = Some(truth.clone());
+    }
+
+    // Queues the receiver's epoch sentence by sentence, each when its last
+    // character came in over the input line. UBX frames come in whole.
+    pub fn receive(&mut self, epoch: &[u8], now: Instant) {
+        let mut arrived = self.input_free.max(now);
+        if epoch.windows(2).any(|sync| sync == [0xB5, 0x62]) {
+            arrived += INPUT_CHAR_TIME * epoch.len() as u32;
+            self.push(arrived, epoch.to_vec());
+        } else {
+            for sentence in epoch.split_inclusive(|&byte| byte == b'\n') {
+                arrived += INPUT_CHAR_TIME * sentence.len() as u32;
+                self.push(arrived, sentence.to_vec());
+            }
+        }
+        self.input_free = arrived;
+    }
+
+    // When something is next to go out: a sentence queued or an instrument
+    // talking
+    pub fn next_due(&self) -> Option<Instant> {
+        let talking = self.instruments.iter().map(|(_, next)| *next);
+        self.next_queued().into_iter().chain(talking).min()
+    }
+
+    // When the next sentence already in goes out
+    pub fn next_queued(&self) -> Option<Instant> {
+        self.queue.first().map(|(due, _)| *due)
+    }
+
+    // Lets the instruments whose time has come talk, and takes what is due
+    pub fn take_due(&mut self, now: Instant) -> Vec<u8> {
+        for i in 0..self.instruments.len() {
+            let (instrument, next) = self.instruments[i];
+            if next > now {
+                continue;
+            }
+            let jitter = 1.0 + self.rng.gen_range(-CLOCK_JITTER..=CLOCK_JITTER);
+            let period = Duration::from_secs_f64(jitter / instrument.hz());
+            // Ticks missed while the writer was held up are skipped
+            self.instruments[i].1 = (next + period).max(now + period / 2);
+            // Nothing to measure before the first epoch
+            if let Some(sentences) = self.measure(instrument, next) {
+                self.push(next, sentences.into_bytes());
+            }
+        }
+        let due = self.queue.partition_point(|(due, _)| *due <= now);
+        self.queue
+            .drain(..due)
+            .flat_map(|(_, sentence)| sentence)
+            .collect()
+    }
+
+    // After those due at the same time or earlier
+    fn push(&mut self, due: Instant, sentence: Vec<u8>) {
+        let at = self.queue.partition_point(|(queued, _)| *queued <= due);
+        self.queue.insert(at, (due, sentence));
+    }
+
+    fn measure(&mut self, instrument: Instrument, at: Instant) -> Option<String> {
+        let truth = self.truth.as_ref()?;
+        let sentences = match instrument {
+            Instrument::Gyro(_) => Hdt {
+                talker: "HE".to_string(),
+                heading: Some(truth.heading),
+            }
+            .to_nmea(),
+            Instrument::Compass(_) => Hdg {
+                talker: "HC".to_string(),
+                heading: truth.heading,
+                deviation: None,
+                variation: None,
+            }
+            .to_nmea(),
+            Instrument::Depth(_) => {
+                // The bottom wanders a little between soundings
+                self.depth_m = (self.depth_m + self.rng.gen_range(-0.2..=0.2)).max(1.0);
+                let depth_m = self.depth_m;
+                let dpt = Dpt {
+                    talker: "SD".to_string(),
+                    depth_m,
+                    offset_m: 0.5,
+                };
+                let dbt = Dbt {
+                    talker: "SD".to_string(),
+                    depth_m,
+                };
+                format!("{}{}", dpt.to_nmea(), dbt.to_nmea())
+            }
+            Instrument::Ais(_) => {
+                let (report, channel) = self.next_report(at)?;
+                let (payload, fill_bits) = report.encode();
+                Vdm {
+                    talker: "AI".to_string(),
+                    channel,
+                    payload,
+                    fill_bits,
+                }
+                .to_nmea()
+            }
+        };
+        Some(sentences)
+    }
+
+    // The next vessel's report, on alternating channels. The vessels show
+    // up within a few kilometres of the receiver's first fix.
+    fn next_report(&mut self, at: Instant) -> Option<(PositionReport, char)> {
+        let truth = self.truth.as_ref()?;
+        if self.vessels.is_empty() {
+            let home = truth.position();
+            for _ in 0..VESSELS {
+                let bearing = self.rng.gen_range(0.0..360.0_f64).to_radians();
+                let distance = self.rng.gen_range(500.0..3000.0);
+                self.vessels.push(Vessel {
+                    mmsi: self.rng.gen_range(200_000_000..775_000_000),
+                    origin: home.offset(distance * bearing.cos(), distance * bearing.sin()),
+                    speed_knots: self.rng.gen_range(0.0..15.0_f64).round(),
+                    course: self.rng.gen_range(0.0..360.0_f64).round(),
+                });
+            }
+        }
+        let vessel = &self.vessels[self.next_vessel % VESSELS];
+        self.next_vessel += 1;
+        let travelled = vessel.speed_knots * 1852.0 / 3600.0
+            * at.saturating_duration_since(self.started).as_secs_f64();
+        let course = vessel.course.to_radians();
+        let report = PositionReport {
+            mmsi: vessel.mmsi,
+            position: vessel
+                .origin
+                .offset(travelled * course.cos(), travelled * course.sin()),
+            speed_knots: vessel.speed_knots,
+            course: vessel.course,
+            heading: Some(vessel.course),
+            second: truth.time.second(),
+        };
+        let channel = if self.next_vessel.is_multiple_of(2) {
+            'B'
+        } else {
+            'A'
+        };
+        Some((report, channel))
+    }
+}
@@ -0,0 +1,164 @@
+// src/named_pipe.rs
+
+use crate::output::OutputSink;
+use std::error::Error;
+use std::io;
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use windows_sys::Win32::Foundation::{
+    CloseHandle, GetLastError, ERROR_BROKEN_PIPE, ERROR_NO_DATA, ERROR_PIPE_CONNECTED,
+    ERROR_PIPE_LISTENING, HANDLE, INVALID_HANDLE_VALUE,
+};
+use windows_sys::Win32::Storage::FileSystem::{WriteFile, PIPE_ACCESS_OUTBOUND};
+use windows_sys::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_NOWAIT, PIPE_READMODE_BYTE,
+    PIPE_TYPE_BYTE,
+};
+
+const PIPE_PREFIX: &str = r"\\.\pipe\";
+const PIPE_BUFFER_SIZE: u32 = 64 * 1024;
+// How long a write may wait for a slow reader before the data is dropped
+const WRITE_RETRIES: u32 = 100;
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+// Windows counterpart of the FIFO output: serves a named pipe such as
+// \\.\pipe\nmea_sim to one reader at a time. Data is discarded while no
+// reader is connected and the pipe is offered again after a disconnect.
+pub struct NamedPipeSink {
+    name: String,
+    handle: HANDLE,
+    connected: bool,
+}
+
+// The pipe handle is only ever used by the thread owning the sink
+unsafe impl Send for NamedPipeSink {}
+
+impl NamedPipeSink {
+    // Accepts a full pipe path or a bare name, e.g. "nmea_sim"
+    pub fn create(name: &str) -> Result<Self, Box<dyn Error>> {
+        let name = if name.starts_with(PIPE_PREFIX) {
+            name.to_string()
+        } else {
+            format!("{}{}", PIPE_PREFIX, name)
+        };
+        let wide_name: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+
+        info!("Creating named pipe: {}", name);
+        // PIPE_NOWAIT keeps connecting and writing from ever blocking the loop
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide_name.as_ptr(),
+                PIPE_ACCESS_OUTBOUND,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_NOWAIT,
+                1,
+                PIPE_BUFFER_SIZE,
+                PIPE_BUFFER_SIZE,
+                0,
+                ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let err = io::Error::last_os_error();
+            error!("Failed to create named pipe {}: {}", name, err);
+            return Err(Box::new(err));
+        }
+
+        Ok(NamedPipeSink {
+            name,
+            handle,
+            connected: false,
+        })
+    }
+
+    // Checks for a waiting client. Returns Ok(false) if there is none yet.
+    fn try_connect(&mut self) -> Result<bool, Box<dyn Error>> {
+        if unsafe { ConnectNamedPipe(self.handle, ptr::null_mut()) } != 0 {
+            self.connected = true;
+        } else {
+            match unsafe { GetLastError() } {
+                ERROR_PIPE_CONNECTED => self.connected = true,
+                ERROR_PIPE_LISTENING => return Ok(false),
+                // A client connected and already left again
+                ERROR_NO_DATA => {
+                    self.disconnect();
+                    return Ok(false);
+                }
+                code => return Err(Box::new(io::Error::from_raw_os_error(code as i32))),
+            }
+        }
+
+        info!("Named pipe reader connected: {}", self.name);
+        Ok(true)
+    }
+
+    fn disconnect(&mut self) {
+        unsafe {
+            DisconnectNamedPipe(self.handle);
+        }
+        if self.connected {
+            info!("Named pipe reader disconnected: {}", self.name);
+        }
+        self.connected = false;
+    }
+}
+
+impl OutputSink for NamedPipeSink {
+    fn name(&self) -> String {
+        format!("pipe:{}", self.name)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if !self.connected && !self.try_connect()? {
+            return Ok(());
+        }
+
+        let mut remaining = data;
+        let mut retries = 0;
+        while !remaining.is_empty() {
+            let mut written: u32 = 0;
+            let ok = unsafe {
+                WriteFile(
+                    self.handle,
+                    remaining.as_ptr(),
+                    remaining.len() as u32,
+                    &mut written,
+                    ptr::null_mut(),
+                )
+            };
+
+            if ok == 0 {
+                match unsafe { GetLastError() } {
+                    ERROR_NO_DATA | ERROR_BROKEN_PIPE => {
+                        self.disconnect();
+                        return Ok(());
+                    }
+                    code => return Err(Box::new(io::Error::from_raw_os_error(code as i32))),
+                }
+            }
+
+            remaining = &remaining[written as usize..];
+            if written == 0 {
+                // The reader's buffer is full; give it a moment
+                retries += 1;
+                if retries > WRITE_RETRIES {
+                    warn!("Named pipe reader on {} is not keeping up", self.name);
+                    return Ok(());
+                }
+                thread::sleep(WRITE_RETRY_DELAY);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for NamedPipeSink {
+    fn drop(&mut self) {
+        unsafe {
+            DisconnectNamedPipe(self.handle);
+            CloseHandle(self.handle);
+        }
+    }
+}
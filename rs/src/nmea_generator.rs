@@ -1,17 +1,75 @@
-use chrono::Utc;
+use crate::checksum::wrap_sentence;
+use crate::geoid;
+use crate::position::Position;
+use crate::sentences::{
+    check_length, Accuracy, Gga, Gll, Gsa, Gst, Gsv, GsvSatellite, Hdg, Hdt, Rmc, Sentence,
+    SentenceBuffer, Txt, Vhw, Vtg, GSA_SLOTS, MAX_SENTENCE_LENGTH, MINUTE_DECIMALS,
+};
+use crate::template::{SentenceTemplate, TemplateValues};
+use chrono::{DateTime, TimeDelta, Utc};
 use rand::{
-    distributions::{Distribution, Uniform},
-    rngs::ThreadRng,
-    thread_rng,
+    distributions::{Distribution, Uniform, WeightedIndex},
+    rngs::StdRng,
+    SeedableRng,
 };
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+// Corrections of a simulated differential fix, as from a base sending once
+// a second
+const SIMULATED_CORRECTION_AGE: f64 = 1.0;
+const SIMULATED_STATION_ID: u16 = 0;
+
+// Corrections of a simulated DGPS network arrive this often, so their age
+// runs up to it and drops back to 0
+const NETWORK_CORRECTION_INTERVAL: f64 = 10.0;
+const NETWORK_HANDOVER: Duration = Duration::from_secs(60);
+
+// VDOP as a multiple of the HDOP
+const VDOP_RATIO: (f64, f64) = (1.3, 1.9);
+
+// GST accuracy scales with the HDOP, but no better than at this one
+const MIN_GST_HDOP: f64 = 0.8;
+
+pub const KMH_PER_KNOT: f64 = 1.852;
+
+// Talkers of HDG, that of a magnetic compass, and of VHW, a speed log
+const COMPASS_TALKER: &str = "HC";
+const SPEED_LOG_TALKER: &str = "VW";
+
+// Galileo OSNMA status, as a notice in a TXT sentence of the epoch. No
+// receiver reports it in NMEA yet, so this is our own text, e.g.
+//   $GATXT,01,01,02,OSNMA=AUTHENTICATED*hh
+pub const OSNMA_AUTHENTICATED: &str = "OSNMA=AUTHENTICATED";
+pub const OSNMA_UNAUTHENTICATED: &str = "OSNMA=UNAUTHENTICATED";
 
 pub struct RandomGenerator {
-    rng: ThreadRng,
+    rng: StdRng,
+}
+
+impl Default for RandomGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RandomGenerator {
     pub fn new() -> Self {
-        RandomGenerator { rng: thread_rng() }
+        RandomGenerator {
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    // The same seed gives the same sequence on every platform and run
+    pub fn from_seed(seed: u64) -> Self {
+        RandomGenerator {
+            rng: StdRng::seed_from_u64(seed),
+        }
     }
 
     pub fn random_uniform(&mut self, min: f64, max: f64) -> f64 {
@@ -25,56 +83,406 @@ impl RandomGenerator {
     }
 }
 
-#[derive(Debug, Clone)]
-struct Satellite {
-    constellation: Constellation,
-    id: u16,
+#[derive(Debug, Clone, Serialize)]
+pub struct Satellite {
+    pub constellation: Constellation,
+    pub id: u16,
+    // Degrees above the horizon and clockwise from true north
+    pub elevation: u8,
+    pub azimuth: u16,
+    // C/N0 in dB-Hz; None while the signal is too weak to track
+    pub snr: Option<u8>,
+    // Part of the solution, as GSA lists and GGA counts it
+    pub used: bool,
 }
 
 impl Satellite {
+    // A satellite the fix uses, wherever it is in the sky
     pub fn new(constell: Constellation, id: u16) -> Self {
         Satellite {
             constellation: constell,
             id,
+            elevation: 0,
+            azimuth: 0,
+            snr: None,
+            used: true,
         }
     }
 
-    pub fn new_random() -> Self {
-        let mut rg = RandomGenerator::new();
-        let constell = Constellation::get_random(&mut rg);
-        let id = match constell {
-            Constellation::GPS => rg.random_int(1, 32),
-            Constellation::GLONASS => rg.random_int(65, 96),
-            Constellation::GALILEO => rg.random_int(1, 36),
-            Constellation::BEIDOU => rg.random_int(101, 136),
-            Constellation::QZSS => rg.random_int(183, 202),
-        } as u16;
+    // Random satellite of the systems a receiver tracks by default
+    pub fn new_random(rg: &mut RandomGenerator) -> Self {
+        let constell = Constellation::get_random(&Constellation::DEFAULT, rg);
+        Satellite::new_random_of(constell, rg)
+    }
+
+    // Random satellite of the given constellation, somewhere above the
+    // elevation mask. Satellites are spread evenly over the sky, so there
+    // are more of them low than high.
+    pub fn new_random_of(constell: Constellation, rg: &mut RandomGenerator) -> Self {
+        let prns = constell.prns();
+        let id = rg.random_int(*prns.start() as i32, *prns.end() as i32) as u16;
+        let elevation = random_elevation(rg);
         Satellite {
-            constellation: constell,
-            id,
+            elevation: elevation.round() as u8,
+            azimuth: rg.random_int(0, 359) as u16,
+            ..Satellite::new(constell, id)
         }
     }
 }
 
+// Satellites lower than this are not tracked, as in most receivers
+const ELEVATION_MASK: f64 = 5.0;
+
+// Elevation of a satellite somewhere in the sky above the mask
+fn random_elevation(rg: &mut RandomGenerator) -> f64 {
+    rg.random_uniform(ELEVATION_MASK.to_radians().sin(), 1.0)
+        .asin()
+        .to_degrees()
+}
+
+// How fast satellites in medium Earth orbit cross the sky as seen from the
+// ground, in degrees per second; a pass takes hours
+const SKY_RATE: (f64, f64) = (0.002, 0.008);
+// Seconds a newly tracked satellite takes until the fix uses it, to lock on
+// and decode its ephemeris, and to lock on again after losing the signal
+const ACQUISITION_TIME: (f64, f64) = (6.0, 30.0);
+const REACQUISITION_TIME: f64 = 2.0;
+
+// A satellite in view as it moves across the sky. Elevation and azimuth
+// are kept unrounded, so that slow motion adds up.
 #[derive(Debug, Clone)]
+struct SkySatellite {
+    constellation: Constellation,
+    id: u16,
+    elevation: f64,
+    azimuth: f64,
+    // Degrees per second; the elevation rate is negative while setting
+    elevation_rate: f64,
+    azimuth_rate: f64,
+    // Highest elevation of the pass, where a rising satellite starts to set
+    culmination: f64,
+    // Seconds of tracking still needed before the fix uses it
+    acquiring: f64,
+}
+
+impl SkySatellite {
+    // Somewhere in the sky, rising or setting. SBAS satellites are
+    // geostationary and stay where they are.
+    fn new(constellation: Constellation, id: u16, rg: &mut RandomGenerator) -> Self {
+        let elevation = random_elevation(rg);
+        let (elevation_rate, azimuth_rate) = match constellation {
+            Constellation::SBAS => (0.0, 0.0),
+            _ => (
+                rg.random_uniform(SKY_RATE.0, SKY_RATE.1),
+                rg.random_uniform(-SKY_RATE.1, SKY_RATE.1),
+            ),
+        };
+        let culmination = rg.random_uniform(elevation, 90.0);
+        let setting = rg.random_int(0, 1) == 0;
+        SkySatellite {
+            constellation,
+            id,
+            elevation,
+            azimuth: rg.random_uniform(0.0, 360.0),
+            elevation_rate: if setting {
+                -elevation_rate
+            } else {
+                elevation_rate
+            },
+            azimuth_rate,
+            culmination,
+            acquiring: 0.0,
+        }
+    }
+
+    // Just above the mask, on its way up
+    fn rising(constellation: Constellation, id: u16, rg: &mut RandomGenerator) -> Self {
+        let mut satellite = SkySatellite::new(constellation, id, rg);
+        satellite.elevation = ELEVATION_MASK;
+        satellite.elevation_rate = satellite.elevation_rate.abs();
+        satellite.culmination = rg.random_uniform(ELEVATION_MASK + 10.0, 90.0);
+        satellite
+    }
+
+    fn advance(&mut self, seconds: f64) {
+        self.elevation = (self.elevation + self.elevation_rate * seconds).min(90.0);
+        if self.elevation_rate > 0.0 && self.elevation >= self.culmination {
+            self.elevation_rate = -self.elevation_rate;
+        }
+        self.azimuth = (self.azimuth + self.azimuth_rate * seconds).rem_euclid(360.0);
+    }
+}
+
+// Weakest signal a receiver keeps tracking, in dB-Hz
+pub const TRACKING_THRESHOLD: f64 = 20.0;
+
+// Surroundings of the antenna, which attenuate the signals and block low
+// satellites. Switching it mid-run shows how the device under test copes
+// with losing and reacquiring satellites.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Environment {
+    #[default]
+    OpenSky,
+    Suburban,
+    Urban,
+    Indoor,
+}
+
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Environment::OpenSky => "open-sky",
+            Environment::Suburban => "suburban",
+            Environment::Urban => "urban",
+            Environment::Indoor => "indoor",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Environment {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "open-sky" => Ok(Environment::OpenSky),
+            "suburban" => Ok(Environment::Suburban),
+            "urban" => Ok(Environment::Urban),
+            "indoor" => Ok(Environment::Indoor),
+            _ => Err(format!(
+                "Unknown environment '{}', expected open-sky, suburban, urban or indoor",
+                value
+            )
+            .into()),
+        }
+    }
+
+    // C/N0 of a satellite at this elevation, or None when it is too weak to
+    // track. Under open sky it rises from about 31 dB-Hz at the mask to 48
+    // at the zenith with the antenna gain; trees and buildings take off a
+    // few dB and block low satellites now and then, walls take off most.
+    pub fn cn0(&self, elevation: u8, rg: &mut RandomGenerator) -> Option<u8> {
+        let elevation = elevation as f64;
+        let open_sky = 30.0 + 18.0 * elevation.to_radians().sin();
+        let blocked = rg.random_uniform(0.0, 1.0);
+        let loss = match self {
+            Environment::OpenSky => 0.0,
+            Environment::Suburban if elevation < 20.0 && blocked < 0.3 => 12.0,
+            Environment::Suburban => 3.0,
+            Environment::Urban if elevation < 40.0 && blocked < 0.6 => 20.0,
+            Environment::Urban => 8.0,
+            Environment::Indoor => 18.0,
+        };
+        let cn0 = open_sky - loss + rg.random_uniform(-2.0, 2.0);
+        (cn0 >= TRACKING_THRESHOLD).then(|| cn0.round() as u8)
+    }
+}
+
+// How far the heading is off the course over the ground, as wind or a
+// current pushes a vehicle sideways and it points a little upwind to make
+// good its course, e.g. {"model": "current", "knots": 2.0, "set": 90.0}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "model", rename_all = "kebab-case")]
+pub enum Crab {
+    // Heading along the course
+    #[default]
+    None,
+    // A fixed angle, positive clockwise of the course
+    Offset {
+        degrees: f64,
+    },
+    // A current of this speed setting towards this direction, or the
+    // leeway of the wind; the heading is that through the water
+    Current {
+        knots: f64,
+        set: f64,
+    },
+}
+
+impl fmt::Display for Crab {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Crab::None => f.write_str("none"),
+            Crab::Offset { degrees } => write!(f, "offset:{}", degrees),
+            Crab::Current { knots, set } => write!(f, "current:{}@{}", knots, set),
+        }
+    }
+}
+
+impl Crab {
+    // "none", "offset:<degrees>" or "current:<knots>@<set>"
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = || {
+            format!(
+                "Invalid crab '{}', expected none, offset:<degrees> or current:<knots>@<set>",
+                value
+            )
+        };
+        let number = |value: &str| value.parse::<f64>().ok().filter(|v| v.is_finite());
+        let crab = match value.split_once(':') {
+            None if value == "none" => Some(Crab::None),
+            Some(("offset", degrees)) => number(degrees).map(|degrees| Crab::Offset { degrees }),
+            Some(("current", current)) => current.split_once('@').and_then(|(knots, set)| {
+                Some(Crab::Current {
+                    knots: number(knots).filter(|knots| *knots >= 0.0)?,
+                    set: number(set)?.rem_euclid(360.0),
+                })
+            }),
+            _ => None,
+        };
+        crab.ok_or_else(|| invalid().into())
+    }
+
+    // Heading and speed through the water of a vehicle making good this
+    // course and speed over the ground
+    pub fn through_water(&self, speed_knots: f64, course: f64) -> (f64, f64) {
+        let (heading, speed) = match *self {
+            Crab::None => (course, speed_knots),
+            Crab::Offset { degrees } => (course + degrees, speed_knots),
+            Crab::Current { knots, set } => {
+                // Velocity over the ground less the current's; heading
+                // along the course when drifting with it
+                let (radians, set) = (course.to_radians(), set.to_radians());
+                let north = speed_knots * radians.cos() - knots * set.cos();
+                let east = speed_knots * radians.sin() - knots * set.sin();
+                let speed = north.hypot(east);
+                if speed < 1e-6 {
+                    (course, 0.0)
+                } else {
+                    (east.atan2(north).to_degrees(), speed)
+                }
+            }
+        };
+        (heading.rem_euclid(360.0), speed)
+    }
+}
+
+// Reference stations of a DGPS network the receiver hands over between,
+// each for a while in turn, e.g. "101,102,103@120"
+#[derive(Debug, Clone, PartialEq)]
+pub struct DgpsNetwork {
+    pub stations: Vec<u16>,
+    pub handover: Duration,
+}
+
+impl fmt::Display for DgpsNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stations: Vec<String> = self.stations.iter().map(u16::to_string).collect();
+        write!(f, "{}@{}", stations.join(","), self.handover.as_secs_f64())
+    }
+}
+
+impl DgpsNetwork {
+    // Station IDs of 0 to 1023, and the seconds until the next one takes
+    // over, 1 to a day and a minute by default
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = || {
+            format!(
+                "Invalid DGPS network '{}', expected station IDs of 0 to 1023 and a handover of \
+                 1 to 86400 s, e.g. 101,102@120",
+                value
+            )
+        };
+        let (stations, handover) = match value.split_once('@') {
+            Some((stations, handover)) => {
+                let handover = handover
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|secs| (1.0..=86400.0).contains(secs))
+                    .ok_or_else(invalid)?;
+                (stations, Duration::from_secs_f64(handover))
+            }
+            None => (value, NETWORK_HANDOVER),
+        };
+        let stations = stations
+            .split(',')
+            .map(|id| id.trim().parse::<u16>().ok().filter(|id| *id <= 1023))
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(invalid)?;
+        Ok(DgpsNetwork { stations, handover })
+    }
+
+    // The station whose turn it is at this time
+    pub fn station(&self, time: &DateTime<Utc>) -> u16 {
+        let turn = seconds(time) / self.handover.as_secs_f64();
+        self.stations[turn as usize % self.stations.len()]
+    }
+
+    // Seconds since the latest correction, rising to the interval they
+    // come in and dropping back to 0 as the next one arrives
+    pub fn correction_age(&self, time: &DateTime<Utc>) -> f64 {
+        seconds(time).rem_euclid(NETWORK_CORRECTION_INTERVAL)
+    }
+}
+
+fn seconds(time: &DateTime<Utc>) -> f64 {
+    time.timestamp() as f64 + time.timestamp_subsec_nanos() as f64 / 1e9
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Constellation {
     GPS,
     GLONASS,
     GALILEO,
     BEIDOU,
     QZSS,
+    NAVIC,
+    // Geostationary satellites of WAAS, EGNOS, MSAS, GAGAN and the like.
+    // They augment the other systems rather than being one to draw
+    // satellites from, so they are in neither ALL nor DEFAULT and come
+    // with NmeaGenerator::sbas instead.
+    #[serde(skip_deserializing)]
+    SBAS,
+}
+
+impl fmt::Display for Constellation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Constellation::GPS => "GPS",
+            Constellation::GLONASS => "GLONASS",
+            Constellation::GALILEO => "GALILEO",
+            Constellation::BEIDOU => "BEIDOU",
+            Constellation::QZSS => "QZSS",
+            Constellation::NAVIC => "NAVIC",
+            Constellation::SBAS => "SBAS",
+        };
+        f.write_str(name)
+    }
 }
 
 impl Constellation {
-    pub fn to_string(&self) -> String {
+    // PRNs the satellites of the system have in NMEA
+    pub fn prns(&self) -> std::ops::RangeInclusive<u16> {
         match self {
-            Constellation::GPS => "GPS".to_string(),
-            Constellation::GLONASS => "GLONASS".to_string(),
-            Constellation::GALILEO => "GALILEO".to_string(),
-            Constellation::BEIDOU => "BEIDOU".to_string(),
-            Constellation::QZSS => "QZSS".to_string(),
+            Constellation::GPS => 1..=32,
+            Constellation::GLONASS => 65..=96,
+            Constellation::GALILEO => 1..=36,
+            Constellation::BEIDOU => 101..=136,
+            Constellation::QZSS => 183..=202,
+            Constellation::NAVIC => 1..=14,
+            Constellation::SBAS => 120..=158,
         }
     }
+
+    pub const ALL: [Constellation; 6] = [
+        Constellation::GPS,
+        Constellation::GLONASS,
+        Constellation::GALILEO,
+        Constellation::BEIDOU,
+        Constellation::QZSS,
+        Constellation::NAVIC,
+    ];
+
+    // Systems satellites are drawn from unless others are enabled; NavIC
+    // only covers the region around India and few receivers track it
+    pub const DEFAULT: [Constellation; 5] = [
+        Constellation::GPS,
+        Constellation::GLONASS,
+        Constellation::GALILEO,
+        Constellation::BEIDOU,
+        Constellation::QZSS,
+    ];
+
     pub fn to_code(&self) -> String {
         match self {
             Constellation::GPS => "GP".to_string(),
@@ -82,260 +490,1064 @@ impl Constellation {
             Constellation::GALILEO => "GA".to_string(),
             Constellation::BEIDOU => "GB".to_string(),
             Constellation::QZSS => "GQ".to_string(),
+            Constellation::NAVIC => "GI".to_string(),
+            // Listed among the GPS satellites, as receivers do
+            Constellation::SBAS => "GP".to_string(),
         }
     }
 
-    pub fn len() -> usize {
-        5 // Number of constellations
+    // System ID of GSA and GSV since NMEA 4.10; SBAS satellites count as
+    // GPS ones
+    pub fn system_id(&self) -> u8 {
+        match self {
+            Constellation::GPS | Constellation::SBAS => 1,
+            Constellation::GLONASS => 2,
+            Constellation::GALILEO => 3,
+            Constellation::BEIDOU => 4,
+            Constellation::QZSS => 5,
+            Constellation::NAVIC => 6,
+        }
     }
 
-    pub fn get_random(rg: &mut RandomGenerator) -> Self {
-        let range = Uniform::from(0..Constellation::len());
-        let index = range.sample(&mut rg.rng);
+    // Signal ID of the one signal simulated: L1 C/A, Galileo's E1, BeiDou's
+    // B1I and NavIC's L5 SPS
+    pub fn signal_id(&self) -> u8 {
+        match self {
+            Constellation::GALILEO => 7,
+            _ => 1,
+        }
+    }
 
-        match index {
-            0 => Constellation::GPS,
-            1 => Constellation::GLONASS,
-            2 => Constellation::GALILEO,
-            3 => Constellation::BEIDOU,
-            4 => Constellation::QZSS,
-            _ => panic!("Invalid index"),
+    // Typical share of the satellites in view of an open-sky receiver; the
+    // regional systems have few satellites
+    pub fn weight(&self) -> u32 {
+        match self {
+            Constellation::GPS => 10,
+            Constellation::GLONASS => 8,
+            Constellation::GALILEO => 9,
+            Constellation::BEIDOU => 12,
+            Constellation::QZSS => 3,
+            Constellation::NAVIC => 3,
+            Constellation::SBAS => 2,
+        }
+    }
+
+    // One of the enabled systems, each as likely as its weight
+    pub fn get_random(enabled: &[Constellation], rg: &mut RandomGenerator) -> Self {
+        let weights = WeightedIndex::new(enabled.iter().map(Constellation::weight))
+            .expect("At least one constellation is needed");
+        enabled[weights.sample(&mut rg.rng)].clone()
+    }
+}
+
+// Talker ID of the position sentences GGA, RMC and GLL. Receivers send the
+// one of their only system, GN once they combine several, or some that of
+// the system most satellites belong to; old GPS-only designs always GP.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TalkerPolicy {
+    Gps,
+    #[default]
+    Combined,
+    Dominant,
+}
+
+impl TalkerPolicy {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "gp" => Ok(TalkerPolicy::Gps),
+            "gn" => Ok(TalkerPolicy::Combined),
+            "dominant" => Ok(TalkerPolicy::Dominant),
+            _ => Err(format!("Unknown talker '{}', expected gp, gn or dominant", value).into()),
+        }
+    }
+
+    // Talker ID of a fix from these satellites, GP without any; ties go to
+    // the system listed first
+    pub fn talker(&self, satellites: &[Satellite]) -> String {
+        let count = |constellation: &Constellation| {
+            satellites
+                .iter()
+                .filter(|sat| sat.used && &sat.constellation == constellation)
+                .count()
+        };
+        let systems = Constellation::ALL
+            .iter()
+            .filter(|constellation| count(constellation) > 0)
+            .count();
+        match self {
+            TalkerPolicy::Combined if systems > 1 => "GN".to_string(),
+            TalkerPolicy::Combined | TalkerPolicy::Dominant if systems > 0 => Constellation::ALL
+                .iter()
+                .rev()
+                .max_by_key(|constellation| count(constellation))
+                .map(Constellation::to_code)
+                .unwrap_or_default(),
+            _ => "GP".to_string(),
+        }
+    }
+}
+
+// How closely sentences follow the standard. Strict ones are as NMEA 0183
+// 4.11 has them: times to the hundredth of a second, mode indicators in
+// RMC, GLL and VTG, RMC's navigational status, system IDs in GSA, GSV per
+// system with its signal ID, and no sentence longer than
+// MAX_SENTENCE_LENGTH. Permissive ones are as older receivers send them,
+// quirks included: whole seconds, no GLL mode, GSV with every satellite
+// under GP, and no limit on the length.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EncodingMode {
+    Strict,
+    #[default]
+    Permissive,
+}
+
+impl EncodingMode {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "strict" => Ok(EncodingMode::Strict),
+            "permissive" => Ok(EncodingMode::Permissive),
+            _ => Err(format!(
+                "Unknown encoding '{}', expected strict or permissive",
+                value
+            )
+            .into()),
         }
     }
 }
 
-pub struct LocationData {
-    pub latitude: String,
-    pub ns: char,
-    pub longitude: String,
-    pub ew: char,
+// Everything the receiver "knows" in one epoch; all sentences of the epoch
+// are encoded from the same fix
+#[derive(Debug, Clone, Serialize)]
+pub struct Fix {
+    pub time: DateTime<Utc>,
+    pub latitude: f64,
+    pub longitude: f64,
+    // Above mean sea level; above the ellipsoid it is higher by the geoid
+    // height
+    pub altitude: f64,
+    pub geoid_height: f64,
+    pub speed_knots: f64,
+    // Over the ground, and where the vehicle points
+    pub course: f64,
+    pub heading: f64,
+    pub water_speed_knots: f64,
+    pub fix_quality: u8,
+    pub hdop: f64,
+    pub satellites: Vec<Satellite>,
+    // Whether the navigation data passed OSNMA authentication; left out
+    // when not simulated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticated: Option<bool>,
 }
 
+impl Fix {
+    pub fn position(&self) -> Position {
+        Position::new(self.latitude, self.longitude, self.altitude)
+    }
+
+    // Satellites in the solution, of all those in view
+    pub fn satellites_used(&self) -> usize {
+        self.satellites.iter().filter(|sat| sat.used).count()
+    }
+}
+
+// How often each sentence is sent, as every n-th epoch; 0 disables it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SentenceRates {
+    pub rmc: u32,
+    pub gga: u32,
+    pub gll: u32,
+    pub gsa: u32,
+    pub gsv: u32,
+    // Off unless asked for
+    pub vtg: u32,
+    pub vhw: u32,
+    pub hdt: u32,
+    pub hdg: u32,
+    pub gst: u32,
+}
+
+// Every formatter there is a rate for
+pub const FORMATTERS: [&str; 10] = [
+    "RMC", "GGA", "GLL", "GSA", "GSV", "VTG", "VHW", "HDT", "HDG", "GST",
+];
+
+impl Default for SentenceRates {
+    fn default() -> Self {
+        SentenceRates {
+            rmc: 1,
+            gga: 1,
+            gll: 1,
+            gsa: 1,
+            gsv: 1,
+            vtg: 0,
+            vhw: 0,
+            hdt: 0,
+            hdg: 0,
+            gst: 0,
+        }
+    }
+}
+
+impl SentenceRates {
+    pub fn get(&self, formatter: &str) -> Option<u32> {
+        let mut rates = *self;
+        rates.get_mut(formatter).map(|rate| *rate)
+    }
+
+    // Rate of a sentence by its formatter, e.g. "GGA"
+    pub fn get_mut(&mut self, formatter: &str) -> Option<&mut u32> {
+        match formatter {
+            "RMC" => Some(&mut self.rmc),
+            "GGA" => Some(&mut self.gga),
+            "GLL" => Some(&mut self.gll),
+            "GSA" => Some(&mut self.gsa),
+            "GSV" => Some(&mut self.gsv),
+            "VTG" => Some(&mut self.vtg),
+            "VHW" => Some(&mut self.vhw),
+            "HDT" => Some(&mut self.hdt),
+            "HDG" => Some(&mut self.hdg),
+            "GST" => Some(&mut self.gst),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct NmeaGenerator {
     rg: RandomGenerator,
+    // Reported GGA fix quality; picked at random each epoch when unset
+    pub fix_quality: Option<u8>,
+    // Latitude, longitude and altitude to report instead of random ones
+    pub position: Option<Position>,
+    pub speed_knots: Option<f64>,
+    pub course: Option<f64>,
+    // How the heading follows the course
+    pub crab: Crab,
+    pub satellites: Option<usize>,
+    pub hdop: Option<f64>,
+    // Constellations satellites are drawn from, by their weights;
+    // Constellation::DEFAULT when unset
+    pub constellations: Option<Vec<Constellation>>,
+    // What the signals pass through on their way to the antenna
+    pub environment: Environment,
+    // Time of every fix instead of the system clock
+    pub time: Option<DateTime<Utc>>,
+    // Reports no fix, as receivers do without one: status V, GGA quality 0
+    // and empty coordinates instead of stale ones
+    pub no_fix: bool,
+    // Seconds GGA reports as the age of the data; left empty when unset
+    pub data_age: Option<f64>,
+    // SBAS corrections are received: one or two of the satellites in view
+    // are SBAS ones, and the fix is differential unless its quality is set
+    pub sbas: bool,
+    // OSNMA status each fix reports; no TXT sentence for it when unset
+    pub authenticated: Option<bool>,
+    // Age of the corrections in seconds and their reference station, which
+    // GGA reports with a DGPS or RTK fix; simulated ones when unset
+    pub correction_age: Option<f64>,
+    pub station_id: Option<u16>,
+    // Reference stations taking turns without real corrections
+    pub dgps_network: Option<DgpsNetwork>,
+    // Digits of the coordinate minutes, up to MAX_MINUTE_DECIMALS
+    pub minute_decimals: usize,
+    pub talker_policy: TalkerPolicy,
+    pub encoding: EncodingMode,
+    pub sentence_rates: SentenceRates,
+    // Sentences of our own from the config file, after the others
+    pub templates: Arc<Vec<SentenceTemplate>>,
+    // How many sentences each template sent, by its text, so the count
+    // goes on when the config file is reloaded
+    template_sequences: HashMap<String, u64>,
+    // Numbers the next sentences of the templates skip, so consumers see
+    // a sentence go missing
+    sequence_gap: u64,
+    // Number of epochs encoded so far, to apply the sentence rates
+    epoch: u64,
+    // Satellites in view, kept from fix to fix as they rise, get acquired
+    // and set; how many there are without a pinned count, and how many of
+    // them are SBAS ones
+    sky: Vec<SkySatellite>,
+    in_view: Option<usize>,
+    sbas_in_view: Option<usize>,
+    // Time of the previous fix, to move the satellites on from
+    last_time: Option<DateTime<Utc>>,
 }
 
 impl NmeaGenerator {
     pub fn new() -> Self {
         NmeaGenerator {
             rg: RandomGenerator::new(),
+            fix_quality: None,
+            position: None,
+            speed_knots: None,
+            course: None,
+            crab: Crab::default(),
+            satellites: None,
+            hdop: None,
+            constellations: None,
+            environment: Environment::default(),
+            time: None,
+            no_fix: false,
+            data_age: None,
+            sbas: false,
+            authenticated: None,
+            correction_age: None,
+            station_id: None,
+            dgps_network: None,
+            minute_decimals: MINUTE_DECIMALS,
+            talker_policy: TalkerPolicy::default(),
+            encoding: EncodingMode::default(),
+            sentence_rates: SentenceRates::default(),
+            templates: Arc::default(),
+            template_sequences: HashMap::new(),
+            sequence_gap: 0,
+            epoch: 0,
+            sky: Vec::new(),
+            in_view: None,
+            sbas_in_view: None,
+            last_time: None,
         }
     }
 
-    fn generate_location(&mut self) -> LocationData {
-        let latitude = self.rg.random_uniform(-90.0, 90.0);
-        let ns = if latitude >= 0.0 { 'N' } else { 'S' };
-        let lat_deg = latitude.abs().floor();
-        let lat_min = (latitude.abs() - lat_deg) * 60.0;
+    // Draws every random value from the seed, for reproducible runs
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(RandomGenerator::from_seed(seed))
+    }
+
+    // Satellites, DOPs and unpinned values all come from this one generator
+    pub fn with_rng(rg: RandomGenerator) -> Self {
+        NmeaGenerator { rg, ..Self::new() }
+    }
 
-        let longitude = self.rg.random_uniform(-180.0, 180.0);
-        let ew = if longitude >= 0.0 { 'E' } else { 'W' };
-        let lon_deg = longitude.abs().floor();
-        let lon_min = (longitude.abs() - lon_deg) * 60.0;
+    pub fn generate_fix(&mut self) -> Fix {
+        let position = match self.position {
+            Some(position) => position,
+            None => Position::new(
+                self.rg.random_uniform(-90.0, 90.0),
+                self.rg.random_uniform(-180.0, 180.0),
+                self.rg.random_uniform(0.0, 1000.0),
+            ),
+        };
+        let time = self.time.unwrap_or_else(Utc::now);
+        // A pinned clock moves the sky on a second per fix
+        let seconds = match self.last_time {
+            Some(last) if time > last => (time - last).num_milliseconds() as f64 / 1000.0,
+            Some(_) => 1.0,
+            None => 0.0,
+        };
+        self.last_time = Some(time);
+        let satellites = self.generate_satellites(seconds);
+        let fix_quality = match self.fix_quality {
+            Some(fix_quality) => fix_quality,
+            None if self.sbas => 2,
+            None => self.rg.random_int(0, 5) as u8,
+        };
+        let speed_knots = match self.speed_knots {
+            Some(speed_knots) => speed_knots,
+            None => self.rg.random_uniform(0.0, 100.0),
+        };
+        let course = match self.course {
+            Some(course) => course,
+            None => self.rg.random_uniform(0.0, 360.0),
+        };
+        let (heading, water_speed_knots) = self.crab.through_water(speed_knots, course);
 
-        LocationData {
-            latitude: format!("{:02}{:07.4}", lat_deg as u32, lat_min),
-            ns,
-            longitude: format!("{:03}{:07.4}", lon_deg as u32, lon_min),
-            ew,
+        Fix {
+            time,
+            latitude: position.lat_deg,
+            longitude: position.lon_deg,
+            altitude: position.alt_m,
+            geoid_height: geoid::separation(position.lat_deg, position.lon_deg),
+            speed_knots,
+            course,
+            heading,
+            water_speed_knots,
+            fix_quality,
+            hdop: match self.hdop {
+                Some(hdop) => hdop,
+                None => self.rg.random_uniform(0.5, 10.0),
+            },
+            satellites,
+            authenticated: self.authenticated,
         }
     }
 
-    fn get_utc_time(&self) -> String {
-        let now = Utc::now();
-        now.format("%H%M%S").to_string()
+    fn reported_position(&self, fix: &Fix) -> Option<Position> {
+        (!self.no_fix).then(|| fix.position())
     }
 
-    fn get_utc_date(&self) -> String {
-        let now = Utc::now();
-        now.format("%d%m%y").to_string()
+    fn generate_gga(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        let fix_quality = if self.no_fix { 0 } else { fix.fix_quality };
+        // DGPS, RTK fixed and RTK float; the age of the data takes the
+        // place of the age of the corrections when reported
+        let differential = matches!(fix_quality, 2 | 4 | 5);
+        // Real corrections come from one station of their own
+        let network = self
+            .dgps_network
+            .as_ref()
+            .filter(|_| self.correction_age.is_none());
+        let gga = |minute_decimals| Gga {
+            talker: self.talker_policy.talker(&fix.satellites),
+            time: Some(fix.time),
+            time_decimals: self.time_decimals(),
+            position: self.reported_position(fix),
+            minute_decimals,
+            fix_quality,
+            satellites: fix.satellites_used(),
+            hdop: fix.hdop,
+            geoid_height: Some(fix.geoid_height),
+            age: self.data_age.or(differential.then(|| {
+                self.correction_age
+                    .or_else(|| network.map(|network| network.correction_age(&fix.time)))
+                    .unwrap_or(SIMULATED_CORRECTION_AGE)
+            })),
+            // With SBAS corrections the station is the satellite they come
+            // from, as receivers report it
+            station_id: differential.then(|| {
+                self.station_id
+                    .or_else(|| network.map(|network| network.station(&fix.time)))
+                    .or_else(|| sbas_station(&fix.satellites))
+                    .unwrap_or(SIMULATED_STATION_ID)
+            }),
+        };
+        self.encode_fitting(out, gga)
+    }
+
+    fn generate_rmc(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        let rmc = |minute_decimals| Rmc {
+            talker: self.talker_policy.talker(&fix.satellites),
+            time: Some(fix.time),
+            time_decimals: self.time_decimals(),
+            date: Some(fix.time.date_naive()),
+            valid: !self.no_fix,
+            position: self.reported_position(fix),
+            minute_decimals,
+            speed_knots: Some(fix.speed_knots),
+            course: Some(fix.course),
+            mode: Some(self.mode(fix)),
+            // Nothing simulated is unsafe to navigate by
+            nav_status: self.strict().then_some(if self.no_fix { 'V' } else { 'S' }),
+        };
+        self.encode_fitting(out, rmc)
     }
 
-    fn calculate_checksum(&self, sentence: &str) -> String {
-        let mut checksum: u8 = 0;
-        for c in sentence.as_bytes() {
-            checksum ^= c;
+    // Mode indicator of RMC, VTG and GLL
+    fn mode(&self, fix: &Fix) -> char {
+        match fix.fix_quality {
+            _ if self.no_fix => 'N',
+            2 => 'D',
+            4 => 'R',
+            5 => 'F',
+            6 => 'E',
+            _ => 'A',
         }
+    }
 
-        format!("{:02X}", checksum)
+    fn generate_vtg(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        Vtg {
+            talker: self.talker_policy.talker(&fix.satellites),
+            course: Some(fix.course),
+            speed_knots: Some(fix.speed_knots),
+            speed_kmh: Some(fix.speed_knots * KMH_PER_KNOT),
+            mode: Some(self.mode(fix)),
+        }
+        .encode(out)
     }
 
-    fn complete_sentence(&self, sentence: &str) -> String {
-        format!("${}*{}\r\n", sentence, self.calculate_checksum(sentence))
+    fn generate_vhw(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        Vhw {
+            talker: SPEED_LOG_TALKER.to_string(),
+            heading: Some(fix.heading),
+            speed_knots: fix.water_speed_knots,
+            speed_kmh: fix.water_speed_knots * KMH_PER_KNOT,
+        }
+        .encode(out)
     }
 
-    fn generate_gga(&mut self, loc: &LocationData, num_satellites: i32) -> String {
-        let utc_time = self.get_utc_time();
-        let fix_quality = self.rg.random_int(0, 5);
-        let altitude = self.rg.random_uniform(0.0, 1000.0);
-        let hdop = self.rg.random_uniform(0.5, 10.0);
-        let geoid_height = self.rg.random_uniform(-100.0, 100.0);
+    // Heading of a dual antenna receiver, which loses it with the fix
+    fn generate_hdt(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        Hdt {
+            talker: self.talker_policy.talker(&fix.satellites),
+            heading: (!self.no_fix).then_some(fix.heading),
+        }
+        .encode(out)
+    }
 
-        let sentence = format!(
-            "GPGGA,{},{},{},{},{},{},{},{:.1},{:.1},M,{:.1},M,,",
-            utc_time,
-            loc.latitude,
-            loc.ns,
-            loc.longitude,
-            loc.ew,
-            fix_quality,
-            num_satellites,
-            hdop,
-            altitude,
-            geoid_height
-        );
-
-        self.complete_sentence(&sentence)
-    }
-
-    fn generate_rmc(&mut self, loc: &LocationData) -> String {
-        let utc_time = self.get_utc_time();
-        let status = 'A';
-        let latitude = format!("{}{}", loc.latitude, loc.ns);
-        let longitude = format!("{}{}", loc.longitude, loc.ew);
-        let speed = self.rg.random_uniform(0.0, 100.0);
-        let course = self.rg.random_uniform(0.0, 360.0);
-        let utc_date = self.get_utc_date();
-
-        let sentence = format!(
-            "GPRMC,{},{},{},{},{},{:.1},{:.1},{},{},,,",
-            utc_time, status, latitude, loc.ns, longitude, loc.ew, speed, course, utc_date
-        );
-
-        self.complete_sentence(&sentence)
-    }
-
-    fn generate_gll(&mut self, loc: &LocationData) -> String {
-        let latitude = format!("{}{}", loc.latitude, loc.ns);
-        let longitude = format!("{}{}", loc.longitude, loc.ew);
-        let utc_time = self.get_utc_time();
-        let status = 'A';
-
-        let sentence = format!(
-            "GPGLL,{},{},{},{},{},{}",
-            latitude, loc.ns, longitude, loc.ew, utc_time, status
-        );
-
-        self.complete_sentence(&sentence)
-    }
-
-    fn generate_gsa(&mut self, satellites: &Vec<Satellite>) -> String {
-        let mode = 'A';
-        let fix_type = 3;
-        let mut msgs = Vec::new();
-
-        let pdop = self.rg.random_uniform(0.5, 10.0);
-        let hdop = self.rg.random_uniform(0.5, 10.0);
-        let vdop = self.rg.random_uniform(0.5, 10.0);
-
-        // Separate the satellites by constellation
-        let mut sats_by_constell = vec![Vec::new(); Constellation::len()];
-        for sat in satellites {
-            let index = match sat.constellation {
-                Constellation::GPS => 0,
-                Constellation::GLONASS => 1,
-                Constellation::GALILEO => 2,
-                Constellation::BEIDOU => 3,
-                Constellation::QZSS => 4,
-            };
-            sats_by_constell[index].push(sat);
+    // Heading of a compass without deviation. Magnetic variation is not
+    // simulated, so it reads the true heading and leaves both empty.
+    fn generate_hdg(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        Hdg {
+            talker: COMPASS_TALKER.to_string(),
+            heading: fix.heading,
+            deviation: None,
+            variation: None,
         }
+        .encode(out)
+    }
 
-        for i in 0..sats_by_constell.len() {
-            let constellations = &sats_by_constell[i];
-            if constellations.is_empty() {
-                continue;
+    // Accuracy as the fix quality has it: centimetres with RTK fixed,
+    // decimetres with float, metres without corrections
+    fn generate_gst(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        let accuracy = (!self.no_fix).then(|| {
+            let sigma = fix_sigma(fix.fix_quality)
+                * fix.hdop.max(MIN_GST_HDOP)
+                * self.rg.random_uniform(0.8, 1.2);
+            let latitude = sigma * self.rg.random_uniform(0.6, 1.0);
+            let longitude = sigma * self.rg.random_uniform(0.6, 1.0);
+            // The ellipse lies along the larger of the two errors
+            Accuracy {
+                rms: sigma * self.rg.random_uniform(1.0, 1.5),
+                semi_major: latitude.max(longitude),
+                semi_minor: latitude.min(longitude),
+                orientation: if latitude >= longitude { 0.0 } else { 90.0 },
+                latitude,
+                longitude,
+                altitude: sigma * self.rg.random_uniform(1.5, 2.0),
             }
+        });
+        Gst {
+            talker: self.talker_policy.talker(&fix.satellites),
+            time: Some(fix.time),
+            time_decimals: self.time_decimals(),
+            accuracy,
+        }
+        .encode(out)
+    }
 
-            let constell = constellations[0].constellation.to_code();
-            let sats_str = constellations
-                .iter()
-                .map(|sat| sat.id.to_string())
-                .collect::<Vec<String>>()
-                .join(",");
-
-            // Pad the satellite IDs to have 12 fields
-            let mut sats_str_padded = sats_str;
-            for _ in constellations.len()..12 {
-                sats_str_padded.push(',');
+    fn generate_gll(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        let gll = |minute_decimals| Gll {
+            talker: self.talker_policy.talker(&fix.satellites),
+            position: self.reported_position(fix),
+            minute_decimals,
+            time: Some(fix.time),
+            time_decimals: self.time_decimals(),
+            valid: !self.no_fix,
+            mode: self.strict().then(|| self.mode(fix)),
+        };
+        self.encode_fitting(out, gll)
+    }
+
+    fn strict(&self) -> bool {
+        self.encoding == EncodingMode::Strict
+    }
+
+    fn time_decimals(&self) -> usize {
+        if self.strict() {
+            2
+        } else {
+            0
+        }
+    }
+
+    // Encodes a sentence with coordinates. Strict receivers give up digits
+    // of the minutes, down to one, rather than go over MAX_SENTENCE_LENGTH.
+    fn encode_fitting<S: Sentence>(&self, out: &mut SentenceBuffer, sentence: impl Fn(usize) -> S) {
+        let mut minute_decimals = self.minute_decimals;
+        while self.strict()
+            && minute_decimals > 1
+            && sentence(minute_decimals).to_nmea().len() > MAX_SENTENCE_LENGTH
+        {
+            minute_decimals -= 1;
+        }
+        sentence(minute_decimals).encode(out)
+    }
+
+    // Drops what strict encoding still could not fit after `start`
+    fn enforce_length(&self, out: &mut SentenceBuffer, start: usize) {
+        if !self.strict() {
+            return;
+        }
+        out.retain_sentences(start, |line| match check_length(line) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Dropping sentence: {}", e);
+                false
             }
+        });
+    }
 
-            let sentence = format!(
-                "{constell}GSA,{mode},{fix_type},{sats_str_padded},{pdop:.1},{hdop:.1},{vdop:.1}",
-                constell = constell,
-                mode = mode,
-                fix_type = fix_type,
-                sats_str_padded = sats_str_padded,
-                pdop = pdop,
-                hdop = hdop,
-                vdop = vdop
-            );
+    fn generate_template(
+        &mut self,
+        template: &SentenceTemplate,
+        fix: &Fix,
+        out: &mut SentenceBuffer,
+    ) {
+        let sequence = self
+            .template_sequences
+            .entry(template.text.clone())
+            .or_default();
+        let next = *sequence;
+        *sequence = next.wrapping_add(1);
+        let values = TemplateValues {
+            fix,
+            position: self.reported_position(fix),
+            fix_quality: if self.no_fix { 0 } else { fix.fix_quality },
+            time_decimals: self.time_decimals(),
+            minute_decimals: self.minute_decimals,
+            sequence: next,
+        };
+        let body = template.fill(&values, &mut self.rg);
+        // Strict encoding drops it with the others that are too long
+        if !self.strict() {
+            if let Err(e) = check_length(&wrap_sentence(&body)) {
+                warn!("Sending anyway: {}", e);
+            }
+        }
+        out.push_sentence(&body);
+    }
+
+    // The HDOP is that of GGA. Satellites are all above the antenna, so the
+    // vertical dilution is worse than the horizontal one, and PDOP is both
+    // together.
+    fn generate_gsa(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        let satellites = &fix.satellites;
+        let hdop = fix.hdop;
+        let vdop = hdop * self.rg.random_uniform(VDOP_RATIO.0, VDOP_RATIO.1);
+        let pdop = hdop.hypot(vdop);
+
+        // Separate the satellites used by constellation. Older designs that
+        // talk GP only list them all in one GSA instead, continued in the
+        // next once it fills up.
+        let combined = self.talker_policy == TalkerPolicy::Gps && !self.strict();
+        let sats_by_constell: Vec<Vec<&Satellite>> = if combined {
+            vec![satellites.iter().filter(|sat| sat.used).collect()]
+        } else {
+            Constellation::ALL
+                .iter()
+                .map(|constell| {
+                    satellites
+                        .iter()
+                        .filter(|sat| sat.used && &sat.constellation == constell)
+                        .collect()
+                })
+                .collect()
+        };
+
+        for sats in sats_by_constell.iter().filter(|sats| !sats.is_empty()) {
+            let gsa = Gsa {
+                talker: match combined {
+                    true => Constellation::GPS.to_code(),
+                    false => sats[0].constellation.to_code(),
+                },
+                mode: 'A',
+                fix_type: if self.no_fix { 1 } else { 3 },
+                satellite_ids: sats.iter().map(|sat| sat.id).collect(),
+                pdop,
+                hdop,
+                vdop,
+                system_id: self.strict().then(|| sats[0].constellation.system_id()),
+            };
+            // More than a GSA can list go on in the next one
+            for gsa in gsa.split() {
+                gsa.encode(out);
+            }
+        }
+    }
 
-            msgs.push(self.complete_sentence(&sentence));
+    // All satellites in view under GP, as older receivers list them, or
+    // strictly one set of messages per system with its own talker
+    fn generate_gsv(&mut self, satellites: &[Satellite], out: &mut SentenceBuffer) {
+        let in_view = |system: Option<&Constellation>| -> Vec<GsvSatellite> {
+            satellites
+                .iter()
+                .filter(|sat| {
+                    system.is_none_or(|system| sat.constellation.system_id() == system.system_id())
+                })
+                .map(|sat| GsvSatellite {
+                    id: sat.id,
+                    elevation: Some(sat.elevation),
+                    azimuth: Some(sat.azimuth),
+                    snr: sat.snr,
+                })
+                .collect()
+        };
+        if !self.strict() {
+            for message in Gsv::messages("GP", &in_view(None)) {
+                message.encode(out);
+            }
+            return;
+        }
+        for system in &Constellation::ALL {
+            for mut message in Gsv::messages(&system.to_code(), &in_view(Some(system))) {
+                message.signal_id = Some(system.signal_id());
+                message.encode(out);
+            }
         }
+    }
 
-        msgs.join("")
+    fn generate_txt(&mut self, authenticated: bool, out: &mut SentenceBuffer) {
+        Txt {
+            talker: Constellation::GALILEO.to_code(),
+            total_messages: 1,
+            message_number: 1,
+            text_id: 2,
+            text: if authenticated {
+                OSNMA_AUTHENTICATED
+            } else {
+                OSNMA_UNAUTHENTICATED
+            }
+            .to_string(),
+        }
+        .encode(out)
     }
 
-    fn generate_gsv(&mut self, satellites: &[Satellite]) -> String {
-        let num_msgs = (satellites.len() + 3) / 4; // Each GSV message can contain up to 4 satellites
-        let mut msgs = Vec::new();
+    // The satellites in view after moving them on by this many seconds.
+    // Those that set are replaced by new ones rising, which show up with a
+    // weak signal and only join the fix once acquired. Satellites of systems
+    // no longer enabled are dropped, and the lowest ones give way to a
+    // smaller pinned count.
+    fn generate_satellites(&mut self, seconds: f64) -> Vec<Satellite> {
+        let enabled = match &self.constellations {
+            Some(constellations) if !constellations.is_empty() => constellations.clone(),
+            _ => Constellation::DEFAULT.to_vec(),
+        };
+        let in_view = match self.satellites {
+            Some(count) => count,
+            None => *self
+                .in_view
+                .get_or_insert_with(|| self.rg.random_int(6, 16) as usize),
+        };
+        let sbas_in_view = match self.sbas {
+            true => (*self
+                .sbas_in_view
+                .get_or_insert_with(|| self.rg.random_int(1, 2) as usize))
+            .min(in_view),
+            false => 0,
+        };
 
-        for i in 0..num_msgs {
-            let start = i * 4;
-            let end = if i == num_msgs - 1 {
-                satellites.len()
+        for satellite in &mut self.sky {
+            satellite.advance(seconds);
+        }
+        let before = self.sky.len();
+        self.sky.retain(|sat| sat.elevation >= ELEVATION_MASK);
+        let set = before - self.sky.len();
+        let sbas = self.sbas;
+        self.sky.retain(|sat| match sat.constellation {
+            Constellation::SBAS => sbas,
+            _ => enabled.contains(&sat.constellation),
+        });
+        // SBAS satellites go last, as receivers list them
+        let (mut others, mut sbas_sky): (Vec<_>, Vec<_>) = std::mem::take(&mut self.sky)
+            .into_iter()
+            .partition(|sat| sat.constellation != Constellation::SBAS);
+
+        // Too many: the lowest are blocked first
+        while others.len() > in_view - sbas_in_view {
+            let lowest = (0..others.len())
+                .min_by(|&a, &b| others[a].elevation.total_cmp(&others[b].elevation))
+                .unwrap();
+            others.remove(lowest);
+        }
+        sbas_sky.truncate(sbas_in_view);
+        // Too few: replacements for those that set rise, others appear
+        // wherever they are. Those of the first fix are tracked already, as
+        // after a warm start.
+        let first = seconds == 0.0 && others.is_empty();
+        let mut rising = set;
+        while others.len() < in_view - sbas_in_view {
+            let constellation = Constellation::get_random(&enabled, &mut self.rg);
+            let id = self.free_prn(&constellation, &others);
+            let mut satellite = if rising > 0 {
+                rising -= 1;
+                SkySatellite::rising(constellation, id, &mut self.rg)
             } else {
-                (i + 1) * 4
+                SkySatellite::new(constellation, id, &mut self.rg)
+            };
+            if !first {
+                satellite.acquiring = self
+                    .rg
+                    .random_uniform(ACQUISITION_TIME.0, ACQUISITION_TIME.1);
+            }
+            others.push(satellite);
+        }
+        while sbas_sky.len() < sbas_in_view {
+            let id = self.free_prn(&Constellation::SBAS, &sbas_sky);
+            sbas_sky.push(SkySatellite::new(Constellation::SBAS, id, &mut self.rg));
+        }
+        self.sky = others;
+        self.sky.append(&mut sbas_sky);
+
+        // Signals come and go with the surroundings; a lost one takes a
+        // moment to lock on to again
+        let mut satellites: Vec<Satellite> = Vec::with_capacity(self.sky.len());
+        for sky in &mut self.sky {
+            let elevation = sky.elevation.round() as u8;
+            let snr = self.environment.cn0(elevation, &mut self.rg);
+            sky.acquiring = match snr {
+                Some(_) => (sky.acquiring - seconds).max(0.0),
+                None => sky.acquiring.max(REACQUISITION_TIME),
             };
-            let sats = &satellites[start..end];
+            satellites.push(Satellite {
+                constellation: sky.constellation.clone(),
+                id: sky.id,
+                elevation,
+                azimuth: sky.azimuth as u16,
+                snr,
+                used: false,
+            });
+        }
+        // The fix uses the highest of the acquired satellites, as many of
+        // each system as its GSA can list; SBAS ones only send corrections
+        let mut candidates: Vec<usize> = (0..satellites.len())
+            .filter(|&i| {
+                satellites[i].snr.is_some()
+                    && self.sky[i].acquiring == 0.0
+                    && satellites[i].constellation != Constellation::SBAS
+            })
+            .collect();
+        candidates.sort_by(|&a, &b| self.sky[b].elevation.total_cmp(&self.sky[a].elevation));
+        for i in candidates {
+            let constellation = &satellites[i].constellation;
+            let used = satellites
+                .iter()
+                .filter(|sat| sat.used && &sat.constellation == constellation)
+                .count();
+            satellites[i].used = used < GSA_SLOTS;
+        }
 
-            let num_sats = sats.len();
-            let num_sats_str = num_sats.to_string();
-            let msg_num = (i + 1).to_string();
-            let total_msgs = num_msgs.to_string();
+        satellites
+    }
 
-            let mut sats_str = String::new();
-            for sat in sats {
-                sats_str.push_str(&format!("{},{},{},", sat.id, 0, 0)); // Elevation and Azimuth set to 0 for simplicity
-            }
+    // A PRN of the system no satellite in view has yet, if there is one
+    fn free_prn(&mut self, constellation: &Constellation, sky: &[SkySatellite]) -> u16 {
+        let free: Vec<u16> = constellation
+            .prns()
+            .filter(|&id| {
+                !sky.iter()
+                    .any(|sat| &sat.constellation == constellation && sat.id == id)
+            })
+            .collect();
+        let prns = if free.is_empty() {
+            constellation.prns().collect()
+        } else {
+            free
+        };
+        prns[self.rg.random_int(0, prns.len() as i32 - 1) as usize]
+    }
 
-            let sentence = format!(
-                "GPGSV,{total_msgs},{msg_num},{num_sats_str},{sats_str}",
-                total_msgs = total_msgs,
-                msg_num = msg_num,
-                num_sats_str = num_sats_str,
-                sats_str = sats_str
-            );
+    // Endless epochs of sentences, one burst per call to next(). Pacing and
+    // transport are up to the caller.
+    pub fn iter(&mut self) -> Epochs<'_> {
+        Epochs { generator: self }
+    }
 
-            msgs.push(self.complete_sentence(&sentence));
+    // Epochs `interval` apart, all at once: the clock moves on by the
+    // interval instead of waiting for it, e.g. for a test that needs five
+    // minutes of sentences. It starts at the pinned time or now, and stays
+    // pinned after the burst so the next one carries on from there.
+    pub fn generate_epochs(&mut self, count: usize, interval: Duration) -> Burst {
+        let step = TimeDelta::from_std(interval).expect("Interval out of range");
+        let mut time = self.time.unwrap_or_else(Utc::now);
+        let mut epochs = Vec::with_capacity(count);
+        for _ in 0..count {
+            self.time = Some(time);
+            let fix = self.generate_fix();
+            let sentences = self.encode_sentences(&fix);
+            epochs.push(Epoch { fix, sentences });
+            time += step;
         }
+        self.time = Some(time);
+        Burst { epochs }
+    }
 
-        msgs.join("")
+    // The epochs of `duration` at one per `interval`, which must not be zero
+    pub fn generate_duration(&mut self, duration: Duration, interval: Duration) -> Burst {
+        assert!(!interval.is_zero(), "Epochs need an interval");
+        let count = duration.as_nanos() / interval.as_nanos();
+        self.generate_epochs(count as usize, interval)
     }
 
-    fn generate_satellites(&mut self) -> Vec<Satellite> {
-        let num_satellites = self.rg.random_int(4, 12);
-        let mut satellites = Vec::new();
-        for _ in 0..num_satellites {
-            satellites.push(Satellite::new_random());
+    // Like iter(), but yields one burst every `interval`. The interval
+    // starts on the first poll, which must be inside a tokio runtime.
+    #[cfg(feature = "stream")]
+    pub fn into_stream(self, interval: Duration) -> EpochStream {
+        EpochStream {
+            generator: self,
+            period: interval,
+            interval: None,
         }
+    }
 
-        satellites
+    pub fn encode_sentences(&mut self, fix: &Fix) -> String {
+        let mut out = SentenceBuffer::new();
+        self.encode_epoch(fix, &mut out);
+        out.into_string()
     }
 
-    pub fn generate_sentences(&mut self) -> String {
-        let loc = self.generate_location();
-        let active_satellites = self.generate_satellites();
-        let num_satellites = active_satellites.len() as i32;
+    // Appends the sentences due this epoch. Callers producing epochs in a
+    // loop clear and reuse one buffer instead of allocating every cycle.
+    pub fn encode_epoch(&mut self, fix: &Fix, out: &mut SentenceBuffer) {
+        let start = out.len();
+        let rates = self.sentence_rates;
+        let epoch = self.epoch;
+        self.epoch += 1;
+        let due = |rate: u32| rate != 0 && epoch.is_multiple_of(rate as u64);
+
+        if due(rates.rmc) {
+            self.generate_rmc(fix, out);
+        }
+        if due(rates.gga) {
+            self.generate_gga(fix, out);
+        }
+        if due(rates.gll) {
+            self.generate_gll(fix, out);
+        }
+        if due(rates.gsa) {
+            self.generate_gsa(fix, out);
+        }
+        if due(rates.gsv) {
+            self.generate_gsv(&fix.satellites, out);
+        }
+        if due(rates.vtg) {
+            self.generate_vtg(fix, out);
+        }
+        if due(rates.vhw) {
+            self.generate_vhw(fix, out);
+        }
+        if due(rates.hdt) {
+            self.generate_hdt(fix, out);
+        }
+        if due(rates.hdg) {
+            self.generate_hdg(fix, out);
+        }
+        if due(rates.gst) {
+            self.generate_gst(fix, out);
+        }
+        if let Some(authenticated) = fix.authenticated {
+            self.generate_txt(authenticated, out);
+        }
+        let templates = Arc::clone(&self.templates);
+        if self.sequence_gap > 0 {
+            for template in templates.iter().filter(|template| template.has_sequence()) {
+                let sequence = self
+                    .template_sequences
+                    .entry(template.text.clone())
+                    .or_default();
+                *sequence = sequence.wrapping_add(self.sequence_gap);
+            }
+            self.sequence_gap = 0;
+        }
+        for template in templates.iter().filter(|template| due(template.every)) {
+            self.generate_template(template, fix, out);
+        }
+        self.enforce_length(out, start);
+    }
+
+    // The next sentence of each template numbering its sentences skips this
+    // many numbers
+    pub fn skip_sequence(&mut self, count: u64) {
+        self.sequence_gap = self.sequence_gap.wrapping_add(count);
+    }
+
+    // A single sentence, as asked for by a query; None if we don't generate
+    // that formatter
+    pub fn encode_sentence(&mut self, formatter: &str, fix: &Fix) -> Option<String> {
+        let mut out = SentenceBuffer::new();
+        match formatter {
+            "RMC" => self.generate_rmc(fix, &mut out),
+            "GGA" => self.generate_gga(fix, &mut out),
+            "GLL" => self.generate_gll(fix, &mut out),
+            "GSA" => self.generate_gsa(fix, &mut out),
+            "GSV" => self.generate_gsv(&fix.satellites, &mut out),
+            "VTG" => self.generate_vtg(fix, &mut out),
+            "VHW" => self.generate_vhw(fix, &mut out),
+            "HDT" => self.generate_hdt(fix, &mut out),
+            "HDG" => self.generate_hdg(fix, &mut out),
+            "GST" => self.generate_gst(fix, &mut out),
+            _ => return None,
+        }
+        self.enforce_length(&mut out, 0);
+        Some(out.into_string())
+    }
+}
+
+// Horizontal standard deviation in metres at an HDOP of 1 by GGA fix
+// quality
+fn fix_sigma(fix_quality: u8) -> f64 {
+    match fix_quality {
+        4 => 0.01,
+        5 => 0.3,
+        2 => 0.8,
+        6 => 10.0,
+        _ => 2.5,
+    }
+}
+
+fn sbas_station(satellites: &[Satellite]) -> Option<u16> {
+    satellites
+        .iter()
+        .find(|sat| sat.constellation == Constellation::SBAS)
+        .map(|sat| sat.id)
+}
+
+// Epochs generated in one go, see generate_epochs. Displayed, it is the
+// stream they make up.
+#[derive(Debug, Clone, Default)]
+pub struct Burst {
+    pub epochs: Vec<Epoch>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Epoch {
+    pub fix: Fix,
+    pub sentences: String,
+}
+
+impl fmt::Display for Burst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.epochs
+            .iter()
+            .try_for_each(|epoch| f.write_str(&epoch.sentences))
+    }
+}
+
+pub struct Epochs<'a> {
+    generator: &'a mut NmeaGenerator,
+}
+
+impl Iterator for Epochs<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let fix = self.generator.generate_fix();
+        Some(self.generator.encode_sentences(&fix))
+    }
+}
+
+#[cfg(feature = "stream")]
+pub struct EpochStream {
+    generator: NmeaGenerator,
+    period: Duration,
+    interval: Option<tokio::time::Interval>,
+}
+
+#[cfg(feature = "stream")]
+impl EpochStream {
+    pub fn generator(&mut self) -> &mut NmeaGenerator {
+        &mut self.generator
+    }
+}
 
-        let mut sentences = String::new();
-        sentences.push_str(&self.generate_rmc(&loc));
-        sentences.push_str(&self.generate_gga(&loc, num_satellites));
-        sentences.push_str(&self.generate_gll(&loc));
-        sentences.push_str(&self.generate_gsa(&active_satellites));
-        sentences.push_str(&self.generate_gsv(&active_satellites));
+#[cfg(feature = "stream")]
+impl futures_core::Stream for EpochStream {
+    type Item = String;
 
-        sentences
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<String>> {
+        let period = self.period;
+        let interval = self.interval.get_or_insert_with(|| {
+            let mut interval = tokio::time::interval(period);
+            // A slow consumer delays the next epoch rather than getting a burst
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            interval
+        });
+        std::task::ready!(interval.poll_tick(cx));
+        let generator = &mut self.generator;
+        let fix = generator.generate_fix();
+        std::task::Poll::Ready(Some(generator.encode_sentences(&fix)))
     }
 }
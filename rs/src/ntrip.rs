@@ -0,0 +1,268 @@
+// src/ntrip.rs
+
+use crate::rtcm::Rtcm3Parser;
+use crate::state::SharedState;
+use std::error::Error;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// GGA fix qualities used while corrections are or are not flowing
+const FIX_GPS: u8 = 1;
+const FIX_DGPS: u8 = 2;
+const FIX_RTK: u8 = 4;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Caster address in the form [ntrip://][user:pass@]host[:port]/mountpoint
+#[derive(Debug, Clone)]
+pub struct NtripConfig {
+    pub host: String,
+    pub port: u16,
+    pub mountpoint: String,
+    pub credentials: Option<(String, String)>,
+    // Corrections older than this no longer count as flowing
+    pub timeout: Duration,
+}
+
+impl NtripConfig {
+    pub fn parse(url: &str) -> Result<Self, Box<dyn Error>> {
+        let rest = url.strip_prefix("ntrip://").unwrap_or(url);
+        let (authority, mountpoint) = rest
+            .split_once('/')
+            .ok_or_else(|| format!("Missing mountpoint in NTRIP caster '{}'", url))?;
+        if mountpoint.is_empty() {
+            return Err(format!("Missing mountpoint in NTRIP caster '{}'", url).into());
+        }
+
+        let (credentials, host_port) = match authority.rsplit_once('@') {
+            Some((user_pass, host_port)) => {
+                let (user, pass) = user_pass.split_once(':').unwrap_or((user_pass, ""));
+                (Some((user.to_string(), pass.to_string())), host_port)
+            }
+            None => (None, authority),
+        };
+
+        let (host, port) = match host_port.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("Invalid port in NTRIP caster '{}'", url))?,
+            ),
+            None => (host_port, 2101),
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in NTRIP caster '{}'", url).into());
+        }
+
+        Ok(NtripConfig {
+            host: host.to_string(),
+            port,
+            mountpoint: mountpoint.to_string(),
+            credentials,
+            timeout: Duration::from_secs(5),
+        })
+    }
+}
+
+// Receives RTCM3 from a caster and reports the fix quality a rover would
+// reach with it: RTK while observations arrive, DGPS for other correction
+// messages and a plain GPS fix once the stream times out.
+pub struct NtripClient {
+    config: NtripConfig,
+    state: SharedState,
+    shutdown_event: Arc<AtomicBool>,
+    last_correction: Option<Instant>,
+    last_observation: Option<Instant>,
+}
+
+impl NtripClient {
+    pub fn new(config: NtripConfig, state: SharedState, shutdown_event: Arc<AtomicBool>) -> Self {
+        NtripClient {
+            config,
+            state,
+            shutdown_event,
+            last_correction: None,
+            last_observation: None,
+        }
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+
+    fn run(mut self) {
+        self.set_fix_quality(FIX_GPS);
+
+        while !self.shutdown_event.load(Ordering::SeqCst) {
+            match self.connect() {
+                Ok(stream) => {
+                    if let Err(e) = self.receive(stream) {
+                        warn!("NTRIP connection to {} lost: {}", self.config.host, e);
+                    }
+                }
+                Err(e) => warn!("NTRIP connection to {} failed: {}", self.config.host, e),
+            }
+            self.update_fix_quality();
+
+            // Wait before reconnecting, but stay responsive to shutdown
+            let retry_at = Instant::now() + RECONNECT_DELAY;
+            while Instant::now() < retry_at && !self.shutdown_event.load(Ordering::SeqCst) {
+                thread::sleep(READ_TIMEOUT);
+                self.update_fix_quality();
+            }
+        }
+        info!("NTRIP client exiting.");
+    }
+
+    fn connect(&self) -> Result<TcpStream, Box<dyn Error>> {
+        info!(
+            "Connecting to NTRIP caster {}:{}/{}",
+            self.config.host, self.config.port, self.config.mountpoint
+        );
+        let mut stream = TcpStream::connect((self.config.host.as_str(), self.config.port))?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+        let mut request = format!(
+            "GET /{} HTTP/1.0\r\nHost: {}\r\nUser-Agent: NTRIP nmea_simulator/{}\r\n",
+            self.config.mountpoint,
+            self.config.host,
+            env!("CARGO_PKG_VERSION")
+        );
+        if let Some((user, pass)) = &self.config.credentials {
+            let token = base64_encode(format!("{}:{}", user, pass).as_bytes());
+            request.push_str(&format!("Authorization: Basic {}\r\n", token));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        // NTRIP 1 casters answer "ICY 200 OK", NTRIP 2 ones a regular HTTP status
+        let status = read_status_line(&mut stream)?;
+        let ok = status.starts_with("ICY 200")
+            || (status.starts_with("HTTP/1.") && status.split(' ').nth(1) == Some("200"));
+        if !ok {
+            return Err(format!("Caster rejected request: {}", status.trim()).into());
+        }
+        info!("NTRIP stream started: {}", status.trim());
+
+        Ok(stream)
+    }
+
+    fn receive(&mut self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        let mut parser = Rtcm3Parser::new();
+        let mut buf = [0u8; 4096];
+
+        while !self.shutdown_event.load(Ordering::SeqCst) {
+            match stream.read(&mut buf) {
+                Ok(0) => return Err("caster closed the connection".into()),
+                Ok(n) => {
+                    let now = Instant::now();
+                    let message_types = parser.push(&buf[..n]);
+                    for &message_type in &message_types {
+                        self.last_correction = Some(now);
+                        if is_observation_message(message_type) {
+                            self.last_observation = Some(now);
+                        }
+                    }
+                    if !message_types.is_empty() {
+                        let mut state = self.state.lock().unwrap();
+                        state.correction_received = Some(now);
+                        state.reference_station = parser.station_id;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(Box::new(e)),
+            }
+            self.update_fix_quality();
+        }
+
+        Ok(())
+    }
+
+    fn update_fix_quality(&mut self) {
+        let fresh = |t: Option<Instant>| t.is_some_and(|t| t.elapsed() < self.config.timeout);
+        let fix_quality = if fresh(self.last_observation) {
+            FIX_RTK
+        } else if fresh(self.last_correction) {
+            FIX_DGPS
+        } else {
+            FIX_GPS
+        };
+        self.set_fix_quality(fix_quality);
+    }
+
+    fn set_fix_quality(&self, fix_quality: u8) {
+        let mut state = self.state.lock().unwrap();
+        if state.fix_quality != Some(fix_quality) {
+            info!("NTRIP corrections: fix quality now {}", fix_quality);
+            state.fix_quality = Some(fix_quality);
+        }
+    }
+}
+
+// Reads the response header byte by byte so no RTCM data is consumed,
+// returning the status line
+fn read_status_line(stream: &mut TcpStream) -> Result<String, Box<dyn Error>> {
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    while !header.ends_with(b"\r\n\r\n") {
+        // NTRIP 1 "ICY 200 OK" is only followed by a single CRLF
+        if header.starts_with(b"ICY") && header.ends_with(b"\r\n") {
+            break;
+        }
+        if Instant::now() > deadline || header.len() > 8192 {
+            return Err("No valid response from caster".into());
+        }
+        match stream.read(&mut byte) {
+            Ok(0) => return Err("Caster closed the connection".into()),
+            Ok(_) => header.push(byte[0]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+
+    let header = String::from_utf8_lossy(&header);
+    Ok(header.lines().next().unwrap_or_default().to_string())
+}
+
+// Legacy RTK observables (1001-1012) and MSM observations (1071-1137)
+fn is_observation_message(message_type: u16) -> bool {
+    (1001..=1012).contains(&message_type) || (1071..=1137).contains(&message_type)
+}
+
+pub fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        out.push(ALPHABET[(n >> 18) as usize & 0x3F] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 0x3F] as char);
+        out.push(if chunk.len() > 1 {
+            ALPHABET[(n >> 6) as usize & 0x3F] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            ALPHABET[n as usize & 0x3F] as char
+        } else {
+            '='
+        });
+    }
+    out
+}
@@ -0,0 +1,198 @@
+// src/options.rs
+
+use crate::android::AndroidTarget;
+use crate::faults::Fault;
+use crate::mavlink::GpsMessage;
+use crate::mux::Instrument;
+use crate::nmea_generator::{Crab, DgpsNetwork, EncodingMode, Environment, TalkerPolicy};
+use crate::ntrip::NtripConfig;
+use crate::output::{Framing, OutputSpec, PtyMode};
+use crate::pps::PpsSpec;
+use crate::rtcm::RtcmBase;
+use crate::sentences::MINUTE_DECIMALS;
+use crate::tag_block::TagBlocks;
+use crate::trajectory::TrajectorySource;
+use crate::truth_input::TruthInput;
+use crate::ubx::Protocol;
+use crate::validate::KINEMATICS_TOLERANCE;
+use std::time::Duration;
+
+pub struct Options {
+    // Symlink paths of the PTYs, unset in FIFO mode; single-port mode has
+    // no output path. With more than one port these are patterns where {n}
+    // is the port number.
+    pub gps_input_path: Option<String>,
+    pub gps_output_path: Option<String>,
+    pub pty_mode: PtyMode,
+    // Hold a lock file next to each input link while serving
+    pub lock_links: bool,
+    pub fifo_path: Option<String>,
+    pub ports: usize,
+    pub outputs: Vec<OutputSpec>,
+    // Line speed of the PTYs; also paces the primary output
+    pub baud: Option<u32>,
+    pub framing: Framing,
+    // Pulse per second at the top of each second the sentences carry
+    pub pps: Option<PpsSpec>,
+    // NMEA sentences, UBX frames or both on every port
+    pub protocol: Protocol,
+    // Caster to take the fix quality from
+    pub ntrip: Option<NtripConfig>,
+    // Simulated base station and where its RTCM3 stream goes
+    pub rtcm_base: Option<RtcmBase>,
+    pub rtcm_outputs: Vec<OutputSpec>,
+    // Where Signal K deltas of the first port go
+    pub signalk_outputs: Vec<OutputSpec>,
+    // Where the truth records of the first port go, one JSON line per epoch
+    pub truth_outputs: Vec<OutputSpec>,
+    // Damage done to the sentences of every port
+    pub faults: Vec<Fault>,
+    // Instruments talking on the same port as through a multiplexer
+    pub mux: Vec<Instrument>,
+    // NMEA 4.0 TAG blocks in front of the sentences of every port
+    pub tag_blocks: Option<TagBlocks>,
+    // File to log everything clients write into the ports
+    pub received_log_path: Option<String>,
+    // Created once the simulator is ready, see SimState::ready
+    pub ready_file: Option<String>,
+    // TOML file with rate, sentences and pinned values, reloaded on change
+    pub config_path: Option<String>,
+    // TOML timeline of control commands, and where the run is recorded as
+    // one
+    pub scenario_path: Option<String>,
+    pub scenario_record_path: Option<String>,
+    // Truth from an external simulator instead of the generator, and how
+    // long it stays valid without an update
+    pub truth_input: Option<TruthInput>,
+    pub truth_timeout: Duration,
+    // Autopilot to feed the fixes of the first port to, over MAVLink
+    pub mavlink_output: Option<String>,
+    pub mavlink_message: GpsMessage,
+    // Android device or emulator whose location follows the first port
+    pub android_output: Option<AndroidTarget>,
+    // Publish the fixes and sentences of the first port to ROS 2, through
+    // these zenoh endpoints or peers found by scouting when there are none
+    pub ros2: bool,
+    pub ros2_connect: Vec<String>,
+    pub ros2_frame_id: String,
+    // Rhai scenario script steering the simulation
+    pub script_path: Option<String>,
+    // Unix socket taking JSON-line control commands
+    pub control_path: Option<String>,
+    // Interactive terminal UI instead of per-epoch log lines
+    pub tui: bool,
+    // Short commands typed on stdin
+    pub repl: bool,
+    // Start paused and send an epoch at a time on request, e.g. while the
+    // client is stepped through in a debugger
+    pub step: bool,
+    // How long after its fix an epoch goes out, and whether GGA reports it
+    // as the age of the data
+    pub latency: Duration,
+    pub report_age: bool,
+    // Digits of the coordinate minutes in every sentence, and the talker
+    // ID of the position sentences
+    pub minute_decimals: usize,
+    pub talker_policy: TalkerPolicy,
+    // NMEA 0183 4.11 to the letter, or as older receivers have it
+    pub encoding: EncodingMode,
+    // Start out receiving SBAS corrections, and in these surroundings
+    // instead of under open sky
+    pub sbas: bool,
+    pub environment: Option<Environment>,
+    // Heading off the course from the start, as in a crosswind
+    pub crab: Option<Crab>,
+    // Reference stations a differential fix reports in turn, with the age
+    // of their corrections going up and down
+    pub dgps_network: Option<DgpsNetwork>,
+    // Where the positions come from at the start, external when unset
+    pub trajectory: Option<TrajectorySource>,
+    // How far the speeds, courses and headings sent may be off the fix,
+    // and whether one beyond it fails the run instead of being logged
+    pub kinematics_tolerance: f64,
+    pub strict: bool,
+    // Finite runs end after this many epochs per port or this long
+    pub count: Option<u64>,
+    pub duration: Option<Duration>,
+    // Seed of the random values; picked at random and reported when unset
+    pub seed: Option<u64>,
+    // Address of the HTTP control API
+    pub api_addr: Option<String>,
+    // Address of the HTTP status server (SSE stream and truth state)
+    pub http_addr: Option<String>,
+    // Address of the Prometheus /metrics endpoint
+    pub metrics_addr: Option<String>,
+    // Log a summary of the stats this often
+    pub stats_interval: Option<Duration>,
+}
+
+// No ports of its own, for embedding; outputs come from Simulator::add_output
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            gps_input_path: None,
+            gps_output_path: None,
+            pty_mode: PtyMode::default(),
+            lock_links: false,
+            fifo_path: None,
+            ports: 1,
+            outputs: Vec::new(),
+            baud: None,
+            framing: Framing::default(),
+            pps: None,
+            protocol: Protocol::default(),
+            ntrip: None,
+            rtcm_base: None,
+            rtcm_outputs: Vec::new(),
+            signalk_outputs: Vec::new(),
+            truth_outputs: Vec::new(),
+            faults: Vec::new(),
+            mux: Vec::new(),
+            tag_blocks: None,
+            received_log_path: None,
+            ready_file: None,
+            config_path: None,
+            scenario_path: None,
+            scenario_record_path: None,
+            truth_input: None,
+            truth_timeout: Duration::from_secs(2),
+            mavlink_output: None,
+            mavlink_message: GpsMessage::default(),
+            android_output: None,
+            ros2: false,
+            ros2_connect: Vec::new(),
+            ros2_frame_id: "gps".to_string(),
+            script_path: None,
+            control_path: None,
+            api_addr: None,
+            tui: false,
+            repl: false,
+            step: false,
+            latency: Duration::ZERO,
+            report_age: false,
+            minute_decimals: MINUTE_DECIMALS,
+            talker_policy: TalkerPolicy::default(),
+            encoding: EncodingMode::default(),
+            sbas: false,
+            environment: None,
+            crab: None,
+            dgps_network: None,
+            trajectory: None,
+            kinematics_tolerance: KINEMATICS_TOLERANCE,
+            strict: false,
+            count: None,
+            duration: None,
+            seed: None,
+            http_addr: None,
+            metrics_addr: None,
+            stats_interval: None,
+        }
+    }
+}
+
+impl Options {
+    // Expands the {n} placeholder of a path pattern for the given port
+    pub fn port_path(pattern: &str, port: usize) -> String {
+        pattern.replace("{n}", &port.to_string())
+    }
+}
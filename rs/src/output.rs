@@ -0,0 +1,855 @@
+// src/output.rs
+
+#[cfg(unix)]
+use crate::fifo::FifoSink;
+#[cfg(windows)]
+use crate::named_pipe::NamedPipeSink;
+use crate::stats::SharedStats;
+use crate::tag_block::{self, TagBlocks};
+#[cfg(feature = "websocket")]
+use crate::websocket::WebSocketSink;
+use chrono::Utc;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "udp")]
+use std::net::{ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+// How far a stalled reader may fall behind before writes are dropped
+const MAX_BACKLOG: usize = 64 * 1024;
+// A failed output is reopened this often, and closed for good once it
+// has been failing for RECONNECT_TIMEOUT
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+// A target for the generated data. Writes never block on a slow reader:
+// sinks queue what the reader cannot take yet and drop whole writes once
+// it is too far behind. An error means the target itself is gone.
+pub trait OutputSink: Send {
+    // Human readable description of the target, used in log messages
+    fn name(&self) -> String;
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>>;
+
+    // Pushes out anything the sink buffers; called after every write
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
+    // Whether reconnect() can bring the sink back after an error. Sinks
+    // that can't are closed on their first error.
+    fn reconnects(&self) -> bool {
+        false
+    }
+
+    // Reopens the target after an error
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        Err(format!("{} cannot reconnect", self.name()).into())
+    }
+
+    // Clients that connected and disconnected since the last call, for
+    // sinks that serve clients of their own
+    fn take_client_events(&mut self) -> (u64, u64) {
+        (0, 0)
+    }
+}
+
+// What a non-blocking writer could not take yet. Queued data goes out in
+// order, so sentences are never split; once the reader is MAX_BACKLOG
+// behind, whole writes are dropped instead.
+pub struct Backlog {
+    name: String,
+    pending: Vec<u8>,
+    dropped: u64,
+}
+
+impl Backlog {
+    pub fn new(name: &str) -> Self {
+        Backlog {
+            name: name.to_string(),
+            pending: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn write<W: Write>(&mut self, writer: &mut W, data: &[u8]) -> io::Result<()> {
+        if self.pending.len() + data.len() > MAX_BACKLOG {
+            if self.dropped == 0 {
+                warn!("{} is not keeping up, dropping data", self.name);
+            }
+            self.dropped += 1;
+        } else {
+            self.pending.extend_from_slice(data);
+        }
+
+        while !self.pending.is_empty() {
+            match writer.write(&self.pending) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if self.dropped > 0 {
+            info!("{} caught up, {} writes dropped", self.name, self.dropped);
+            self.dropped = 0;
+        }
+        Ok(())
+    }
+
+    // Forgets queued data, e.g. when the reader went away
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.dropped = 0;
+    }
+}
+
+// A reader connected to one of the broadcasting sinks
+pub struct Client {
+    pub stream: TcpStream,
+    pub peer: String,
+    backlog: Backlog,
+}
+
+impl Client {
+    pub fn new(stream: TcpStream) -> Result<Self, Box<dyn Error>> {
+        stream.set_nonblocking(true)?;
+        let peer = stream
+            .peer_addr()
+            .map(|p| p.to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        Ok(Client {
+            stream,
+            backlog: Backlog::new(&format!("Client {}", peer)),
+            peer,
+        })
+    }
+
+    pub fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.backlog.write(&mut self.stream, data)
+    }
+}
+
+// Writes to an existing device node, e.g. the slave side of a PTY or a
+// serial port such as one end of a com0com pair on Windows
+pub struct PtySink {
+    path: String,
+    file: File,
+    backlog: Backlog,
+}
+
+impl PtySink {
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        info!("Opening output path: {}", path);
+        Ok(PtySink {
+            path: path.to_string(),
+            file: Self::open_file(path)?,
+            backlog: Backlog::new(path),
+        })
+    }
+
+    fn open_file(path: &str) -> Result<File, Box<dyn Error>> {
+        let mut options = OpenOptions::new();
+        options.write(true);
+        // A reader that stops reading must not stall the epoch loop
+        #[cfg(unix)]
+        options.custom_flags(libc::O_NONBLOCK);
+        let file = options.open(path).map_err(|e| {
+            error!("Failed to open {}: {}", path, e);
+            e
+        })?;
+        Ok(file)
+    }
+}
+
+impl OutputSink for PtySink {
+    fn name(&self) -> String {
+        self.path.clone()
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.backlog.write(&mut self.file, data)?;
+        Ok(())
+    }
+
+    fn reconnects(&self) -> bool {
+        true
+    }
+
+    // E.g. a USB serial adapter that was unplugged and plugged in again
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.file = Self::open_file(&self.path)?;
+        self.backlog.clear();
+        Ok(())
+    }
+}
+
+// Standard output, for piping generated or replayed sentences
+pub struct StdoutSink;
+
+impl OutputSink for StdoutSink {
+    fn name(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        io::stdout().lock().write_all(data)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        io::stdout().flush()?;
+        Ok(())
+    }
+}
+
+// Appends to a regular file, creating it if needed
+pub struct FileSink {
+    path: String,
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn create(path: &str) -> Result<Self, Box<dyn Error>> {
+        info!("Opening output file: {}", path);
+        Ok(FileSink {
+            path: path.to_string(),
+            writer: BufWriter::new(Self::open_file(path)?),
+        })
+    }
+
+    fn open_file(path: &str) -> Result<File, Box<dyn Error>> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| {
+                error!("Failed to open {}: {}", path, e);
+                e
+            })?;
+        Ok(file)
+    }
+}
+
+impl OutputSink for FileSink {
+    fn name(&self) -> String {
+        format!("file:{}", self.path)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn reconnects(&self) -> bool {
+        true
+    }
+
+    // Whatever was still buffered is lost with the old file
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer = BufWriter::new(Self::open_file(&self.path)?);
+        Ok(())
+    }
+}
+
+// Listens on a TCP address and broadcasts to every connected client.
+// Clients come and go without affecting the sink itself.
+#[cfg(feature = "tcp")]
+pub struct TcpSink {
+    addr: String,
+    listener: TcpListener,
+    clients: Vec<Client>,
+    // Since take_client_events() was last called
+    connects: u64,
+    disconnects: u64,
+}
+
+#[cfg(feature = "tcp")]
+impl TcpSink {
+    pub fn bind(addr: &str) -> Result<Self, Box<dyn Error>> {
+        let listener = listen(addr)?;
+        info!("Listening for TCP clients on {}", addr);
+
+        Ok(TcpSink {
+            addr: addr.to_string(),
+            listener,
+            clients: Vec::new(),
+            connects: 0,
+            disconnects: 0,
+        })
+    }
+
+    fn accept_clients(&mut self) -> Result<(), Box<dyn Error>> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    info!("TCP client connected to {}: {}", self.addr, peer);
+                    stream.set_nodelay(true)?;
+                    self.clients.push(Client::new(stream)?);
+                    self.connects += 1;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tcp")]
+impl OutputSink for TcpSink {
+    fn name(&self) -> String {
+        format!("tcp:{}", self.addr)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.accept_clients()?;
+
+        let addr = &self.addr;
+        let clients = self.clients.len();
+        self.clients.retain_mut(|client| match client.send(data) {
+            Ok(()) => true,
+            Err(e) => {
+                info!(
+                    "TCP client {} disconnected from {}: {}",
+                    client.peer, addr, e
+                );
+                false
+            }
+        });
+        self.disconnects += (clients - self.clients.len()) as u64;
+
+        Ok(())
+    }
+
+    fn reconnects(&self) -> bool {
+        true
+    }
+
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.listener = listen(&self.addr)?;
+        self.disconnects += self.clients.len() as u64;
+        self.clients.clear();
+        Ok(())
+    }
+
+    fn take_client_events(&mut self) -> (u64, u64) {
+        let events = (self.connects, self.disconnects);
+        (self.connects, self.disconnects) = (0, 0);
+        events
+    }
+}
+
+pub fn listen(addr: &str) -> Result<TcpListener, Box<dyn Error>> {
+    let listener = TcpListener::bind(addr).map_err(|e| {
+        error!("Failed to listen on {}: {}", addr, e);
+        e
+    })?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+// Sends every write as one datagram to a fixed address, e.g. a chart
+// plotter listening for NMEA over UDP. Nobody listening is not an error.
+#[cfg(feature = "udp")]
+pub struct UdpSink {
+    addr: String,
+    socket: UdpSocket,
+}
+
+#[cfg(feature = "udp")]
+impl UdpSink {
+    pub fn connect(addr: &str) -> Result<Self, Box<dyn Error>> {
+        info!("Sending UDP datagrams to {}", addr);
+        Ok(UdpSink {
+            addr: addr.to_string(),
+            socket: Self::open_socket(addr)?,
+        })
+    }
+
+    fn open_socket(addr: &str) -> Result<UdpSocket, Box<dyn Error>> {
+        let target = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("No address found for {}", addr))?;
+        let local = if target.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_broadcast(true)?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket)
+    }
+}
+
+#[cfg(feature = "udp")]
+impl OutputSink for UdpSink {
+    fn name(&self) -> String {
+        format!("udp:{}", self.addr)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        match self.socket.send(data) {
+            Ok(_) => Ok(()),
+            Err(e)
+                if e.kind() == ErrorKind::WouldBlock
+                    || e.kind() == ErrorKind::ConnectionRefused =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    fn reconnects(&self) -> bool {
+        true
+    }
+
+    // Resolves the address again, it may have moved
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.socket = Self::open_socket(&self.addr)?;
+        Ok(())
+    }
+}
+
+// How the PTYs of a port are laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PtyMode {
+    // One PTY: the simulator writes to its master, clients open the slave
+    SinglePort,
+    // The simulator writes to the input PTY, clients read the output PTY;
+    // what clients write only reaches the command handler
+    #[default]
+    LinkedPair,
+    // A linked pair that also forwards what clients write to the input
+    // PTY, like a null modem cable
+    Loopback,
+}
+
+impl PtyMode {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "single-port" => Ok(PtyMode::SinglePort),
+            "linked-pair" => Ok(PtyMode::LinkedPair),
+            "loopback" => Ok(PtyMode::Loopback),
+            _ => Err(format!(
+                "Unknown mode '{}', expected single-port, linked-pair or loopback",
+                value
+            )
+            .into()),
+        }
+    }
+
+    // Symlinks a port of this mode needs
+    pub fn paths(&self) -> usize {
+        match self {
+            PtyMode::SinglePort => 1,
+            PtyMode::LinkedPair | PtyMode::Loopback => 2,
+        }
+    }
+}
+
+// Serial line framing, used to work out how long one character takes on
+// the wire, e.g. 8N1 = 1 start + 8 data + no parity + 1 stop = 10 bits
+#[derive(Debug, Clone, Copy)]
+pub struct Framing {
+    pub data_bits: u32,
+    pub parity: bool,
+    pub stop_bits: u32,
+}
+
+impl Framing {
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let bytes = spec.as_bytes();
+        if bytes.len() != 3 {
+            return Err(format!("Invalid framing '{}', expected e.g. 8N1", spec).into());
+        }
+
+        let data_bits = match bytes[0] {
+            b'5'..=b'8' => (bytes[0] - b'0') as u32,
+            _ => return Err(format!("Invalid data bits in framing '{}'", spec).into()),
+        };
+        let parity = match bytes[1].to_ascii_uppercase() {
+            b'N' => false,
+            b'E' | b'O' => true,
+            _ => return Err(format!("Invalid parity in framing '{}'", spec).into()),
+        };
+        let stop_bits = match bytes[2] {
+            b'1' | b'2' => (bytes[2] - b'0') as u32,
+            _ => return Err(format!("Invalid stop bits in framing '{}'", spec).into()),
+        };
+
+        Ok(Framing {
+            data_bits,
+            parity,
+            stop_bits,
+        })
+    }
+
+    pub fn bits_per_char(&self) -> u32 {
+        1 + self.data_bits + self.parity as u32 + self.stop_bits
+    }
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Framing {
+            data_bits: 8,
+            parity: false,
+            stop_bits: 1,
+        }
+    }
+}
+
+// Longest a receiver may lag its fixes
+pub const MAX_LATENCY: Duration = Duration::from_secs(60);
+
+// Holds epochs back, so what the outputs get lags what was generated by
+// the receiver's output latency
+#[derive(Default)]
+pub struct DelayLine {
+    queue: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl DelayLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: Vec<u8>, delay: Duration) {
+        self.queue.push_back((Instant::now() + delay, data));
+    }
+
+    pub fn next_due(&self) -> Option<Instant> {
+        self.queue.front().map(|(due, _)| *due)
+    }
+
+    // The oldest data if its time has come. Data goes out in order, also
+    // when the delay got shorter in between.
+    pub fn pop_due(&mut self, now: Instant) -> Option<Vec<u8>> {
+        match self.queue.front() {
+            Some((due, _)) if *due <= now => self.queue.pop_front().map(|(_, data)| data),
+            _ => None,
+        }
+    }
+}
+
+// Throttles writes to the character rate of a serial line at the given baud
+// rate, one byte at a time, so readers see realistic inter-character gaps.
+pub struct PacedSink {
+    inner: Box<dyn OutputSink>,
+    baud: u32,
+    char_time: Duration,
+    // Earliest time the next character may go out
+    next_char: Instant,
+}
+
+impl PacedSink {
+    pub fn new(inner: Box<dyn OutputSink>, baud: u32, framing: Framing) -> Self {
+        let char_time = Duration::from_secs_f64(framing.bits_per_char() as f64 / baud as f64);
+        PacedSink {
+            inner,
+            baud,
+            char_time,
+            next_char: Instant::now(),
+        }
+    }
+}
+
+impl OutputSink for PacedSink {
+    fn name(&self) -> String {
+        format!("{}@{}", self.inner.name(), self.baud)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        // Don't let idle time accumulate into a burst allowance
+        let now = Instant::now();
+        if self.next_char < now {
+            self.next_char = now;
+        }
+
+        for byte in data {
+            let now = Instant::now();
+            if self.next_char > now {
+                thread::sleep(self.next_char - now);
+            }
+            self.inner.write_all(std::slice::from_ref(byte))?;
+            self.inner.flush()?;
+            self.next_char += self.char_time;
+        }
+
+        Ok(())
+    }
+
+    fn reconnects(&self) -> bool {
+        self.inner.reconnects()
+    }
+
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.inner.reconnect()
+    }
+
+    fn take_client_events(&mut self) -> (u64, u64) {
+        self.inner.take_client_events()
+    }
+}
+
+// Output target as given on the command line, e.g. "tcp:0.0.0.0:10110"
+#[derive(Debug, Clone)]
+pub enum OutputSpec {
+    Pty(String),
+    File(String),
+    #[cfg(feature = "tcp")]
+    Tcp(String),
+    #[cfg(feature = "udp")]
+    Udp(String),
+    #[cfg(feature = "websocket")]
+    WebSocket(String),
+    Fifo(String),
+}
+
+impl OutputSpec {
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let (kind, target) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid output '{}', expected <kind>:<target>", spec))?;
+
+        if target.is_empty() {
+            return Err(format!("Missing target in output '{}'", spec).into());
+        }
+
+        match kind {
+            "pty" | "serial" => Ok(OutputSpec::Pty(target.to_string())),
+            "file" => Ok(OutputSpec::File(target.to_string())),
+            #[cfg(feature = "tcp")]
+            "tcp" => Ok(OutputSpec::Tcp(target.to_string())),
+            #[cfg(feature = "udp")]
+            "udp" => Ok(OutputSpec::Udp(target.to_string())),
+            #[cfg(feature = "websocket")]
+            "ws" => Ok(OutputSpec::WebSocket(target.to_string())),
+            #[cfg(not(feature = "tcp"))]
+            "tcp" => Err(format!("Output '{}' needs a build with the tcp feature", spec).into()),
+            #[cfg(not(feature = "udp"))]
+            "udp" => Err(format!("Output '{}' needs a build with the udp feature", spec).into()),
+            #[cfg(not(feature = "websocket"))]
+            "ws" => {
+                Err(format!("Output '{}' needs a build with the websocket feature", spec).into())
+            }
+            "fifo" => Ok(OutputSpec::Fifo(target.to_string())),
+            _ => Err(format!("Unknown output kind '{}' in '{}'", kind, spec).into()),
+        }
+    }
+
+    pub fn open(&self) -> Result<Box<dyn OutputSink>, Box<dyn Error>> {
+        Ok(match self {
+            OutputSpec::Pty(path) => Box::new(PtySink::open(path)?),
+            OutputSpec::File(path) => Box::new(FileSink::create(path)?),
+            #[cfg(feature = "tcp")]
+            OutputSpec::Tcp(addr) => Box::new(TcpSink::bind(addr)?),
+            #[cfg(feature = "udp")]
+            OutputSpec::Udp(addr) => Box::new(UdpSink::connect(addr)?),
+            #[cfg(feature = "websocket")]
+            OutputSpec::WebSocket(addr) => Box::new(WebSocketSink::bind(addr)?),
+            #[cfg(unix)]
+            OutputSpec::Fifo(path) => Box::new(FifoSink::create(path)?),
+            #[cfg(windows)]
+            OutputSpec::Fifo(name) => Box::new(NamedPipeSink::create(name)?),
+        })
+    }
+}
+
+// Sentences an output gets, for consumers that only want some of the
+// stream. Outputs are picked by kind, e.g. "udp", or by name, e.g.
+// "file:ais.nmea" or a PTY's path; sentences by formatter, e.g. "VDM", or
+// by address, e.g. "HEHDT".
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentenceFilter {
+    pub output: String,
+    pub sentences: Vec<String>,
+}
+
+impl SentenceFilter {
+    pub fn matches(&self, output_name: &str) -> bool {
+        match output_name.strip_prefix(self.output.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with([':', '@']),
+            None => false,
+        }
+    }
+
+    // The sentences of the data it lets through; anything else, like UBX
+    // frames, stays out
+    pub fn apply<'a>(&self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        let passes = |line: &[u8]| {
+            let line = tag_block::skip(line);
+            let address = match line.first() {
+                Some(b'$' | b'!') => &line[1..],
+                _ => return false,
+            };
+            let end = address
+                .iter()
+                .position(|&c| c == b',' || c == b'*')
+                .unwrap_or(address.len());
+            let address = &address[..end];
+            self.sentences.iter().any(|sentence| {
+                let sentence = sentence.as_bytes();
+                address == sentence
+                    || (sentence.len() == 3 && address.len() == 5 && address.ends_with(sentence))
+            })
+        };
+        let mut lines = data.split_inclusive(|&byte| byte == b'\n');
+        if lines.all(passes) {
+            return Cow::Borrowed(data);
+        }
+        Cow::Owned(
+            data.split_inclusive(|&byte| byte == b'\n')
+                .filter(|line| passes(line))
+                .flatten()
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+// Fans every write out to all sinks. A sink that fails is reopened once a
+// second where it supports that, and otherwise, or after RECONNECT_TIMEOUT
+// without success, closed so the remaining consumers keep receiving data.
+#[derive(Default)]
+pub struct MultiSink {
+    sinks: Vec<Output>,
+    // Where what went out, client events and write errors are counted
+    stats: Option<SharedStats>,
+    filters: Arc<Vec<SentenceFilter>>,
+    tag_blocks: Option<TagBlocks>,
+}
+
+struct Output {
+    sink: Box<dyn OutputSink>,
+    // When the sink started failing, and when to try reopening it next
+    failed: Option<(Instant, Instant)>,
+}
+
+impl MultiSink {
+    pub fn new() -> Self {
+        MultiSink::default()
+    }
+
+    pub fn set_stats(&mut self, stats: SharedStats) {
+        self.stats = Some(stats);
+    }
+
+    // The first filter matching an output applies to it
+    pub fn set_filters(&mut self, filters: Arc<Vec<SentenceFilter>>) {
+        self.filters = filters;
+    }
+
+    // Every output gets the same TAG blocks, counting lines together
+    pub fn set_tag_blocks(&mut self, tag_blocks: TagBlocks) {
+        self.tag_blocks = Some(tag_blocks);
+    }
+
+    pub fn add(&mut self, sink: Box<dyn OutputSink>) {
+        self.sinks.push(Output { sink, failed: None });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.sinks.iter().map(|output| output.sink.name()).collect()
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) {
+        let now = Instant::now();
+        let tagged;
+        let data = match &mut self.tag_blocks {
+            Some(tag_blocks) => {
+                tagged = tag_blocks.apply(data, Utc::now());
+                &tagged[..]
+            }
+            None => data,
+        };
+        let stats = self.stats.as_deref();
+        let filters = &self.filters;
+        let mut written = false;
+        self.sinks.retain_mut(|output| {
+            let sink = &mut output.sink;
+            if let Some((since, next_attempt)) = output.failed {
+                if now < next_attempt {
+                    return true;
+                }
+                match sink.reconnect() {
+                    Ok(()) => {
+                        info!("Reconnected output {}", sink.name());
+                        output.failed = None;
+                    }
+                    Err(_) if now - since < RECONNECT_TIMEOUT => {
+                        output.failed = Some((since, now + RECONNECT_INTERVAL));
+                        return true;
+                    }
+                    Err(e) => {
+                        warn!("Closing output {}: {}", sink.name(), e);
+                        return false;
+                    }
+                }
+            }
+
+            let data = match filters.iter().find(|filter| filter.matches(&sink.name())) {
+                Some(filter) => filter.apply(data),
+                None => Cow::Borrowed(data),
+            };
+            // Nothing it wants in this write
+            if data.is_empty() {
+                return true;
+            }
+            let result = sink.write_all(&data).and_then(|()| sink.flush());
+            if let Some(stats) = stats {
+                let (connects, disconnects) = sink.take_client_events();
+                stats.count_clients(connects, disconnects);
+            }
+            match result {
+                Ok(()) => {
+                    written = true;
+                    true
+                }
+                Err(e) => {
+                    if let Some(stats) = stats {
+                        stats.count_write_error();
+                    }
+                    error!("Error writing to {}: {}", sink.name(), e);
+                    if sink.reconnects() {
+                        // The first attempt comes with the next write
+                        output.failed = Some((now, now));
+                        true
+                    } else {
+                        warn!("Closing output {}", sink.name());
+                        false
+                    }
+                }
+            }
+        });
+        // Counted once however many outputs it went to
+        if let Some(stats) = stats.filter(|_| written) {
+            stats.count_output(data);
+        }
+    }
+}
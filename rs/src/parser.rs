@@ -0,0 +1,509 @@
+// src/parser.rs
+
+use crate::checksum::checksum;
+use crate::position::Position;
+use crate::sentences::{
+    Accuracy, Gga, Gll, Gsa, Gst, Gsv, GsvSatellite, Hdg, Hdt, Rmc, Sentence, SentenceBuffer, Txt,
+    Vhw, Vtg, MINUTE_DECIMALS,
+};
+use crate::tag_block;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use std::ops::RangeInclusive;
+use thiserror::Error;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ParseError {
+    #[error("Bad checksum {found:?}, expected {expected:02X}")]
+    Checksum { expected: u8, found: String },
+    #[error("Invalid address {0:?}")]
+    Address(String),
+    #[error("Unsupported sentence {0}")]
+    Unsupported(String),
+    #[error("{formatter} has {found} fields, expected {expected}")]
+    FieldCount {
+        formatter: String,
+        expected: String,
+        found: usize,
+    },
+    #[error("Invalid {name} {value:?}")]
+    Field { name: &'static str, value: String },
+}
+
+// Any of the sentences the generator writes
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParsedSentence {
+    Gga(Gga),
+    Rmc(Rmc),
+    Gll(Gll),
+    Gsa(Gsa),
+    Gsv(Gsv),
+    Txt(Txt),
+    Vtg(Vtg),
+    Vhw(Vhw),
+    Hdt(Hdt),
+    Hdg(Hdg),
+    Gst(Gst),
+}
+
+impl Sentence for ParsedSentence {
+    fn address(&self) -> String {
+        match self {
+            ParsedSentence::Gga(gga) => gga.address(),
+            ParsedSentence::Rmc(rmc) => rmc.address(),
+            ParsedSentence::Gll(gll) => gll.address(),
+            ParsedSentence::Gsa(gsa) => gsa.address(),
+            ParsedSentence::Gsv(gsv) => gsv.address(),
+            ParsedSentence::Txt(txt) => txt.address(),
+            ParsedSentence::Vtg(vtg) => vtg.address(),
+            ParsedSentence::Vhw(vhw) => vhw.address(),
+            ParsedSentence::Hdt(hdt) => hdt.address(),
+            ParsedSentence::Hdg(hdg) => hdg.address(),
+            ParsedSentence::Gst(gst) => gst.address(),
+        }
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        match self {
+            ParsedSentence::Gga(gga) => gga.encode(out),
+            ParsedSentence::Rmc(rmc) => rmc.encode(out),
+            ParsedSentence::Gll(gll) => gll.encode(out),
+            ParsedSentence::Gsa(gsa) => gsa.encode(out),
+            ParsedSentence::Gsv(gsv) => gsv.encode(out),
+            ParsedSentence::Txt(txt) => txt.encode(out),
+            ParsedSentence::Vtg(vtg) => vtg.encode(out),
+            ParsedSentence::Vhw(vhw) => vhw.encode(out),
+            ParsedSentence::Hdt(hdt) => hdt.encode(out),
+            ParsedSentence::Hdg(hdg) => hdg.encode(out),
+            ParsedSentence::Gst(gst) => gst.encode(out),
+        }
+    }
+}
+
+impl ParsedSentence {
+    // Time of the fix, for the sentences that carry one and once the
+    // receiver knows it
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            ParsedSentence::Gga(gga) => gga.time,
+            ParsedSentence::Rmc(rmc) => rmc.time,
+            ParsedSentence::Gll(gll) => gll.time,
+            ParsedSentence::Gst(gst) => gst.time,
+            ParsedSentence::Gsa(_)
+            | ParsedSentence::Gsv(_)
+            | ParsedSentence::Txt(_)
+            | ParsedSentence::Vtg(_)
+            | ParsedSentence::Vhw(_)
+            | ParsedSentence::Hdt(_)
+            | ParsedSentence::Hdg(_) => None,
+        }
+    }
+}
+
+// Reads sentences back into the structs they are encoded from, so output
+// and logs can be checked by re-encoding them. Sentences with only a time
+// of day get the date of the last RMC, or today's before the first one.
+#[derive(Default)]
+pub struct Parser {
+    date: Option<NaiveDate>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser { date: None }
+    }
+
+    // One line, with or without the leading '$' and the line ending
+    pub fn parse(&mut self, line: &str) -> Result<ParsedSentence, ParseError> {
+        let body = strip_checksum(line)?;
+        let mut fields = body.split(',');
+        let address = fields.next().unwrap_or_default();
+        let fields: Vec<&str> = fields.collect();
+
+        let valid_address = !address.is_empty()
+            && address
+                .bytes()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit());
+        if !valid_address {
+            return Err(ParseError::Address(address.to_string()));
+        }
+        // Proprietary sentences like PMTK have no talker
+        if address.len() != 5 || address.starts_with('P') {
+            return Err(ParseError::Unsupported(address.to_string()));
+        }
+        let (talker, formatter) = address.split_at(2);
+        let talker = talker.to_string();
+
+        match formatter {
+            "GGA" => {
+                expect_fields(formatter, &fields, 14..=14)?;
+                Ok(ParsedSentence::Gga(Gga {
+                    talker,
+                    time: self.time_of_day(fields[0])?,
+                    time_decimals: decimals(fields[0]),
+                    position: position(&fields[1..5], fields[8])?,
+                    minute_decimals: minute_decimals(fields[1]),
+                    fix_quality: number(fields[5], "fix quality")?,
+                    satellites: number(fields[6], "satellite count")?,
+                    hdop: number(fields[7], "HDOP")?,
+                    geoid_height: optional_number(fields[10], "geoid height")?,
+                    age: optional_number(fields[12], "age")?,
+                    station_id: optional_number(fields[13], "station ID")?,
+                }))
+            }
+            "RMC" => {
+                expect_fields(formatter, &fields, 11..=13)?;
+                let date = match fields[8] {
+                    "" => None,
+                    date => Some(NaiveDate::parse_from_str(date, "%d%m%y").map_err(|_| {
+                        ParseError::Field {
+                            name: "date",
+                            value: date.to_string(),
+                        }
+                    })?),
+                };
+                if date.is_some() {
+                    self.date = date;
+                }
+                Ok(ParsedSentence::Rmc(Rmc {
+                    talker,
+                    time: self.time_of_day(fields[0])?,
+                    time_decimals: decimals(fields[0]),
+                    date,
+                    valid: status(fields[1])?,
+                    position: position(&fields[2..6], "")?,
+                    minute_decimals: minute_decimals(fields[2]),
+                    speed_knots: optional_number(fields[6], "speed")?,
+                    course: optional_number(fields[7], "course")?,
+                    mode: optional_char(fields.get(11), "mode")?,
+                    nav_status: optional_char(fields.get(12), "navigational status")?,
+                }))
+            }
+            "GLL" => {
+                expect_fields(formatter, &fields, 6..=7)?;
+                Ok(ParsedSentence::Gll(Gll {
+                    talker,
+                    position: position(&fields[0..4], "")?,
+                    minute_decimals: minute_decimals(fields[0]),
+                    time: self.time_of_day(fields[4])?,
+                    time_decimals: decimals(fields[4]),
+                    valid: status(fields[5])?,
+                    mode: optional_char(fields.get(6), "mode")?,
+                }))
+            }
+            "GSA" => {
+                expect_fields(formatter, &fields, 17..=usize::MAX)?;
+                // NMEA 4.10 appends the system ID to the 12 ID slots and DOPs
+                let (fields, system_id) = match fields.len() {
+                    18 => (&fields[..17], Some(hex_digit(fields[17], "system ID")?)),
+                    _ => (&fields[..], None),
+                };
+                let (ids, dops) = fields[2..].split_at(fields.len() - 5);
+                Ok(ParsedSentence::Gsa(Gsa {
+                    talker,
+                    mode: single_char(fields[0], "mode")?,
+                    fix_type: number(fields[1], "fix type")?,
+                    satellite_ids: ids
+                        .iter()
+                        .filter(|id| !id.is_empty())
+                        .map(|id| number(id, "satellite ID"))
+                        .collect::<Result<_, _>>()?,
+                    pdop: number(dops[0], "PDOP")?,
+                    hdop: number(dops[1], "HDOP")?,
+                    vdop: number(dops[2], "VDOP")?,
+                    system_id,
+                }))
+            }
+            "GSV" => {
+                expect_fields(formatter, &fields, 3..=usize::MAX)?;
+                Ok(ParsedSentence::Gsv(Gsv {
+                    talker,
+                    total_messages: number(fields[0], "message count")?,
+                    message_number: number(fields[1], "message number")?,
+                    satellites_in_view: number(fields[2], "satellite count")?,
+                    satellites: gsv_satellites(&fields[3..])?,
+                    signal_id: match fields[3..].len() % 4 {
+                        1 => Some(hex_digit(fields[fields.len() - 1], "signal ID")?),
+                        _ => None,
+                    },
+                }))
+            }
+            "TXT" => {
+                expect_fields(formatter, &fields, 4..=4)?;
+                Ok(ParsedSentence::Txt(Txt {
+                    talker,
+                    total_messages: number(fields[0], "message count")?,
+                    message_number: number(fields[1], "message number")?,
+                    text_id: number(fields[2], "text ID")?,
+                    text: fields[3].to_string(),
+                }))
+            }
+            "VTG" => {
+                expect_fields(formatter, &fields, 8..=9)?;
+                Ok(ParsedSentence::Vtg(Vtg {
+                    talker,
+                    course: optional_number(fields[0], "course")?,
+                    speed_knots: optional_number(fields[4], "speed")?,
+                    speed_kmh: optional_number(fields[6], "speed")?,
+                    mode: optional_char(fields.get(8), "mode")?,
+                }))
+            }
+            "VHW" => {
+                expect_fields(formatter, &fields, 8..=8)?;
+                Ok(ParsedSentence::Vhw(Vhw {
+                    talker,
+                    heading: optional_number(fields[0], "heading")?,
+                    speed_knots: number(fields[4], "speed")?,
+                    speed_kmh: number(fields[6], "speed")?,
+                }))
+            }
+            "HDT" => {
+                expect_fields(formatter, &fields, 2..=2)?;
+                Ok(ParsedSentence::Hdt(Hdt {
+                    talker,
+                    heading: optional_number(fields[0], "heading")?,
+                }))
+            }
+            "HDG" => {
+                expect_fields(formatter, &fields, 5..=5)?;
+                Ok(ParsedSentence::Hdg(Hdg {
+                    talker,
+                    heading: number(fields[0], "heading")?,
+                    deviation: signed_angle(fields[1], fields[2], "deviation")?,
+                    variation: signed_angle(fields[3], fields[4], "variation")?,
+                }))
+            }
+            "GST" => {
+                expect_fields(formatter, &fields, 8..=8)?;
+                let accuracy = if fields[1..].iter().all(|field| field.is_empty()) {
+                    None
+                } else {
+                    Some(Accuracy {
+                        rms: number(fields[1], "RMS")?,
+                        semi_major: number(fields[2], "semi-major axis")?,
+                        semi_minor: number(fields[3], "semi-minor axis")?,
+                        orientation: number(fields[4], "orientation")?,
+                        latitude: number(fields[5], "latitude error")?,
+                        longitude: number(fields[6], "longitude error")?,
+                        altitude: number(fields[7], "altitude error")?,
+                    })
+                };
+                Ok(ParsedSentence::Gst(Gst {
+                    talker,
+                    time: self.time_of_day(fields[0])?,
+                    time_decimals: decimals(fields[0]),
+                    accuracy,
+                }))
+            }
+            _ => Err(ParseError::Unsupported(address.to_string())),
+        }
+    }
+
+    // None for an empty field
+    fn time_of_day(&self, value: &str) -> Result<Option<DateTime<Utc>>, ParseError> {
+        if value.is_empty() {
+            return Ok(None);
+        }
+        let time =
+            NaiveTime::parse_from_str(value, "%H%M%S%.f").map_err(|_| ParseError::Field {
+                name: "time",
+                value: value.to_string(),
+            })?;
+        let date = self.date.unwrap_or_else(|| Utc::now().date_naive());
+        Ok(Some(date.and_time(time).and_utc()))
+    }
+}
+
+// The sentence between '$' and '*' after checking the checksum, which is
+// optional as in the commands clients send
+pub fn strip_checksum(line: &str) -> Result<&str, ParseError> {
+    let line = line.trim_end();
+    // Anything in a TAG block only frames the sentence
+    let line = tag_block::strip(line)?;
+    let line = line.strip_prefix('$').unwrap_or(line);
+    match line.split_once('*') {
+        Some((sentence, sum)) => {
+            let expected = checksum(sentence);
+            if u8::from_str_radix(sum, 16).ok() != Some(expected) {
+                return Err(ParseError::Checksum {
+                    expected,
+                    found: sum.to_string(),
+                });
+            }
+            Ok(sentence)
+        }
+        None => Ok(line),
+    }
+}
+
+fn expect_fields(
+    formatter: &str,
+    fields: &[&str],
+    expected: RangeInclusive<usize>,
+) -> Result<(), ParseError> {
+    if expected.contains(&fields.len()) {
+        return Ok(());
+    }
+    let (min, max) = expected.into_inner();
+    Err(ParseError::FieldCount {
+        formatter: formatter.to_string(),
+        expected: match max {
+            usize::MAX => format!("at least {}", min),
+            max if max == min => min.to_string(),
+            max => format!("{} to {}", min, max),
+        },
+        found: fields.len(),
+    })
+}
+
+fn number<T: std::str::FromStr>(value: &str, name: &'static str) -> Result<T, ParseError> {
+    value.parse().map_err(|_| ParseError::Field {
+        name,
+        value: value.to_string(),
+    })
+}
+
+// An empty field is left out
+fn optional_number<T: std::str::FromStr>(
+    value: &str,
+    name: &'static str,
+) -> Result<Option<T>, ParseError> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    number(value, name).map(Some)
+}
+
+fn single_char(value: &str, name: &'static str) -> Result<char, ParseError> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(ParseError::Field {
+            name,
+            value: value.to_string(),
+        }),
+    }
+}
+
+// A trailing one-letter field, None when empty or left out
+fn optional_char(value: Option<&&str>, name: &'static str) -> Result<Option<char>, ParseError> {
+    match value {
+        Some(value) if !value.is_empty() => Ok(Some(single_char(value, name)?)),
+        _ => Ok(None),
+    }
+}
+
+// System and signal IDs, one hex digit
+fn hex_digit(value: &str, name: &'static str) -> Result<u8, ParseError> {
+    match single_char(value, name)?.to_digit(16) {
+        Some(digit) => Ok(digit as u8),
+        None => Err(ParseError::Field {
+            name,
+            value: value.to_string(),
+        }),
+    }
+}
+
+fn status(value: &str) -> Result<bool, ParseError> {
+    match value {
+        "A" => Ok(true),
+        "V" => Ok(false),
+        _ => Err(ParseError::Field {
+            name: "status",
+            value: value.to_string(),
+        }),
+    }
+}
+
+// An angle and E or W, negative to the west; empty when both are
+fn signed_angle(
+    value: &str,
+    direction: &str,
+    name: &'static str,
+) -> Result<Option<f64>, ParseError> {
+    let Some(angle) = optional_number::<f64>(value, name)? else {
+        return Ok(None);
+    };
+    match direction {
+        "E" => Ok(Some(angle)),
+        "W" => Ok(Some(-angle)),
+        _ => Err(ParseError::Field {
+            name,
+            value: format!("{},{}", value, direction),
+        }),
+    }
+}
+
+// Latitude, N/S, longitude, E/W. Coordinates may carry their hemisphere
+// as a suffix too, as in our own RMC and GLL. All of them are empty
+// without a fix.
+fn position(fields: &[&str], altitude: &str) -> Result<Option<Position>, ParseError> {
+    if fields.iter().all(|field| field.is_empty()) {
+        return Ok(None);
+    }
+    let latitude = coordinate(fields[0], fields[1], 2, ['N', 'S'], "latitude")?;
+    let longitude = coordinate(fields[2], fields[3], 3, ['E', 'W'], "longitude")?;
+    let alt_m = if altitude.is_empty() {
+        0.0
+    } else {
+        number(altitude, "altitude")?
+    };
+    Ok(Some(Position::new(latitude, longitude, alt_m)))
+}
+
+// Digits after the point of a coordinate; the default without one
+fn minute_decimals(coordinate: &str) -> usize {
+    match coordinate.split_once('.') {
+        Some((_, decimals)) => decimals.len(),
+        None => MINUTE_DECIMALS,
+    }
+}
+
+// Digits of the seconds of a time, e.g. 2 in hhmmss.ss
+fn decimals(time: &str) -> usize {
+    time.split_once('.')
+        .map_or(0, |(_, decimals)| decimals.len())
+}
+
+fn coordinate(
+    value: &str,
+    hemisphere: &str,
+    degree_digits: usize,
+    [positive, negative]: [char; 2],
+    name: &'static str,
+) -> Result<f64, ParseError> {
+    let invalid = || ParseError::Field {
+        name,
+        value: format!("{},{}", value, hemisphere),
+    };
+    if value.len() < degree_digits + 2 || !value.is_char_boundary(degree_digits) {
+        return Err(invalid());
+    }
+    let (degrees, minutes) = value.split_at(degree_digits);
+    let degrees: u32 = degrees.parse().map_err(|_| invalid())?;
+    let minutes: f64 = minutes.parse().map_err(|_| invalid())?;
+    if !(0.0..60.0).contains(&minutes) {
+        return Err(invalid());
+    }
+    let angle = degrees as f64 + minutes / 60.0;
+    match single_char(hemisphere, name) {
+        Ok(c) if c == positive => Ok(angle),
+        Ok(c) if c == negative => Ok(-angle),
+        _ => Err(invalid()),
+    }
+}
+
+// Groups of ID, elevation, azimuth and SNR; NMEA 4.11 appends a signal ID
+// after them
+fn gsv_satellites(fields: &[&str]) -> Result<Vec<GsvSatellite>, ParseError> {
+    fields[..fields.len() - fields.len() % 4]
+        .chunks(4)
+        .map(|sat| {
+            Ok(GsvSatellite {
+                id: number(sat[0], "satellite ID")?,
+                elevation: optional_number(sat[1], "elevation")?,
+                azimuth: optional_number(sat[2], "azimuth")?,
+                snr: optional_number(sat[3], "SNR")?,
+            })
+        })
+        .collect()
+}
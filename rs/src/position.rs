@@ -0,0 +1,140 @@
+// src/position.rs
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const EARTH_RADIUS_M: f64 = 6378137.0;
+
+// A point in WGS84 degrees and metres above mean sea level, which is the
+// geoid's separation below or above the ellipsoid
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub lat_deg: f64,
+    pub lon_deg: f64,
+    pub alt_m: f64,
+}
+
+impl Position {
+    pub fn new(lat_deg: f64, lon_deg: f64, alt_m: f64) -> Self {
+        Position {
+            lat_deg,
+            lon_deg,
+            alt_m,
+        }
+    }
+
+    // Latitude as ddmm.mmmm with `decimals` digits of minutes, and N or S
+    pub fn nmea_latitude(&self, decimals: usize) -> (String, char) {
+        let (latitude, hemisphere) = self.latitude_minutes(decimals);
+        (latitude.to_string(), hemisphere)
+    }
+
+    // Longitude as dddmm.mmmm with `decimals` digits of minutes, and E or W
+    pub fn nmea_longitude(&self, decimals: usize) -> (String, char) {
+        let (longitude, hemisphere) = self.longitude_minutes(decimals);
+        (longitude.to_string(), hemisphere)
+    }
+
+    // As nmea_latitude, formatted only when written
+    pub fn latitude_minutes(&self, decimals: usize) -> (DegreesMinutes, char) {
+        let hemisphere = if self.lat_deg >= 0.0 { 'N' } else { 'S' };
+        (DegreesMinutes::new(self.lat_deg, 2, decimals), hemisphere)
+    }
+
+    // As nmea_longitude, formatted only when written
+    pub fn longitude_minutes(&self, decimals: usize) -> (DegreesMinutes, char) {
+        let hemisphere = if self.lon_deg >= 0.0 { 'E' } else { 'W' };
+        (DegreesMinutes::new(self.lon_deg, 3, decimals), hemisphere)
+    }
+
+    // The point this many metres north and east, on a sphere; close enough
+    // for offsets of a few kilometres
+    pub fn offset(&self, north_m: f64, east_m: f64) -> Self {
+        let lat_deg = self.lat_deg + (north_m / EARTH_RADIUS_M).to_degrees();
+        let parallel_m = EARTH_RADIUS_M * self.lat_deg.to_radians().cos().max(1e-9);
+        let lon_deg = self.lon_deg + (east_m / parallel_m).to_degrees();
+        Position::new(
+            lat_deg.clamp(-90.0, 90.0),
+            (lon_deg + 540.0).rem_euclid(360.0) - 180.0,
+            self.alt_m,
+        )
+    }
+
+    // Great circle distance in metres to another point, on a sphere
+    pub fn distance_m(&self, other: &Position) -> f64 {
+        let (lat1, lat2) = (self.lat_deg.to_radians(), other.lat_deg.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon_deg - self.lon_deg).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().min(1.0).asin()
+    }
+
+    // Initial great circle bearing to another point, in degrees from north
+    pub fn bearing_to(&self, other: &Position) -> f64 {
+        let (lat1, lat2) = (self.lat_deg.to_radians(), other.lat_deg.to_radians());
+        let dlon = (other.lon_deg - self.lon_deg).to_radians();
+        let y = dlon.sin() * lat2.cos();
+        let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+        y.atan2(x).to_degrees().rem_euclid(360.0)
+    }
+
+    // The point a fraction of the way to another, straight across the
+    // coordinates and the short way round the antimeridian
+    pub fn lerp(&self, other: &Position, fraction: f64) -> Self {
+        let dlon = (other.lon_deg - self.lon_deg + 540.0).rem_euclid(360.0) - 180.0;
+        Position::new(
+            self.lat_deg + (other.lat_deg - self.lat_deg) * fraction,
+            (self.lon_deg + dlon * fraction + 540.0).rem_euclid(360.0) - 180.0,
+            self.alt_m + (other.alt_m - self.alt_m) * fraction,
+        )
+    }
+}
+
+// Latitude, longitude and altitude as the control commands take them
+impl From<(f64, f64, f64)> for Position {
+    fn from((lat_deg, lon_deg, alt_m): (f64, f64, f64)) -> Self {
+        Position::new(lat_deg, lon_deg, alt_m)
+    }
+}
+
+// An angle without its sign as whole degrees and decimal minutes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DegreesMinutes {
+    degrees: u64,
+    // In units of the last digit
+    minutes: u64,
+    degree_digits: usize,
+    decimals: usize,
+}
+
+impl DegreesMinutes {
+    // Rounds in whole units of the last digit first, so 59.99999 minutes
+    // carry into the degrees instead of printing as 60.0000
+    fn new(angle: f64, degree_digits: usize, decimals: usize) -> Self {
+        let scale = 10u64.pow(decimals as u32);
+        let units = (angle.abs() * 60.0 * scale as f64).round() as u64;
+        DegreesMinutes {
+            degrees: units / (60 * scale),
+            minutes: units % (60 * scale),
+            degree_digits,
+            decimals,
+        }
+    }
+}
+
+impl fmt::Display for DegreesMinutes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = 10u64.pow(self.decimals as u32);
+        write!(
+            f,
+            "{:0dw$}{:02}",
+            self.degrees,
+            self.minutes / scale,
+            dw = self.degree_digits
+        )?;
+        if self.decimals > 0 {
+            write!(f, ".{:0fw$}", self.minutes % scale, fw = self.decimals)?;
+        }
+        Ok(())
+    }
+}
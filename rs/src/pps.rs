@@ -0,0 +1,232 @@
+// src/pps.rs
+
+#[cfg(unix)]
+use crate::error::SimError;
+#[cfg(unix)]
+use crate::state::SharedState;
+use std::error::Error;
+use std::fmt;
+#[cfg(unix)]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+#[cfg(unix)]
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+#[cfg(unix)]
+use tracing::{info, warn};
+
+// How long a line stays raised, as on most receivers
+pub const PULSE_WIDTH: Duration = Duration::from_millis(100);
+
+// Where the pulse per second goes. The simulated receiver keeps the time
+// of the system clock, so each pulse marks the top of one of its seconds,
+// the second the sentences sent after it carry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PpsSpec {
+    // A modem control line of a serial port. Through a null modem cable it
+    // arrives as DCD on the other end, where the PPS line discipline
+    // (ldattach pps) turns it into a /dev/pps device for ntpd or chrony.
+    // PTYs have no modem lines, so this needs a real or USB serial port.
+    Rts(String),
+    Dtr(String),
+    // A chrony SOCK refclock, fed the way gpsd does, e.g.
+    //   refclock SOCK /run/chrony.ttyS0.sock refid PPS
+    Chrony(String),
+}
+
+impl PpsSpec {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value.split_once(':') {
+            Some(("rts", path)) if !path.is_empty() => Ok(PpsSpec::Rts(path.to_string())),
+            Some(("dtr", path)) if !path.is_empty() => Ok(PpsSpec::Dtr(path.to_string())),
+            Some(("chrony", path)) if !path.is_empty() => Ok(PpsSpec::Chrony(path.to_string())),
+            _ => Err(format!(
+                "Invalid PPS output '{}', expected rts:<serial port>, dtr:<serial port> or chrony:<socket>",
+                value
+            )
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for PpsSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PpsSpec::Rts(path) => write!(f, "rts:{}", path),
+            PpsSpec::Dtr(path) => write!(f, "dtr:{}", path),
+            PpsSpec::Chrony(path) => write!(f, "chrony:{}", path),
+        }
+    }
+}
+
+// Time until the next top of a second of the system clock
+pub fn until_next_second() -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Duration::from_secs(1) - Duration::from_nanos(now.subsec_nanos() as u64)
+}
+
+// Pulses once per second until shutdown, while the receiver has a fix as
+// receivers do. The output is opened up front, so that a port without
+// modem lines fails the start instead of every pulse.
+#[cfg(unix)]
+pub fn spawn_pps(
+    spec: PpsSpec,
+    state: SharedState,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, SimError> {
+    let pulser = Pulser::open(&spec)
+        .map_err(|e| SimError::io(format!("Failed to open PPS output {}", spec), e))?;
+    info!("Sending a pulse per second to {}", spec);
+
+    Ok(thread::spawn(move || {
+        let mut failing = false;
+        while !shutdown_event.load(Ordering::SeqCst) {
+            thread::sleep(until_next_second());
+            let has_fix = {
+                let state = state.lock().unwrap();
+                !state.paused
+                    && !state.no_fix
+                    && state.truth.as_ref().is_some_and(|fix| fix.fix_quality > 0)
+            };
+            if !has_fix {
+                continue;
+            }
+            // Woken a little after the top of the second, never before it
+            let second = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let result = pulser.raise(second).and_then(|()| {
+                thread::sleep(PULSE_WIDTH);
+                pulser.lower()
+            });
+            // Reported once until the output works again
+            match result {
+                Ok(()) if failing => {
+                    info!("PPS output {} works again", spec);
+                    failing = false;
+                }
+                Ok(()) => {}
+                Err(e) if !failing => {
+                    warn!("Failed to pulse {}: {}", spec, e);
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+        info!("PPS output exiting.");
+    }))
+}
+
+#[cfg(unix)]
+enum Pulser {
+    Line {
+        file: std::fs::File,
+        bit: libc::c_int,
+    },
+    Chrony {
+        socket: std::os::unix::net::UnixDatagram,
+        path: String,
+    },
+}
+
+// What chrony's SOCK refclock takes, see refclock_sock.c
+#[cfg(unix)]
+#[repr(C)]
+struct SockSample {
+    tv: libc::timeval,
+    offset: f64,
+    pulse: libc::c_int,
+    leap: libc::c_int,
+    _pad: libc::c_int,
+    magic: libc::c_int,
+}
+
+#[cfg(unix)]
+const SOCK_MAGIC: libc::c_int = 0x534f_434b;
+
+#[cfg(unix)]
+impl Pulser {
+    fn open(spec: &PpsSpec) -> std::io::Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let (path, bit) = match spec {
+            PpsSpec::Rts(path) => (path, libc::TIOCM_RTS),
+            PpsSpec::Dtr(path) => (path, libc::TIOCM_DTR),
+            PpsSpec::Chrony(path) => {
+                // chrony may start after the simulator, so each pulse is
+                // sent to the path rather than over a connection
+                let socket = std::os::unix::net::UnixDatagram::unbound()?;
+                socket.set_nonblocking(true)?;
+                return Ok(Pulser::Chrony {
+                    socket,
+                    path: path.clone(),
+                });
+            }
+        };
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(path)?;
+        let pulser = Pulser::Line { file, bit };
+        // Starts low, and fails here on a device without modem lines
+        pulser.lower()?;
+        Ok(pulser)
+    }
+
+    fn raise(&self, second: u64) -> std::io::Result<()> {
+        match self {
+            Pulser::Line { file, bit } => set_line(file, *bit, true),
+            // The pulse is the top of the second by the local clock itself
+            Pulser::Chrony { socket, path } => {
+                let sample = SockSample {
+                    tv: libc::timeval {
+                        tv_sec: second as libc::time_t,
+                        tv_usec: 0,
+                    },
+                    offset: 0.0,
+                    pulse: 1,
+                    leap: 0,
+                    _pad: 0,
+                    magic: SOCK_MAGIC,
+                };
+                // SockSample is plain data of exactly this size
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(
+                        &sample as *const SockSample as *const u8,
+                        std::mem::size_of::<SockSample>(),
+                    )
+                };
+                socket.send_to(bytes, path).map(|_| ())
+            }
+        }
+    }
+
+    fn lower(&self) -> std::io::Result<()> {
+        match self {
+            Pulser::Line { file, bit } => set_line(file, *bit, false),
+            Pulser::Chrony { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn set_line(file: &std::fs::File, bit: libc::c_int, raised: bool) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let request = if raised {
+        libc::TIOCMBIS
+    } else {
+        libc::TIOCMBIC
+    };
+    // The ioctl only reads the one c_int behind the pointer
+    if unsafe { libc::ioctl(file.as_raw_fd(), request, &bit) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
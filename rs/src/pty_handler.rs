@@ -1,284 +1,659 @@
 // src/pty_handler.rs
 
-use libc::{close, openpty, ptsname};
-use nix::unistd::close as nix_close;
+use crate::error::SimError;
+use crate::output::{Backlog, OutputSink};
+use crate::runtime::shutdown_requested;
+use nix::fcntl::{fcntl, flock, FcntlArg, FlockArg, OFlag};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{grantpt, posix_openpt, unlockpt, PtyMaster};
+use nix::sys::termios::{self, BaudRate, LocalFlags, SetArg};
+use nix::unistd::{self, close as nix_close};
 use std::error::Error;
-use std::ffi::CStr;
-use std::fs;
-use std::fs::OpenOptions;
-use std::io::ErrorKind;
-use std::os::unix::fs::symlink;
-use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
 use std::path::Path;
-use std::ptr;
+use std::process;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use std::thread;
+use std::time::Duration;
+use tokio::io::unix::{AsyncFd, AsyncFdReadyGuard};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
 
-pub struct PtyHandler {
-    pub shutdown_event: Arc<AtomicBool>,
+// Sees every chunk the client writes to the output side of a link
+pub type ReceiveTap = Box<dyn FnMut(&[u8]) + Send>;
+// Told whether a client has the output side open, on start and whenever
+// that changes
+pub type ReaderTap = Box<dyn FnMut(bool) + Send>;
+
+// How often a link without a client checks whether one has arrived
+const HANGUP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// The PTYs of one port, see PtyMode. A linked pair has two PTYs whose
+// master sides are connected, so that whatever is written to the input
+// symlink can be read from the output symlink. A single port has only the
+// input PTY, which clients open and the simulator writes to the master of.
+pub struct PtyLink {
+    pub gps_input_path: String,
+    pub gps_output_path: Option<String>,
+    // What the symlinks point at; cleanup leaves them alone once they point
+    // elsewhere, e.g. after another run took them over
+    pub slave_name1: String,
+    pub slave_name2: Option<String>,
+    // Held while the link exists, see PtyHandler::lock_links
+    pub lock: Option<File>,
     pub master_fd1: Option<RawFd>,
     pub master_fd2: Option<RawFd>,
-    pub forward_thread1: Option<thread::JoinHandle<()>>,
-    pub forward_thread2: Option<thread::JoinHandle<()>>,
-    // Keep the slave FDs open to prevent Bad file descriptor
+    // Watches the masters and moves data between them
+    pub forwarder: Option<JoinHandle<()>>,
+    // Keeps the input side of a pair open, so its master never hangs up.
+    // The PTY clients open is left to them: its master hangs up while none
+    // has it open.
     pub slave_fd1: Option<RawFd>,
-    pub slave_fd2: Option<RawFd>,
+    // Whether what clients write goes to the input side too
+    pub loopback: bool,
+    // Whether a client has the PTY open, as the forwarder last saw it
+    pub connected: Arc<AtomicBool>,
+    // Handed to the forwarder once it starts
+    pub on_receive: Option<ReceiveTap>,
+    pub on_reader: Option<ReaderTap>,
+}
+
+pub struct PtyHandler {
+    pub shutdown_event: Arc<AtomicBool>,
+    pub links: Vec<PtyLink>,
+    // Line speed reported to clients of the slave side, if set
+    pub baud_rate: Option<u32>,
+    // Hold <gps_input_path>.lock for every link, so that a second simulator
+    // on the same paths fails instead of taking them over. Holding it also
+    // means links still there are left by a run that has ended.
+    pub lock_links: bool,
+    // Forward what clients write to the input PTY of linked pairs too, see
+    // PtyMode::Loopback
+    pub loopback: bool,
+    // Runs the forwarders
+    runtime: Handle,
 }
 
 impl PtyHandler {
-    pub fn new(shutdown_event: Arc<AtomicBool>) -> Self {
+    pub fn new(shutdown_event: Arc<AtomicBool>, runtime: Handle) -> Self {
         PtyHandler {
             shutdown_event,
-            master_fd1: None,
-            master_fd2: None,
-            forward_thread1: None,
-            forward_thread2: None,
-            slave_fd1: None,
-            slave_fd2: None,
+            links: Vec::new(),
+            baud_rate: None,
+            lock_links: false,
+            loopback: false,
+            runtime,
         }
     }
 
+    // Creates one more linked pair; may be called once per simulated port
     pub fn setup_linked_ptys(
         &mut self,
         gps_input_path: &str,
         gps_output_path: &str,
-    ) -> Result<(), Box<dyn Error>> {
+        on_receive: Option<ReceiveTap>,
+        on_reader: Option<ReaderTap>,
+    ) -> Result<(), SimError> {
+        let lock = if self.lock_links {
+            Some(lock_file(&lock_path(gps_input_path))?)
+        } else {
+            None
+        };
+
         // Create first PTY
-        let (master_fd1, slave_name1) = self.create_pty()?;
-        println!("Created PTY1: {}", slave_name1);
+        let (master_fd1, slave_fd1, slave_name1) = self.create_pty()?;
+        info!("Created PTY1: {}", slave_name1);
 
         // Create second PTY
-        let (master_fd2, slave_name2) = self.create_pty()?;
-        println!("Created PTY2: {}", slave_name2);
+        let (master_fd2, slave_fd2, slave_name2) = match self.create_pty() {
+            Ok(pty) => pty,
+            Err(e) => {
+                let _ = nix_close(slave_fd1);
+                let _ = nix_close(master_fd1);
+                return Err(e);
+            }
+        };
+        info!("Created PTY2: {}", slave_name2);
+        // The line settings stay with the PTY until its master is closed
+        let _ = nix_close(slave_fd2);
+
+        // Track the link right away so cleanup can close what was created
+        let take_over = lock.is_some();
+        self.links.push(PtyLink {
+            gps_input_path: gps_input_path.to_string(),
+            gps_output_path: Some(gps_output_path.to_string()),
+            slave_name1: slave_name1.clone(),
+            slave_name2: Some(slave_name2.clone()),
+            lock,
+            master_fd1: Some(master_fd1),
+            master_fd2: Some(master_fd2),
+            forwarder: None,
+            slave_fd1: Some(slave_fd1),
+            loopback: self.loopback,
+            connected: Arc::new(AtomicBool::new(false)),
+            on_receive,
+            on_reader,
+        });
 
         // Create symbolic links
-        self.create_symlink(&slave_name1, gps_input_path)?;
-        self.create_symlink(&slave_name2, gps_output_path)?;
+        create_symlink(&slave_name1, gps_input_path, take_over)?;
+        create_symlink(&slave_name2, gps_output_path, take_over)?;
 
-        // Open the slave ends to keep them open
-        let slave_fd1 = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(gps_input_path)
-            .map_err(|e| {
-                eprintln!("Failed to open gps_input_path {}: {}", gps_input_path, e);
-                e
-            })?
-            .as_raw_fd();
-        self.slave_fd1 = Some(slave_fd1);
-        println!("Opened gps_input_path: {}", gps_input_path);
-
-        let slave_fd2 = OpenOptions::new()
+        Ok(())
+    }
+
+    // Creates the one PTY of a single port and returns the sink writing to
+    // its clients; may be called once per simulated port
+    pub fn setup_single_pty(
+        &mut self,
+        gps_input_path: &str,
+        on_receive: Option<ReceiveTap>,
+        on_reader: Option<ReaderTap>,
+    ) -> Result<MasterSink, SimError> {
+        let lock = if self.lock_links {
+            Some(lock_file(&lock_path(gps_input_path))?)
+        } else {
+            None
+        };
+
+        let (master_fd, slave_fd, slave_name) = self.create_pty()?;
+        info!("Created PTY: {}", slave_name);
+        let _ = nix_close(slave_fd);
+
+        let take_over = lock.is_some();
+        let connected = Arc::new(AtomicBool::new(false));
+        self.links.push(PtyLink {
+            gps_input_path: gps_input_path.to_string(),
+            gps_output_path: None,
+            slave_name1: slave_name.clone(),
+            slave_name2: None,
+            lock,
+            master_fd1: Some(master_fd),
+            master_fd2: None,
+            forwarder: None,
+            slave_fd1: None,
+            loopback: false,
+            connected: connected.clone(),
+            on_receive,
+            on_reader,
+        });
+        create_symlink(&slave_name, gps_input_path, take_over)?;
+
+        Ok(MasterSink {
+            path: gps_input_path.to_string(),
+            writer: MasterWriter(master_fd),
+            backlog: Backlog::new(gps_input_path),
+            connected,
+        })
+    }
+
+    // Opens a PTY through the portable posix_openpt/grantpt/unlockpt
+    // sequence, which behaves the same on Linux, Android and macOS, and
+    // returns the master FD, a configured slave FD and the slave path.
+    fn create_pty(&self) -> Result<(RawFd, RawFd, String), SimError> {
+        let pty_error = |context: &str| {
+            let context = context.to_string();
+            move |source| SimError::Pty { context, source }
+        };
+        let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)
+            .map_err(pty_error("Failed to create PTY"))?;
+        grantpt(&master).map_err(pty_error("Failed to grant PTY"))?;
+        unlockpt(&master).map_err(pty_error("Failed to unlock PTY"))?;
+
+        let slave_name =
+            slave_name(&master).map_err(pty_error("Failed to get slave device name"))?;
+
+        let slave = OpenOptions::new()
             .read(true)
             .write(true)
-            .open(gps_output_path)
-            .map_err(|e| {
-                eprintln!("Failed to open gps_output_path {}: {}", gps_output_path, e);
-                e
-            })?
-            .as_raw_fd();
-        self.slave_fd2 = Some(slave_fd2);
-        println!("Opened gps_output_path: {}", gps_output_path);
-
-        // Store master FDs for forwarding
-        self.master_fd1 = Some(master_fd1);
-        self.master_fd2 = Some(master_fd2);
+            .custom_flags(libc::O_NOCTTY)
+            .open(&slave_name)
+            .map_err(|e| SimError::io(format!("Failed to open {}", slave_name), e))?;
 
-        Ok(())
+        // Configure the line before any client gets to open the slave
+        self.configure_slave(slave.as_raw_fd(), &slave_name)?;
+
+        // The forwarder waits for the master to become readable instead
+        fcntl(master.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .map_err(pty_error("Failed to make PTY master non-blocking"))?;
+
+        Ok((master.into_raw_fd(), slave.into_raw_fd(), slave_name))
     }
 
-    fn create_pty(&self) -> Result<(RawFd, String), Box<dyn Error>> {
-        let mut master_fd: i32 = 0;
-        let mut slave_fd: i32 = 0;
-
-        let result = unsafe {
-            openpty(
-                &mut master_fd,
-                &mut slave_fd,
-                ptr::null_mut(),
-                ptr::null_mut(),
-                ptr::null_mut(),
-            )
+    // Put the slave into raw mode without echo, so that nothing written to
+    // one end of the link is reflected back into the stream, and apply the
+    // configured baud rate.
+    fn configure_slave(&self, slave_fd: RawFd, slave_name: &str) -> Result<(), SimError> {
+        let baud = match self.baud_rate {
+            Some(baud) => Some(baud_rate_from_u32(baud).ok_or_else(|| {
+                SimError::Usage(format!("Unsupported baud rate for PTY: {}", baud))
+            })?),
+            None => None,
         };
+        let configure = || -> nix::Result<()> {
+            let mut attrs = termios::tcgetattr(slave_fd)?;
+            termios::cfmakeraw(&mut attrs);
+            attrs.local_flags.remove(
+                LocalFlags::ECHO | LocalFlags::ECHOE | LocalFlags::ECHOK | LocalFlags::ECHONL,
+            );
+            if let Some(baud) = baud {
+                termios::cfsetspeed(&mut attrs, baud)?;
+            }
+            termios::tcsetattr(slave_fd, SetArg::TCSANOW, &attrs)
+        };
+        configure().map_err(|source| SimError::Pty {
+            context: format!("Failed to configure {}", slave_name),
+            source,
+        })
+    }
 
-        if result != 0 {
-            eprintln!("Failed to create PTY");
-            return Err(Box::new(std::io::Error::last_os_error()));
-        }
+    pub fn start_forwarding(&mut self) -> Result<(), SimError> {
+        for link in self.links.iter_mut() {
+            if link.forwarder.is_some() {
+                continue;
+            }
 
-        // Get the slave device name using ptsname
-        let slave_name_ptr = unsafe { ptsname(master_fd) };
-        if slave_name_ptr.is_null() {
-            eprintln!("Failed to get slave device name");
-            return Err(Box::new(std::io::Error::last_os_error()));
+            let Some(master_fd1) = link.master_fd1 else {
+                return Err(SimError::Other(format!(
+                    "PTY link for {} is closed",
+                    link.gps_input_path
+                )));
+            };
+
+            let (input, output) = match (link.master_fd2, &link.gps_output_path) {
+                (Some(master_fd2), Some(gps_output_path)) => {
+                    // Registering the master needs the runtime's reactor
+                    let _runtime = self.runtime.enter();
+                    let master1 = AsyncFd::new(master_fd1).map_err(|e| {
+                        SimError::io(format!("Failed to watch {}", link.gps_input_path), e)
+                    })?;
+                    (
+                        Some((master1, link.gps_input_path.clone())),
+                        (master_fd2, gps_output_path.clone()),
+                    )
+                }
+                // The only PTY of a single port is the one clients open
+                _ => (None, (master_fd1, link.gps_input_path.clone())),
+            };
+            let forwarder = Forwarder {
+                shutdown_event: self.shutdown_event.clone(),
+                input,
+                output,
+                loopback: link.loopback,
+                connected: link.connected.clone(),
+                tap: link.on_receive.take(),
+                on_reader: link.on_reader.take(),
+            };
+            link.forwarder = Some(self.runtime.spawn(forwarder.run()));
         }
 
-        let c_str = unsafe { CStr::from_ptr(slave_name_ptr) };
-        let slave_name = c_str.to_str()?.to_string();
+        Ok(())
+    }
+
+    pub fn cleanup(&mut self) -> Result<(), SimError> {
+        // Signal the forwarders to shut down
+        self.shutdown_event.store(true, Ordering::SeqCst);
+
+        for mut link in self.links.drain(..) {
+            // Wait for the forwarder to finish
+            if let Some(forwarder) = link.forwarder.take() {
+                let _ = self.runtime.block_on(forwarder);
+            }
 
-        // Close the slave FD as we don't need it here
-        unsafe {
-            libc::close(slave_fd);
+            // Remove the symbolic links
+            remove_symlink(&link.gps_input_path, &link.slave_name1)?;
+            if let (Some(gps_output_path), Some(slave_name2)) =
+                (&link.gps_output_path, &link.slave_name2)
+            {
+                remove_symlink(gps_output_path, slave_name2)?;
+                info!(
+                    "Cleaned up symbolic links {} and {}.",
+                    link.gps_input_path, gps_output_path
+                );
+            } else {
+                info!("Cleaned up symbolic link {}.", link.gps_input_path);
+            }
+
+            // Close the slave FD
+            if let Some(slave_fd1) = link.slave_fd1.take() {
+                let _ = nix_close(slave_fd1);
+            }
+
+            // Close master FDs
+            if let Some(master_fd1) = link.master_fd1.take() {
+                let _ = nix_close(master_fd1);
+            }
+            if let Some(master_fd2) = link.master_fd2.take() {
+                let _ = nix_close(master_fd2);
+            }
+            info!("Closed PTYs for {}", link.gps_input_path);
+
+            // Removed while still held, so nobody takes a lock on a file
+            // that is about to go
+            if link.lock.take().is_some() {
+                let _ = fs::remove_file(lock_path(&link.gps_input_path));
+            }
         }
 
-        Ok((master_fd, slave_name))
+        Ok(())
     }
+}
 
-    fn create_symlink(&self, target: &str, link_path: &str) -> Result<(), Box<dyn Error>> {
-        println!("Creating symlink from {} to {}", link_path, target);
-        let link = Path::new(link_path);
-        if link.exists() {
-            fs::remove_file(link)?;
+// Runs the cleanup on early returns too, e.g. when a later service fails
+// to start after the links were set up
+impl Drop for PtyHandler {
+    fn drop(&mut self) {
+        if let Err(e) = self.cleanup() {
+            error!("Failed to clean up PTYs: {}", e);
         }
-        symlink(target, link)?;
-        Ok(())
     }
+}
 
-    pub fn start_forwarding(&mut self) -> Result<(), Box<dyn Error>> {
-        let shutdown_event = self.shutdown_event.clone();
+// Points the link at the target, replacing what is there atomically. Only
+// links left by an earlier run are replaced: dangling ones, whose PTY went
+// away with that run, and with take_over any link. Anything else may
+// belong to a simulator still running, or not be a link at all.
+fn create_symlink(target: &str, link_path: &str, take_over: bool) -> Result<(), SimError> {
+    info!("Creating symlink from {} to {}", link_path, target);
+    let link_error = |source| SimError::Symlink {
+        link: link_path.to_string(),
+        target: target.to_string(),
+        source,
+    };
+    let link = Path::new(link_path);
+    match fs::symlink_metadata(link) {
+        Ok(metadata) if !metadata.file_type().is_symlink() => {
+            return Err(link_error(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a file that is not a symlink is in the way",
+            )));
+        }
+        // Following the link finds nothing
+        Ok(_) if !link.exists() => {
+            info!("Replacing stale link {} of an earlier run", link_path);
+        }
+        Ok(_) if take_over => {
+            info!("Taking over {} from an earlier run", link_path);
+        }
+        Ok(_) => {
+            let current = fs::read_link(link).unwrap_or_default();
+            return Err(link_error(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "it points at {}, which is in use; is another simulator running?",
+                    current.display()
+                ),
+            )));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(link_error(e)),
+    }
 
-        let master_fd1 = self.master_fd1.unwrap();
-        let master_fd2 = self.master_fd2.unwrap();
+    // Renamed over the old link, so clients never find the path missing
+    let temporary = format!("{}.{}.tmp", link_path, process::id());
+    let _ = fs::remove_file(&temporary);
+    symlink(target, &temporary)
+        .and_then(|_| fs::rename(&temporary, link))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temporary);
+            link_error(e)
+        })
+}
 
-        // Forward data from master_fd1 to master_fd2
-        let shutdown_event_clone = shutdown_event.clone();
-        let forward_thread1 = thread::spawn(move || {
-            let mut buf = [0u8; 1024];
-            loop {
-                if shutdown_event_clone.load(Ordering::SeqCst) {
-                    break;
-                }
-                match unsafe {
-                    libc::read(master_fd1, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
-                } {
-                    n if n > 0 => {
-                        let write_result = unsafe {
-                            libc::write(master_fd2, buf.as_ptr() as *const libc::c_void, n as usize)
-                        };
-                        if write_result == -1 {
-                            let err = std::io::Error::last_os_error();
-                            eprintln!("Error writing to master_fd2: {}", err);
-                            break;
-                        }
-                    }
-                    0 => {
-                        // EOF reached
-                        println!("EOF on master_fd1");
-                        break;
-                    }
-                    -1 => {
-                        let err = std::io::Error::last_os_error();
-                        if err.kind() == ErrorKind::Interrupted {
-                            continue;
-                        } else {
-                            eprintln!("Error reading from master_fd1: {}", err);
+// Removes the link if it still points at the target
+fn remove_symlink(link_path: &str, target: &str) -> Result<(), SimError> {
+    let ours = fs::read_link(link_path).is_ok_and(|current| current == Path::new(target));
+    if ours {
+        fs::remove_file(link_path)
+            .map_err(|e| SimError::io(format!("Failed to remove {}", link_path), e))?;
+    }
+    Ok(())
+}
+
+fn lock_path(gps_input_path: &str) -> String {
+    format!("{}.lock", gps_input_path)
+}
+
+// Takes the lock for as long as the file stays open. It goes away with the
+// process however that ends, so a lock file left by a crash is free.
+fn lock_file(path: &str) -> Result<File, SimError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| SimError::io(format!("Failed to open {}", path), e))?;
+    flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock).map_err(|e| {
+        SimError::io(
+            format!("{} is held by another simulator", path),
+            io::Error::from(e),
+        )
+    })?;
+    // The PID of the holder, for whoever finds the file
+    let _ = file.set_len(0);
+    let _ = writeln!(&file, "{}", process::id());
+    Ok(file)
+}
+
+// Moves the data of one link until shutdown. What the simulator writes to
+// the input side goes to the client; while no client has its PTY open, it
+// is dropped instead of piling up for whoever opens it next. What clients
+// write goes to the tap, and in loopback mode to the input side too.
+struct Forwarder {
+    shutdown_event: Arc<AtomicBool>,
+    // Master and path of the PTY the simulator writes to; a single port
+    // has none, the simulator writes to the client's master itself
+    input: Option<(AsyncFd<RawFd>, String)>,
+    // Master and path of the PTY clients open
+    output: (RawFd, String),
+    loopback: bool,
+    connected: Arc<AtomicBool>,
+    tap: Option<ReceiveTap>,
+    on_reader: Option<ReaderTap>,
+}
+
+impl Forwarder {
+    async fn run(mut self) {
+        let (output, output_path) = self.output.clone();
+        let label = match &self.input {
+            Some((_, input_path)) => format!("{} <-> {}", input_path, output_path),
+            None => output_path.clone(),
+        };
+        // Writes never wait for a reader; see Backlog
+        let mut to_input = self
+            .input
+            .as_ref()
+            .map(|(master, path)| (MasterWriter(*master.get_ref()), Backlog::new(path)));
+        let mut to_output = (MasterWriter(output), Backlog::new(&output_path));
+        // Watched only while a client has it open, as a hangup stays in the
+        // readiness for as long as the master is registered
+        let mut client: Option<AsyncFd<RawFd>> = None;
+        let mut connected = None;
+        let mut buf = [0u8; 1024];
+        loop {
+            let reader = !hung_up(output);
+            if connected != Some(reader) {
+                if reader {
+                    info!("Client opened {}, resuming", output_path);
+                    match AsyncFd::new(output) {
+                        Ok(master) => client = Some(master),
+                        Err(e) => {
+                            error!("Failed to watch {}: {}", output_path, e);
                             break;
                         }
                     }
-                    _ => break,
+                } else {
+                    info!("No client on {}, pausing", output_path);
+                    client = None;
+                    to_output.1.clear();
+                }
+                self.connected.store(reader, Ordering::SeqCst);
+                if let Some(on_reader) = self.on_reader.as_mut() {
+                    on_reader(reader);
                 }
+                connected = Some(reader);
             }
-            println!("Forwarding thread1 exiting.");
-            // Do not close master_fd1 here
-        });
 
-        // Forward data from master_fd2 to master_fd1
-        let shutdown_event_clone = shutdown_event.clone();
-        let forward_thread2 = thread::spawn(move || {
-            let mut buf = [0u8; 1024];
-            loop {
-                if shutdown_event_clone.load(Ordering::SeqCst) {
-                    break;
-                }
-                match unsafe {
-                    libc::read(master_fd2, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
-                } {
-                    n if n > 0 => {
-                        let write_result = unsafe {
-                            libc::write(master_fd1, buf.as_ptr() as *const libc::c_void, n as usize)
-                        };
-                        if write_result == -1 {
-                            let err = std::io::Error::last_os_error();
-                            eprintln!("Error writing to master_fd1: {}", err);
-                            break;
-                        }
-                    }
-                    0 => {
-                        // EOF reached
-                        println!("EOF on master_fd2");
-                        break;
+            // Only waiting is raced; a chunk once read is always handled
+            let input = self.input.as_ref().map(|(master, _)| master);
+            let result = tokio::select! {
+                _ = shutdown_requested(&self.shutdown_event) => break,
+                ready = readable(input) => match ready {
+                    Ok(guard) => {
+                        let to = client.is_some().then_some(&mut to_output);
+                        forward_chunk(guard, to, &mut buf, None)
                     }
-                    -1 => {
-                        let err = std::io::Error::last_os_error();
-                        if err.kind() == ErrorKind::Interrupted {
-                            continue;
-                        } else {
-                            eprintln!("Error reading from master_fd2: {}", err);
-                            break;
-                        }
+                    Err(e) => Err(e),
+                },
+                ready = readable(client.as_ref()) => match ready {
+                    Ok(guard) => {
+                        let to = if self.loopback { to_input.as_mut() } else { None };
+                        forward_chunk(guard, to, &mut buf, self.tap.as_mut())
                     }
-                    _ => break,
+                    Err(e) => Err(e),
+                },
+                // A master without a slave reads as EIO, so the client's
+                // side is polled until one comes
+                _ = tokio::time::sleep(HANGUP_POLL_INTERVAL), if client.is_none() => Ok(true),
+            };
+            match result {
+                Ok(true) => {}
+                // The client closing its PTY between polls
+                Err(_) if hung_up(output) => {}
+                Ok(false) => {
+                    info!("EOF while forwarding {}", label);
+                    break;
+                }
+                Err(e) => {
+                    error!("Error forwarding {}: {}", label, e);
+                    break;
                 }
             }
-            println!("Forwarding thread2 exiting.");
-            // Do not close master_fd2 here
-        });
+        }
+        info!("Forwarding {} exiting.", label);
+        // Do not close the master FDs here
+    }
+}
 
-        // Store the forwarding threads so we can join them later
-        self.forward_thread1 = Some(forward_thread1);
-        self.forward_thread2 = Some(forward_thread2);
+// Waits for the master to become readable, or forever without one
+async fn readable(master: Option<&AsyncFd<RawFd>>) -> io::Result<AsyncFdReadyGuard<'_, RawFd>> {
+    match master {
+        Some(master) => master.readable().await,
+        None => std::future::pending().await,
+    }
+}
 
-        Ok(())
+// Reads what is available on a readable master and queues all of it for
+// the other, or drops it without one. Returns Ok(false) on EOF.
+fn forward_chunk(
+    mut ready: AsyncFdReadyGuard<'_, RawFd>,
+    to: Option<&mut (MasterWriter, Backlog)>,
+    buf: &mut [u8],
+    tap: Option<&mut ReceiveTap>,
+) -> io::Result<bool> {
+    let result = ready.try_io(|fd| unistd::read(*fd.get_ref(), buf).map_err(io::Error::from));
+    let n = match result {
+        Ok(Ok(n)) => n,
+        Ok(Err(e)) => {
+            // Wait for the next event rather than failing again right away
+            ready.clear_ready();
+            return Err(e);
+        }
+        // Spurious wakeup, wait again
+        Err(_would_block) => return Ok(true),
+    };
+    if n == 0 {
+        return Ok(false);
     }
+    if let Some(tap) = tap {
+        tap(&buf[..n]);
+    }
+    if let Some((writer, backlog)) = to {
+        backlog.write(writer, &buf[..n])?;
+    }
+    Ok(true)
+}
 
-    pub fn cleanup(
-        &mut self,
-        gps_input_path: &str,
-        gps_output_path: &str,
-    ) -> Result<(), Box<dyn Error>> {
-        // Signal forwarding threads to shutdown
-        self.shutdown_event.store(true, Ordering::SeqCst);
+// Whether no client has the slave side of this master open
+fn hung_up(master: RawFd) -> bool {
+    let mut fds = [PollFd::new(master, PollFlags::empty())];
+    matches!(poll(&mut fds, 0), Ok(n) if n > 0)
+        && fds[0]
+            .revents()
+            .is_some_and(|revents| revents.contains(PollFlags::POLLHUP))
+}
 
-        // Wait for forwarding threads to finish
-        if let Some(thread) = self.forward_thread1.take() {
-            let _ = thread.join();
-        }
-        if let Some(thread) = self.forward_thread2.take() {
-            let _ = thread.join();
-        }
+// What the simulator writes to a single port, written into the master of
+// its PTY while a client has it open and dropped otherwise
+pub struct MasterSink {
+    path: String,
+    writer: MasterWriter,
+    backlog: Backlog,
+    connected: Arc<AtomicBool>,
+}
 
-        // Remove the symbolic links
-        if Path::new(gps_input_path).exists() {
-            fs::remove_file(gps_input_path)?;
-        }
-        if Path::new(gps_output_path).exists() {
-            fs::remove_file(gps_output_path)?;
-        }
-        println!("Cleaned up symbolic links.");
+impl OutputSink for MasterSink {
+    fn name(&self) -> String {
+        self.path.clone()
+    }
 
-        // Close the slave FDs
-        if let Some(slave_fd1) = self.slave_fd1.take() {
-            let _ = nix_close(slave_fd1);
-            println!("Closed slave_fd1");
-        }
-        if let Some(slave_fd2) = self.slave_fd2.take() {
-            let _ = nix_close(slave_fd2);
-            println!("Closed slave_fd2");
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if !self.connected.load(Ordering::SeqCst) {
+            self.backlog.clear();
+            return Ok(());
         }
+        self.backlog.write(&mut self.writer, data)?;
+        Ok(())
+    }
+}
 
-        // Close master FDs
-        if let Some(master_fd1) = self.master_fd1.take() {
-            let _ = nix_close(master_fd1);
-            println!("Closed master_fd1");
-        }
-        if let Some(master_fd2) = self.master_fd2.take() {
-            let _ = nix_close(master_fd2);
-            println!("Closed master_fd2");
-        }
+// Non-blocking writes to a master; a full PTY buffer is WouldBlock
+struct MasterWriter(RawFd);
 
+impl Write for MasterWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        unistd::write(self.0, buf).map_err(io::Error::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn slave_name(master: &PtyMaster) -> nix::Result<String> {
+    nix::pty::ptsname_r(master)
+}
+
+// ptsname_r is Linux only; elsewhere fall back to ptsname, which is fine as
+// PTYs are only ever created from the main thread
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn slave_name(master: &PtyMaster) -> nix::Result<String> {
+    unsafe { nix::pty::ptsname(master) }
+}
+
+fn baud_rate_from_u32(baud: u32) -> Option<BaudRate> {
+    Some(match baud {
+        1200 => BaudRate::B1200,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        230400 => BaudRate::B230400,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        460800 => BaudRate::B460800,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        921600 => BaudRate::B921600,
+        _ => return None,
+    })
+}
@@ -0,0 +1,94 @@
+// src/recorder.rs
+
+use chrono::{SecondsFormat, Utc};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+const BYTES_PER_LINE: usize = 16;
+
+// Logs what clients write into the ports, one timestamped hex+ASCII dump
+// per chunk read. All ports share the file; records name their port.
+#[derive(Clone)]
+pub struct Recorder {
+    path: String,
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> Result<Self, Box<dyn Error>> {
+        info!("Recording received data to {}", path);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .inspect_err(|e| error!("Failed to open {}: {}", path, e))?;
+
+        Ok(Recorder {
+            path: path.to_string(),
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+        })
+    }
+
+    // Returns a receive tap that records under the given port name
+    pub fn tap(&self, port: &str) -> impl FnMut(&[u8]) + Send {
+        let recorder = self.clone();
+        let port = port.to_string();
+        move |data| {
+            if let Err(e) = recorder.record(&port, data) {
+                error!("Error recording to {}: {}", recorder.path, e);
+            }
+        }
+    }
+
+    pub fn record(&self, port: &str, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let record = format_record(port, data);
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(record.as_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// Header line, then lines like hexdump -C:
+//   0000  24 50 4d 54 4b 32 32 30  2c 31 30 30 30 2a 31 46  |$PMTK220,1000*1F|
+fn format_record(port: &str, data: &[u8]) -> String {
+    let mut out = format!(
+        "{} {} {} bytes\n",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        port,
+        data.len()
+    );
+
+    for (i, line) in data.chunks(BYTES_PER_LINE).enumerate() {
+        let _ = write!(out, "  {:04x} ", i * BYTES_PER_LINE);
+        for j in 0..BYTES_PER_LINE {
+            if j == BYTES_PER_LINE / 2 {
+                out.push(' ');
+            }
+            match line.get(j) {
+                Some(byte) => {
+                    let _ = write!(out, " {:02x}", byte);
+                }
+                None => out.push_str("   "),
+            }
+        }
+
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(out, "  |{}|", ascii);
+    }
+
+    out
+}
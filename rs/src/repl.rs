@@ -0,0 +1,222 @@
+// src/repl.rs
+
+use crate::control::{ControlCommand, Controller};
+use crate::nmea_generator::{Crab, Environment};
+use crate::trajectory::TrajectorySource;
+use std::error::Error;
+use std::io::{self, BufRead};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+
+const HELP: &str = "Commands:
+  pos <lat> <lon> [alt]         pin the position
+  speed <knots> [course]        pin speed and optionally course
+  crab <none|offset:<deg>|current:<knots>@<set>>
+  trajectory <external|walk|route:<file>> [jump]
+  fix <none|gps|dgps|rtk|float|0-8|auto>
+  rtk <fixed-s> <float-s> | rtk off
+                                RTK fixed and float by turns
+  sats <0-12|auto> | hdop <value|auto>
+  sbas <on|off>                 SBAS corrections and a DGPS fix
+  env <open-sky|suburban|urban|indoor>
+  rate <hz> [port]              epoch rate of all ports or one
+  sentence <GGA|RMC|...> <on|off> [port]
+  pause | resume | step [n] | release
+  lose-fix | regain-fix         report no fix, with empty coordinates
+  osnma <on|off|none>           OSNMA status the epochs report
+  inject <sentence> [port]      e.g. inject GPTXT,01,01,02,hello
+  inject-every <s> <sentence> [port] | clear-injections [port]
+  state | help | quit";
+
+// Reads short commands from stdin, one per line, and applies them through
+// the same controller as the control socket. Ends on EOF or "quit".
+pub fn spawn_repl(
+    controller: Controller,
+    shutdown_event: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    println!("Reading commands from stdin, type 'help' for a list");
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            let line = line.trim();
+            match line {
+                "" => continue,
+                "help" | "?" => println!("{}", HELP),
+                "quit" | "exit" => {
+                    shutdown_event.store(true, Ordering::SeqCst);
+                    break;
+                }
+                _ => match parse_command(line) {
+                    Ok(command) => {
+                        let query = matches!(command, ControlCommand::GetState);
+                        match controller.apply(command) {
+                            Ok(reply) if query => println!("{}", reply),
+                            Ok(_) => println!("ok: {}", line),
+                            Err(e) => println!("error: {}", e),
+                        }
+                    }
+                    Err(e) => println!("error: {}", e),
+                },
+            }
+            if shutdown_event.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+    })
+}
+
+fn parse_command(line: &str) -> Result<ControlCommand, Box<dyn Error>> {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    let args: Vec<&str> = words.collect();
+
+    let command = match (name, args.as_slice()) {
+        ("pos" | "position", [latitude, longitude, rest @ ..]) if rest.len() <= 1 => {
+            ControlCommand::SetPosition {
+                latitude: number(latitude)?,
+                longitude: number(longitude)?,
+                altitude: rest.first().map(|a| number(a)).transpose()?.unwrap_or(0.0),
+            }
+        }
+        ("speed", [knots, rest @ ..]) if rest.len() <= 1 => ControlCommand::SetSpeed {
+            knots: number(knots)?,
+            course: rest.first().map(|c| number(c)).transpose()?,
+        },
+        ("fix", [quality]) => ControlCommand::SetFixQuality {
+            fix_quality: fix_quality(quality)?,
+        },
+        ("rtk", [fixed, float]) => ControlCommand::SetRtkOscillation {
+            fixed: Some(number(fixed)?),
+            float: Some(number(float)?),
+        },
+        ("rtk", ["off"]) => ControlCommand::SetRtkOscillation {
+            fixed: None,
+            float: None,
+        },
+        ("sats", [count]) => ControlCommand::SetSatellites {
+            count: match *count {
+                "auto" => None,
+                _ => Some(
+                    count
+                        .parse()
+                        .map_err(|_| format!("Invalid satellite count: {}", count))?,
+                ),
+            },
+        },
+        ("hdop", [hdop]) => ControlCommand::SetHdop {
+            hdop: match *hdop {
+                "auto" => None,
+                _ => Some(number(hdop)?),
+            },
+        },
+        ("crab", [crab]) => ControlCommand::SetCrab {
+            crab: Crab::parse(crab)?,
+        },
+        ("trajectory", [source, rest @ ..]) if rest.len() <= 1 => ControlCommand::SetTrajectory {
+            trajectory: TrajectorySource::parse(source)?,
+            jump: match rest {
+                [] => false,
+                ["jump"] => true,
+                _ => return Err(format!("Expected jump, got '{}'", rest[0]).into()),
+            },
+        },
+        ("sbas", [state]) => ControlCommand::SetSbas {
+            enabled: on_off(state)?,
+        },
+        ("env" | "environment", [environment]) => ControlCommand::SetEnvironment {
+            environment: Environment::parse(environment)?,
+        },
+        ("rate", [hz, rest @ ..]) if rest.len() <= 1 => ControlCommand::SetRate {
+            hz: number(hz)?,
+            port: port(rest)?,
+        },
+        ("sentence", [sentence, state, rest @ ..]) if rest.len() <= 1 => {
+            ControlCommand::SetSentence {
+                sentence: sentence.to_string(),
+                enabled: on_off(state)?,
+                port: port(rest)?,
+            }
+        }
+        ("pause", []) => ControlCommand::Pause,
+        ("resume", []) => ControlCommand::Resume,
+        ("release", []) => ControlCommand::Release,
+        ("lose-fix", []) => ControlCommand::LoseFix,
+        ("regain-fix", []) => ControlCommand::RegainFix,
+        ("osnma", [status]) => ControlCommand::SetAuthentication {
+            authenticated: match *status {
+                "on" => Some(true),
+                "off" => Some(false),
+                "none" => None,
+                _ => return Err(format!("Expected on, off or none, got '{}'", status).into()),
+            },
+        },
+        ("step", []) => ControlCommand::Advance { epochs: 1 },
+        ("step", [epochs]) => ControlCommand::Advance {
+            epochs: epochs
+                .parse()
+                .map_err(|_| format!("Invalid epoch count: {}", epochs))?,
+        },
+        ("inject", [sentence, rest @ ..]) if rest.len() <= 1 => ControlCommand::InjectSentence {
+            sentence: sentence.to_string(),
+            port: port(rest)?,
+            time: None,
+            every: None,
+        },
+        ("inject-every", [seconds, sentence, rest @ ..]) if rest.len() <= 1 => {
+            ControlCommand::InjectSentence {
+                sentence: sentence.to_string(),
+                port: port(rest)?,
+                time: None,
+                every: Some(number(seconds)?),
+            }
+        }
+        ("clear-injections", rest) if rest.len() <= 1 => {
+            ControlCommand::ClearInjections { port: port(rest)? }
+        }
+        ("state", []) => ControlCommand::GetState,
+        _ => return Err(format!("Unknown command '{}', type 'help' for a list", line).into()),
+    };
+    Ok(command)
+}
+
+fn number(value: &str) -> Result<f64, Box<dyn Error>> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .ok_or_else(|| format!("Invalid number: {}", value).into())
+}
+
+fn on_off(value: &str) -> Result<bool, Box<dyn Error>> {
+    match value {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(format!("Expected on or off, got '{}'", value).into()),
+    }
+}
+
+fn port(args: &[&str]) -> Result<Option<usize>, Box<dyn Error>> {
+    args.first()
+        .map(|p| p.parse().map_err(|_| format!("Invalid port: {}", p).into()))
+        .transpose()
+}
+
+// GGA fix quality by name or number; "auto" hands it back to the generator
+fn fix_quality(value: &str) -> Result<Option<u8>, Box<dyn Error>> {
+    let quality = match value.to_ascii_lowercase().as_str() {
+        "auto" => return Ok(None),
+        "none" | "no" => 0,
+        "gps" => 1,
+        "dgps" => 2,
+        "pps" => 3,
+        "rtk" => 4,
+        "float" => 5,
+        "dr" => 6,
+        other => other
+            .parse()
+            .map_err(|_| format!("Unknown fix quality: {}", value))?,
+    };
+    Ok(Some(quality))
+}
@@ -0,0 +1,194 @@
+// src/replay.rs
+
+use crate::checksum::checksum;
+use crate::error::SimError;
+use crate::parser::Parser;
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Timelike};
+use std::fs;
+use std::time::Duration;
+
+// Between epochs whose sentences carry no time
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+// A recorded NMEA log split into epochs, to be sent out at the pace it was
+// recorded at. A sentence with a time other than that of the current epoch
+// starts the next one; sentences without a time, like GSA and GSV, and
+// lines we can't parse go with the epoch before them.
+pub struct Replay {
+    pub epochs: Vec<Epoch>,
+}
+
+pub struct Epoch {
+    pub time: Option<NaiveTime>,
+    // The lines as recorded, each ending in CRLF
+    pub sentences: String,
+}
+
+impl Replay {
+    pub fn load(path: &str) -> Result<Self, SimError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| SimError::io(format!("Failed to read {}", path), e))?;
+        Ok(Self::from_log(&text))
+    }
+
+    pub fn from_log(text: &str) -> Self {
+        let mut parser = Parser::new();
+        let mut epochs: Vec<Epoch> = Vec::new();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let time = parser
+                .parse(line)
+                .ok()
+                .and_then(|sentence| sentence.time())
+                .map(|time| time.time());
+            match epochs.last_mut() {
+                Some(epoch) if time.is_none() || epoch.time.is_none() || epoch.time == time => {
+                    epoch.time = epoch.time.or(time);
+                }
+                _ => epochs.push(Epoch {
+                    time,
+                    sentences: String::new(),
+                }),
+            }
+            let epoch = epochs.last_mut().unwrap();
+            epoch.sentences.push_str(line);
+            epoch.sentences.push_str("\r\n");
+        }
+        Replay { epochs }
+    }
+
+    // How long to wait after sending the given epoch, from the times of
+    // it and the next one; logs may run past midnight
+    pub fn interval_after(&self, index: usize) -> Duration {
+        let times = (
+            self.epochs.get(index).and_then(|epoch| epoch.time),
+            self.epochs.get(index + 1).and_then(|epoch| epoch.time),
+        );
+        match times {
+            (Some(time), Some(next)) => {
+                let mut delta = next.signed_duration_since(time);
+                if delta < TimeDelta::zero() {
+                    delta += TimeDelta::days(1);
+                }
+                delta.to_std().unwrap_or(DEFAULT_INTERVAL)
+            }
+            _ => DEFAULT_INTERVAL,
+        }
+    }
+
+    // From the first epoch to where a loop starts over, one interval after
+    // the last
+    pub fn duration(&self) -> Duration {
+        (0..self.epochs.len()).map(|i| self.interval_after(i)).sum()
+    }
+}
+
+// Where the time and the date are in the sentences that carry them
+enum DateFields {
+    None,
+    // ddmmyy, as in RMC
+    Packed(usize),
+    // dd, mm and yyyy in fields of their own, as in ZDA
+    Split(usize),
+}
+
+fn time_fields(formatter: &str) -> Option<(usize, DateFields)> {
+    match formatter {
+        "RMC" => Some((1, DateFields::Packed(9))),
+        "ZDA" => Some((1, DateFields::Split(2))),
+        "GGA" | "GNS" | "GST" | "GBS" | "GRS" => Some((1, DateFields::None)),
+        "GLL" => Some((5, DateFields::None)),
+        _ => None,
+    }
+}
+
+// Moves the times and dates of the sentences of an epoch on by the offset,
+// so a log played again from the start goes on from where it ended instead
+// of going back in time. Lines with a wrong checksum are left as recorded,
+// as is everything that is not a time or a date.
+pub fn rebase(sentences: &str, offset: TimeDelta) -> String {
+    sentences
+        .split_inclusive('\n')
+        .map(|line| {
+            let body = line.trim_end();
+            match rebase_sentence(body, offset) {
+                Some(rebased) => rebased + &line[body.len()..],
+                None => line.to_string(),
+            }
+        })
+        .collect()
+}
+
+fn rebase_sentence(sentence: &str, offset: TimeDelta) -> Option<String> {
+    let (body, sum) = match sentence.strip_prefix('$')?.split_once('*') {
+        Some((body, sum)) => (body, Some(sum)),
+        None => (sentence.strip_prefix('$')?, None),
+    };
+    if sum.is_some_and(|sum| u8::from_str_radix(sum, 16) != Ok(checksum(body))) {
+        return None;
+    }
+    let mut fields: Vec<String> = body.split(',').map(String::from).collect();
+    let (time_field, date_fields) = time_fields(fields[0].get(2..)?)?;
+
+    let (time, decimals) = parse_time(fields.get(time_field)?)?;
+    let date = match date_fields {
+        DateFields::None => None,
+        DateFields::Packed(i) => fields
+            .get(i)
+            .and_then(|date| NaiveDate::parse_from_str(date, "%d%m%y").ok()),
+        DateFields::Split(i) => fields.get(i..i + 3).and_then(|date| {
+            let [day, month, year] = date else {
+                return None;
+            };
+            NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)
+        }),
+    };
+    match date {
+        Some(date) => {
+            let rebased = NaiveDateTime::new(date, time).checked_add_signed(offset)?;
+            fields[time_field] = format_time(rebased.time(), decimals);
+            match date_fields {
+                DateFields::Packed(i) => fields[i] = rebased.format("%d%m%y").to_string(),
+                DateFields::Split(i) => {
+                    fields[i] = format!("{:02}", rebased.day());
+                    fields[i + 1] = format!("{:02}", rebased.month());
+                    fields[i + 2] = format!("{:04}", rebased.year());
+                }
+                DateFields::None => {}
+            }
+        }
+        None => fields[time_field] = format_time(time.overflowing_add_signed(offset).0, decimals),
+    }
+
+    let body = fields.join(",");
+    Some(match sum {
+        Some(_) => format!("${}*{:02X}", body, checksum(&body)),
+        None => format!("${}", body),
+    })
+}
+
+// hhmmss with the decimals of the seconds it has
+fn parse_time(field: &str) -> Option<(NaiveTime, usize)> {
+    let (whole, fraction) = field.split_once('.').unwrap_or((field, ""));
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if whole.len() != 6 || fraction.len() > 9 || !digits(whole) || !digits(fraction) {
+        return None;
+    }
+    let nanos = format!("{:0<9}", fraction).parse().ok()?;
+    let time = NaiveTime::from_hms_nano_opt(
+        whole[..2].parse().ok()?,
+        whole[2..4].parse().ok()?,
+        whole[4..].parse().ok()?,
+        nanos,
+    )?;
+    Some((time, fraction.len()))
+}
+
+fn format_time(time: NaiveTime, decimals: usize) -> String {
+    let mut field = time.format("%H%M%S").to_string();
+    if decimals > 0 {
+        let nanos = format!("{:09}", time.nanosecond());
+        field.push('.');
+        field.push_str(&nanos[..decimals]);
+    }
+    field
+}
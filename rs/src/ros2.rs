@@ -0,0 +1,238 @@
+// src/ros2.rs
+
+use crate::nmea_generator::{Constellation, Fix};
+use crate::output::OutputSink;
+use crate::state::SharedState;
+use chrono::{DateTime, Utc};
+use std::error::Error;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info};
+use zenoh::{Session, Wait};
+
+// ROS 2 topics over zenoh, as zenoh-bridge-ros2dds relays them to and from
+// DDS: the key is the topic name, the payload the CDR serialized message.
+// Run the bridge next to the ROS 2 nodes, e.g.
+//   zenoh-bridge-ros2dds -l tcp/0.0.0.0:7447
+// and pass --ros2-connect tcp/<host>:7447, or let the simulator find it by
+// multicast scouting. Topic names are those of nmea_navsat_driver.
+pub const FIX_TOPIC: &str = "fix";
+pub const SENTENCE_TOPIC: &str = "nmea_sentence";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// sensor_msgs/NavSatStatus
+const STATUS_NO_FIX: i8 = -1;
+const STATUS_FIX: i8 = 0;
+const STATUS_SBAS_FIX: i8 = 1;
+const STATUS_GBAS_FIX: i8 = 2;
+const SERVICE_GPS: u16 = 1;
+const SERVICE_GLONASS: u16 = 2;
+const SERVICE_COMPASS: u16 = 4;
+const SERVICE_GALILEO: u16 = 8;
+// sensor_msgs/NavSatFix
+const COVARIANCE_TYPE_APPROXIMATED: u8 = 1;
+
+// User equivalent range error of a standalone fix, which HDOP scales into
+// the approximated covariance as nmea_navsat_driver does
+const UERE_METERS: f64 = 4.0;
+
+// Little endian plain CDR after its encapsulation header; fields are
+// aligned to their size from the end of the header
+struct Cdr(Vec<u8>);
+
+impl Cdr {
+    fn new() -> Self {
+        Cdr(vec![0x00, 0x01, 0x00, 0x00])
+    }
+
+    fn align(&mut self, size: usize) {
+        while !(self.0.len() - 4).is_multiple_of(size) {
+            self.0.push(0);
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn i8(&mut self, value: i8) {
+        self.0.push(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.align(2);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn i32(&mut self, value: i32) {
+        self.align(4);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.align(8);
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Length with the terminating NUL, then the bytes and the NUL
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32 + 1);
+        self.0.extend_from_slice(value.as_bytes());
+        self.0.push(0);
+    }
+
+    // std_msgs/Header
+    fn header(&mut self, stamp: DateTime<Utc>, frame_id: &str) {
+        self.i32(stamp.timestamp() as i32);
+        self.u32(stamp.timestamp_subsec_nanos());
+        self.string(frame_id);
+    }
+}
+
+// sensor_msgs/NavSatFix of a fix, stamped with its time. The altitude is
+// above the WGS 84 ellipsoid there, not above mean sea level.
+pub fn encode_nav_sat_fix(fix: &Fix, no_fix: bool, frame_id: &str) -> Vec<u8> {
+    let mut cdr = Cdr::new();
+    cdr.header(fix.time, frame_id);
+
+    let status = match fix.fix_quality {
+        _ if no_fix => STATUS_NO_FIX,
+        0 => STATUS_NO_FIX,
+        2 => STATUS_SBAS_FIX,
+        4 | 5 => STATUS_GBAS_FIX,
+        _ => STATUS_FIX,
+    };
+    let service = fix
+        .satellites
+        .iter()
+        .filter(|satellite| satellite.used)
+        .fold(0, |service, satellite| match satellite.constellation {
+            Constellation::GPS => service | SERVICE_GPS,
+            Constellation::GLONASS => service | SERVICE_GLONASS,
+            Constellation::BEIDOU => service | SERVICE_COMPASS,
+            Constellation::GALILEO => service | SERVICE_GALILEO,
+            Constellation::QZSS | Constellation::NAVIC | Constellation::SBAS => service,
+        });
+    cdr.i8(status);
+    cdr.u16(service);
+
+    cdr.f64(fix.latitude);
+    cdr.f64(fix.longitude);
+    cdr.f64(fix.altitude + fix.geoid_height);
+    // East, north and up; the vertical error is about twice the horizontal
+    let horizontal = (fix.hdop * UERE_METERS).powi(2);
+    let vertical = (2.0 * fix.hdop * UERE_METERS).powi(2);
+    for (i, variance) in [horizontal, horizontal, vertical].into_iter().enumerate() {
+        for j in 0..3 {
+            cdr.f64(if i == j { variance } else { 0.0 });
+        }
+    }
+    cdr.u8(COVARIANCE_TYPE_APPROXIMATED);
+    cdr.0
+}
+
+// nmea_msgs/Sentence, without its line ending
+pub fn encode_sentence(sentence: &str, stamp: DateTime<Utc>, frame_id: &str) -> Vec<u8> {
+    let mut cdr = Cdr::new();
+    cdr.header(stamp, frame_id);
+    cdr.string(sentence);
+    cdr.0
+}
+
+// Connects to the given zenoh endpoints, e.g. tcp/192.168.1.10:7447, or
+// scouts for peers when there are none
+pub fn open_session(endpoints: &[String]) -> Result<Session, Box<dyn Error>> {
+    let mut config = zenoh::Config::default();
+    if !endpoints.is_empty() {
+        config
+            .insert_json5("connect/endpoints", &serde_json::to_string(endpoints)?)
+            .map_err(|e| format!("Invalid zenoh endpoints {:?}: {}", endpoints, e))?;
+    }
+    let session = zenoh::open(config)
+        .wait()
+        .map_err(|e| format!("Failed to open a zenoh session: {}", e))?;
+    info!(
+        "Publishing ROS 2 topics {} and {} over zenoh",
+        FIX_TOPIC, SENTENCE_TOPIC
+    );
+    Ok(session)
+}
+
+// Publishes every sentence written to the outputs of a port on its own
+pub struct Ros2Sink {
+    session: Session,
+    frame_id: String,
+}
+
+impl Ros2Sink {
+    pub fn new(session: Session, frame_id: &str) -> Self {
+        Ros2Sink {
+            session,
+            frame_id: frame_id.to_string(),
+        }
+    }
+}
+
+impl OutputSink for Ros2Sink {
+    fn name(&self) -> String {
+        format!("ros2:{}", SENTENCE_TOPIC)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let stamp = Utc::now();
+        // UBX frames in between are no sentences
+        for line in String::from_utf8_lossy(data).lines() {
+            let sentence = line.trim_end();
+            if !sentence.starts_with(['$', '!']) {
+                continue;
+            }
+            self.session
+                .put(
+                    SENTENCE_TOPIC,
+                    encode_sentence(sentence, stamp, &self.frame_id),
+                )
+                .wait()
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+// Publishes each fix of the first port as it comes in, until shutdown
+pub fn spawn_fix_publisher(
+    session: Session,
+    frame_id: String,
+    state: SharedState,
+    shutdown_event: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut last_epoch = None;
+        while !shutdown_event.load(Ordering::SeqCst) {
+            thread::sleep(POLL_INTERVAL);
+            let (fix, no_fix) = {
+                let state = state.lock().unwrap();
+                (state.truth.clone(), state.no_fix)
+            };
+            let Some(fix) = fix.filter(|fix| last_epoch != Some(fix.time)) else {
+                continue;
+            };
+            last_epoch = Some(fix.time);
+
+            let message = encode_nav_sat_fix(&fix, no_fix, &frame_id);
+            if let Err(e) = session.put(FIX_TOPIC, message).wait() {
+                error!("Error publishing {}: {}", FIX_TOPIC, e);
+            }
+        }
+        let _ = session.close().wait();
+    })
+}
@@ -0,0 +1,286 @@
+// src/rtcm.rs
+
+use crate::output::MultiSink;
+use chrono::{DateTime, TimeZone, Utc};
+use std::error::Error;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+// WGS84 ellipsoid
+const WGS84_A: f64 = 6378137.0;
+const WGS84_F: f64 = 1.0 / 298.257223563;
+
+// GPS time runs ahead of UTC by the accumulated leap seconds
+const GPS_LEAP_SECONDS: i64 = 18;
+const MS_PER_WEEK: i64 = 7 * 24 * 3600 * 1000;
+
+// Reference station of the simulated base
+#[derive(Debug, Clone, Copy)]
+pub struct RtcmBase {
+    pub station_id: u16,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+}
+
+impl RtcmBase {
+    // Parses "lat,lon,alt" in degrees and meters above the ellipsoid
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let values = spec
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| format!("Invalid base position '{}', expected lat,lon,alt", spec))?;
+
+        match values[..] {
+            [latitude, longitude, altitude]
+                if (-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude) =>
+            {
+                Ok(RtcmBase {
+                    station_id: 0,
+                    latitude,
+                    longitude,
+                    altitude,
+                })
+            }
+            _ => Err(format!("Invalid base position '{}', expected lat,lon,alt", spec).into()),
+        }
+    }
+
+    // Earth-centered, earth-fixed coordinates in meters
+    pub fn ecef(&self) -> (f64, f64, f64) {
+        let lat = self.latitude.to_radians();
+        let lon = self.longitude.to_radians();
+        let e2 = WGS84_F * (2.0 - WGS84_F);
+        let n = WGS84_A / (1.0 - e2 * lat.sin().powi(2)).sqrt();
+
+        (
+            (n + self.altitude) * lat.cos() * lon.cos(),
+            (n + self.altitude) * lat.cos() * lon.sin(),
+            (n * (1.0 - e2) + self.altitude) * lat.sin(),
+        )
+    }
+}
+
+// Packs fields MSB first, as RTCM3 data fields are laid out
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            bits: 0,
+        }
+    }
+
+    fn put(&mut self, value: u64, width: usize) {
+        for i in (0..width).rev() {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                let last = self.bytes.len() - 1;
+                self.bytes[last] |= 0x80 >> (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+
+    fn put_signed(&mut self, value: i64, width: usize) {
+        self.put(value as u64 & ((1u64 << width) - 1), width);
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// Wraps a message payload into a transport frame with preamble and CRC
+pub fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 6);
+    frame.push(0xD3);
+    frame.push(((payload.len() >> 8) & 0x03) as u8);
+    frame.push((payload.len() & 0xFF) as u8);
+    frame.extend_from_slice(payload);
+
+    let crc = crc24q(&frame);
+    frame.push((crc >> 16) as u8);
+    frame.push((crc >> 8) as u8);
+    frame.push(crc as u8);
+    frame
+}
+
+// Message 1005: stationary RTK reference station antenna reference point
+pub fn encode_1005(base: &RtcmBase) -> Vec<u8> {
+    let (x, y, z) = base.ecef();
+    let mut w = BitWriter::new();
+
+    w.put(1005, 12);
+    w.put(base.station_id as u64, 12);
+    w.put(0, 6); // ITRF realization year
+    w.put(1, 1); // GPS
+    w.put(0, 1); // GLONASS
+    w.put(0, 1); // Galileo
+    w.put(0, 1); // Reference station, not a physical one
+    w.put_signed((x * 10000.0).round() as i64, 38);
+    w.put(0, 1); // Single receiver oscillator
+    w.put(0, 1); // Reserved
+    w.put_signed((y * 10000.0).round() as i64, 38);
+    w.put(0, 2); // Quarter cycle indicator
+    w.put_signed((z * 10000.0).round() as i64, 38);
+
+    w.into_bytes()
+}
+
+// Message 1074: GPS MSM4 carrying only the header. It marks the epoch as
+// observed without claiming any satellite measurements.
+pub fn encode_1074_placeholder(station_id: u16, time: DateTime<Utc>) -> Vec<u8> {
+    let mut w = BitWriter::new();
+
+    w.put(1074, 12);
+    w.put(station_id as u64, 12);
+    w.put(gps_time_of_week_ms(time) as u64, 30);
+    w.put(0, 1); // Multiple message bit, this is the last one
+    w.put(0, 3); // Issue of data station
+    w.put(0, 7); // Reserved
+    w.put(0, 2); // Clock steering indicator
+    w.put(0, 2); // External clock indicator
+    w.put(0, 1); // Divergence-free smoothing
+    w.put(0, 3); // Smoothing interval
+    w.put(0, 64); // Satellite mask
+    w.put(0, 32); // Signal mask
+
+    w.into_bytes()
+}
+
+pub fn gps_time_of_week_ms(time: DateTime<Utc>) -> i64 {
+    gps_time_ms(time).rem_euclid(MS_PER_WEEK)
+}
+
+// Weeks since the GPS epoch, without rollover
+pub fn gps_week(time: DateTime<Utc>) -> i64 {
+    gps_time_ms(time).div_euclid(MS_PER_WEEK)
+}
+
+fn gps_time_ms(time: DateTime<Utc>) -> i64 {
+    let gps_epoch = Utc.with_ymd_and_hms(1980, 1, 6, 0, 0, 0).unwrap();
+    (time - gps_epoch).num_milliseconds() + GPS_LEAP_SECONDS * 1000
+}
+
+// Sends the base station messages once per second until shutdown
+pub fn spawn_base_output(
+    base: RtcmBase,
+    mut outputs: MultiSink,
+    shutdown_event: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let interval = Duration::from_secs(1);
+        let mut next_epoch = Instant::now();
+
+        while !shutdown_event.load(Ordering::SeqCst) && !outputs.is_empty() {
+            let mut data = frame(&encode_1005(&base));
+            data.extend(frame(&encode_1074_placeholder(base.station_id, Utc::now())));
+            outputs.write_all(&data);
+
+            next_epoch += interval;
+            let now = Instant::now();
+            if next_epoch > now {
+                thread::sleep(next_epoch - now);
+            } else {
+                next_epoch = now;
+            }
+        }
+        info!("RTCM base output exiting.");
+    })
+}
+
+// Splits a byte stream into RTCM3 frames, skipping anything that does not
+// carry a valid CRC, and yields the message type of each frame
+#[derive(Default)]
+pub struct Rtcm3Parser {
+    buf: Vec<u8>,
+    // Reference station of the latest message that names one
+    pub station_id: Option<u16>,
+}
+
+impl Rtcm3Parser {
+    pub fn new() -> Self {
+        Rtcm3Parser {
+            buf: Vec::new(),
+            station_id: None,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Vec<u16> {
+        self.buf.extend_from_slice(data);
+        let mut message_types = Vec::new();
+
+        loop {
+            // Resynchronise on the preamble
+            match self.buf.iter().position(|&b| b == 0xD3) {
+                Some(start) => {
+                    self.buf.drain(..start);
+                }
+                None => {
+                    self.buf.clear();
+                    break;
+                }
+            }
+            if self.buf.len() < 3 {
+                break;
+            }
+
+            let length = (((self.buf[1] & 0x03) as usize) << 8) | self.buf[2] as usize;
+            let frame_len = 3 + length + 3;
+            if self.buf.len() < frame_len {
+                break;
+            }
+
+            let crc = crc24q(&self.buf[..3 + length]);
+            let expected = ((self.buf[3 + length] as u32) << 16)
+                | ((self.buf[4 + length] as u32) << 8)
+                | self.buf[5 + length] as u32;
+            if crc == expected && length >= 2 {
+                let message_type = ((self.buf[3] as u16) << 4) | (self.buf[4] as u16 >> 4);
+                // Observations, station coordinates and descriptors have the
+                // 12 bit station ID right after the message type
+                let names_station = (1001..=1012).contains(&message_type)
+                    || message_type == 1033
+                    || (1071..=1137).contains(&message_type);
+                if names_station && length >= 3 {
+                    self.station_id = Some(((self.buf[4] as u16 & 0x0F) << 8) | self.buf[5] as u16);
+                }
+                message_types.push(message_type);
+                self.buf.drain(..frame_len);
+            } else {
+                // Not a real frame start, skip this preamble byte
+                self.buf.drain(..1);
+            }
+        }
+
+        message_types
+    }
+}
+
+// CRC-24Q as used by RTCM3
+pub fn crc24q(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0;
+    for &byte in data {
+        crc ^= (byte as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x0100_0000 != 0 {
+                crc ^= 0x0186_4CFB;
+            }
+        }
+    }
+    crc & 0x00FF_FFFF
+}
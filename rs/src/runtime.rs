@@ -0,0 +1,26 @@
+// src/runtime.rs
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// PTY forwarding, the control socket and the HTTP servers share one small
+// runtime, so every forwarder and connection is a task rather than a
+// thread. The epoch loops of the ports keep threads of their own.
+pub fn new_io_runtime() -> io::Result<Runtime> {
+    Builder::new_multi_thread()
+        .worker_threads(2)
+        .thread_name("nmea-io")
+        .enable_all()
+        .build()
+}
+
+// Completes once the shutdown flag is raised
+pub async fn shutdown_requested(shutdown_event: &AtomicBool) {
+    while !shutdown_event.load(Ordering::SeqCst) {
+        tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,281 @@
+// src/scenario.rs
+
+use crate::control::{ControlCommand, Controller, MAX_INJECTION_TIME};
+use crate::error::SimError;
+use crate::nmea_generator::Fix;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// A timeline of control commands in TOML. Each event runs "at" seconds
+// after the start or "after" seconds after the previous event:
+//
+//   [[event]]
+//   at = 0
+//   command = "set-position"
+//   latitude = 37.77
+//   longitude = -122.41
+//
+//   [[event]]
+//   after = 60
+//   command = "set-fix-quality"
+//   fix_quality = 0
+//
+//   [[event]]
+//   after = 10
+//   command = "set-rtk-oscillation"
+//   fixed = 20
+//   float = 5
+//
+//   [[event]]
+//   after = 10
+//   command = "corrupt-sentences"
+//   count = 5
+//
+//   [[event]]
+//   after = 5
+//   command = "lose-fix"
+//
+//   [[event]]
+//   after = 5
+//   command = "set-authentication"
+//   authenticated = false
+//
+//   [[event]]
+//   after = 10
+//   command = "inject-sentence"
+//   sentence = "PXYZ,MODE,2"
+//
+//   [[event]]
+//   after = 20
+//   command = "stop"
+//
+// Commands are those of the control socket, plus "stop" to end the run.
+pub struct Scenario {
+    // Sorted by time, in seconds since the start
+    pub events: Vec<(f64, Action)>,
+}
+
+#[derive(Debug)]
+pub enum Action {
+    Command(ControlCommand),
+    Stop,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    #[serde(default)]
+    event: Vec<EventEntry>,
+}
+
+#[derive(Deserialize)]
+struct EventEntry {
+    at: Option<f64>,
+    after: Option<f64>,
+    // The command and its arguments
+    #[serde(flatten)]
+    command: toml::Table,
+}
+
+impl Scenario {
+    pub fn load(path: &str) -> Result<Self, SimError> {
+        Self::read(path).map_err(|e| SimError::Scenario {
+            path: path.to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let file: ScenarioFile = toml::from_str(&text)?;
+
+        let mut events = Vec::new();
+        let mut time = 0.0;
+        for (i, entry) in file.event.into_iter().enumerate() {
+            let context = |e: &dyn std::fmt::Display| format!("Event {}: {}", i + 1, e);
+            time = match (entry.at, entry.after) {
+                (Some(at), None) => at,
+                (None, Some(after)) => time + after,
+                (None, None) => return Err(context(&"needs \"at\" or \"after\"").into()),
+                (Some(_), Some(_)) => {
+                    return Err(context(&"takes either \"at\" or \"after\"").into())
+                }
+            };
+            // Within a year, as injections
+            if !(0.0..=MAX_INJECTION_TIME).contains(&time) {
+                return Err(context(&format!("invalid time {}", time)).into());
+            }
+
+            let action = match entry.command.get("command").and_then(|c| c.as_str()) {
+                Some("stop") => Action::Stop,
+                _ => Action::Command(
+                    toml::Value::Table(entry.command)
+                        .try_into()
+                        .map_err(|e| context(&e))?,
+                ),
+            };
+            events.push((time, action));
+        }
+        // Stable, so events at the same time keep the file's order
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Ok(Scenario { events })
+    }
+}
+
+// Writes a run down as a scenario that plays it back: the truth of every
+// epoch the first port sends as a set-truth event, the commands applied in
+// between, e.g. typed in the terminal UI or sent to the control socket, and
+// a stop at the end. Events are flushed as they happen, so a run that is
+// killed still leaves a file to replay. Settings of the command line, such
+// as --fault, are not part of it.
+#[derive(Clone)]
+pub struct ScenarioRecorder {
+    path: String,
+    start: Instant,
+    writer: Arc<Mutex<BufWriter<File>>>,
+}
+
+#[derive(Serialize)]
+struct RecordedEvent<'a> {
+    at: f64,
+    #[serde(flatten)]
+    command: &'a ControlCommand,
+}
+
+#[derive(Serialize)]
+struct RecordedStop {
+    at: f64,
+    command: &'static str,
+}
+
+// One [[event]] table at a time
+#[derive(Serialize)]
+struct RecordedEntry<T> {
+    event: [T; 1],
+}
+
+impl ScenarioRecorder {
+    pub fn create(path: &str, seed: u64) -> Result<Self, SimError> {
+        let file = File::create(path)
+            .map_err(|e| SimError::io(format!("Failed to create scenario {}", path), e))?;
+        info!("Recording the run as a scenario to {}", path);
+        let recorder = ScenarioRecorder {
+            path: path.to_string(),
+            start: Instant::now(),
+            writer: Arc::new(Mutex::new(BufWriter::new(file))),
+        };
+        recorder.write(&format!(
+            "# Recorded {}; replay with --scenario {} --seed {}\n",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            path,
+            seed
+        ));
+        Ok(recorder)
+    }
+
+    pub fn record_command(&self, command: &ControlCommand) {
+        // Queries change nothing to replay
+        if matches!(command, ControlCommand::GetState) {
+            return;
+        }
+        self.record(&RecordedEvent {
+            at: self.elapsed(),
+            command,
+        });
+    }
+
+    pub fn record_truth(&self, fix: &Fix) {
+        let command = ControlCommand::SetTruth {
+            latitude: fix.latitude,
+            longitude: fix.longitude,
+            altitude: fix.altitude,
+            speed_knots: fix.speed_knots,
+            course: fix.course,
+            fix_quality: Some(fix.fix_quality),
+            hdop: Some(fix.hdop),
+            // More than set-satellites takes are left to the generator
+            satellites: Some(fix.satellites.len()).filter(|&count| count <= 12),
+        };
+        self.record(&RecordedEvent {
+            at: self.elapsed(),
+            command: &command,
+        });
+    }
+
+    pub fn record_stop(&self) {
+        self.record(&RecordedStop {
+            at: self.elapsed(),
+            command: "stop",
+        });
+    }
+
+    // Milliseconds are as close as the scenario thread keeps to its timeline
+    fn elapsed(&self) -> f64 {
+        (self.start.elapsed().as_secs_f64() * 1000.0).round() / 1000.0
+    }
+
+    fn record<T: Serialize>(&self, event: &T) {
+        match toml::to_string(&RecordedEntry { event: [event] }) {
+            Ok(entry) => self.write(&format!("\n{}", entry)),
+            Err(e) => warn!("Failed to encode a scenario event: {}", e),
+        }
+    }
+
+    fn write(&self, text: &str) {
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writer
+            .write_all(text.as_bytes())
+            .and_then(|_| writer.flush())
+        {
+            error!("Error recording to {}: {}", self.path, e);
+        }
+    }
+}
+
+// Plays the timeline against the controller in real time
+pub fn spawn_scenario(
+    scenario: Scenario,
+    controller: Controller,
+    shutdown_event: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    info!("Running scenario with {} events", scenario.events.len());
+    let start = Instant::now();
+    thread::spawn(move || {
+        for (time, action) in scenario.events {
+            let due = start + Duration::from_secs_f64(time);
+            while Instant::now() < due {
+                if shutdown_event.load(Ordering::SeqCst) {
+                    return;
+                }
+                thread::sleep(POLL_INTERVAL.min(due.saturating_duration_since(Instant::now())));
+            }
+
+            info!("Scenario at {:.1} s: {:?}", time, action);
+            match action {
+                Action::Command(command) => {
+                    if let Err(e) = controller.apply(command) {
+                        warn!("Scenario event at {:.1} s failed: {}", time, e);
+                    }
+                }
+                Action::Stop => {
+                    shutdown_event.store(true, Ordering::SeqCst);
+                    return;
+                }
+            }
+        }
+        info!("Scenario finished");
+    })
+}
@@ -0,0 +1,426 @@
+// src/scripting.rs
+
+use crate::control::{ControlCommand, Controller};
+use crate::nmea_generator::{Crab, Environment, Fix};
+use crate::state::SharedState;
+use crate::trajectory::TrajectorySource;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Map, AST};
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs;
+use std::rc::Rc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+const TICK: Duration = Duration::from_millis(50);
+
+// Scenario scripts are Rhai. The script body runs once at startup and
+// registers callbacks; times are seconds since the script started:
+//
+//   at(60, || set_satellites(4));
+//   every(10, || print(`t=${elapsed()}`));
+//   let zone = [[37.0, -122.0], [37.0, -121.0], [38.0, -121.0]];
+//   on_epoch(|fix| if inside(fix.latitude, fix.longitude, zone) { set_hdop(8.0) });
+//   at(300, || emit("GPTXT,01,01,02,scenario done"));
+//
+// Simulator API:
+//   set_position(lat, lon[, alt]), set_speed(knots[, course]),
+//   set_crab("current:2@90"), set_trajectory("route:drive.csv"[, jump]), set_fix_quality(q),
+//   set_rtk_oscillation(fixed, float), set_satellites(n), set_hdop(h), set_sbas(on),
+//   set_environment("urban"), set_rate(hz),
+//   set_sentence("GGA", on), emit(sentence), pause(), resume(), release(),
+//   lose_fix(), regain_fix(), set_authenticated(on), stop(), elapsed(),
+//   truth(),
+//   inside(lat, lon, polygon)
+// Setters accept () to hand a value back to the generator.
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+struct Timer {
+    due: f64,
+    period: Option<f64>,
+    callback: FnPtr,
+}
+
+#[derive(Default)]
+struct Callbacks {
+    timers: Vec<Timer>,
+    on_epoch: Vec<FnPtr>,
+}
+
+// Compiles and starts the script on its own thread. Errors in the script
+// body fail startup; errors in callbacks are only reported.
+pub fn spawn_script(
+    path: &str,
+    controller: Controller,
+    state: SharedState,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let source = fs::read_to_string(path)
+        .inspect_err(|e| error!("Failed to read script {}: {}", path, e))?;
+    let path = path.to_string();
+    let (started, startup) = mpsc::channel();
+
+    let thread = thread::spawn(move || {
+        let start = Instant::now();
+        let callbacks = Rc::new(RefCell::new(Callbacks::default()));
+        let engine = build_engine(&controller, &state, &shutdown_event, &callbacks, start);
+
+        let ast = match engine.compile(&source) {
+            Ok(ast) => ast,
+            Err(e) => {
+                let _ = started.send(Err(format!("{}: {}", path, e)));
+                return;
+            }
+        };
+        if let Err(e) = engine.run_ast(&ast) {
+            let _ = started.send(Err(format!("{}: {}", path, e)));
+            return;
+        }
+        let _ = started.send(Ok(()));
+        info!("Running scenario script {}", path);
+
+        run_callbacks(&engine, &ast, &callbacks, &state, &shutdown_event, start);
+    });
+
+    match startup.recv() {
+        Ok(Ok(())) => Ok(thread),
+        Ok(Err(e)) => Err(format!("Script error in {}", e).into()),
+        Err(_) => Err("Script thread exited during startup".into()),
+    }
+}
+
+fn run_callbacks(
+    engine: &Engine,
+    ast: &AST,
+    callbacks: &RefCell<Callbacks>,
+    state: &SharedState,
+    shutdown_event: &AtomicBool,
+    start: Instant,
+) {
+    let mut last_epoch = None;
+    while !shutdown_event.load(Ordering::SeqCst) {
+        thread::sleep(TICK);
+        let now = start.elapsed().as_secs_f64();
+
+        // Callbacks may add timers, so take the due ones out first
+        let due: Vec<Timer> = {
+            let mut callbacks = callbacks.borrow_mut();
+            let (due, pending) = callbacks.timers.drain(..).partition(|t| t.due <= now);
+            callbacks.timers = pending;
+            due
+        };
+        for mut timer in due {
+            report(timer.callback.call::<Dynamic>(engine, ast, ()));
+            if let Some(period) = timer.period {
+                timer.due += period;
+                callbacks.borrow_mut().timers.push(timer);
+            }
+        }
+
+        // Epoch callbacks see each fix of the first port once
+        let fix = state.lock().unwrap().truth.clone();
+        if let Some(fix) = fix {
+            if last_epoch != Some(fix.time) {
+                last_epoch = Some(fix.time);
+                let hooks = callbacks.borrow().on_epoch.clone();
+                for hook in hooks {
+                    report(hook.call::<Dynamic>(engine, ast, (fix_to_map(&fix),)));
+                }
+            }
+        }
+    }
+}
+
+fn report(result: ScriptResult<Dynamic>) {
+    if let Err(e) = result {
+        error!("Script error: {}", e);
+    }
+}
+
+fn build_engine(
+    controller: &Controller,
+    state: &SharedState,
+    shutdown_event: &Arc<AtomicBool>,
+    callbacks: &Rc<RefCell<Callbacks>>,
+    start: Instant,
+) -> Engine {
+    let mut engine = Engine::new();
+
+    let scheduled = callbacks.clone();
+    engine.register_fn("at", move |seconds: Dynamic, callback: FnPtr| {
+        let due = number(seconds)?;
+        scheduled.borrow_mut().timers.push(Timer {
+            due,
+            period: None,
+            callback,
+        });
+        Ok::<_, Box<EvalAltResult>>(())
+    });
+    let scheduled = callbacks.clone();
+    engine.register_fn("every", move |seconds: Dynamic, callback: FnPtr| {
+        let period = number(seconds)?;
+        if period <= 0.0 {
+            return Err::<_, Box<EvalAltResult>>(format!("Invalid period {}", period).into());
+        }
+        let due = start.elapsed().as_secs_f64() + period;
+        scheduled.borrow_mut().timers.push(Timer {
+            due,
+            period: Some(period),
+            callback,
+        });
+        Ok(())
+    });
+    let scheduled = callbacks.clone();
+    engine.register_fn("on_epoch", move |callback: FnPtr| {
+        scheduled.borrow_mut().on_epoch.push(callback);
+    });
+    engine.register_fn("elapsed", move || start.elapsed().as_secs_f64());
+
+    let c = controller.clone();
+    engine.register_fn("set_position", move |lat: Dynamic, lon: Dynamic| {
+        apply(
+            &c,
+            ControlCommand::SetPosition {
+                latitude: number(lat)?,
+                longitude: number(lon)?,
+                altitude: 0.0,
+            },
+        )
+    });
+    let c = controller.clone();
+    engine.register_fn(
+        "set_position",
+        move |lat: Dynamic, lon: Dynamic, alt: Dynamic| {
+            apply(
+                &c,
+                ControlCommand::SetPosition {
+                    latitude: number(lat)?,
+                    longitude: number(lon)?,
+                    altitude: number(alt)?,
+                },
+            )
+        },
+    );
+    let c = controller.clone();
+    engine.register_fn("set_speed", move |knots: Dynamic| {
+        let knots = number(knots)?;
+        apply(
+            &c,
+            ControlCommand::SetSpeed {
+                knots,
+                course: None,
+            },
+        )
+    });
+    let c = controller.clone();
+    engine.register_fn("set_speed", move |knots: Dynamic, course: Dynamic| {
+        let command = ControlCommand::SetSpeed {
+            knots: number(knots)?,
+            course: Some(number(course)?),
+        };
+        apply(&c, command)
+    });
+    let c = controller.clone();
+    engine.register_fn("set_fix_quality", move |quality: Dynamic| {
+        let fix_quality = optional_int(quality)?
+            .map(|q| u8::try_from(q).map_err(|_| format!("Invalid fix quality {}", q)))
+            .transpose()?;
+        apply(&c, ControlCommand::SetFixQuality { fix_quality })
+    });
+    let c = controller.clone();
+    engine.register_fn(
+        "set_rtk_oscillation",
+        move |fixed: Dynamic, float: Dynamic| {
+            let dwell = |secs: Dynamic| -> ScriptResult<Option<f64>> {
+                if secs.is_unit() {
+                    Ok(None)
+                } else {
+                    Ok(Some(number(secs)?))
+                }
+            };
+            let (fixed, float) = (dwell(fixed)?, dwell(float)?);
+            apply(&c, ControlCommand::SetRtkOscillation { fixed, float })
+        },
+    );
+    let c = controller.clone();
+    engine.register_fn("set_satellites", move |count: Dynamic| {
+        let count = optional_int(count)?.map(|n| n as usize);
+        apply(&c, ControlCommand::SetSatellites { count })
+    });
+    let c = controller.clone();
+    engine.register_fn("set_hdop", move |hdop: Dynamic| {
+        let hdop = if hdop.is_unit() {
+            None
+        } else {
+            Some(number(hdop)?)
+        };
+        apply(&c, ControlCommand::SetHdop { hdop })
+    });
+    let c = controller.clone();
+    engine.register_fn("set_crab", move |crab: &str| {
+        let crab = Crab::parse(crab).map_err(|e| e.to_string())?;
+        apply(&c, ControlCommand::SetCrab { crab })
+    });
+    let c = controller.clone();
+    engine.register_fn("set_trajectory", move |source: &str| {
+        set_trajectory(&c, source, false)
+    });
+    let c = controller.clone();
+    engine.register_fn("set_trajectory", move |source: &str, jump: bool| {
+        set_trajectory(&c, source, jump)
+    });
+    let c = controller.clone();
+    engine.register_fn("set_sbas", move |enabled: bool| {
+        apply(&c, ControlCommand::SetSbas { enabled })
+    });
+    let c = controller.clone();
+    engine.register_fn("set_environment", move |environment: &str| {
+        let environment = Environment::parse(environment).map_err(|e| e.to_string())?;
+        apply(&c, ControlCommand::SetEnvironment { environment })
+    });
+    let c = controller.clone();
+    engine.register_fn("set_rate", move |hz: Dynamic| {
+        let hz = number(hz)?;
+        apply(&c, ControlCommand::SetRate { hz, port: None })
+    });
+    let c = controller.clone();
+    engine.register_fn("set_sentence", move |sentence: &str, enabled: bool| {
+        let command = ControlCommand::SetSentence {
+            sentence: sentence.to_string(),
+            enabled,
+            port: None,
+        };
+        apply(&c, command)
+    });
+    let c = controller.clone();
+    engine.register_fn("emit", move |sentence: &str| {
+        let command = ControlCommand::InjectSentence {
+            sentence: sentence.to_string(),
+            port: None,
+            time: None,
+            every: None,
+        };
+        apply(&c, command)
+    });
+    let c = controller.clone();
+    engine.register_fn("pause", move || apply(&c, ControlCommand::Pause));
+    let c = controller.clone();
+    engine.register_fn("resume", move || apply(&c, ControlCommand::Resume));
+    let c = controller.clone();
+    engine.register_fn("release", move || apply(&c, ControlCommand::Release));
+    let c = controller.clone();
+    engine.register_fn("lose_fix", move || apply(&c, ControlCommand::LoseFix));
+    let c = controller.clone();
+    engine.register_fn("regain_fix", move || apply(&c, ControlCommand::RegainFix));
+    let c = controller.clone();
+    engine.register_fn("set_authenticated", move |authenticated: Dynamic| {
+        let authenticated = if authenticated.is_unit() {
+            None
+        } else {
+            Some(
+                authenticated
+                    .as_bool()
+                    .map_err(|t| format!("Expected a bool, got {}", t))?,
+            )
+        };
+        apply(&c, ControlCommand::SetAuthentication { authenticated })
+    });
+
+    let shutdown_event = shutdown_event.clone();
+    engine.register_fn("stop", move || {
+        info!("Scenario script requested shutdown");
+        shutdown_event.store(true, Ordering::SeqCst);
+    });
+    let state = state.clone();
+    engine.register_fn("truth", move || match &state.lock().unwrap().truth {
+        Some(fix) => Dynamic::from_map(fix_to_map(fix)),
+        None => Dynamic::UNIT,
+    });
+    engine.register_fn("inside", inside);
+
+    engine
+}
+
+fn apply(controller: &Controller, command: ControlCommand) -> ScriptResult<()> {
+    controller
+        .apply(command)
+        .map(|_| ())
+        .map_err(|e| e.to_string().into())
+}
+
+fn set_trajectory(controller: &Controller, source: &str, jump: bool) -> ScriptResult<()> {
+    let trajectory = TrajectorySource::parse(source).map_err(|e| e.to_string())?;
+    apply(
+        controller,
+        ControlCommand::SetTrajectory { trajectory, jump },
+    )
+}
+
+// Scripts may write 60 as well as 60.0
+fn number(value: Dynamic) -> ScriptResult<f64> {
+    match (value.as_float(), value.as_int()) {
+        (Ok(float), _) => Ok(float),
+        (_, Ok(int)) => Ok(int as f64),
+        _ => Err(format!("Expected a number, got {}", value.type_name()).into()),
+    }
+}
+
+fn optional_int(value: Dynamic) -> ScriptResult<Option<i64>> {
+    if value.is_unit() {
+        return Ok(None);
+    }
+    match value.as_int() {
+        Ok(int) if int >= 0 => Ok(Some(int)),
+        _ => Err(format!("Expected a non-negative integer, got {}", value).into()),
+    }
+}
+
+fn fix_to_map(fix: &Fix) -> Map {
+    let mut map = Map::new();
+    map.insert("time".into(), fix.time.to_rfc3339().into());
+    map.insert("latitude".into(), fix.latitude.into());
+    map.insert("longitude".into(), fix.longitude.into());
+    map.insert("altitude".into(), fix.altitude.into());
+    map.insert("speed_knots".into(), fix.speed_knots.into());
+    map.insert("course".into(), fix.course.into());
+    map.insert("heading".into(), fix.heading.into());
+    map.insert("water_speed_knots".into(), fix.water_speed_knots.into());
+    map.insert("fix_quality".into(), (fix.fix_quality as i64).into());
+    map.insert("hdop".into(), fix.hdop.into());
+    map.insert("satellites".into(), (fix.satellites_used() as i64).into());
+    if let Some(authenticated) = fix.authenticated {
+        map.insert("authenticated".into(), authenticated.into());
+    }
+    map
+}
+
+// Ray casting over a polygon given as [[lat, lon], ...]
+fn inside(latitude: Dynamic, longitude: Dynamic, polygon: Array) -> ScriptResult<bool> {
+    let (y, x) = (number(latitude)?, number(longitude)?);
+    let vertices = polygon
+        .into_iter()
+        .map(|vertex| {
+            let point = vertex
+                .try_cast::<Array>()
+                .filter(|point| point.len() == 2)
+                .ok_or("Polygon vertices must be [lat, lon]")?;
+            Ok((number(point[0].clone())?, number(point[1].clone())?))
+        })
+        .collect::<ScriptResult<Vec<(f64, f64)>>>()?;
+
+    let mut inside = false;
+    for i in 0..vertices.len() {
+        let (yi, xi) = vertices[i];
+        let (yj, xj) = vertices[(i + vertices.len() - 1) % vertices.len()];
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+    }
+    Ok(inside)
+}
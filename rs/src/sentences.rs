@@ -0,0 +1,728 @@
+// src/sentences.rs
+
+use crate::checksum::{checksum, wrap_sentence};
+use crate::position::Position;
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc};
+use std::fmt::{self, Display, Write};
+
+// One NMEA 0183 sentence with typed fields. Encoders only lay the fields
+// out; where the values come from is up to the generator.
+pub trait Sentence {
+    // Talker and formatter, e.g. "GPGGA"
+    fn address(&self) -> String;
+
+    // Appends the complete line with checksum and line ending
+    fn encode(&self, out: &mut SentenceBuffer);
+
+    // The comma separated fields after the address
+    fn fields(&self) -> Vec<String> {
+        let line = self.to_nmea();
+        let body = &line[1..line.rfind('*').unwrap_or(line.len())];
+        body.split(',').skip(1).map(str::to_string).collect()
+    }
+
+    fn to_nmea(&self) -> String {
+        let mut out = SentenceBuffer::new();
+        self.encode(&mut out);
+        out.into_string()
+    }
+}
+
+// Sentences encoded one after the other, e.g. those of an epoch. Fields
+// are formatted straight into it, so a buffer that is cleared and reused
+// every cycle stops allocating once it has grown to the size of an epoch.
+#[derive(Debug, Default)]
+pub struct SentenceBuffer {
+    text: String,
+    // Where the sentence being written starts
+    start: usize,
+}
+
+impl SentenceBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.start = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    // Drops the sentences after `start` that `keep` says no to
+    pub fn retain_sentences(&mut self, start: usize, mut keep: impl FnMut(&str) -> bool) {
+        let kept: String = self.text[start..]
+            .split_inclusive('\n')
+            .filter(|line| keep(line))
+            .collect();
+        self.text.truncate(start);
+        self.text.push_str(&kept);
+        self.start = self.start.min(self.text.len());
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+
+    // Starts a sentence with '$' and the address
+    pub fn begin(&mut self, talker: &str, formatter: &str) {
+        self.start = self.text.len();
+        self.text.push('$');
+        self.text.push_str(talker);
+        self.text.push_str(formatter);
+    }
+
+    // Starts an encapsulation sentence, e.g. AIVDM, with '!'
+    pub fn begin_encapsulated(&mut self, talker: &str, formatter: &str) {
+        self.begin(talker, formatter);
+        self.text.replace_range(self.start..=self.start, "!");
+    }
+
+    pub fn field(&mut self, value: impl Display) {
+        self.text.push(',');
+        // Writing into a String never fails
+        let _ = write!(self.text, "{}", value);
+    }
+
+    // Appends a complete sentence of its body, e.g. "PXYZ,1,2", as
+    // wrap_sentence has it
+    pub fn push_sentence(&mut self, body: &str) {
+        self.start = self.text.len();
+        self.text.push_str(&wrap_sentence(body));
+    }
+
+    // Adds the checksum of the sentence and the line ending
+    pub fn finish(&mut self) {
+        let sum = checksum(&self.text[self.start + 1..]);
+        let _ = write!(self.text, "*{:02X}\r\n", sum);
+    }
+}
+
+// Writes nothing for None, and the value with the formatting options of
+// the field otherwise
+struct Optional<T>(Option<T>);
+
+impl<T: Display> Display for Optional<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(value) => value.fmt(f),
+            None => Ok(()),
+        }
+    }
+}
+
+// Digits of the minutes in coordinates, by default and at most; receivers
+// report 4 to 8 depending on their precision
+pub const MINUTE_DECIMALS: usize = 4;
+pub const MAX_MINUTE_DECIMALS: usize = 8;
+
+// Characters a sentence may have from '$' to the line ending: 79 between
+// them
+pub const MAX_SENTENCE_LENGTH: usize = 82;
+
+// Err for a line too long for NMEA 0183, line ending included
+pub fn check_length(line: &str) -> Result<(), String> {
+    let length = line.trim_end().len() + 2;
+    if length > MAX_SENTENCE_LENGTH {
+        return Err(format!(
+            "{} has {} characters, more than the {} NMEA 0183 allows",
+            line.trim_end(),
+            length,
+            MAX_SENTENCE_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+// hhmmss, and that many digits of the seconds after it; empty until the
+// receiver knows the time
+fn utc_time(out: &mut SentenceBuffer, time: Option<&DateTime<Utc>>, decimals: usize) {
+    out.field(Optional(time.map(|time| UtcTime(time, decimals))));
+}
+
+// hhmmss with as many decimals of the seconds
+pub struct UtcTime<'a>(pub &'a DateTime<Utc>, pub usize);
+
+impl Display for UtcTime<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let UtcTime(time, decimals) = *self;
+        write!(
+            f,
+            "{:02}{:02}{:02}",
+            time.hour(),
+            time.minute(),
+            time.second()
+        )?;
+        if decimals == 0 {
+            return Ok(());
+        }
+        // Cut, not rounded, so the second stays the same; a leap second
+        // has its nanoseconds past 999_999_999
+        let decimals = decimals.min(9);
+        let fraction = time.nanosecond().min(999_999_999) / 10u32.pow(9 - decimals as u32);
+        write!(f, ".{:0decimals$}", fraction)
+    }
+}
+
+fn utc_date(out: &mut SentenceBuffer, date: Option<NaiveDate>) {
+    out.field(Optional(date.map(|date| {
+        format!(
+            "{:02}{:02}{:02}",
+            date.day(),
+            date.month(),
+            date.year() % 100
+        )
+    })));
+}
+
+fn status(out: &mut SentenceBuffer, valid: bool) {
+    out.field(if valid { 'A' } else { 'V' });
+}
+
+// Latitude, N/S, longitude and E/W, or four empty fields without a fix
+fn coordinates(out: &mut SentenceBuffer, position: Option<&Position>, decimals: usize) {
+    let Some(position) = position else {
+        for _ in 0..4 {
+            out.field("");
+        }
+        return;
+    };
+    let (latitude, ns) = position.latitude_minutes(decimals);
+    let (longitude, ew) = position.longitude_minutes(decimals);
+    out.field(latitude);
+    out.field(ns);
+    out.field(longitude);
+    out.field(ew);
+}
+
+// Fix data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gga {
+    pub talker: String,
+    // Empty before the receiver knows it, as in the other sentences
+    pub time: Option<DateTime<Utc>>,
+    // Digits of the seconds; NMEA 4.11 has two, older receivers none
+    pub time_decimals: usize,
+    // Empty fields without a fix
+    pub position: Option<Position>,
+    pub minute_decimals: usize,
+    pub fix_quality: u8,
+    pub satellites: usize,
+    pub hdop: f64,
+    pub geoid_height: Option<f64>,
+    // Age of the differential corrections in seconds, or of the data when
+    // reporting latency, and the reference station of the corrections
+    pub age: Option<f64>,
+    pub station_id: Option<u16>,
+}
+
+impl Sentence for Gga {
+    fn address(&self) -> String {
+        format!("{}GGA", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "GGA");
+        utc_time(out, self.time.as_ref(), self.time_decimals);
+        coordinates(out, self.position.as_ref(), self.minute_decimals);
+        out.field(self.fix_quality);
+        out.field(self.satellites);
+        out.field(format_args!("{:.1}", self.hdop));
+        let altitude = self.position.map(|position| position.alt_m);
+        out.field(format_args!("{:.1}", Optional(altitude)));
+        out.field('M');
+        out.field(format_args!("{:.1}", Optional(self.geoid_height)));
+        out.field('M');
+        out.field(format_args!("{:.1}", Optional(self.age)));
+        out.field(format_args!("{:04}", Optional(self.station_id)));
+        out.finish();
+    }
+}
+
+// Recommended minimum data
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rmc {
+    pub talker: String,
+    pub time: Option<DateTime<Utc>>,
+    pub time_decimals: usize,
+    // Receivers may know the time of day before the date
+    pub date: Option<NaiveDate>,
+    pub valid: bool,
+    pub position: Option<Position>,
+    pub minute_decimals: usize,
+    // Empty without a fix
+    pub speed_knots: Option<f64>,
+    pub course: Option<f64>,
+    // 'A' autonomous, 'D' differential, 'R' RTK fixed, 'F' RTK float,
+    // 'E' estimated, 'N' not valid;
+    // receivers before NMEA 2.3 leave it out
+    pub mode: Option<char>,
+    // 'S' safe, 'C' caution, 'U' unsafe, 'V' not valid; NMEA 4.10 added
+    // it after the mode
+    pub nav_status: Option<char>,
+}
+
+impl Sentence for Rmc {
+    fn address(&self) -> String {
+        format!("{}RMC", self.talker)
+    }
+
+    // Time, status, position, speed, course, date, magnetic variation and
+    // its direction, mode and navigational status
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "RMC");
+        utc_time(out, self.time.as_ref(), self.time_decimals);
+        status(out, self.valid);
+        coordinates(out, self.position.as_ref(), self.minute_decimals);
+        out.field(format_args!("{:.1}", Optional(self.speed_knots)));
+        out.field(format_args!("{:.1}", Optional(self.course)));
+        utc_date(out, self.date);
+        out.field("");
+        out.field("");
+        if let Some(mode) = self.mode {
+            out.field(mode);
+            if let Some(nav_status) = self.nav_status {
+                out.field(nav_status);
+            }
+        }
+        out.finish();
+    }
+}
+
+// Geographic position
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gll {
+    pub talker: String,
+    pub position: Option<Position>,
+    pub minute_decimals: usize,
+    pub time: Option<DateTime<Utc>>,
+    pub time_decimals: usize,
+    pub valid: bool,
+    // As in RMC, since NMEA 2.3
+    pub mode: Option<char>,
+}
+
+impl Sentence for Gll {
+    fn address(&self) -> String {
+        format!("{}GLL", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "GLL");
+        coordinates(out, self.position.as_ref(), self.minute_decimals);
+        utc_time(out, self.time.as_ref(), self.time_decimals);
+        status(out, self.valid);
+        if let Some(mode) = self.mode {
+            out.field(mode);
+        }
+        out.finish();
+    }
+}
+
+// Course and speed over the ground
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vtg {
+    pub talker: String,
+    // True course; the magnetic one is left empty. All three are empty
+    // without a fix.
+    pub course: Option<f64>,
+    pub speed_knots: Option<f64>,
+    pub speed_kmh: Option<f64>,
+    // As in RMC
+    pub mode: Option<char>,
+}
+
+impl Sentence for Vtg {
+    fn address(&self) -> String {
+        format!("{}VTG", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "VTG");
+        out.field(format_args!("{:.1}", Optional(self.course)));
+        out.field('T');
+        out.field("");
+        out.field('M');
+        out.field(format_args!("{:.1}", Optional(self.speed_knots)));
+        out.field('N');
+        out.field(format_args!("{:.1}", Optional(self.speed_kmh)));
+        out.field('K');
+        if let Some(mode) = self.mode {
+            out.field(mode);
+        }
+        out.finish();
+    }
+}
+
+// Heading and speed through the water, as from a log; the magnetic
+// heading is left empty
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vhw {
+    pub talker: String,
+    pub heading: Option<f64>,
+    pub speed_knots: f64,
+    pub speed_kmh: f64,
+}
+
+impl Sentence for Vhw {
+    fn address(&self) -> String {
+        format!("{}VHW", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "VHW");
+        out.field(format_args!("{:.1}", Optional(self.heading)));
+        out.field('T');
+        out.field("");
+        out.field('M');
+        out.field(format_args!("{:.1}", self.speed_knots));
+        out.field('N');
+        out.field(format_args!("{:.1}", self.speed_kmh));
+        out.field('K');
+        out.finish();
+    }
+}
+
+// True heading, where the vehicle points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hdt {
+    pub talker: String,
+    // Empty without a fix
+    pub heading: Option<f64>,
+}
+
+impl Sentence for Hdt {
+    fn address(&self) -> String {
+        format!("{}HDT", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "HDT");
+        out.field(format_args!("{:.1}", Optional(self.heading)));
+        out.field('T');
+        out.finish();
+    }
+}
+
+// Heading of a magnetic sensor, with its deviation and the magnetic
+// variation when known; both are positive to the east
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hdg {
+    pub talker: String,
+    pub heading: f64,
+    pub deviation: Option<f64>,
+    pub variation: Option<f64>,
+}
+
+impl Sentence for Hdg {
+    fn address(&self) -> String {
+        format!("{}HDG", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "HDG");
+        out.field(format_args!("{:.1}", self.heading));
+        for angle in [self.deviation, self.variation] {
+            out.field(format_args!("{:.1}", Optional(angle.map(f64::abs))));
+            out.field(Optional(
+                angle.map(|angle| if angle < 0.0 { 'W' } else { 'E' }),
+            ));
+        }
+        out.finish();
+    }
+}
+
+// Error statistics of the position, as standard deviations in metres of
+// the error ellipse and along each axis; what surveying software reads
+// the accuracy of an RTK fix from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gst {
+    pub talker: String,
+    pub time: Option<DateTime<Utc>>,
+    pub time_decimals: usize,
+    // Empty fields without a fix
+    pub accuracy: Option<Accuracy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Accuracy {
+    // Of the pseudorange residuals
+    pub rms: f64,
+    pub semi_major: f64,
+    pub semi_minor: f64,
+    // Of the semi-major axis, in degrees from true north
+    pub orientation: f64,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: f64,
+}
+
+impl Sentence for Gst {
+    fn address(&self) -> String {
+        format!("{}GST", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "GST");
+        utc_time(out, self.time.as_ref(), self.time_decimals);
+        match &self.accuracy {
+            Some(accuracy) => {
+                out.field(format_args!("{:.3}", accuracy.rms));
+                out.field(format_args!("{:.3}", accuracy.semi_major));
+                out.field(format_args!("{:.3}", accuracy.semi_minor));
+                out.field(format_args!("{:.1}", accuracy.orientation));
+                out.field(format_args!("{:.3}", accuracy.latitude));
+                out.field(format_args!("{:.3}", accuracy.longitude));
+                out.field(format_args!("{:.3}", accuracy.altitude));
+            }
+            None => {
+                for _ in 0..7 {
+                    out.field("");
+                }
+            }
+        }
+        out.finish();
+    }
+}
+
+// Water depth below the transducer, and the transducer's offset: positive
+// to the waterline, negative to the keel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dpt {
+    pub talker: String,
+    pub depth_m: f64,
+    pub offset_m: f64,
+}
+
+impl Sentence for Dpt {
+    fn address(&self) -> String {
+        format!("{}DPT", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "DPT");
+        out.field(format_args!("{:.1}", self.depth_m));
+        out.field(format_args!("{:.1}", self.offset_m));
+        // Maximum range, left out by most sounders
+        out.field("");
+        out.finish();
+    }
+}
+
+// Depth below the transducer in feet, metres and fathoms
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dbt {
+    pub talker: String,
+    pub depth_m: f64,
+}
+
+impl Sentence for Dbt {
+    fn address(&self) -> String {
+        format!("{}DBT", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "DBT");
+        out.field(format_args!("{:.1}", self.depth_m / 0.3048));
+        out.field('f');
+        out.field(format_args!("{:.1}", self.depth_m));
+        out.field('M');
+        out.field(format_args!("{:.1}", self.depth_m / 1.8288));
+        out.field('F');
+        out.finish();
+    }
+}
+
+// An AIS message received on radio channel A or B, armored as it fits in
+// one sentence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Vdm {
+    pub talker: String,
+    pub channel: char,
+    pub payload: String,
+    pub fill_bits: u8,
+}
+
+impl Sentence for Vdm {
+    fn address(&self) -> String {
+        format!("{}VDM", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin_encapsulated(&self.talker, "VDM");
+        // One fragment of one, no sequential message ID
+        out.field(1);
+        out.field(1);
+        out.field("");
+        out.field(self.channel);
+        out.field(&self.payload);
+        out.field(self.fill_bits);
+        out.finish();
+    }
+}
+
+// Satellite IDs one GSA lists
+pub const GSA_SLOTS: usize = 12;
+
+// DOP and the satellites used, one sentence per constellation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gsa {
+    pub talker: String,
+    // 'A' for automatic 2D/3D switching, 'M' for manual
+    pub mode: char,
+    // 1 no fix, 2 2D, 3 3D
+    pub fix_type: u8,
+    // Only the first GSA_SLOTS are sent, see split
+    pub satellite_ids: Vec<u16>,
+    pub pdop: f64,
+    pub hdop: f64,
+    pub vdop: f64,
+    // GNSS system ID NMEA 4.10 added, see Constellation::system_id
+    pub system_id: Option<u8>,
+}
+
+impl Gsa {
+    // GSAs listing all of the satellites, GSA_SLOTS to each, as receivers
+    // continue a system's list in the next sentence once it fills up
+    pub fn split(&self) -> Vec<Gsa> {
+        if self.satellite_ids.len() <= GSA_SLOTS {
+            return vec![self.clone()];
+        }
+        self.satellite_ids
+            .chunks(GSA_SLOTS)
+            .map(|ids| Gsa {
+                satellite_ids: ids.to_vec(),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+impl Sentence for Gsa {
+    fn address(&self) -> String {
+        format!("{}GSA", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "GSA");
+        out.field(self.mode);
+        out.field(self.fix_type);
+        // Empty slots after the last satellite
+        for slot in 0..GSA_SLOTS {
+            out.field(Optional(self.satellite_ids.get(slot)));
+        }
+        out.field(format_args!("{:.1}", self.pdop));
+        out.field(format_args!("{:.1}", self.hdop));
+        out.field(format_args!("{:.1}", self.vdop));
+        if let Some(system_id) = self.system_id {
+            out.field(format_args!("{:X}", system_id));
+        }
+        out.finish();
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GsvSatellite {
+    pub id: u16,
+    // Empty while the receiver has no almanac for the satellite
+    pub elevation: Option<u8>,
+    pub azimuth: Option<u16>,
+    // Empty while the satellite is not tracked
+    pub snr: Option<u8>,
+}
+
+// Satellites in view, up to 4 per sentence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gsv {
+    pub talker: String,
+    pub total_messages: usize,
+    pub message_number: usize,
+    // Of all messages together
+    pub satellites_in_view: usize,
+    pub satellites: Vec<GsvSatellite>,
+    // Signal the SNRs are of, which NMEA 4.10 added, see
+    // Constellation::signal_id
+    pub signal_id: Option<u8>,
+}
+
+impl Gsv {
+    // The messages listing all of the satellites, four per message. The
+    // last one only has those left; the standard needs no empty fields for
+    // the groups it lacks.
+    pub fn messages(talker: &str, satellites: &[GsvSatellite]) -> Vec<Gsv> {
+        let total_messages = satellites.len().div_ceil(4);
+        satellites
+            .chunks(4)
+            .enumerate()
+            .map(|(i, chunk)| Gsv {
+                talker: talker.to_string(),
+                total_messages,
+                message_number: i + 1,
+                satellites_in_view: satellites.len(),
+                satellites: chunk.to_vec(),
+                signal_id: None,
+            })
+            .collect()
+    }
+}
+
+impl Sentence for Gsv {
+    fn address(&self) -> String {
+        format!("{}GSV", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "GSV");
+        out.field(self.total_messages);
+        out.field(self.message_number);
+        out.field(self.satellites_in_view);
+        for sat in &self.satellites {
+            out.field(sat.id);
+            out.field(Optional(sat.elevation));
+            out.field(Optional(sat.azimuth));
+            out.field(Optional(sat.snr));
+        }
+        if let Some(signal_id) = self.signal_id {
+            out.field(format_args!("{:X}", signal_id));
+        }
+        out.finish();
+    }
+}
+
+// Text transmission, e.g. antenna status or a receiver's own notices. Text
+// longer than one sentence is split over several messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Txt {
+    pub talker: String,
+    pub total_messages: usize,
+    pub message_number: usize,
+    // 00 error, 01 warning, 02 notice, 07 user
+    pub text_id: u8,
+    pub text: String,
+}
+
+impl Sentence for Txt {
+    fn address(&self) -> String {
+        format!("{}TXT", self.talker)
+    }
+
+    fn encode(&self, out: &mut SentenceBuffer) {
+        out.begin(&self.talker, "TXT");
+        out.field(format_args!("{:02}", self.total_messages));
+        out.field(format_args!("{:02}", self.message_number));
+        out.field(format_args!("{:02}", self.text_id));
+        out.field(&self.text);
+        out.finish();
+    }
+}
@@ -0,0 +1,88 @@
+// src/signalk.rs
+
+use crate::nmea_generator::Fix;
+use chrono::SecondsFormat;
+use serde_json::json;
+
+const METERS_PER_SECOND_PER_KNOT: f64 = 1852.0 / 3600.0;
+
+// Signal K names for the GGA fix qualities
+fn method_quality(fix_quality: u8) -> &'static str {
+    match fix_quality {
+        0 => "no GPS",
+        1 => "GNSS Fix",
+        2 => "DGNSS fix",
+        3 => "Precise GNSS",
+        4 => "RTK fixed integer",
+        5 => "RTK float",
+        6 => "Estimated (DR) mode",
+        7 => "Manual input",
+        _ => "Simulator mode",
+    }
+}
+
+// Encodes a fix as a Signal K delta for the own vessel. Values are in SI
+// units as the specification requires, i.e. m/s and radians.
+pub fn encode_delta(fix: &Fix) -> serde_json::Value {
+    json!({
+        "context": "vessels.self",
+        "updates": [{
+            "source": {
+                "label": "nmea_simulator",
+                "type": "NMEA0183",
+                "talker": "GP",
+            },
+            "timestamp": fix.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "values": [
+                {
+                    "path": "navigation.position",
+                    "value": {
+                        "latitude": fix.latitude,
+                        "longitude": fix.longitude,
+                        "altitude": fix.altitude,
+                    },
+                },
+                {
+                    "path": "navigation.speedOverGround",
+                    "value": fix.speed_knots * METERS_PER_SECOND_PER_KNOT,
+                },
+                {
+                    "path": "navigation.courseOverGroundTrue",
+                    "value": fix.course.to_radians(),
+                },
+                {
+                    "path": "navigation.speedThroughWater",
+                    "value": fix.water_speed_knots * METERS_PER_SECOND_PER_KNOT,
+                },
+                {
+                    "path": "navigation.headingTrue",
+                    "value": fix.heading.to_radians(),
+                },
+                {
+                    "path": "navigation.datetime",
+                    "value": fix.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                },
+                {
+                    "path": "navigation.gnss.methodQuality",
+                    "value": method_quality(fix.fix_quality),
+                },
+                {
+                    "path": "navigation.gnss.satellites",
+                    "value": fix.satellites_used(),
+                },
+                {
+                    "path": "navigation.gnss.horizontalDilution",
+                    "value": fix.hdop,
+                },
+                {
+                    "path": "navigation.gnss.antennaAltitude",
+                    "value": fix.altitude,
+                },
+                {
+                    "path": "navigation.gnss.geoidalSeparation",
+                    "value": fix.geoid_height,
+                },
+            ],
+        }],
+    })
+}
@@ -0,0 +1,1021 @@
+// src/simulator.rs
+
+use crate::builder::SimulatorBuilder;
+#[cfg(unix)]
+use crate::commands;
+use crate::config::{self, Config};
+use crate::control::{self, Controller};
+use crate::error::SimError;
+use crate::faults::{self, FaultInjector};
+use crate::http::{self, SseSink};
+use crate::mux::Multiplexer;
+use crate::nmea_generator::{Fix, NmeaGenerator};
+use crate::ntrip::NtripClient;
+use crate::options::Options;
+use crate::output::{DelayLine, Framing, MultiSink, OutputSink, OutputSpec, PacedSink, PtyMode};
+use crate::position::Position;
+#[cfg(unix)]
+use crate::pty_handler::{PtyHandler, ReaderTap};
+#[cfg(unix)]
+use crate::recorder::Recorder;
+#[cfg(feature = "ros2")]
+use crate::ros2;
+use crate::scenario::{self, Scenario, ScenarioRecorder};
+#[cfg(feature = "scripting")]
+use crate::scripting;
+use crate::sentences::SentenceBuffer;
+use crate::state::{self, PortControl, SharedState};
+use crate::stats::{self, SharedStats, Stats};
+use crate::trajectory::Motion;
+use crate::truth_input::{self, TruthInput};
+#[cfg(all(unix, feature = "tui"))]
+use crate::tui;
+#[cfg(feature = "ubx")]
+use crate::ubx;
+use crate::validate;
+use crate::{android, mavlink, pps, repl, rtcm, runtime, signalk};
+use std::fs;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+// How often readiness is checked until reached
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long after the pulse of a second its first epoch goes out with --pps
+const PPS_TO_EPOCH: Duration = Duration::from_millis(50);
+
+// Per-epoch "Sent to" lines, turned off while the terminal UI is up
+static LOG_EPOCHS: AtomicBool = AtomicBool::new(true);
+
+// One simulator run: the receivers of all ports and the services around
+// them. Embedding it in a test looks like
+//
+//   let mut simulator = Simulator::new(Options::default());
+//   simulator.add_output(Box::new(my_sink));
+//   let controller = simulator.controller();
+//   let shutdown = simulator.shutdown_handle();
+//   let run = std::thread::spawn(move || simulator.run());
+//   controller.apply(ControlCommand::SetPosition { .. })?;
+//   ...
+//   shutdown.store(true, Ordering::SeqCst);
+//   run.join().unwrap()?;
+pub struct Simulator {
+    pub options: Options,
+    state: SharedState,
+    ports: Vec<Arc<PortControl>>,
+    controller: Controller,
+    shutdown_event: Arc<AtomicBool>,
+    // Extra sinks on the first port, next to those in the options
+    extra_outputs: Vec<Box<dyn OutputSink>>,
+    stats: SharedStats,
+}
+
+impl Simulator {
+    // The settings of a run one at a time, see SimulatorBuilder
+    pub fn builder() -> SimulatorBuilder {
+        SimulatorBuilder::new()
+    }
+
+    pub fn new(options: Options) -> Self {
+        let state = state::new_shared_state();
+        // Controllers check injected sentences against it before run()
+        state.lock().unwrap().encoding = options.encoding;
+        let ports: Vec<_> = (0..options.ports)
+            .map(|_| {
+                let port = PortControl::new(options.protocol);
+                port.update(|state| {
+                    state.latency = options.latency;
+                    state.report_age = options.report_age;
+                });
+                port
+            })
+            .collect();
+        let controller = Controller::new(state.clone(), ports.clone());
+        Simulator {
+            options,
+            state,
+            ports,
+            controller,
+            shutdown_event: Arc::new(AtomicBool::new(false)),
+            extra_outputs: Vec::new(),
+            stats: Stats::new(),
+        }
+    }
+
+    // Steers the simulation like the control socket does; usable before
+    // and during run()
+    pub fn controller(&self) -> Controller {
+        self.controller.clone()
+    }
+
+    pub fn state(&self) -> SharedState {
+        self.state.clone()
+    }
+
+    // What the ports sent so far and to how many clients
+    pub fn stats(&self) -> SharedStats {
+        self.stats.clone()
+    }
+
+    // Setting this flag ends run() after the current epoch
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        self.shutdown_event.clone()
+    }
+
+    pub fn add_output(&mut self, sink: Box<dyn OutputSink>) {
+        self.extra_outputs.push(sink);
+    }
+
+    // Opens the ports, starts the services and blocks until shutdown or
+    // until every output of every port has failed
+    pub fn run(self) -> Result<(), SimError> {
+        let Simulator {
+            options,
+            state,
+            ports,
+            controller,
+            shutdown_event,
+            mut extra_outputs,
+            stats,
+        } = self;
+        let runtime = runtime::new_io_runtime()
+            .map_err(|e| SimError::io("Failed to start the I/O runtime", e))?;
+
+        // Take the fix quality from a live correction stream if requested
+        let ntrip_thread = options
+            .ntrip
+            .clone()
+            .map(|config| NtripClient::new(config, state.clone(), shutdown_event.clone()).spawn());
+
+        // Simulated base station corrections go to their own outputs
+        let rtcm_thread = options.rtcm_base.map(|base| {
+            let outputs = open_outputs(None, &options.rtcm_outputs, None, options.framing);
+            rtcm::spawn_base_output(base, outputs, shutdown_event.clone())
+        });
+
+        // Every port simulates its own receiver on its own thread, each
+        // with its own seed derived from the run's
+        let seed = options.seed.unwrap_or_else(rand::random);
+        info!(
+            "Random seed {}, pass --seed {} to repeat this run",
+            seed, seed
+        );
+        let scenario_recorder = match &options.scenario_record_path {
+            Some(path) => {
+                let recorder = ScenarioRecorder::create(path, seed)?;
+                controller.record_to(recorder.clone());
+                Some(recorder)
+            }
+            None => None,
+        };
+
+        // Signal K deltas, truth records and the recorded scenario are
+        // encoded from the fix of the first port
+        let open_fix_outputs = |specs: &[OutputSpec]| {
+            if specs.is_empty() {
+                None
+            } else {
+                Some(open_outputs(None, specs, None, options.framing))
+            }
+        };
+        let mut fix_outputs = Some(FixOutputs {
+            signalk: open_fix_outputs(&options.signalk_outputs),
+            truth: open_fix_outputs(&options.truth_outputs),
+            scenario: scenario_recorder.clone(),
+            mux: None,
+            state: true,
+        });
+
+        // Status server for browsers, fed from the first port
+        let sse_sink = SseSink::new();
+        let http_task = match &options.http_addr {
+            Some(addr) => Some(http::spawn_status_server(
+                addr,
+                state.clone(),
+                sse_sink.clone(),
+                runtime.handle(),
+                shutdown_event.clone(),
+            )?),
+            None => None,
+        };
+        let metrics_task = match &options.metrics_addr {
+            Some(addr) => Some(http::spawn_metrics_server(
+                addr,
+                stats.clone(),
+                state.clone(),
+                runtime.handle(),
+                shutdown_event.clone(),
+            )?),
+            None => None,
+        };
+        #[cfg(unix)]
+        let pps_thread = match options.pps.clone() {
+            Some(spec) => Some(pps::spawn_pps(spec, state.clone(), shutdown_event.clone())?),
+            None => None,
+        };
+        let stats_task = options.stats_interval.map(|interval| {
+            runtime.spawn(stats::log_summaries(
+                stats.clone(),
+                interval,
+                shutdown_event.clone(),
+            ))
+        });
+
+        // Each port has either its PTYs, see PtyMode, or a named pipe as its
+        // primary output. PTYs only exist on Unix, Options rejects them elsewhere.
+        #[cfg(unix)]
+        let mut pty_handler = PtyHandler::new(shutdown_event.clone(), runtime.handle().clone());
+        #[cfg(unix)]
+        {
+            pty_handler.baud_rate = options.baud;
+            pty_handler.lock_links = options.lock_links;
+            pty_handler.loopback = options.pty_mode == PtyMode::Loopback;
+        }
+        #[cfg(unix)]
+        let recorder = match &options.received_log_path {
+            Some(path) => Some(Recorder::create(path)?),
+            None => None,
+        };
+        let mut port_specs = Vec::new();
+        for (port, control) in ports.iter().enumerate() {
+            let mut specs = Vec::new();
+            let mut primary: Option<Box<dyn OutputSink>> = None;
+            let control = control.clone();
+            #[cfg(unix)]
+            if let Some(gps_input_path) = &options.gps_input_path {
+                let gps_input_path = Options::port_path(gps_input_path, port);
+                let gps_output_path = options
+                    .gps_output_path
+                    .as_ref()
+                    .map(|path| Options::port_path(path, port));
+                // Configuration commands from the client change this port only
+                let mut handle_commands = commands::receive_handler(control.clone());
+                // Named after the path clients open
+                let client_path = gps_output_path.as_ref().unwrap_or(&gps_input_path);
+                let mut record = recorder.as_ref().map(|r| r.tap(client_path));
+                let on_receive = Box::new(move |data: &[u8]| {
+                    if let Some(record) = record.as_mut() {
+                        record(data);
+                    }
+                    handle_commands(data);
+                });
+                // The generator pauses without a client if nothing else
+                // takes its epochs
+                let only_output =
+                    options.fifo_path.is_none() && (port > 0 || options.outputs.is_empty());
+                let on_reader = {
+                    let control = control.clone();
+                    let stats = stats.clone();
+                    // The forwarder starts out reporting no client
+                    let mut was_connected = false;
+                    Box::new(move |connected: bool| {
+                        if connected != was_connected {
+                            stats.count_clients(connected as u64, !connected as u64);
+                            was_connected = connected;
+                        }
+                        if only_output {
+                            control.update(|port_state| port_state.waiting_for_reader = !connected)
+                        }
+                    }) as ReaderTap
+                };
+                match (options.pty_mode, &gps_output_path) {
+                    (PtyMode::SinglePort, _) => {
+                        let sink = pty_handler.setup_single_pty(
+                            &gps_input_path,
+                            Some(on_receive),
+                            Some(on_reader),
+                        )?;
+                        primary = Some(Box::new(sink));
+                    }
+                    (_, Some(gps_output_path)) => {
+                        pty_handler.setup_linked_ptys(
+                            &gps_input_path,
+                            gps_output_path,
+                            Some(on_receive),
+                            Some(on_reader),
+                        )?;
+                        specs.push(OutputSpec::Pty(gps_input_path));
+                    }
+                    (_, None) => {
+                        return Err(SimError::Usage(format!(
+                            "Linked PTYs need an output path besides {}",
+                            gps_input_path
+                        )))
+                    }
+                }
+            }
+            if let Some(fifo_path) = &options.fifo_path {
+                specs.push(OutputSpec::Fifo(Options::port_path(fifo_path, port)));
+            }
+
+            // Additional outputs are fed from the first port
+            if port == 0 {
+                specs.extend(options.outputs.iter().cloned());
+            }
+            port_specs.push((primary, specs, control));
+        }
+        #[cfg(unix)]
+        pty_handler.start_forwarding()?;
+
+        {
+            let mut state = state.lock().unwrap();
+            state.paused |= options.step;
+            state.trajectory.seed(seed);
+            if let Some(trajectory) = &options.trajectory {
+                let waypoints = trajectory.waypoints()?;
+                state
+                    .trajectory
+                    .switch(trajectory.clone(), waypoints, None, true);
+            }
+            state.sbas |= options.sbas;
+            if let Some(crab) = options.crab {
+                state.crab = crab;
+            }
+            if let Some(environment) = options.environment {
+                state.environment = environment;
+            }
+        }
+
+        // Settings from the config file apply before the first epoch and again
+        // whenever the file changes
+        let config_thread = match &options.config_path {
+            Some(path) => {
+                let config = Config::load(path)?;
+                config.apply(&state, &ports);
+                info!("Loaded config from {}", path);
+                Some(config::spawn_config_watcher(
+                    path,
+                    state.clone(),
+                    ports.clone(),
+                    shutdown_event.clone(),
+                )?)
+            }
+            None => None,
+        };
+        // Sentences injected at a scenario time count from here, unless the
+        // config file scheduled some already
+        state
+            .lock()
+            .unwrap()
+            .started
+            .get_or_insert_with(Instant::now);
+
+        // External scripts steer the simulation through the control socket or
+        // the REST API
+        #[cfg(unix)]
+        let control_task = match &options.control_path {
+            Some(path) => Some(control::spawn_control_socket(
+                path,
+                controller.clone(),
+                runtime.handle(),
+                shutdown_event.clone(),
+            )?),
+            None => None,
+        };
+        let api_task = match &options.api_addr {
+            Some(addr) => Some(control::spawn_api_server(
+                addr,
+                controller.clone(),
+                runtime.handle(),
+                shutdown_event.clone(),
+            )?),
+            None => None,
+        };
+        // Hardware-in-the-loop: positions come from an external vehicle
+        // simulator and the receiver loses its fix when they stop coming
+        let truth_thread = match &options.truth_input {
+            Some(input) => {
+                state.lock().unwrap().external_truth_timeout = Some(options.truth_timeout);
+                match input {
+                    TruthInput::Udp(addr) => Some(truth_input::spawn_truth_listener(
+                        addr,
+                        controller.clone(),
+                        shutdown_event.clone(),
+                    )?),
+                    TruthInput::Mavlink(addr) => Some(mavlink::spawn_mavlink_listener(
+                        addr,
+                        controller.clone(),
+                        shutdown_event.clone(),
+                    )?),
+                    TruthInput::Control => None,
+                }
+            }
+            None => None,
+        };
+        let mavlink_thread = match &options.mavlink_output {
+            Some(addr) => Some(mavlink::spawn_mavlink_output(
+                addr,
+                options.mavlink_message,
+                state.clone(),
+                shutdown_event.clone(),
+            )?),
+            None => None,
+        };
+        let android_thread = match options.android_output.clone() {
+            Some(target) => Some(android::spawn_android_output(
+                target,
+                state.clone(),
+                shutdown_event.clone(),
+            )?),
+            None => None,
+        };
+        // NavSatFix from the truth of the first port, its sentences from a
+        // sink among its outputs
+        #[cfg(feature = "ros2")]
+        let ros2_session = if options.ros2 {
+            Some(ros2::open_session(&options.ros2_connect)?)
+        } else {
+            None
+        };
+        #[cfg(feature = "ros2")]
+        let ros2_thread = ros2_session.clone().map(|session| {
+            ros2::spawn_fix_publisher(
+                session,
+                options.ros2_frame_id.clone(),
+                state.clone(),
+                shutdown_event.clone(),
+            )
+        });
+        let scenario_thread = match &options.scenario_path {
+            Some(path) => {
+                let scenario = Scenario::load(path)?;
+                Some(scenario::spawn_scenario(
+                    scenario,
+                    controller.clone(),
+                    shutdown_event.clone(),
+                ))
+            }
+            None => None,
+        };
+        #[cfg(feature = "scripting")]
+        let script_thread = match &options.script_path {
+            Some(path) => Some(scripting::spawn_script(
+                path,
+                controller.clone(),
+                state.clone(),
+                shutdown_event.clone(),
+            )?),
+            None => None,
+        };
+        // Runs detached, blocked on stdin until the process exits
+        if options.repl {
+            repl::spawn_repl(controller.clone(), shutdown_event.clone());
+        }
+        let ready_task = runtime.spawn(announce_ready(
+            ports.clone(),
+            state.clone(),
+            options.ready_file.clone(),
+            shutdown_event.clone(),
+        ));
+        LOG_EPOCHS.store(!options.tui, Ordering::Relaxed);
+        #[cfg(all(unix, feature = "tui"))]
+        let tui_thread = if options.tui {
+            Some(tui::spawn(tui::TuiContext {
+                controller,
+                state: state.clone(),
+                ports,
+                shutdown_event: shutdown_event.clone(),
+            })?)
+        } else {
+            None
+        };
+
+        // Receivers send the sentences of a second after its pulse
+        if options.pps.is_some() {
+            thread::sleep(pps::until_next_second() + PPS_TO_EPOCH);
+        }
+        // --duration counts from when the ports start sending
+        let start = Instant::now();
+        let mut port_threads = Vec::new();
+        for (port, (primary_sink, specs, control)) in port_specs.into_iter().enumerate() {
+            let mut outputs = open_outputs(primary_sink, &specs, options.baud, options.framing);
+            outputs.set_stats(stats.clone());
+            if let Some(tag_blocks) = &options.tag_blocks {
+                outputs.set_tag_blocks(tag_blocks.clone());
+            }
+            let primary = port == 0;
+            if primary {
+                if options.http_addr.is_some() {
+                    outputs.add(Box::new(sse_sink.clone()));
+                }
+                #[cfg(feature = "ros2")]
+                if let Some(session) = &ros2_session {
+                    outputs.add(Box::new(ros2::Ros2Sink::new(
+                        session.clone(),
+                        &options.ros2_frame_id,
+                    )));
+                }
+                for sink in extra_outputs.drain(..) {
+                    outputs.add(sink);
+                }
+            }
+            let mut fix_outputs = if primary {
+                fix_outputs.take().unwrap_or_default()
+            } else {
+                FixOutputs::default()
+            };
+            if !options.mux.is_empty() {
+                fix_outputs.mux = Some(Multiplexer::new(
+                    &options.mux,
+                    seed.wrapping_add(port as u64),
+                ));
+            }
+
+            let limits = RunLimits {
+                shutdown_event: shutdown_event.clone(),
+                max_epochs: options.count,
+                // Past what an Instant holds is never
+                deadline: options
+                    .duration
+                    .and_then(|duration| start.checked_add(duration)),
+                kinematics: KinematicsCheck {
+                    tolerance: options.kinematics_tolerance,
+                    strict: options.strict,
+                    failing: false,
+                },
+            };
+            let state = state.clone();
+            let faults = options.faults.clone();
+            let minute_decimals = options.minute_decimals;
+            let talker_policy = options.talker_policy;
+            let dgps_network = options.dgps_network.clone();
+            port_threads.push(thread::spawn(move || {
+                // Initialize NMEA generator
+                let mut nmea_generator = NmeaGenerator::with_seed(seed.wrapping_add(port as u64));
+                nmea_generator.minute_decimals = minute_decimals;
+                nmea_generator.talker_policy = talker_policy;
+                nmea_generator.dgps_network = dgps_network;
+                let mut faults = FaultInjector::new(faults, seed.wrapping_add(port as u64));
+
+                // Write NMEA messages to all outputs
+                write_nmea_messages(
+                    &mut outputs,
+                    fix_outputs,
+                    &mut nmea_generator,
+                    &mut faults,
+                    &control,
+                    &state,
+                    limits,
+                )
+            }));
+        }
+
+        // The run fails with the first port that did, after cleaning up
+        let mut result = Ok(());
+        for port_thread in port_threads {
+            if let Ok(Err(e)) = port_thread.join() {
+                result = result.and(Err(e));
+            }
+        }
+        // With every port done there is nothing left to serve
+        shutdown_event.store(true, Ordering::SeqCst);
+        if let Some(scenario_recorder) = scenario_recorder {
+            scenario_recorder.record_stop();
+        }
+        // Give the terminal back before cleanup reports anything
+        #[cfg(all(unix, feature = "tui"))]
+        if let Some(tui_thread) = tui_thread {
+            let _ = tui_thread.join();
+        }
+
+        // Perform cleanup
+        let _ = runtime.block_on(ready_task);
+        if let Some(path) = &options.ready_file {
+            let _ = fs::remove_file(path);
+        }
+        #[cfg(unix)]
+        pty_handler.cleanup()?;
+        if let Some(ntrip_thread) = ntrip_thread {
+            let _ = ntrip_thread.join();
+        }
+        if let Some(rtcm_thread) = rtcm_thread {
+            let _ = rtcm_thread.join();
+        }
+        #[cfg(unix)]
+        if let Some(pps_thread) = pps_thread {
+            let _ = pps_thread.join();
+        }
+        if let Some(http_task) = http_task {
+            let _ = runtime.block_on(http_task);
+        }
+        if let Some(metrics_task) = metrics_task {
+            let _ = runtime.block_on(metrics_task);
+        }
+        if let Some(stats_task) = stats_task {
+            let _ = runtime.block_on(stats_task);
+        }
+        if let Some(api_task) = api_task {
+            let _ = runtime.block_on(api_task);
+        }
+        if let Some(config_thread) = config_thread {
+            let _ = config_thread.join();
+        }
+        if let Some(truth_thread) = truth_thread {
+            let _ = truth_thread.join();
+        }
+        if let Some(mavlink_thread) = mavlink_thread {
+            let _ = mavlink_thread.join();
+        }
+        if let Some(android_thread) = android_thread {
+            let _ = android_thread.join();
+        }
+        #[cfg(feature = "ros2")]
+        if let Some(ros2_thread) = ros2_thread {
+            let _ = ros2_thread.join();
+        }
+        if let Some(scenario_thread) = scenario_thread {
+            let _ = scenario_thread.join();
+        }
+        #[cfg(feature = "scripting")]
+        if let Some(script_thread) = script_thread {
+            let _ = script_thread.join();
+        }
+        #[cfg(unix)]
+        if let Some(control_task) = control_task {
+            let _ = runtime.block_on(control_task);
+        }
+
+        result
+    }
+}
+
+// Opens a set of outputs; only the primary one is paced to the baud rate.
+// That is the given sink if there is one, the first spec otherwise.
+fn open_outputs(
+    primary: Option<Box<dyn OutputSink>>,
+    specs: &[OutputSpec],
+    baud: Option<u32>,
+    framing: Framing,
+) -> MultiSink {
+    let mut outputs = MultiSink::new();
+    let paced = |sink: Box<dyn OutputSink>| -> Box<dyn OutputSink> {
+        match baud {
+            Some(baud) => Box::new(PacedSink::new(sink, baud, framing)),
+            None => sink,
+        }
+    };
+    let first_spec = if primary.is_some() { None } else { Some(0) };
+    if let Some(sink) = primary {
+        outputs.add(paced(sink));
+    }
+    for (i, spec) in specs.iter().enumerate() {
+        match spec.open() {
+            Ok(sink) if Some(i) == first_spec => outputs.add(paced(sink)),
+            Ok(sink) => outputs.add(sink),
+            Err(e) => warn!("Skipping output {:?}: {}", spec, e),
+        }
+    }
+    outputs
+}
+
+// Marks the simulator ready once every port has sent an epoch, or holds
+// back because it is paused or no client has its PTY open yet; links and
+// services exist by the time the ports start. Creates the ready file then.
+async fn announce_ready(
+    ports: Vec<Arc<PortControl>>,
+    state: SharedState,
+    ready_file: Option<String>,
+    shutdown_event: Arc<AtomicBool>,
+) {
+    loop {
+        if shutdown_event.load(Ordering::SeqCst) {
+            return;
+        }
+        let paused = state.lock().unwrap().paused;
+        let ready = ports.iter().all(|port| {
+            let port_state = port.lock();
+            paused || port_state.epochs_sent > 0 || port_state.waiting_for_reader
+        });
+        if ready {
+            break;
+        }
+        tokio::time::sleep(READY_POLL_INTERVAL).await;
+    }
+
+    state.lock().unwrap().ready = true;
+    if let Some(path) = &ready_file {
+        if let Err(e) = fs::write(path, format!("{}\n", std::process::id())) {
+            warn!("Failed to create ready file {}: {}", path, e);
+            return;
+        }
+    }
+    info!("Ready");
+}
+
+// When a port stops sending: on shutdown, for finite runs after a number
+// of epochs or at a deadline, and in strict mode at an inconsistent epoch
+struct RunLimits {
+    shutdown_event: Arc<AtomicBool>,
+    max_epochs: Option<u64>,
+    deadline: Option<Instant>,
+    kinematics: KinematicsCheck,
+}
+
+impl RunLimits {
+    fn reached(&self, epochs_sent: u64) -> bool {
+        if self.shutdown_event.load(Ordering::SeqCst) {
+            return true;
+        }
+        if self.max_epochs.is_some_and(|max| epochs_sent >= max) {
+            info!("Sent {} epochs, stopping", epochs_sent);
+            return true;
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            info!("Run time is up, stopping");
+            return true;
+        }
+        false
+    }
+}
+
+// Speeds, courses and headings of every epoch sent against its fix. In
+// strict mode the first disagreement ends the run; otherwise it is
+// reported once until the epochs agree again.
+struct KinematicsCheck {
+    tolerance: f64,
+    strict: bool,
+    failing: bool,
+}
+
+impl KinematicsCheck {
+    fn check(&mut self, fix: &Fix, epoch: &str, number: u64) -> Result<(), SimError> {
+        match validate::check_kinematics(fix, epoch, self.tolerance) {
+            Ok(()) if self.failing => {
+                info!("Epoch {} agrees with its fix again", number);
+                self.failing = false;
+            }
+            Ok(()) => {}
+            Err(e) if self.strict => {
+                return Err(SimError::Inconsistent {
+                    epoch: number,
+                    message: e.to_string(),
+                })
+            }
+            Err(e) if !self.failing => {
+                warn!("Epoch {} disagrees with its fix: {}", number, e);
+                self.failing = true;
+            }
+            Err(_) => {}
+        }
+        Ok(())
+    }
+}
+
+// Outputs fed with the fix itself instead of its sentences
+#[derive(Default)]
+struct FixOutputs {
+    signalk: Option<MultiSink>,
+    // One JSON object per epoch with what the sentences were encoded from,
+    // for tests to compare against what their parser decoded
+    truth: Option<MultiSink>,
+    scenario: Option<ScenarioRecorder>,
+    // Instruments measuring the truth, whose sentences a multiplexer mixes
+    // in with the epochs
+    mux: Option<Multiplexer>,
+    // The truth of the shared state, for the API and status displays
+    state: bool,
+}
+
+fn write_nmea_messages(
+    outputs: &mut MultiSink,
+    mut fix_outputs: FixOutputs,
+    nmea_generator: &mut NmeaGenerator,
+    faults: &mut FaultInjector,
+    control: &PortControl,
+    state: &SharedState,
+    mut limits: RunLimits,
+) -> Result<(), SimError> {
+    let mut next_epoch = Instant::now();
+    let mut delayed = DelayLine::new();
+    // Reused by every epoch, so encoding stops allocating once it has grown
+    let mut sentences = SentenceBuffer::new();
+
+    // Main loop to write NMEA messages, until every output has failed
+    while !limits.reached(control.lock().epochs_sent) && !outputs.is_empty() {
+        let paused = {
+            let interval = control.lock().interval;
+            let mut state = state.lock().unwrap();
+            nmea_generator.fix_quality = state.effective_fix_quality();
+            outputs.set_filters(state.output_filters.clone());
+            let pinned = Motion {
+                position: state.position.map(Position::from),
+                speed_knots: state.speed_knots,
+                course: state.course,
+            };
+            let motion = state.trajectory.advance(Instant::now(), interval, pinned);
+            nmea_generator.position = motion.position;
+            nmea_generator.speed_knots = motion.speed_knots;
+            nmea_generator.course = motion.course;
+            nmea_generator.crab = state.crab;
+            nmea_generator.satellites = state.satellites;
+            nmea_generator.hdop = state.hdop;
+            nmea_generator.constellations = state.constellations.clone();
+            nmea_generator.sbas = state.sbas;
+            nmea_generator.environment = state.environment;
+            nmea_generator.no_fix = state.no_fix;
+            nmea_generator.authenticated = state.authenticated;
+            nmea_generator.encoding = state.encoding;
+            nmea_generator.templates = state.templates.clone();
+            nmea_generator.correction_age = state
+                .correction_received
+                .map(|received| received.elapsed().as_secs_f64());
+            nmea_generator.station_id = state.reference_station;
+            state.paused
+        };
+        let (protocol, interval, latency, stepping, waiting_for_reader) = {
+            let mut port_state = control.lock();
+            nmea_generator.sentence_rates = port_state.sentence_rates;
+            nmea_generator.data_age = port_state
+                .report_age
+                .then(|| port_state.latency.as_secs_f64());
+            nmea_generator.skip_sequence(std::mem::take(&mut port_state.sequence_gap));
+            let stepping = port_state.steps > 0;
+            if stepping {
+                port_state.steps -= 1;
+            }
+            (
+                port_state.protocol,
+                port_state.interval,
+                port_state.latency,
+                stepping,
+                port_state.waiting_for_reader,
+            )
+        };
+
+        // Faults may make the receiver report something else than the truth
+        let truth = nmea_generator.generate_fix();
+        let fix = faults.report(&truth);
+        nmea_generator.skip_sequence(faults.sequence_gap());
+        if let Some(mux) = fix_outputs.mux.as_mut() {
+            mux.update(&truth);
+        }
+        sentences.clear();
+        if protocol.nmea() {
+            nmea_generator.encode_epoch(&fix, &mut sentences);
+        }
+        let sentence = sentences.as_str();
+        if fix_outputs.state {
+            state.lock().unwrap().truth = Some(truth.clone());
+        }
+
+        // While paused the fix is still kept for answering queries
+        if !(paused || waiting_for_reader) || stepping {
+            let number = control.lock().epochs_sent;
+            if let Err(e) = limits.kinematics.check(&fix, sentence, number) {
+                limits.shutdown_event.store(true, Ordering::SeqCst);
+                return Err(e);
+            }
+            let sentence = {
+                let mut port_state = control.lock();
+                let (sentence, corrupted) = faults::corrupt_checksums(sentence, port_state.corrupt);
+                port_state.corrupt -= corrupted;
+                faults.apply(&sentence)
+            };
+            #[cfg(feature = "ubx")]
+            let epoch = if protocol.ubx() {
+                [sentence.as_slice(), &ubx::encode_epoch(&fix)].concat()
+            } else {
+                sentence.clone()
+            };
+            #[cfg(not(feature = "ubx"))]
+            let epoch = sentence.clone();
+            // Held back during a stall
+            let epoch = faults.release(epoch);
+            if let Some(signalk_outputs) = fix_outputs.signalk.as_mut() {
+                let delta = format!("{}\n", signalk::encode_delta(&fix));
+                signalk_outputs.write_all(delta.as_bytes());
+            }
+            if let Some(truth_outputs) = fix_outputs.truth.as_mut() {
+                match serde_json::to_string(&truth) {
+                    Ok(record) => truth_outputs.write_all(format!("{}\n", record).as_bytes()),
+                    Err(e) => warn!("Failed to encode truth record: {}", e),
+                }
+            }
+            if let Some(scenario) = &fix_outputs.scenario {
+                scenario.record_truth(&truth);
+            }
+
+            {
+                let mut port_state = control.lock();
+                port_state.epochs_sent += 1;
+                port_state.bytes_sent += epoch.len() as u64;
+                port_state.output_names = outputs.names();
+            }
+            // The terminal UI shows the stream itself
+            let log_epochs = LOG_EPOCHS.load(Ordering::Relaxed) && !epoch.is_empty();
+            if !epoch.is_empty() {
+                delayed.push(epoch, latency);
+            }
+            send_due(outputs, &mut delayed, faults, &mut fix_outputs.mux);
+            if log_epochs && protocol.nmea() {
+                info!(
+                    "Sent to {}: {}",
+                    outputs.names().join(", "),
+                    String::from_utf8_lossy(&sentence).trim()
+                );
+            }
+            #[cfg(feature = "ubx")]
+            if log_epochs && protocol.ubx() {
+                info!(
+                    "Sent to {}: UBX NAV-PVT, NAV-SAT, NAV-DOP",
+                    outputs.names().join(", ")
+                );
+            }
+        }
+
+        // Finite runs end right after their last epoch
+        if limits.reached(control.lock().epochs_sent) {
+            break;
+        }
+
+        // Keep a steady epoch rate even when paced writes take a while
+        next_epoch += interval;
+        // and end a finite run at its deadline, not at the epoch after it
+        let due = next_epoch + faults.jitter(interval);
+        let wait_until = limits.deadline.map_or(due, |deadline| deadline.min(due));
+        let now = Instant::now();
+        if next_epoch > now {
+            // Replies to the client's commands go out as soon as they come in
+            loop {
+                let wake = [
+                    delayed.next_due(),
+                    fix_outputs.mux.as_ref().and_then(Multiplexer::next_due),
+                ]
+                .into_iter()
+                .flatten()
+                .fold(wait_until, Instant::min);
+                let requests = control.wait_for_requests(wake);
+                let mut replies = requests.replies;
+                for formatter in requests.queries {
+                    match nmea_generator.encode_sentence(&formatter, &fix) {
+                        Some(sentence) => replies.extend(sentence.into_bytes()),
+                        None => warn!("Ignoring query for unsupported sentence {}", formatter),
+                    }
+                }
+                if !replies.is_empty() {
+                    outputs.write_all(&replies);
+                }
+                send_due(outputs, &mut delayed, faults, &mut fix_outputs.mux);
+                if requests.step {
+                    next_epoch = Instant::now();
+                    break;
+                }
+                if Instant::now() >= wait_until {
+                    break;
+                }
+            }
+        } else {
+            next_epoch = now;
+        }
+    }
+
+    // Epochs still on their way go out at their time, unless shutting down
+    let on_their_way = |delayed: &DelayLine, mux: &Option<Multiplexer>| {
+        let queued = mux.as_ref().and_then(Multiplexer::next_queued);
+        delayed.next_due().into_iter().chain(queued).min()
+    };
+    while let Some(due) = on_their_way(&delayed, &fix_outputs.mux) {
+        if limits.shutdown_event.load(Ordering::SeqCst) {
+            break;
+        }
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        send_due(outputs, &mut delayed, faults, &mut fix_outputs.mux);
+    }
+    Ok(())
+}
+
+// Writes the epochs whose time has come, in pieces if the faults say so.
+// Behind a multiplexer they go in among its instruments' sentences instead.
+fn send_due(
+    outputs: &mut MultiSink,
+    delayed: &mut DelayLine,
+    faults: &mut FaultInjector,
+    mux: &mut Option<Multiplexer>,
+) {
+    while let Some(epoch) = delayed.pop_due(Instant::now()) {
+        if let Some(mux) = mux.as_mut() {
+            mux.receive(&epoch, Instant::now());
+            continue;
+        }
+        for (i, piece) in faults.pieces(&epoch).into_iter().enumerate() {
+            if i > 0 {
+                thread::sleep(faults::PIECE_GAP);
+            }
+            outputs.write_all(piece);
+        }
+    }
+    if let Some(mux) = mux.as_mut() {
+        let sentences = mux.take_due(Instant::now());
+        if !sentences.is_empty() {
+            outputs.write_all(&sentences);
+        }
+    }
+}
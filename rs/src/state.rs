@@ -0,0 +1,297 @@
+// src/state.rs
+
+use crate::nmea_generator::{Constellation, Crab, EncodingMode, Environment, Fix, SentenceRates};
+use crate::output::SentenceFilter;
+use crate::position::Position;
+use crate::template::SentenceTemplate;
+use crate::trajectory::{Trajectory, TrajectorySource};
+use crate::ubx::Protocol;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Simulation settings that can change while the simulator is running and
+// are applied to every generator at the start of each epoch.
+#[derive(Debug, Default)]
+pub struct SimState {
+    // GGA fix quality forced by e.g. an incoming correction stream; the
+    // generator picks its own when unset
+    pub fix_quality: Option<u8>,
+    // The fix goes back and forth between RTK fixed and float, taking the
+    // place of the quality above while set
+    pub rtk_oscillation: Option<RtkOscillation>,
+    // Arrival of the latest correction and the reference station it came
+    // from, reported by GGA with a differential fix
+    pub correction_received: Option<Instant>,
+    pub reference_station: Option<u16>,
+    // Values pinned by a controller, as latitude, longitude and altitude,
+    // knots and degrees; random when unset
+    pub position: Option<(f64, f64, f64)>,
+    pub speed_knots: Option<f64>,
+    pub course: Option<f64>,
+    // Where the positions come from: the values above, a walk or a route
+    pub trajectory: Trajectory,
+    // How far the heading is off the course, e.g. in a crosswind
+    pub crab: Crab,
+    // Number of satellites in view and HDOP, e.g. to script a degraded sky
+    pub satellites: Option<usize>,
+    pub hdop: Option<f64>,
+    pub constellations: Option<Vec<Constellation>>,
+    // SBAS corrections are received, making the fix differential
+    pub sbas: bool,
+    // Surroundings of the antenna, which the signal strengths follow
+    pub environment: Environment,
+    // Set when the truth comes from an external simulator: without an
+    // update for this long the receiver reports no fix
+    pub external_truth_timeout: Option<Duration>,
+    pub truth_received: Option<Instant>,
+    // No epochs are sent while paused; replies to clients still are
+    pub paused: bool,
+    // The receiver reports no fix, with empty coordinates
+    pub no_fix: bool,
+    // Galileo OSNMA status of every epoch; not reported when unset
+    pub authenticated: Option<bool>,
+    // How closely sentences follow NMEA 0183, injected ones included
+    pub encoding: EncodingMode,
+    // Sentences some outputs get, and sentences of our own, from the
+    // config file
+    pub output_filters: Arc<Vec<SentenceFilter>>,
+    pub templates: Arc<Vec<SentenceTemplate>>,
+    // Latest fix of the first port, as published to status consumers
+    pub truth: Option<Fix>,
+    // Every port is set up and sending, or waiting for a client to open it
+    pub ready: bool,
+    // Start of the run, which sentences injected at a scenario time count
+    // from
+    pub started: Option<Instant>,
+}
+
+impl SimState {
+    // Fix quality to report, no fix while external truth is missing or stale
+    pub fn effective_fix_quality(&self) -> Option<u8> {
+        match self.external_truth_timeout {
+            Some(timeout) if self.truth_received.is_none_or(|t| t.elapsed() > timeout) => Some(0),
+            _ => match &self.rtk_oscillation {
+                Some(oscillation) => Some(oscillation.fix_quality(Instant::now())),
+                None => self.fix_quality,
+            },
+        }
+    }
+
+    // Switches the trajectory from the latest fix. Back on external the
+    // receiver stays where it got to until something else moves it,
+    // instead of jumping about at random.
+    pub fn switch_trajectory(
+        &mut self,
+        trajectory: TrajectorySource,
+        waypoints: Vec<Position>,
+        jump: bool,
+    ) {
+        let from = self.truth.clone();
+        if trajectory == TrajectorySource::External && self.position.is_none() {
+            self.position = from
+                .as_ref()
+                .map(|fix| (fix.latitude, fix.longitude, fix.altitude));
+        }
+        self.trajectory
+            .switch(trajectory, waypoints, from.as_ref(), jump);
+    }
+}
+
+// RTK fixed for a while, then float for a while, and fixed again, as with a
+// baseline too long or a sky too obstructed to hold the ambiguities
+#[derive(Debug, Clone, Copy)]
+pub struct RtkOscillation {
+    pub fixed: Duration,
+    pub float: Duration,
+    // Start of the first fixed dwell
+    pub since: Instant,
+}
+
+impl RtkOscillation {
+    // GGA fix quality at this time: 4 while fixed, 5 while float
+    pub fn fix_quality(&self, now: Instant) -> u8 {
+        let cycle = self.fixed.as_secs_f64() + self.float.as_secs_f64();
+        let into = now.saturating_duration_since(self.since).as_secs_f64() % cycle;
+        if into < self.fixed.as_secs_f64() {
+            4
+        } else {
+            5
+        }
+    }
+}
+
+// A sentence sent at a later time, and again every so often when it repeats
+#[derive(Debug, Clone)]
+pub struct Injection {
+    pub sentence: String,
+    pub due: Instant,
+    pub every: Option<Duration>,
+    // Scheduled by the config file, and replaced when it is reloaded
+    pub from_config: bool,
+}
+
+impl Injection {
+    // Due "at" seconds after the start, or right away, and then every so
+    // often; None for one that is sent once and whose time has passed
+    pub fn schedule(
+        sentence: String,
+        at: Option<f64>,
+        every: Option<Duration>,
+        start: Instant,
+        now: Instant,
+    ) -> Option<Self> {
+        let mut due = at.map_or(now, |at| start + Duration::from_secs_f64(at));
+        if due < now {
+            let every = every?;
+            let behind = (now - due).as_secs_f64() / every.as_secs_f64();
+            due += every.mul_f64(behind.ceil());
+        }
+        Some(Injection {
+            sentence,
+            due,
+            every,
+            from_config: false,
+        })
+    }
+}
+
+pub type SharedState = Arc<Mutex<SimState>>;
+
+pub fn new_shared_state() -> SharedState {
+    Arc::new(Mutex::new(SimState::default()))
+}
+
+// Receiver settings of one port that the device under test can change
+// with configuration commands, and the replies still to be sent to it.
+#[derive(Debug)]
+pub struct PortState {
+    pub interval: Duration,
+    // How long after its fix an epoch goes out, and whether GGA reports
+    // that as the age of the data
+    pub latency: Duration,
+    pub report_age: bool,
+    pub sentence_rates: SentenceRates,
+    pub protocol: Protocol,
+    pub replies: Vec<u8>,
+    // Formatters of queried sentences, answered from the latest fix
+    pub queries: Vec<String>,
+    // Epochs to send right away, even while paused
+    pub steps: u32,
+    // Sentences still to be sent with a wrong checksum
+    pub corrupt: u32,
+    // Numbers the next sentences of the templates skip
+    pub sequence_gap: u64,
+    // Injected sentences waiting for their time
+    pub injections: Vec<Injection>,
+    // What the writer has sent so far, for status displays
+    pub epochs_sent: u64,
+    pub bytes_sent: u64,
+    pub output_names: Vec<String>,
+    // No client has the port's only output open, so there is no point in
+    // generating epochs
+    pub waiting_for_reader: bool,
+}
+
+// Shared between a port's writer thread and whoever handles the commands
+// received on that port. Changes wake the writer so replies go out at once.
+pub struct PortControl {
+    state: Mutex<PortState>,
+    changed: Condvar,
+}
+
+impl PortControl {
+    pub fn new(protocol: Protocol) -> Arc<Self> {
+        Arc::new(PortControl {
+            state: Mutex::new(PortState {
+                interval: Duration::from_secs(1),
+                latency: Duration::ZERO,
+                report_age: false,
+                sentence_rates: SentenceRates::default(),
+                protocol,
+                replies: Vec::new(),
+                queries: Vec::new(),
+                steps: 0,
+                corrupt: 0,
+                sequence_gap: 0,
+                injections: Vec::new(),
+                epochs_sent: 0,
+                bytes_sent: 0,
+                output_names: Vec::new(),
+                waiting_for_reader: false,
+            }),
+            changed: Condvar::new(),
+        })
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, PortState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn update<F: FnOnce(&mut PortState)>(&self, f: F) {
+        f(&mut self.lock());
+        self.changed.notify_all();
+    }
+
+    // Waits until the deadline or until something is requested, whichever
+    // comes first, and takes the queued replies and queries. Injected
+    // sentences that are due count as replies.
+    pub fn wait_for_requests(&self, deadline: Instant) -> Requests {
+        let mut state = self.lock();
+        loop {
+            let now = Instant::now();
+            state.take_due_injections(now);
+            if !state.replies.is_empty() || !state.queries.is_empty() || state.steps > 0 {
+                break;
+            }
+            let until = state
+                .injections
+                .iter()
+                .map(|injection| injection.due)
+                .fold(deadline, Instant::min);
+            if now >= until {
+                break;
+            }
+            state = self.changed.wait_timeout(state, until - now).unwrap().0;
+        }
+        Requests {
+            replies: std::mem::take(&mut state.replies),
+            queries: std::mem::take(&mut state.queries),
+            step: state.steps > 0,
+        }
+    }
+}
+
+impl PortState {
+    // Queues the injected sentences that are due as replies, and schedules
+    // the repeating ones again
+    fn take_due_injections(&mut self, now: Instant) {
+        let mut i = 0;
+        while i < self.injections.len() {
+            let injection = &mut self.injections[i];
+            if injection.due > now {
+                i += 1;
+                continue;
+            }
+            self.replies.extend(injection.sentence.as_bytes());
+            match injection.every {
+                // Once per period, even when the writer was held up
+                Some(every) => {
+                    while injection.due <= now {
+                        injection.due += every;
+                    }
+                    i += 1;
+                }
+                None => {
+                    self.injections.remove(i);
+                }
+            }
+        }
+    }
+}
+
+pub struct Requests {
+    pub replies: Vec<u8>,
+    pub queries: Vec<String>,
+    // An epoch is due right away; the step itself is taken by the writer
+    pub step: bool,
+}
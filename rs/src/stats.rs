@@ -0,0 +1,265 @@
+// src/stats.rs
+
+use crate::runtime::shutdown_requested;
+use crate::state::SimState;
+use crate::tag_block;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+// What the simulator sent and who listened since it started, for keeping
+// an eye on long runs. The outputs of every port, the network sinks and
+// the PTY forwarders all count into the same instance.
+#[derive(Debug, Default)]
+pub struct Stats {
+    sentences: AtomicU64,
+    bytes: AtomicU64,
+    client_connects: AtomicU64,
+    client_disconnects: AtomicU64,
+    write_errors: AtomicU64,
+    // Sentences by formatter, e.g. GGA; proprietary ones by address
+    sentence_counts: Mutex<BTreeMap<String, u64>>,
+}
+
+pub type SharedStats = Arc<Stats>;
+
+// The counters at one point in time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    pub sentences: u64,
+    pub bytes: u64,
+    pub client_connects: u64,
+    pub client_disconnects: u64,
+    pub write_errors: u64,
+    pub sentence_counts: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    pub fn new() -> SharedStats {
+        Arc::new(Stats::default())
+    }
+
+    // Counts one write to the outputs of a port: its bytes, and the NMEA
+    // sentences among them. Binary messages only count as bytes.
+    pub fn count_output(&self, data: &[u8]) {
+        self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        let mut sentences = 0;
+        let mut counts = self.sentence_counts.lock().unwrap();
+        for line in data.split(|&c| c == b'\n') {
+            let Some(line) = tag_block::skip(line).strip_prefix(b"$") else {
+                continue;
+            };
+            let end = line
+                .iter()
+                .position(|&c| c == b',' || c == b'*')
+                .unwrap_or(line.len());
+            let Ok(address) = std::str::from_utf8(&line[..end]) else {
+                continue;
+            };
+            let kind = formatter(address.trim_end());
+            // Only the first sentence of a kind allocates its key
+            match counts.get_mut(kind) {
+                Some(count) => *count += 1,
+                None => {
+                    counts.insert(kind.to_string(), 1);
+                }
+            }
+            sentences += 1;
+        }
+        self.sentences.fetch_add(sentences, Ordering::Relaxed);
+    }
+
+    pub fn count_clients(&self, connects: u64, disconnects: u64) {
+        self.client_connects.fetch_add(connects, Ordering::Relaxed);
+        self.client_disconnects
+            .fetch_add(disconnects, Ordering::Relaxed);
+    }
+
+    pub fn count_write_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            sentences: self.sentences.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            client_connects: self.client_connects.load(Ordering::Relaxed),
+            client_disconnects: self.client_disconnects.load(Ordering::Relaxed),
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            sentence_counts: self.sentence_counts.lock().unwrap().clone(),
+        }
+    }
+}
+
+// GGA of GPGGA; addresses that are not talker and formatter stay whole
+fn formatter(address: &str) -> &str {
+    match address.get(2..) {
+        Some(formatter) if address.len() == 5 && !address.starts_with('P') => formatter,
+        _ => address,
+    }
+}
+
+impl StatsSnapshot {
+    // One line for the log, e.g. "120 sentences (GGA 24, RMC 24, ...),
+    // 8400 bytes, 1 client connects, 0 disconnects, 0 write errors"
+    pub fn summary(&self) -> String {
+        let counts: Vec<String> = self
+            .sentence_counts
+            .iter()
+            .map(|(kind, count)| format!("{} {}", kind, count))
+            .collect();
+        let mut summary = format!("{} sentences", self.sentences);
+        if !counts.is_empty() {
+            summary.push_str(&format!(" ({})", counts.join(", ")));
+        }
+        format!(
+            "{}, {} bytes, {} client connects, {} disconnects, {} write errors",
+            summary, self.bytes, self.client_connects, self.client_disconnects, self.write_errors
+        )
+    }
+
+    // The counters in the Prometheus text format
+    pub fn prometheus(&self) -> String {
+        let mut out = String::new();
+        metric_header(
+            &mut out,
+            "nmea_simulator_sentences_total",
+            "counter",
+            "NMEA sentences sent, by formatter",
+        );
+        for (kind, count) in &self.sentence_counts {
+            sample(
+                &mut out,
+                "nmea_simulator_sentences_total",
+                &format!("formatter=\"{}\"", kind),
+                count,
+            );
+        }
+        let counters = [
+            ("bytes", "Bytes sent", self.bytes),
+            (
+                "client_connects",
+                "Clients that connected to a port",
+                self.client_connects,
+            ),
+            (
+                "client_disconnects",
+                "Clients that disconnected from a port",
+                self.client_disconnects,
+            ),
+            ("write_errors", "Writes an output failed", self.write_errors),
+        ];
+        for (name, help, value) in counters {
+            let name = format!("nmea_simulator_{}_total", name);
+            metric_header(&mut out, &name, "counter", help);
+            sample(&mut out, &name, "", value);
+        }
+        out
+    }
+}
+
+// The simulated truth as gauges in the Prometheus text format: the latest
+// fix of the first port, before faults change what the receiver reports.
+// Only whether the simulation is paused until the first fix.
+pub fn truth_gauges(state: &SimState) -> String {
+    let mut out = String::new();
+    gauge(
+        &mut out,
+        "paused",
+        "Whether epochs are held back",
+        state.paused as u8,
+    );
+    let Some(fix) = &state.truth else {
+        return out;
+    };
+    let gauges = [
+        (
+            "fix",
+            "Whether there is a fix",
+            (fix.fix_quality > 0) as u8 as f64,
+        ),
+        ("fix_quality", "GGA fix quality", fix.fix_quality as f64),
+        ("latitude_degrees", "Latitude", fix.latitude),
+        ("longitude_degrees", "Longitude", fix.longitude),
+        (
+            "altitude_meters",
+            "Altitude above mean sea level",
+            fix.altitude,
+        ),
+        ("speed_knots", "Speed over ground", fix.speed_knots),
+        ("course_degrees", "Course over ground", fix.course),
+        ("hdop", "Horizontal dilution of precision", fix.hdop),
+        (
+            "satellites",
+            "Satellites used",
+            fix.satellites_used() as f64,
+        ),
+        (
+            "fix_timestamp_seconds",
+            "Time of the fix as a Unix timestamp",
+            fix.time.timestamp_millis() as f64 / 1000.0,
+        ),
+    ];
+    for (name, help, value) in gauges {
+        gauge(&mut out, name, help, value);
+    }
+
+    let mut constellations: BTreeMap<String, usize> = BTreeMap::new();
+    for satellite in fix.satellites.iter().filter(|sat| sat.used) {
+        *constellations
+            .entry(satellite.constellation.to_string())
+            .or_default() += 1;
+    }
+    let name = "nmea_simulator_constellation_satellites";
+    metric_header(&mut out, name, "gauge", "Satellites used, by constellation");
+    for (constellation, count) in constellations {
+        sample(
+            &mut out,
+            name,
+            &format!("constellation=\"{}\"", constellation),
+            count,
+        );
+    }
+    out
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl Display) {
+    let name = format!("nmea_simulator_{}", name);
+    metric_header(out, &name, "gauge", help);
+    sample(out, &name, "", value);
+}
+
+// The # HELP and # TYPE lines that come before the samples of a metric
+pub fn metric_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// One sample, with labels like formatter="GGA" or none
+pub fn sample(out: &mut String, name: &str, labels: &str, value: impl Display) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{} {}", name, value);
+    } else {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+// Logs a summary every interval until shutdown, and a last one then
+pub async fn log_summaries(
+    stats: SharedStats,
+    interval: Duration,
+    shutdown_event: Arc<AtomicBool>,
+) {
+    loop {
+        tokio::select! {
+            _ = shutdown_requested(&shutdown_event) => break,
+            _ = tokio::time::sleep(interval) => {
+                info!("Stats: {}", stats.snapshot().summary());
+            }
+        }
+    }
+    info!("Stats: {}", stats.snapshot().summary());
+}
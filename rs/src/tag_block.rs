@@ -0,0 +1,132 @@
+// src/tag_block.rs
+
+use crate::checksum::checksum;
+use crate::parser::ParseError;
+use chrono::{DateTime, Utc};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+
+// NMEA 4.0 TAG blocks in front of the sentences, as shore-side AIS and
+// NMEA networks frame them, e.g.
+//   \s:gps1,c:1709294400,n:17*4B\$GPGGA,...
+// with the source of the line, its UNIX time in seconds and a running line
+// count, and a checksum over the parameters like that of a sentence.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TagBlocks {
+    // s:, up to 15 characters naming where the line comes from
+    pub source: Option<String>,
+    // c:, when the line went out
+    pub time: bool,
+    // n:, counting the lines from 1
+    pub line_count: bool,
+    lines: u64,
+    // Writes may end in the middle of a line, which the next one goes on
+    within_line: bool,
+}
+
+impl fmt::Display for TagBlocks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parameters = Vec::new();
+        if let Some(source) = &self.source {
+            parameters.push(format!("s:{}", source));
+        }
+        if self.time {
+            parameters.push("c".to_string());
+        }
+        if self.line_count {
+            parameters.push("n".to_string());
+        }
+        f.write_str(&parameters.join(","))
+    }
+}
+
+impl TagBlocks {
+    // Parameters to add, e.g. "s:gps1,c,n"
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let mut tag_blocks = TagBlocks::default();
+        for parameter in spec.split(',') {
+            match parameter.split_once(':') {
+                None if parameter == "c" => tag_blocks.time = true,
+                None if parameter == "n" => tag_blocks.line_count = true,
+                Some(("s", source)) => {
+                    let valid = (1..=15).contains(&source.len())
+                        && source
+                            .bytes()
+                            .all(|c| c.is_ascii_graphic() && !b",*\\$!".contains(&c));
+                    if !valid {
+                        return Err(format!("Invalid TAG block source '{}'", source).into());
+                    }
+                    tag_blocks.source = Some(source.to_string());
+                }
+                _ => {
+                    return Err(format!(
+                        "Invalid TAG block parameter '{}', expected s:<source>, c or n",
+                        parameter
+                    )
+                    .into())
+                }
+            }
+        }
+        Ok(tag_blocks)
+    }
+
+    // The data with a TAG block in front of every sentence starting in it
+    pub fn apply(&mut self, data: &[u8], now: DateTime<Utc>) -> Vec<u8> {
+        let mut tagged = Vec::with_capacity(data.len() + data.len() / 2);
+        for line in data.split_inclusive(|&byte| byte == b'\n') {
+            if !self.within_line && matches!(line.first(), Some(b'$' | b'!')) {
+                self.lines += 1;
+                tagged.extend_from_slice(self.block(now).as_bytes());
+            }
+            tagged.extend_from_slice(line);
+            self.within_line = !line.ends_with(b"\n");
+        }
+        tagged
+    }
+
+    fn block(&self, now: DateTime<Utc>) -> String {
+        let mut parameters = String::new();
+        if let Some(source) = &self.source {
+            let _ = write!(parameters, "s:{},", source);
+        }
+        if self.time {
+            let _ = write!(parameters, "c:{},", now.timestamp());
+        }
+        if self.line_count {
+            let _ = write!(parameters, "n:{},", self.lines);
+        }
+        let parameters = parameters.trim_end_matches(',');
+        format!("\\{}*{:02X}\\", parameters, checksum(parameters))
+    }
+}
+
+// The sentence behind a line's TAG block, if it has one
+pub fn skip(line: &[u8]) -> &[u8] {
+    match line.strip_prefix(b"\\") {
+        Some(rest) => match rest.iter().position(|&c| c == b'\\') {
+            Some(end) => &rest[end + 1..],
+            None => line,
+        },
+        None => line,
+    }
+}
+
+// A line without its TAG block, if the block's checksum is right
+pub fn strip(line: &str) -> Result<&str, ParseError> {
+    let Some(rest) = line.strip_prefix('\\') else {
+        return Ok(line);
+    };
+    let Some((block, sentence)) = rest.split_once('\\') else {
+        return Ok(line);
+    };
+    let (parameters, sum) = block.split_once('*').unwrap_or((block, ""));
+    let expected = checksum(parameters);
+    if u8::from_str_radix(sum, 16).ok() != Some(expected) {
+        return Err(ParseError::Checksum {
+            expected,
+            found: sum.to_string(),
+        });
+    }
+    Ok(sentence)
+}
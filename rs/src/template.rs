@@ -0,0 +1,285 @@
+// src/template.rs
+
+use crate::nmea_generator::{Fix, RandomGenerator};
+use crate::position::Position;
+use crate::sentences::UtcTime;
+use std::error::Error;
+use std::fmt::Write;
+
+// Sentences of our own defined in the config file, e.g.
+// "PXYZ,{time},{lat},{lon},{rand:0..100:.1},{seq}", with placeholders the
+// generator fills from the fix of each epoch:
+//
+//   {time}        hhmmss.ss, the decimals as the other sentences have them
+//   {date}        ddmmyy
+//   {lat} {lon}   ddmm.mmmm,N and dddmm.mmmm,E; empty without a fix
+//   {alt} {speed} {course} {heading} {hdop}
+//                 metres, knots, degrees, with one decimal
+//   {quality} {sats}
+//                 GGA fix quality and satellites used
+//   {seq}         0, 1, 2, ... for each sentence the template sent
+//   {seq:MIN..MAX}
+//                 the same from MIN up to MAX, then MIN again
+//   {rand:MIN..MAX}
+//                 uniform between MIN and MAX, without decimals
+//
+// Times, coordinates and numbers take their decimals after a colon, e.g.
+// {lat:.6} or {rand:0..100:.1}. "{{" and "}}" are braces of their own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentenceTemplate {
+    pub text: String,
+    // Every n-th epoch, as the sentence rates have it; 0 disables it
+    pub every: u32,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Value(Value, Option<usize>),
+    Random { min: f64, max: f64, decimals: usize },
+    Sequence { min: u64, max: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value {
+    Time,
+    Date,
+    Latitude,
+    Longitude,
+    Altitude,
+    Speed,
+    Course,
+    Heading,
+    Hdop,
+    Quality,
+    Satellites,
+    Sequence,
+}
+
+impl Value {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "time" => Value::Time,
+            "date" => Value::Date,
+            "lat" => Value::Latitude,
+            "lon" => Value::Longitude,
+            "alt" => Value::Altitude,
+            "speed" => Value::Speed,
+            "course" => Value::Course,
+            "heading" => Value::Heading,
+            "hdop" => Value::Hdop,
+            "quality" => Value::Quality,
+            "sats" => Value::Satellites,
+            "seq" => Value::Sequence,
+            _ => return None,
+        })
+    }
+
+    // Counts and dates have no decimals to choose
+    fn takes_decimals(&self) -> bool {
+        !matches!(
+            self,
+            Value::Date | Value::Quality | Value::Satellites | Value::Sequence
+        )
+    }
+
+    fn write(
+        &self,
+        out: &mut String,
+        values: &TemplateValues,
+        decimals: Option<usize>,
+    ) -> std::fmt::Result {
+        let fix = values.fix;
+        let number = |value: f64| format!("{:.*}", decimals.unwrap_or(1), value);
+        let minute_decimals = decimals.unwrap_or(values.minute_decimals);
+        match self {
+            Value::Time => write!(
+                out,
+                "{}",
+                UtcTime(&fix.time, decimals.unwrap_or(values.time_decimals))
+            ),
+            Value::Date => write!(out, "{}", fix.time.format("%d%m%y")),
+            Value::Latitude => match values.position {
+                Some(position) => {
+                    let (latitude, hemisphere) = position.latitude_minutes(minute_decimals);
+                    write!(out, "{},{}", latitude, hemisphere)
+                }
+                None => write!(out, ","),
+            },
+            Value::Longitude => match values.position {
+                Some(position) => {
+                    let (longitude, hemisphere) = position.longitude_minutes(minute_decimals);
+                    write!(out, "{},{}", longitude, hemisphere)
+                }
+                None => write!(out, ","),
+            },
+            Value::Altitude => write!(out, "{}", number(fix.altitude)),
+            Value::Speed => write!(out, "{}", number(fix.speed_knots)),
+            Value::Course => write!(out, "{}", number(fix.course)),
+            Value::Heading => write!(out, "{}", number(fix.heading)),
+            Value::Hdop => write!(out, "{}", number(fix.hdop)),
+            Value::Quality => write!(out, "{}", values.fix_quality),
+            Value::Satellites => write!(out, "{}", fix.satellites_used()),
+            Value::Sequence => write!(out, "{}", values.sequence),
+        }
+    }
+}
+
+// What the generator knows of the epoch a template is filled for
+pub struct TemplateValues<'a> {
+    pub fix: &'a Fix,
+    // None without a fix
+    pub position: Option<Position>,
+    pub fix_quality: u8,
+    pub time_decimals: usize,
+    pub minute_decimals: usize,
+    pub sequence: u64,
+}
+
+impl SentenceTemplate {
+    // The body of the sentence, its address first, e.g. "PXYZ,{seq}"; a
+    // leading '$' or '!' is kept as in injected sentences
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let body = text.strip_prefix(['$', '!']).unwrap_or(text);
+        let address = body.split(',').next().unwrap_or_default();
+        if address.is_empty()
+            || !address
+                .bytes()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            return Err(format!("Invalid address '{}' in template '{}'", address, text).into());
+        }
+
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(i) = rest.find(['{', '}', '*', '\r', '\n']) {
+            literal.push_str(&rest[..i]);
+            let after = &rest[i + 1..];
+            rest = match (&rest[i..=i], after.chars().next()) {
+                ("{", Some('{')) | ("}", Some('}')) => {
+                    literal.push_str(&rest[i..=i]);
+                    &after[1..]
+                }
+                ("{", _) => {
+                    let end = after
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed placeholder in template '{}'", text))?;
+                    parts.push(Part::Text(std::mem::take(&mut literal)));
+                    parts.push(Part::parse(&after[..end])?);
+                    &after[end + 1..]
+                }
+                (c, _) => {
+                    return Err(format!(
+                        "Unexpected '{}' in template '{}'",
+                        c.escape_default(),
+                        text
+                    )
+                    .into())
+                }
+            };
+        }
+        literal.push_str(rest);
+        parts.push(Part::Text(literal));
+        parts.retain(|part| !matches!(part, Part::Text(text) if text.is_empty()));
+
+        Ok(SentenceTemplate {
+            text: text.to_string(),
+            every: 1,
+            parts,
+        })
+    }
+
+    // Whether it numbers its sentences, so gaps in the numbers show
+    pub fn has_sequence(&self) -> bool {
+        self.parts.iter().any(|part| {
+            matches!(
+                part,
+                Part::Value(Value::Sequence, _) | Part::Sequence { .. }
+            )
+        })
+    }
+
+    // The body without its placeholders; no sentence it fills is shorter
+    pub fn literal(&self) -> String {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // The body with the placeholders filled in, for wrap_sentence
+    pub fn fill(&self, values: &TemplateValues, rg: &mut RandomGenerator) -> String {
+        let mut body = String::new();
+        for part in &self.parts {
+            // Writing into a String never fails
+            let _ = match part {
+                Part::Text(text) => write!(body, "{}", text),
+                Part::Value(value, decimals) => value.write(&mut body, values, *decimals),
+                Part::Random { min, max, decimals } => {
+                    let value = rg.random_uniform(*min, *max);
+                    write!(body, "{:.*}", decimals, value)
+                }
+                // Wide enough for a range of every u64
+                Part::Sequence { min, max } => {
+                    let length = (max - min) as u128 + 1;
+                    write!(body, "{}", min + (values.sequence as u128 % length) as u64)
+                }
+            };
+        }
+        body
+    }
+}
+
+impl Part {
+    // A placeholder without its braces, e.g. "lat:.6" or "rand:0..100:.1"
+    fn parse(placeholder: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = || format!("Invalid placeholder {{{}}}", placeholder);
+        let (name, argument) = match placeholder.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (placeholder, None),
+        };
+        if name == "rand" {
+            let argument = argument.ok_or_else(invalid)?;
+            let (range, decimals) = match argument.split_once(':') {
+                Some((range, decimals)) => (range, Some(decimals)),
+                None => (argument, None),
+            };
+            let (min, max) = range
+                .split_once("..")
+                .and_then(|(min, max)| Some((min.parse::<f64>().ok()?, max.parse::<f64>().ok()?)))
+                .filter(|(min, max)| min.is_finite() && max.is_finite() && min < max)
+                .ok_or_else(invalid)?;
+            let decimals = decimals.map(parse_decimals).transpose()?.unwrap_or(0);
+            return Ok(Part::Random { min, max, decimals });
+        }
+        if let ("seq", Some(range)) = (name, argument) {
+            let (min, max) = range
+                .split_once("..")
+                .and_then(|(min, max)| Some((min.parse::<u64>().ok()?, max.parse::<u64>().ok()?)))
+                .filter(|(min, max)| min < max)
+                .ok_or_else(invalid)?;
+            return Ok(Part::Sequence { min, max });
+        }
+
+        let value =
+            Value::parse(name).ok_or_else(|| format!("Unknown placeholder {{{}}}", placeholder))?;
+        let decimals = argument.map(parse_decimals).transpose()?;
+        if decimals.is_some() && !value.takes_decimals() {
+            return Err(invalid().into());
+        }
+        Ok(Part::Value(value, decimals))
+    }
+}
+
+// ".N", up to 9 digits
+fn parse_decimals(text: &str) -> Result<usize, Box<dyn Error>> {
+    text.strip_prefix('.')
+        .and_then(|digits| digits.parse().ok())
+        .filter(|&decimals| decimals <= 9)
+        .ok_or_else(|| format!("Invalid decimals '{}', expected e.g. .2", text).into())
+}
@@ -0,0 +1,279 @@
+// src/trajectory.rs
+
+use crate::nmea_generator::Fix;
+use crate::position::Position;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::time::{Duration, Instant};
+
+// After a switch the position glides from where the receiver was onto the
+// new source over this long, so clients see no jump unless one is asked for
+pub const BLEND_TIME: Duration = Duration::from_secs(5);
+
+// Speeds when none is pinned, and how far a walk may turn in a second
+const WALK_SPEED_KNOTS: f64 = 3.0;
+const ROUTE_SPEED_KNOTS: f64 = 10.0;
+const WALK_TURN_DEG_PER_S: f64 = 15.0;
+const METERS_PER_SECOND_PER_KNOT: f64 = 1852.0 / 3600.0;
+
+// Where the positions come from, e.g. {"source": "route", "path": "drive.csv"}
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "kebab-case")]
+pub enum TrajectorySource {
+    // Pinned by set-position, set-truth or --truth-input, random when unset
+    #[default]
+    External,
+    // Wandering from where the receiver is, at the pinned speed or a walk
+    Walk,
+    // Along the waypoints of a file, back to the first after the last
+    Route {
+        path: String,
+    },
+}
+
+impl fmt::Display for TrajectorySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrajectorySource::External => f.write_str("external"),
+            TrajectorySource::Walk => f.write_str("walk"),
+            TrajectorySource::Route { path } => write!(f, "route:{}", path),
+        }
+    }
+}
+
+impl TrajectorySource {
+    // "external", "walk" or "route:<path>"
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value.split_once(':') {
+            None if value == "external" => Ok(TrajectorySource::External),
+            None if value == "walk" => Ok(TrajectorySource::Walk),
+            Some(("route", path)) if !path.is_empty() => Ok(TrajectorySource::Route {
+                path: path.to_string(),
+            }),
+            _ => Err(format!(
+                "Invalid trajectory '{}', expected external, walk or route:<path>",
+                value
+            )
+            .into()),
+        }
+    }
+
+    // Waypoints of a route, none for the other sources
+    pub fn waypoints(&self) -> Result<Vec<Position>, Box<dyn Error>> {
+        match self {
+            TrajectorySource::Route { path } => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read route {}: {}", path, e))?;
+                parse_route(&text).map_err(|e| format!("Invalid route {}: {}", path, e).into())
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+// A waypoint per line as latitude,longitude[,altitude] in degrees and
+// metres above mean sea level; blank lines and # comments are skipped
+pub fn parse_route(text: &str) -> Result<Vec<Position>, Box<dyn Error>> {
+    let mut waypoints = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields = line
+            .split(',')
+            .map(|field| field.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("line {}: {}", number + 1, e))?;
+        let waypoint = match fields[..] {
+            [lat, lon] => Position::new(lat, lon, 0.0),
+            [lat, lon, alt] => Position::new(lat, lon, alt),
+            _ => {
+                return Err(format!(
+                    "line {}: expected latitude,longitude[,altitude]",
+                    number + 1
+                )
+                .into())
+            }
+        };
+        if !(-90.0..=90.0).contains(&waypoint.lat_deg)
+            || !(-180.0..=180.0).contains(&waypoint.lon_deg)
+        {
+            return Err(format!("line {}: coordinates out of range", number + 1).into());
+        }
+        waypoints.push(waypoint);
+    }
+    if waypoints.is_empty() {
+        return Err("no waypoints".into());
+    }
+    Ok(waypoints)
+}
+
+// What an epoch reports, each random when unset
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Motion {
+    pub position: Option<Position>,
+    pub speed_knots: Option<f64>,
+    pub course: Option<f64>,
+}
+
+// The source in use and how far it got. It moves on by the time passed
+// since it last did, whichever port asks, so every port reports the same
+// trajectory.
+#[derive(Debug)]
+pub struct Trajectory {
+    pub source: TrajectorySource,
+    waypoints: Vec<Position>,
+    // Where the walk or the route got to, the leg of the route it is on and
+    // the course it is going
+    current: Option<Position>,
+    leg: usize,
+    course: f64,
+    updated: Option<Instant>,
+    // Where a blend started, and how far into it the trajectory moved
+    blend: Option<(Position, Duration)>,
+    rng: StdRng,
+}
+
+impl Default for Trajectory {
+    fn default() -> Self {
+        Trajectory {
+            source: TrajectorySource::External,
+            waypoints: Vec::new(),
+            current: None,
+            leg: 0,
+            course: 0.0,
+            updated: None,
+            blend: None,
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl Trajectory {
+    // Makes walks repeat with the run
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Switches to another source with the waypoints it loaded, blending
+    // from the latest fix unless it should jump; a walk sets off from there
+    pub fn switch(
+        &mut self,
+        source: TrajectorySource,
+        waypoints: Vec<Position>,
+        from: Option<&Fix>,
+        jump: bool,
+    ) {
+        self.current = match source {
+            TrajectorySource::Walk => from.map(Fix::position),
+            TrajectorySource::Route { .. } => waypoints.first().copied(),
+            TrajectorySource::External => None,
+        };
+        if let Some(fix) = from {
+            self.course = fix.course;
+        }
+        self.blend = from
+            .filter(|_| !jump)
+            .map(|fix| (fix.position(), Duration::ZERO));
+        self.source = source;
+        self.waypoints = waypoints;
+        self.leg = 0;
+        self.updated = None;
+    }
+
+    // What to report at this moment, moving on by the time since the last
+    // call but at most max_step, so nothing jumps after a pause. pinned is
+    // what the controller set, which external reports as it is.
+    pub fn advance(&mut self, now: Instant, max_step: Duration, pinned: Motion) -> Motion {
+        let step = self
+            .updated
+            .map_or(Duration::ZERO, |updated| {
+                now.saturating_duration_since(updated)
+            })
+            .min(max_step);
+        self.updated = Some(now);
+        let dt = step.as_secs_f64();
+
+        let target = match self.source {
+            TrajectorySource::External => pinned,
+            TrajectorySource::Walk => self.walk(dt, pinned),
+            TrajectorySource::Route { .. } => self.follow_route(dt, pinned),
+        };
+
+        // A random position has nothing to blend into
+        let (Some((from, elapsed)), Some(to)) = (self.blend, target.position) else {
+            self.blend = None;
+            return target;
+        };
+        let elapsed = elapsed + step;
+        if elapsed >= BLEND_TIME {
+            self.blend = None;
+            return target;
+        }
+        self.blend = Some((from, elapsed));
+        // Easing in and out, so the speed changes gradually too
+        let t = elapsed.as_secs_f64() / BLEND_TIME.as_secs_f64();
+        Motion {
+            position: Some(from.lerp(&to, t * t * (3.0 - 2.0 * t))),
+            ..target
+        }
+    }
+
+    fn walk(&mut self, dt: f64, pinned: Motion) -> Motion {
+        let start = self
+            .current
+            .or(pinned.position)
+            .unwrap_or(Position::new(0.0, 0.0, 0.0));
+        let speed_knots = pinned.speed_knots.unwrap_or(WALK_SPEED_KNOTS);
+        let turn = self.rng.gen_range(-1.0..=1.0) * WALK_TURN_DEG_PER_S * dt;
+        self.course = (self.course + turn).rem_euclid(360.0);
+        let distance = speed_knots * METERS_PER_SECOND_PER_KNOT * dt;
+        let course = self.course.to_radians();
+        let position = start.offset(distance * course.cos(), distance * course.sin());
+        self.current = Some(position);
+        Motion {
+            position: Some(position),
+            speed_knots: Some(speed_knots),
+            course: Some(self.course),
+        }
+    }
+
+    fn follow_route(&mut self, dt: f64, pinned: Motion) -> Motion {
+        let speed_knots = pinned.speed_knots.unwrap_or(ROUTE_SPEED_KNOTS);
+        let count = self.waypoints.len();
+        let mut position = self.current.unwrap_or(self.waypoints[0]);
+        let length: f64 = (0..count)
+            .map(|i| self.waypoints[i].distance_m(&self.waypoints[(i + 1) % count]))
+            .sum();
+        if length > 0.0 {
+            // Whole laps leave it where it was
+            let mut remaining = (speed_knots * METERS_PER_SECOND_PER_KNOT * dt) % length;
+            loop {
+                let next = self.waypoints[(self.leg + 1) % count];
+                let to_next = position.distance_m(&next);
+                if remaining < to_next {
+                    let bearing = position.bearing_to(&next).to_radians();
+                    let mut moved =
+                        position.offset(remaining * bearing.cos(), remaining * bearing.sin());
+                    moved.alt_m += (next.alt_m - position.alt_m) * remaining / to_next;
+                    position = moved;
+                    break;
+                }
+                remaining -= to_next;
+                position = next;
+                self.leg = (self.leg + 1) % count;
+            }
+            self.course = position.bearing_to(&self.waypoints[(self.leg + 1) % count]);
+        }
+        self.current = Some(position);
+        Motion {
+            position: Some(position),
+            speed_knots: Some(speed_knots),
+            course: Some(self.course),
+        }
+    }
+}
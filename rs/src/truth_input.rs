@@ -0,0 +1,82 @@
+// src/truth_input.rs
+
+use crate::control::{command_from_body, Controller};
+use std::error::Error;
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::thread;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Where an external vehicle simulator sends the truth to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TruthInput {
+    Udp(String),
+    // MAVLink HIL_GPS or GPS_INPUT messages over UDP
+    Mavlink(String),
+    // set-truth commands on the control socket or REST API
+    Control,
+}
+
+impl TruthInput {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value.split_once(':') {
+            Some(("udp", addr)) if !addr.is_empty() => Ok(TruthInput::Udp(addr.to_string())),
+            Some(("mavlink", addr)) if !addr.is_empty() => {
+                Ok(TruthInput::Mavlink(addr.to_string()))
+            }
+            None if value == "control" => Ok(TruthInput::Control),
+            _ => Err(format!(
+                "Invalid truth input '{}', expected udp:<addr:port>, mavlink:<addr:port> or control",
+                value
+            )
+            .into()),
+        }
+    }
+}
+
+// Receives the vehicle state from an external simulator as one JSON object
+// per UDP datagram, with the fields of the set-truth control command:
+//   {"latitude": 37.77, "longitude": -122.41, "altitude": 12.0,
+//    "speed_knots": 8.5, "course": 92.0}
+pub fn spawn_truth_listener(
+    addr: &str,
+    controller: Controller,
+    shutdown_event: Arc<AtomicBool>,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let socket = UdpSocket::bind(addr).inspect_err(|e| error!("Failed to bind {}: {}", addr, e))?;
+    socket.set_read_timeout(Some(READ_TIMEOUT))?;
+    info!("Receiving truth on udp:{}", addr);
+
+    Ok(thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        let mut received = false;
+        while !shutdown_event.load(Ordering::SeqCst) {
+            match socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    let result = command_from_body("set-truth", &buf[..len])
+                        .and_then(|command| controller.apply(command));
+                    match result {
+                        Ok(_) if !received => {
+                            info!("Receiving truth from {}", from);
+                            received = true;
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("Ignoring truth update from {}: {}", from, e),
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => {
+                    error!("Error receiving truth: {}", e);
+                    thread::sleep(READ_TIMEOUT);
+                }
+            }
+        }
+    }))
+}
@@ -0,0 +1,457 @@
+// src/tui.rs
+
+use crate::control::{ControlCommand, Controller};
+use crate::nmea_generator::{Fix, FORMATTERS};
+use crate::state::{PortControl, SharedState};
+use nix::unistd::{close, dup, dup2, pipe};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::canvas::{Canvas, Map, MapResolution};
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::Duration;
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+const LOG_LINES: usize = 200;
+const SPEED_STEP: f64 = 1.0;
+const COURSE_STEP: f64 = 5.0;
+
+// Everything the UI needs to show the simulation and steer it
+pub struct TuiContext {
+    pub controller: Controller,
+    pub state: SharedState,
+    pub ports: Vec<Arc<PortControl>>,
+    pub shutdown_event: Arc<AtomicBool>,
+}
+
+// Runs the terminal UI until the user quits or the simulator shuts down.
+// The UI draws on /dev/tty; stdout and stderr are captured into its log
+// pane meanwhile so that other threads' messages don't garble the screen.
+pub fn spawn(context: TuiContext) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    let capture = OutputCapture::start()?;
+
+    Ok(thread::spawn(move || {
+        let result = run(tty, &context, &capture.lines);
+        capture.restore();
+        if let Err(e) = result {
+            eprintln!("Terminal UI failed: {}", e);
+        }
+        context.shutdown_event.store(true, Ordering::SeqCst);
+    }))
+}
+
+fn run(
+    tty: File,
+    context: &TuiContext,
+    log: &Mutex<VecDeque<String>>,
+) -> Result<(), Box<dyn Error>> {
+    enable_raw_mode()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(tty))?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+    terminal.clear()?;
+
+    let mut ui = Ui {
+        status: "Ready".to_string(),
+    };
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        while !context.shutdown_event.load(Ordering::SeqCst) {
+            terminal.draw(|frame| ui.draw(frame, context, log))?;
+
+            if event::poll(REFRESH_INTERVAL)? {
+                if let Event::Key(key) = event::read()? {
+                    let quit = key.code == KeyCode::Char('q')
+                        || key.code == KeyCode::Esc
+                        || (key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL));
+                    if quit {
+                        break;
+                    }
+                    if key.kind == KeyEventKind::Press {
+                        ui.handle_key(key.code, context);
+                    }
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    let _ = execute!(terminal.backend_mut(), LeaveAlternateScreen);
+    let _ = disable_raw_mode();
+    let _ = terminal.show_cursor();
+    result
+}
+
+struct Ui {
+    // Outcome of the last key command
+    status: String,
+}
+
+impl Ui {
+    fn handle_key(&mut self, code: KeyCode, context: &TuiContext) {
+        let (truth, speed, course, fix_quality, paused) = {
+            let state = context.state.lock().unwrap();
+            (
+                state.truth.clone(),
+                state.speed_knots,
+                state.course,
+                state.fix_quality,
+                state.paused,
+            )
+        };
+        let speed = speed
+            .or(truth.as_ref().map(|fix| fix.speed_knots))
+            .unwrap_or(0.0);
+        let course = course
+            .or(truth.as_ref().map(|fix| fix.course))
+            .unwrap_or(0.0);
+
+        let command = match code {
+            KeyCode::Up | KeyCode::Down => {
+                let step = if code == KeyCode::Up {
+                    SPEED_STEP
+                } else {
+                    -SPEED_STEP
+                };
+                ControlCommand::SetSpeed {
+                    knots: (speed + step).max(0.0),
+                    course: None,
+                }
+            }
+            KeyCode::Left | KeyCode::Right => {
+                let step = if code == KeyCode::Right {
+                    COURSE_STEP
+                } else {
+                    -COURSE_STEP
+                };
+                ControlCommand::SetSpeed {
+                    knots: speed,
+                    course: Some(course + step),
+                }
+            }
+            // Sentences in the order of FORMATTERS
+            KeyCode::Char(c @ '1'..='9') => {
+                let sentence = FORMATTERS[c as usize - '1' as usize];
+                let enabled = context
+                    .ports
+                    .first()
+                    .and_then(|port| port.lock().sentence_rates.get(sentence))
+                    .unwrap_or(0)
+                    == 0;
+                ControlCommand::SetSentence {
+                    sentence: sentence.to_string(),
+                    enabled,
+                    port: None,
+                }
+            }
+            // Outage: force "no fix" until toggled back
+            KeyCode::Char('o') => ControlCommand::SetFixQuality {
+                fix_quality: if fix_quality == Some(0) {
+                    None
+                } else {
+                    Some(0)
+                },
+            },
+            KeyCode::Char('p') if paused => ControlCommand::Resume,
+            KeyCode::Char('p') => ControlCommand::Pause,
+            KeyCode::Char('s') => ControlCommand::Advance { epochs: 1 },
+            KeyCode::Char('r') => ControlCommand::Release,
+            _ => return,
+        };
+
+        let description = format!("{:?}", command);
+        self.status = match context.controller.apply(command) {
+            Ok(_) => description,
+            Err(e) => format!("Error: {}", e),
+        };
+    }
+
+    fn draw(&self, frame: &mut Frame, context: &TuiContext, log: &Mutex<VecDeque<String>>) {
+        let (truth, paused, pinned) = {
+            let state = context.state.lock().unwrap();
+            let pinned = state.position.is_some()
+                || state.speed_knots.is_some()
+                || state.course.is_some()
+                || state.fix_quality.is_some();
+            (state.truth.clone(), state.paused, pinned)
+        };
+
+        let [top, middle, bottom, footer] = Layout::vertical([
+            Constraint::Length(13),
+            Constraint::Min(8),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [truth_area, map_area] =
+            Layout::horizontal([Constraint::Length(40), Constraint::Min(20)]).areas(top);
+        let [sats_area, outputs_area] =
+            Layout::horizontal([Constraint::Length(40), Constraint::Min(20)]).areas(middle);
+
+        self.draw_truth(frame, truth_area, truth.as_ref(), paused, pinned);
+        draw_map(frame, map_area, truth.as_ref());
+        draw_satellites(frame, sats_area, truth.as_ref());
+        draw_outputs(frame, outputs_area, &context.ports);
+        draw_log(frame, bottom, log);
+
+        frame.render_widget(
+            Line::from(
+                " q quit  ↑↓ speed  ←→ course  1-9 RMC/GGA/GLL/GSA/GSV/VTG/VHW/HDT/HDG  o outage  p pause  s step  r release",
+            )
+            .style(Style::default().fg(Color::Black).bg(Color::Gray)),
+            footer,
+        );
+    }
+
+    fn draw_truth(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        truth: Option<&Fix>,
+        paused: bool,
+        pinned: bool,
+    ) {
+        let mut lines = match truth {
+            Some(fix) => vec![
+                Line::from(format!("Time      {}", fix.time.format("%H:%M:%S%.3f"))),
+                Line::from(format!("Latitude  {:.6}", fix.latitude)),
+                Line::from(format!("Longitude {:.6}", fix.longitude)),
+                Line::from(format!("Altitude  {:.1} m", fix.altitude)),
+                Line::from(format!("Speed     {:.1} kn", fix.speed_knots)),
+                Line::from(format!(
+                    "Course    {:.1}° {}",
+                    fix.course,
+                    compass_arrow(fix.course)
+                )),
+                Line::from(format!(
+                    "Heading   {:.1}° {}",
+                    fix.heading,
+                    compass_arrow(fix.heading)
+                )),
+                Line::from(format!(
+                    "Fix       {} ({})",
+                    fix_quality_name(fix.fix_quality),
+                    fix.fix_quality
+                )),
+                Line::from(format!(
+                    "HDOP {:.1}  satellites {} of {}",
+                    fix.hdop,
+                    fix.satellites_used(),
+                    fix.satellites.len()
+                )),
+            ],
+            None => vec![Line::from("No fix generated yet")],
+        };
+
+        let mut mode = Vec::new();
+        if paused {
+            mode.push("PAUSED");
+        }
+        if pinned {
+            mode.push("controlled");
+        }
+        if !mode.is_empty() {
+            lines.push(Line::from(mode.join(", ")).bold().yellow());
+        }
+        lines.push(Line::from(self.status.clone()).dim());
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" Truth ")),
+            area,
+        );
+    }
+}
+
+fn draw_map(frame: &mut Frame, area: Rect, truth: Option<&Fix>) {
+    let position = truth.map(|fix| (fix.longitude, fix.latitude));
+    let canvas = Canvas::default()
+        .block(Block::bordered().title(" Position "))
+        .x_bounds([-180.0, 180.0])
+        .y_bounds([-90.0, 90.0])
+        .paint(move |ctx| {
+            ctx.draw(&Map {
+                color: Color::DarkGray,
+                resolution: MapResolution::Low,
+            });
+            if let Some((x, y)) = position {
+                ctx.layer();
+                ctx.print(x, y, "X".red().bold());
+            }
+        });
+    frame.render_widget(canvas, area);
+}
+
+fn draw_satellites(frame: &mut Frame, area: Rect, truth: Option<&Fix>) {
+    let rows: Vec<Row> = truth
+        .map(|fix| {
+            fix.satellites
+                .iter()
+                .map(|sat| {
+                    Row::new(vec![
+                        sat.constellation.to_string(),
+                        sat.id.to_string(),
+                        sat.elevation.to_string(),
+                        sat.snr.map_or("-".to_string(), |snr| snr.to_string()),
+                        if sat.used { "yes" } else { "" }.to_string(),
+                    ])
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let widths = [
+        Constraint::Length(12),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(6),
+        Constraint::Length(5),
+    ];
+    let table = Table::new(rows, widths)
+        .header(Row::new(vec!["System", "PRN", "Elev", "C/N0", "Used"]).bold())
+        .block(Block::bordered().title(" Satellites "));
+    frame.render_widget(table, area);
+}
+
+fn draw_outputs(frame: &mut Frame, area: Rect, ports: &[Arc<PortControl>]) {
+    let rows: Vec<Row> = ports
+        .iter()
+        .enumerate()
+        .map(|(i, port)| {
+            let state = port.lock();
+            let enabled: Vec<&str> = FORMATTERS
+                .iter()
+                .copied()
+                .filter(|s| state.sentence_rates.get(s).is_some_and(|rate| rate > 0))
+                .collect();
+            Row::new(vec![
+                i.to_string(),
+                format!("{:.1} Hz", 1.0 / state.interval.as_secs_f64()),
+                state.epochs_sent.to_string(),
+                state.bytes_sent.to_string(),
+                enabled.join(" "),
+                state.output_names.join(", "),
+            ])
+        })
+        .collect();
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(4),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(20),
+            Constraint::Min(10),
+        ],
+    )
+    .header(
+        Row::new(vec![
+            "Port",
+            "Rate",
+            "Epochs",
+            "Bytes",
+            "Sentences",
+            "Outputs",
+        ])
+        .bold(),
+    )
+    .block(Block::bordered().title(" Outputs "));
+    frame.render_widget(table, area);
+}
+
+fn draw_log(frame: &mut Frame, area: Rect, log: &Mutex<VecDeque<String>>) {
+    let height = area.height.saturating_sub(2) as usize;
+    let log = log.lock().unwrap();
+    let lines: Vec<Line> = log
+        .iter()
+        .skip(log.len().saturating_sub(height))
+        .map(|line| Line::from(line.as_str()))
+        .collect();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Log ")),
+        area,
+    );
+}
+
+fn compass_arrow(course: f64) -> &'static str {
+    const ARROWS: [&str; 8] = ["↑ N", "↗ NE", "→ E", "↘ SE", "↓ S", "↙ SW", "← W", "↖ NW"];
+    ARROWS[((course.rem_euclid(360.0) + 22.5) / 45.0) as usize % 8]
+}
+
+fn fix_quality_name(fix_quality: u8) -> &'static str {
+    match fix_quality {
+        0 => "no fix",
+        1 => "GPS",
+        2 => "DGPS",
+        3 => "PPS",
+        4 => "RTK fixed",
+        5 => "RTK float",
+        6 => "dead reckoning",
+        7 => "manual",
+        _ => "simulation",
+    }
+}
+
+// Redirects stdout and stderr into a pipe and keeps the last lines written
+// to it, until restored
+struct OutputCapture {
+    saved_stdout: RawFd,
+    saved_stderr: RawFd,
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl OutputCapture {
+    fn start() -> Result<Self, Box<dyn Error>> {
+        let (read_fd, write_fd) = pipe()?;
+        let _ = io::stdout().flush();
+        let saved_stdout = dup(libc::STDOUT_FILENO)?;
+        let saved_stderr = dup(libc::STDERR_FILENO)?;
+        dup2(write_fd, libc::STDOUT_FILENO)?;
+        dup2(write_fd, libc::STDERR_FILENO)?;
+        close(write_fd)?;
+
+        let lines = Arc::new(Mutex::new(VecDeque::new()));
+        let sink = lines.clone();
+        // Ends once the pipe is no longer stdout or stderr
+        thread::spawn(move || {
+            let reader = BufReader::new(unsafe { File::from_raw_fd(read_fd) });
+            for line in reader.lines().map_while(Result::ok) {
+                let mut lines = sink.lock().unwrap();
+                if lines.len() == LOG_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+        });
+
+        Ok(OutputCapture {
+            saved_stdout,
+            saved_stderr,
+            lines,
+        })
+    }
+
+    fn restore(&self) {
+        let _ = io::stdout().flush();
+        let _ = dup2(self.saved_stdout, libc::STDOUT_FILENO);
+        let _ = dup2(self.saved_stderr, libc::STDERR_FILENO);
+        let _ = close(self.saved_stdout);
+        let _ = close(self.saved_stderr);
+    }
+}
@@ -0,0 +1,218 @@
+// src/ubx.rs
+
+#[cfg(feature = "ubx")]
+use crate::nmea_generator::{Constellation, Fix};
+#[cfg(feature = "ubx")]
+use crate::rtcm::gps_time_of_week_ms;
+#[cfg(feature = "ubx")]
+use chrono::{Datelike, Timelike};
+use std::error::Error;
+
+const SYNC: [u8; 2] = [0xB5, 0x62];
+#[cfg(feature = "ubx")]
+const CLASS_NAV: u8 = 0x01;
+#[cfg(feature = "ubx")]
+const NAV_DOP: u8 = 0x04;
+#[cfg(feature = "ubx")]
+const NAV_PVT: u8 = 0x07;
+#[cfg(feature = "ubx")]
+const NAV_SAT: u8 = 0x35;
+
+#[cfg(feature = "ubx")]
+const METERS_PER_SECOND_PER_KNOT: f64 = 1852.0 / 3600.0;
+
+// Which protocols a port speaks. UBX frames follow the NMEA sentences of the
+// same epoch when both are enabled. Frames for configuration commands from
+// clients are always there, the navigation messages need the ubx feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Nmea,
+    Ubx,
+    Both,
+}
+
+impl Protocol {
+    pub fn parse(value: &str) -> Result<Self, Box<dyn Error>> {
+        match value {
+            "nmea" => Ok(Protocol::Nmea),
+            #[cfg(feature = "ubx")]
+            "ubx" => Ok(Protocol::Ubx),
+            #[cfg(feature = "ubx")]
+            "both" | "nmea+ubx" => Ok(Protocol::Both),
+            #[cfg(not(feature = "ubx"))]
+            "ubx" | "both" | "nmea+ubx" => {
+                Err(format!("Protocol '{}' needs a build with the ubx feature", value).into())
+            }
+            _ => Err(format!("Unknown protocol '{}', expected nmea, ubx or both", value).into()),
+        }
+    }
+
+    pub fn nmea(&self) -> bool {
+        matches!(self, Protocol::Nmea | Protocol::Both)
+    }
+
+    pub fn ubx(&self) -> bool {
+        matches!(self, Protocol::Ubx | Protocol::Both)
+    }
+}
+
+// Wraps a payload into a UBX frame with sync chars, length and checksum
+pub fn frame(class: u8, id: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 8);
+    frame.extend_from_slice(&SYNC);
+    frame.push(class);
+    frame.push(id);
+    frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    frame.extend_from_slice(payload);
+
+    let (ck_a, ck_b) = checksum(&frame[2..]);
+    frame.push(ck_a);
+    frame.push(ck_b);
+    frame
+}
+
+// 8-bit Fletcher checksum over class, ID, length and payload
+pub fn checksum(data: &[u8]) -> (u8, u8) {
+    let mut ck_a: u8 = 0;
+    let mut ck_b: u8 = 0;
+    for &byte in data {
+        ck_a = ck_a.wrapping_add(byte);
+        ck_b = ck_b.wrapping_add(ck_a);
+    }
+    (ck_a, ck_b)
+}
+
+// The navigation messages a receiver sends for one epoch
+#[cfg(feature = "ubx")]
+pub fn encode_epoch(fix: &Fix) -> Vec<u8> {
+    let mut data = frame(CLASS_NAV, NAV_PVT, &encode_nav_pvt(fix));
+    data.extend(frame(CLASS_NAV, NAV_SAT, &encode_nav_sat(fix)));
+    data.extend(frame(CLASS_NAV, NAV_DOP, &encode_nav_dop(fix)));
+    data
+}
+
+// NAV-PVT: navigation position, velocity and time solution
+#[cfg(feature = "ubx")]
+pub fn encode_nav_pvt(fix: &Fix) -> Vec<u8> {
+    let (fix_type, flags) = match fix.fix_quality {
+        0 => (0, 0x00),
+        6 => (1, 0x01),
+        // RTK fixed and float, flagged via carrSoln
+        4 => (3, 0x01 | 0x02 | 0x80),
+        5 => (3, 0x01 | 0x02 | 0x40),
+        2 | 3 => (3, 0x01 | 0x02),
+        _ => (3, 0x01),
+    };
+
+    let speed_mm_s = fix.speed_knots * METERS_PER_SECOND_PER_KNOT * 1000.0;
+    let course = fix.course.to_radians();
+    let vdop = vdop(fix.hdop);
+
+    let mut p = Vec::with_capacity(92);
+    p.extend_from_slice(&(gps_time_of_week_ms(fix.time) as u32).to_le_bytes());
+    p.extend_from_slice(&(fix.time.year() as u16).to_le_bytes());
+    p.push(fix.time.month() as u8);
+    p.push(fix.time.day() as u8);
+    p.push(fix.time.hour() as u8);
+    p.push(fix.time.minute() as u8);
+    p.push(fix.time.second() as u8);
+    p.push(0x07); // Valid date and time, fully resolved
+    p.extend_from_slice(&50u32.to_le_bytes()); // Time accuracy, ns
+    p.extend_from_slice(&(fix.time.nanosecond() as i32).to_le_bytes());
+    p.push(fix_type);
+    p.push(flags);
+    p.push(0xE0); // Date and time confirmed
+    p.push(fix.satellites_used() as u8);
+    p.extend_from_slice(&((fix.longitude * 1e7).round() as i32).to_le_bytes());
+    p.extend_from_slice(&((fix.latitude * 1e7).round() as i32).to_le_bytes());
+    p.extend_from_slice(
+        &(((fix.altitude + fix.geoid_height) * 1000.0).round() as i32).to_le_bytes(),
+    );
+    p.extend_from_slice(&((fix.altitude * 1000.0).round() as i32).to_le_bytes());
+    p.extend_from_slice(&((fix.hdop * 2500.0).round() as u32).to_le_bytes()); // hAcc, mm
+    p.extend_from_slice(&((vdop * 2500.0).round() as u32).to_le_bytes()); // vAcc, mm
+    p.extend_from_slice(&((speed_mm_s * course.cos()).round() as i32).to_le_bytes());
+    p.extend_from_slice(&((speed_mm_s * course.sin()).round() as i32).to_le_bytes());
+    p.extend_from_slice(&0i32.to_le_bytes()); // velD
+    p.extend_from_slice(&(speed_mm_s.round() as i32).to_le_bytes());
+    p.extend_from_slice(&((fix.course * 1e5).round() as i32).to_le_bytes());
+    p.extend_from_slice(&200u32.to_le_bytes()); // Speed accuracy, mm/s
+    p.extend_from_slice(&5_000_000u32.to_le_bytes()); // Heading accuracy, 1e-5 deg
+    p.extend_from_slice(&((pdop(fix.hdop) * 100.0).round() as u16).to_le_bytes());
+    p.push(if fix_type == 0 { 0x01 } else { 0x00 }); // flags3: invalid lat/lon/height
+    p.extend_from_slice(&[0; 5]); // Reserved
+    p.extend_from_slice(&0i32.to_le_bytes()); // Heading of vehicle, not valid
+    p.extend_from_slice(&0i16.to_le_bytes()); // Magnetic declination
+    p.extend_from_slice(&0u16.to_le_bytes()); // Declination accuracy
+    p
+}
+
+// NAV-SAT: satellites in view and whether the solution uses them
+#[cfg(feature = "ubx")]
+pub fn encode_nav_sat(fix: &Fix) -> Vec<u8> {
+    let mut p = Vec::with_capacity(8 + 12 * fix.satellites.len());
+    p.extend_from_slice(&(gps_time_of_week_ms(fix.time) as u32).to_le_bytes());
+    p.push(1); // Message version
+    p.push(fix.satellites.len() as u8);
+    p.extend_from_slice(&[0; 2]); // Reserved
+
+    for sat in &fix.satellites {
+        let (gnss_id, sv_id) = match sat.constellation {
+            Constellation::GPS => (0, sat.id),
+            Constellation::GALILEO => (2, sat.id),
+            Constellation::BEIDOU => (3, sat.id - 100),
+            // The generator draws QZSS from 183-202; fold onto the 10 slots
+            Constellation::QZSS => (5, (sat.id - 183) % 10 + 1),
+            Constellation::GLONASS => (6, sat.id - 64),
+            Constellation::NAVIC => (7, sat.id),
+            Constellation::SBAS => (1, sat.id),
+        };
+        p.push(gnss_id);
+        p.push(sv_id as u8);
+        p.push(sat.snr.unwrap_or(0));
+        p.push(sat.elevation);
+        p.extend_from_slice(&(sat.azimuth as i16).to_le_bytes());
+        p.extend_from_slice(&0i16.to_le_bytes()); // Pseudorange residual
+                                                  // Healthy, code locked while tracked, and whether the solution uses it
+        let mut flags = 0x10u32;
+        if sat.snr.is_some() {
+            flags |= 0x04;
+        }
+        if sat.used {
+            flags |= 0x08;
+        }
+        p.extend_from_slice(&flags.to_le_bytes());
+    }
+    p
+}
+
+// NAV-DOP: dilution of precision. Only HDOP is part of the fix, the other
+// values are derived from it.
+#[cfg(feature = "ubx")]
+pub fn encode_nav_dop(fix: &Fix) -> Vec<u8> {
+    let hdop = fix.hdop;
+    let vdop = vdop(hdop);
+    let pdop = pdop(hdop);
+    let tdop = hdop * 0.8;
+    let gdop = (pdop * pdop + tdop * tdop).sqrt();
+    let ndop = hdop / std::f64::consts::SQRT_2;
+
+    let mut p = Vec::with_capacity(18);
+    p.extend_from_slice(&(gps_time_of_week_ms(fix.time) as u32).to_le_bytes());
+    for dop in [gdop, pdop, tdop, vdop, hdop, ndop, ndop] {
+        p.extend_from_slice(&((dop * 100.0).round() as u16).to_le_bytes());
+    }
+    p
+}
+
+#[cfg(feature = "ubx")]
+fn vdop(hdop: f64) -> f64 {
+    hdop * 1.5
+}
+
+#[cfg(feature = "ubx")]
+fn pdop(hdop: f64) -> f64 {
+    let vdop = vdop(hdop);
+    (hdop * hdop + vdop * vdop).sqrt()
+}
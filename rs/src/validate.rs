@@ -0,0 +1,295 @@
+// src/validate.rs
+
+use crate::nmea_generator::{Fix, KMH_PER_KNOT};
+use crate::parser::{ParseError, ParsedSentence, Parser};
+use crate::position::Position;
+use crate::sentences::GSA_SLOTS;
+use chrono::{DateTime, TimeDelta, Utc};
+use thiserror::Error;
+
+// Why a sentence of a log is invalid
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum Violation {
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    #[error("{name} {value} out of range")]
+    Range { name: &'static str, value: String },
+    #[error("Time goes back from {previous} to {time}")]
+    TimeBackwards { previous: String, time: String },
+    #[error("{name} {value} disagrees with {expected} of the fix")]
+    Disagrees {
+        name: &'static str,
+        value: String,
+        expected: String,
+    },
+}
+
+// How far speeds, in knots and km/h, and angles in degrees may be off the
+// fix by default: twice the rounding to the one decimal they are sent with
+pub const KINEMATICS_TOLERANCE: f64 = 0.1;
+
+// Checks the lines of one log in order: that each sentence parses, that
+// its values are in range and that time never goes backwards. Logs of real
+// devices can be triaged with it as well as our own output.
+pub struct Validator {
+    parser: Parser,
+    // Unsupported sentences, e.g. proprietary ones, are violations too
+    strict: bool,
+    last_time: Option<DateTime<Utc>>,
+}
+
+impl Validator {
+    pub fn new(strict: bool) -> Self {
+        Validator {
+            parser: Parser::new(),
+            strict,
+            last_time: None,
+        }
+    }
+
+    pub fn check(&mut self, line: &str) -> Result<(), Violation> {
+        let sentence = match self.parser.parse(line) {
+            Ok(sentence) => sentence,
+            Err(ParseError::Unsupported(_)) if !self.strict => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        check_ranges(&sentence)?;
+
+        if let Some(time) = sentence.time() {
+            // Sentences before the first RMC of a new day still carry the
+            // date of the old one, so a jump back by most of a day is the
+            // clock passing midnight
+            let previous = self
+                .last_time
+                .filter(|&previous| time < previous && previous - time < TimeDelta::hours(12));
+            if let Some(previous) = previous {
+                return Err(Violation::TimeBackwards {
+                    previous: previous.format("%H:%M:%S%.3f").to_string(),
+                    time: time.format("%H:%M:%S%.3f").to_string(),
+                });
+            }
+            self.last_time = Some(time);
+        }
+        Ok(())
+    }
+}
+
+fn check_ranges(sentence: &ParsedSentence) -> Result<(), Violation> {
+    match sentence {
+        ParsedSentence::Gga(gga) => {
+            check_position(gga.position.as_ref())?;
+            check("fix quality", gga.fix_quality, gga.fix_quality <= 8)?;
+            check("HDOP", gga.hdop, gga.hdop >= 0.0)?;
+        }
+        ParsedSentence::Rmc(rmc) => {
+            check_position(rmc.position.as_ref())?;
+            check_speed(rmc.speed_knots)?;
+            check_course(rmc.course)?;
+        }
+        ParsedSentence::Gll(gll) => check_position(gll.position.as_ref())?,
+        ParsedSentence::Gsa(gsa) => {
+            check("mode", gsa.mode, matches!(gsa.mode, 'A' | 'M'))?;
+            check("fix type", gsa.fix_type, (1..=3).contains(&gsa.fix_type))?;
+            check(
+                "satellite count",
+                gsa.satellite_ids.len(),
+                gsa.satellite_ids.len() <= GSA_SLOTS,
+            )?;
+            for (name, dop) in [("PDOP", gsa.pdop), ("HDOP", gsa.hdop), ("VDOP", gsa.vdop)] {
+                check(name, dop, dop >= 0.0)?;
+            }
+        }
+        ParsedSentence::Gsv(gsv) => {
+            check(
+                "message number",
+                gsv.message_number,
+                (1..=gsv.total_messages).contains(&gsv.message_number),
+            )?;
+            check(
+                "satellite count",
+                gsv.satellites.len(),
+                gsv.satellites.len() <= 4,
+            )?;
+            // Earlier messages have four satellites each
+            check(
+                "satellites in view",
+                gsv.satellites_in_view,
+                gsv.satellites_in_view >= (gsv.message_number - 1) * 4 + gsv.satellites.len(),
+            )?;
+            for sat in &gsv.satellites {
+                if let Some(elevation) = sat.elevation {
+                    check("elevation", elevation, elevation <= 90)?;
+                }
+                if let Some(azimuth) = sat.azimuth {
+                    check("azimuth", azimuth, azimuth <= 360)?;
+                }
+            }
+        }
+        ParsedSentence::Txt(txt) => check(
+            "message number",
+            txt.message_number,
+            (1..=txt.total_messages).contains(&txt.message_number),
+        )?,
+        ParsedSentence::Vtg(vtg) => {
+            check_speed(vtg.speed_knots)?;
+            check_course(vtg.course)?;
+        }
+        ParsedSentence::Vhw(vhw) => {
+            check("speed", vhw.speed_knots, vhw.speed_knots >= 0.0)?;
+            if let Some(heading) = vhw.heading {
+                check("heading", heading, (0.0..=360.0).contains(&heading))?;
+            }
+        }
+        ParsedSentence::Hdt(hdt) => {
+            if let Some(heading) = hdt.heading {
+                check("heading", heading, (0.0..=360.0).contains(&heading))?;
+            }
+        }
+        ParsedSentence::Hdg(hdg) => {
+            check("heading", hdg.heading, (0.0..=360.0).contains(&hdg.heading))?;
+            let angles = [("deviation", hdg.deviation), ("variation", hdg.variation)];
+            for (name, angle) in angles {
+                if let Some(angle) = angle {
+                    check(name, angle, angle.abs() <= 180.0)?;
+                }
+            }
+        }
+        ParsedSentence::Gst(gst) => {
+            if let Some(accuracy) = gst.accuracy {
+                let orientation = accuracy.orientation;
+                check(
+                    "orientation",
+                    orientation,
+                    (0.0..=180.0).contains(&orientation),
+                )?;
+                check(
+                    "semi-minor axis",
+                    accuracy.semi_minor,
+                    (0.0..=accuracy.semi_major).contains(&accuracy.semi_minor),
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Checks that the speeds, courses and headings of an epoch's sentences are
+// those of the fix they were encoded from, in every unit they come in
+pub fn check_kinematics(fix: &Fix, epoch: &str, tolerance: f64) -> Result<(), Violation> {
+    let speed = |name, value: f64, expected: f64| {
+        agree(name, value, expected, (value - expected).abs(), tolerance)
+    };
+    // 359.96 goes out as 360.0
+    let angle = |name, value: f64, expected: f64| {
+        let difference = (value - expected).rem_euclid(360.0);
+        agree(
+            name,
+            value,
+            expected,
+            difference.min(360.0 - difference),
+            tolerance,
+        )
+    };
+    let mut parser = Parser::new();
+    for line in epoch.lines() {
+        match parser.parse(line)? {
+            ParsedSentence::Rmc(rmc) => {
+                if let Some(speed_knots) = rmc.speed_knots {
+                    speed("RMC speed", speed_knots, fix.speed_knots)?;
+                }
+                if let Some(course) = rmc.course {
+                    angle("RMC course", course, fix.course)?;
+                }
+            }
+            ParsedSentence::Vtg(vtg) => {
+                if let Some(speed_knots) = vtg.speed_knots {
+                    speed("VTG speed", speed_knots, fix.speed_knots)?;
+                }
+                if let Some(speed_kmh) = vtg.speed_kmh {
+                    speed("VTG km/h", speed_kmh, fix.speed_knots * KMH_PER_KNOT)?;
+                }
+                if let Some(course) = vtg.course {
+                    angle("VTG course", course, fix.course)?;
+                }
+            }
+            ParsedSentence::Vhw(vhw) => {
+                speed("VHW speed", vhw.speed_knots, fix.water_speed_knots)?;
+                speed(
+                    "VHW km/h",
+                    vhw.speed_kmh,
+                    fix.water_speed_knots * KMH_PER_KNOT,
+                )?;
+                if let Some(heading) = vhw.heading {
+                    angle("VHW heading", heading, fix.heading)?;
+                }
+            }
+            ParsedSentence::Hdt(hdt) => {
+                if let Some(heading) = hdt.heading {
+                    angle("HDT heading", heading, fix.heading)?;
+                }
+            }
+            ParsedSentence::Hdg(hdg) => angle("HDG heading", hdg.heading, fix.heading)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn agree(
+    name: &'static str,
+    value: f64,
+    expected: f64,
+    difference: f64,
+    tolerance: f64,
+) -> Result<(), Violation> {
+    if difference <= tolerance {
+        return Ok(());
+    }
+    Err(Violation::Disagrees {
+        name,
+        value: value.to_string(),
+        expected: format!("{:.3}", expected),
+    })
+}
+
+// Nothing to check without a fix
+fn check_position(position: Option<&Position>) -> Result<(), Violation> {
+    let Some(position) = position else {
+        return Ok(());
+    };
+    check(
+        "latitude",
+        position.lat_deg,
+        (-90.0..=90.0).contains(&position.lat_deg),
+    )?;
+    check(
+        "longitude",
+        position.lon_deg,
+        (-180.0..=180.0).contains(&position.lon_deg),
+    )
+}
+
+// Speed over the ground and course, each empty without a fix
+fn check_speed(speed_knots: Option<f64>) -> Result<(), Violation> {
+    match speed_knots {
+        Some(speed_knots) => check("speed", speed_knots, speed_knots >= 0.0),
+        None => Ok(()),
+    }
+}
+
+fn check_course(course: Option<f64>) -> Result<(), Violation> {
+    match course {
+        Some(course) => check("course", course, (0.0..=360.0).contains(&course)),
+        None => Ok(()),
+    }
+}
+
+fn check(name: &'static str, value: impl ToString, valid: bool) -> Result<(), Violation> {
+    if valid {
+        return Ok(());
+    }
+    Err(Violation::Range {
+        name,
+        value: value.to_string(),
+    })
+}
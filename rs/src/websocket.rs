@@ -0,0 +1,203 @@
+// src/websocket.rs
+
+use crate::http::{self, write_response};
+use crate::ntrip::base64_encode;
+use crate::output::{self, Client, OutputSink};
+use std::error::Error;
+use std::io::{ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Appended to the client key to form the handshake accept value (RFC 6455)
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Listens for WebSocket clients and broadcasts every write as one text
+// message. Like TcpSink, clients come and go without affecting the sink.
+pub struct WebSocketSink {
+    addr: String,
+    listener: TcpListener,
+    clients: Vec<Client>,
+    // Since take_client_events() was last called
+    connects: u64,
+    disconnects: u64,
+}
+
+impl WebSocketSink {
+    pub fn bind(addr: &str) -> Result<Self, Box<dyn Error>> {
+        let listener = output::listen(addr)?;
+        info!("Listening for WebSocket clients on {}", addr);
+
+        Ok(WebSocketSink {
+            addr: addr.to_string(),
+            listener,
+            clients: Vec::new(),
+            connects: 0,
+            disconnects: 0,
+        })
+    }
+
+    fn accept_clients(&mut self) -> Result<(), Box<dyn Error>> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => match handshake(stream) {
+                    Ok(stream) => {
+                        info!("WebSocket client connected to {}: {}", self.addr, peer);
+                        self.clients.push(Client::new(stream)?);
+                        self.connects += 1;
+                    }
+                    Err(e) => warn!("WebSocket handshake with {} failed: {}", peer, e),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+    }
+}
+
+impl OutputSink for WebSocketSink {
+    fn name(&self) -> String {
+        format!("ws:{}", self.addr)
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.accept_clients()?;
+
+        let message = text_frame(data);
+        let addr = &self.addr;
+        let clients = self.clients.len();
+        self.clients
+            .retain_mut(|client| match client.send(&message) {
+                Ok(()) => true,
+                Err(e) => {
+                    info!(
+                        "WebSocket client {} disconnected from {}: {}",
+                        client.peer, addr, e
+                    );
+                    false
+                }
+            });
+        self.disconnects += (clients - self.clients.len()) as u64;
+
+        Ok(())
+    }
+
+    fn reconnects(&self) -> bool {
+        true
+    }
+
+    fn reconnect(&mut self) -> Result<(), Box<dyn Error>> {
+        self.listener = output::listen(&self.addr)?;
+        self.disconnects += self.clients.len() as u64;
+        self.clients.clear();
+        Ok(())
+    }
+
+    fn take_client_events(&mut self) -> (u64, u64) {
+        let events = (self.connects, self.disconnects);
+        (self.connects, self.disconnects) = (0, 0);
+        events
+    }
+}
+
+// Answers the opening handshake; any request path is accepted
+fn handshake(mut stream: TcpStream) -> Result<TcpStream, Box<dyn Error>> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let request = http::read_request(&stream)?;
+
+    let key = match request.header("sec-websocket-key") {
+        Some(key) if request.method == "GET" => key,
+        _ => {
+            write_response(
+                &mut stream,
+                "400 Bad Request",
+                "text/plain",
+                b"WebSocket upgrade required\n",
+            )?;
+            return Err("Not a WebSocket upgrade request".into());
+        }
+    };
+
+    let accept = base64_encode(&sha1(format!("{}{}", key, HANDSHAKE_GUID).as_bytes()));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+// Unmasked server-to-client text frame with the FIN bit set
+fn text_frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x81);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// SHA-1, only needed for the handshake accept value
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (out, word) in digest.chunks_mut(4).zip(h) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
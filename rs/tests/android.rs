@@ -0,0 +1,64 @@
+// tests/android.rs
+
+// The adb commands that move an Android device or emulator to a fix.
+
+use chrono::{TimeZone, Utc};
+use nmea_simulator::android::AndroidTarget;
+use nmea_simulator::nmea_generator::{Constellation, Fix, Satellite};
+
+fn fix() -> Fix {
+    Fix {
+        time: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+        latitude: 48.1173,
+        longitude: 11.5167,
+        altitude: 545.4,
+        geoid_height: 46.9,
+        speed_knots: 12.5,
+        course: 84.4,
+        heading: 84.4,
+        water_speed_knots: 12.5,
+        fix_quality: 1,
+        hdop: 0.9,
+        satellites: vec![
+            Satellite::new(Constellation::GPS, 4),
+            Satellite::new(Constellation::GPS, 9),
+        ],
+        authenticated: None,
+    }
+}
+
+#[test]
+fn parses_targets() {
+    assert_eq!(
+        AndroidTarget::parse("emulator:emulator-5554").unwrap(),
+        AndroidTarget::Emulator("emulator-5554".to_string())
+    );
+    let device = AndroidTarget::parse("device:R58M123ABC").unwrap();
+    assert_eq!(device.serial(), "R58M123ABC");
+    assert_eq!(device.to_string(), "device:R58M123ABC");
+    for invalid in ["emulator:", "emulator-5554", "phone:R58M123ABC"] {
+        assert!(AndroidTarget::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn emulator_takes_a_geo_fix() {
+    let target = AndroidTarget::Emulator("emulator-5554".to_string());
+    // Longitude first, speed in knots
+    assert_eq!(
+        target.location_args(&fix()).join(" "),
+        "-s emulator-5554 emu geo fix 11.5167000 48.1173000 545.4 2 12.5"
+    );
+}
+
+#[test]
+fn device_takes_the_location_service_extras() {
+    let target = AndroidTarget::Device("R58M123ABC".to_string());
+    assert_eq!(
+        target.location_args(&fix()).join(" "),
+        "-s R58M123ABC shell am start-foreground-service --user 0 \
+         -n io.appium.settings/.LocationService \
+         --es latitude 48.1173000 --es longitude 11.5167000 --es altitude 545.4 \
+         --es speed 6.43 --es bearing 84.4"
+    );
+}
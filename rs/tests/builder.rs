@@ -0,0 +1,328 @@
+// tests/builder.rs
+
+// A simulator set up through the builder, run in-process into a sink of
+// the test for a few epochs.
+
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::Capture;
+use nmea_simulator::nmea_generator::OSNMA_UNAUTHENTICATED;
+use nmea_simulator::parser::{ParsedSentence, Parser};
+use nmea_simulator::position::Position;
+use nmea_simulator::{
+    ControlCommand, EncodingMode, MotionProfile, OutputSink, SimError, Simulator,
+};
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[test]
+fn runs_with_builder_settings() {
+    let capture = Capture::default();
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .profile(MotionProfile::Car)
+        .position(Position::new(48.1173, 11.5167, 545.4))
+        .sentence("RMC")
+        .sentence("gga")
+        .seed(7)
+        .count(4)
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap();
+    simulator.run().unwrap();
+
+    let output = capture.output();
+    let mut parser = Parser::new();
+    let sentences: Vec<_> = output
+        .lines()
+        .map(|line| parser.parse(line).unwrap())
+        .collect();
+    // Four epochs, each with one RMC and one GGA
+    assert_eq!(sentences.len(), 8, "{:?}", output);
+    for sentence in sentences {
+        match sentence {
+            ParsedSentence::Rmc(rmc) => {
+                assert_eq!(rmc.speed_knots, Some(MotionProfile::Car.speed_knots()));
+                assert!((rmc.position.unwrap().lat_deg - 48.1173).abs() < 1e-4);
+            }
+            ParsedSentence::Gga(gga) => {
+                assert!((gga.position.unwrap().lon_deg - 11.5167).abs() < 1e-4)
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
+
+// A strict run ends with an error at the first epoch off its fix, here
+// every epoch as nothing is within a negative tolerance
+#[test]
+fn strict_runs_fail_on_inconsistent_epochs() {
+    let run = |tolerance: f64| {
+        Simulator::builder()
+            .rate_hz(10.0)
+            .sentence("RMC")
+            .sentence("VTG")
+            .sentence("VHW")
+            .count(10)
+            .options(|options| {
+                options.strict = true;
+                options.kinematics_tolerance = tolerance;
+            })
+            .sink(Box::new(Capture::default()))
+            .build()
+            .unwrap()
+            .run()
+    };
+    run(0.1).unwrap();
+    let result = run(-1.0);
+    assert!(
+        matches!(result, Err(SimError::Inconsistent { epoch: 0, .. })),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn reports_no_fix_after_losing_it() {
+    let capture = Capture::default();
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .position(Position::new(48.1173, 11.5167, 545.4))
+        .sentence("RMC")
+        .sentence("GGA")
+        .count(2)
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap();
+    simulator
+        .controller()
+        .apply(ControlCommand::LoseFix)
+        .unwrap();
+    simulator.run().unwrap();
+
+    let output = capture.output();
+    let mut parser = Parser::new();
+    for line in output.lines() {
+        match parser.parse(line).unwrap() {
+            ParsedSentence::Rmc(rmc) => assert!(!rmc.valid && rmc.position.is_none()),
+            ParsedSentence::Gga(gga) => {
+                assert_eq!(gga.fix_quality, 0);
+                assert!(gga.position.is_none());
+                assert!(line.contains(",,,,,0,"), "{:?}", line);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn reports_osnma_status() {
+    let capture = Capture::default();
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("GGA")
+        .count(2)
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap();
+    let controller = simulator.controller();
+    let command = r#"{"command": "set-authentication", "authenticated": false}"#;
+    assert_eq!(controller.handle_line(command)["ok"], true);
+    simulator.run().unwrap();
+
+    let output = capture.output();
+    let mut parser = Parser::new();
+    let texts: Vec<_> = output
+        .lines()
+        .filter_map(|line| match parser.parse(line).unwrap() {
+            ParsedSentence::Txt(txt) => Some(txt),
+            _ => None,
+        })
+        .collect();
+    // One notice after the sentences of each epoch
+    assert_eq!(texts.len(), 2, "{:?}", output);
+    assert!(output.lines().last().unwrap().starts_with("$GATXT,"));
+    for txt in texts {
+        assert_eq!((txt.text_id, txt.text.as_str()), (2, OSNMA_UNAUTHENTICATED));
+    }
+    // And the truth carries it
+    let state = controller.apply(ControlCommand::GetState).unwrap();
+    assert_eq!(state["authenticated"], false);
+    assert_eq!(state["truth"]["authenticated"], false);
+}
+
+// Keeps when each write came in
+type Write = (DateTime<Utc>, String);
+
+#[derive(Clone, Default)]
+struct Timed(Arc<Mutex<Vec<Write>>>);
+
+impl OutputSink for Timed {
+    fn name(&self) -> String {
+        "timed".to_string()
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let data = String::from_utf8_lossy(data).to_string();
+        self.0.lock().unwrap().push((Utc::now(), data));
+        Ok(())
+    }
+}
+
+#[test]
+fn sends_epochs_after_the_latency() {
+    let timed = Timed::default();
+    let start = Utc::now();
+    Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("GGA")
+        .latency(Duration::from_millis(300))
+        .options(|options| options.report_age = true)
+        .count(3)
+        .sink(Box::new(timed.clone()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    let writes = timed.0.lock().unwrap();
+    assert_eq!(writes.len(), 3);
+    let mut parser = Parser::new();
+    for (_, data) in writes.iter() {
+        let ParsedSentence::Gga(gga) = parser.parse(data).unwrap() else {
+            panic!("Unexpected {:?}", data);
+        };
+        assert_eq!(gga.age, Some(0.3));
+    }
+    // The first fix is generated right away and arrives after the latency
+    let lag = (writes[0].0 - start).num_milliseconds();
+    assert!((300..600).contains(&lag), "{} ms", lag);
+}
+
+// Stepping sends nothing until asked, then one epoch at a time
+#[test]
+fn steps_one_epoch_at_a_time() {
+    let timed = Timed::default();
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("GGA")
+        .count(3)
+        .options(|options| options.step = true)
+        .sink(Box::new(timed.clone()))
+        .build()
+        .unwrap();
+    let controller = simulator.controller();
+    let run = std::thread::spawn(move || simulator.run());
+
+    let epochs = || timed.0.lock().unwrap().len();
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(epochs(), 0);
+    for step in 1..=3 {
+        controller
+            .apply(ControlCommand::Advance { epochs: 1 })
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(epochs(), step);
+    }
+    run.join().unwrap().unwrap();
+}
+
+#[test]
+fn stops_at_duration() {
+    let start = Instant::now();
+    Simulator::builder()
+        .rate_hz(10.0)
+        .duration(Duration::from_millis(250))
+        .sink(Box::new(Capture::default()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // Too long to ever end, which leaves it to the count
+    Simulator::builder()
+        .rate_hz(10.0)
+        .count(2)
+        .duration(Duration::MAX)
+        .sink(Box::new(Capture::default()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+}
+
+#[test]
+fn rejects_invalid_settings() {
+    let result = Simulator::builder().rate_hz(100.0).build();
+    assert!(matches!(result, Err(SimError::Usage(_))));
+    let result = Simulator::builder().sentence("XYZ").build();
+    assert!(matches!(result, Err(SimError::Usage(_))));
+    let result = Simulator::builder()
+        .latency(Duration::from_secs(61))
+        .build();
+    assert!(matches!(result, Err(SimError::Usage(_))));
+}
+
+#[test]
+fn announces_readiness() {
+    let dir = std::env::temp_dir().join(format!("nmea_ready_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let ready_file = dir.join("ready");
+    let path = ready_file.to_str().unwrap().to_string();
+
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .options(|options| options.ready_file = Some(path))
+        .sink(Box::new(Capture::default()))
+        .build()
+        .unwrap();
+    let state = simulator.state();
+    let shutdown = simulator.shutdown_handle();
+    let run = std::thread::spawn(move || simulator.run());
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !ready_file.exists() {
+        assert!(Instant::now() < deadline, "never became ready");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(state.lock().unwrap().ready);
+
+    shutdown.store(true, std::sync::atomic::Ordering::SeqCst);
+    run.join().unwrap().unwrap();
+    // Gone once the simulator is
+    assert!(!ready_file.exists());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn refuses_long_sentences_when_strict() {
+    let long = format!("GPTXT,01,01,02,{}", "x".repeat(70));
+    let inject = |encoding| {
+        let capture = Capture::default();
+        let simulator = Simulator::builder()
+            .encoding(encoding)
+            .rate_hz(10.0)
+            .sentence("GGA")
+            .count(2)
+            .sink(Box::new(capture.clone()))
+            .build()
+            .unwrap();
+        let result = simulator
+            .controller()
+            .apply(ControlCommand::InjectSentence {
+                sentence: long.clone(),
+                port: None,
+                time: None,
+                every: None,
+            });
+        simulator.run().unwrap();
+        let output = capture.output();
+        (result.is_ok(), output.contains(&long))
+    };
+    assert_eq!(inject(EncodingMode::Strict), (false, false));
+    // Sent as it is otherwise, like a legacy device would
+    assert_eq!(inject(EncodingMode::Permissive), (true, true));
+}
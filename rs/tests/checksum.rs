@@ -0,0 +1,47 @@
+// tests/checksum.rs
+
+// Wrapping sentence bodies and checking them, as clients of the library do.
+
+use nmea_simulator::parser::ParseError;
+use nmea_simulator::{checksum, verify, wrap_sentence};
+
+#[test]
+fn wraps_bodies() {
+    assert_eq!(checksum("GPTXT,01,01,02,hello"), 0x2F);
+    assert_eq!(
+        wrap_sentence("GPTXT,01,01,02,hello"),
+        "$GPTXT,01,01,02,hello*2F\r\n"
+    );
+    assert_eq!(
+        wrap_sentence("$GPTXT,01,01,02,hello"),
+        "$GPTXT,01,01,02,hello*2F\r\n"
+    );
+    let vdm = wrap_sentence("!AIVDM,1,1,,A,13aEOK?P00PD2wVMdLDRhgvL289?,0");
+    assert!(
+        vdm.starts_with("!AIVDM,") && vdm.ends_with("\r\n"),
+        "{}",
+        vdm
+    );
+}
+
+#[test]
+fn verifies_sentences() {
+    let line = wrap_sentence("GPTXT,01,01,02,hello");
+    assert_eq!(verify(&line), Ok("GPTXT,01,01,02,hello"));
+    assert_eq!(verify("!AIVDM,1,1,,A,1,0*17"), Ok("AIVDM,1,1,,A,1,0"));
+    assert_eq!(
+        verify("\\s:gps1*1C\\$GPTXT,01,01,02,hello*2F"),
+        Ok("GPTXT,01,01,02,hello")
+    );
+
+    let wrong = |line| match verify(line) {
+        Err(ParseError::Checksum { expected, found }) => (expected, found),
+        result => panic!("{:?} for {}", result, line),
+    };
+    assert_eq!(wrong("$GPTXT,01,01,02,hello*2E"), (0x2F, "2E".to_string()));
+    // Strict parsers want the checksum there and in uppercase
+    assert_eq!(wrong("$GPTXT,01,01,02,hello"), (0x2F, String::new()));
+    wrong("$GPTXT,01,01,02,hello*2f");
+    wrong("$GPTXT,01,01,02,hello*02F");
+    wrong("\\s:gps1*00\\$GPTXT,01,01,02,hello*2F");
+}
@@ -0,0 +1,68 @@
+// tests/cli.rs
+
+// What the serve arguments accept without PTYs, as in a container.
+
+use clap::Parser;
+use nmea_simulator::cli::Cli;
+use nmea_simulator::error::SimError;
+use nmea_simulator::options::Options;
+
+fn parse(args: &[&str]) -> Result<Cli, SimError> {
+    Cli::try_parse_from(std::iter::once("nmea_simulator").chain(args.iter().copied()))
+        .map_err(|e| SimError::Usage(e.to_string()))
+}
+
+fn serve(args: &[&str]) -> Result<Options, SimError> {
+    parse(args)?.serve.into_options()
+}
+
+#[test]
+fn headless_serves_only_outputs() {
+    let options = serve(&["--headless", "-o", "file:a.nmea,file:b.nmea"]).unwrap();
+    assert_eq!(options.gps_input_path, None);
+    assert_eq!(options.fifo_path, None);
+    assert_eq!(options.outputs.len(), 2);
+}
+
+#[test]
+fn headless_needs_an_output() {
+    let result = serve(&["--headless"]);
+    assert!(
+        matches!(result, Err(SimError::Usage(_))),
+        "{:?}",
+        result.err()
+    );
+    // Nor does it mix with PTYs
+    let result = serve(&["--headless", "-o", "file:a.nmea", "/tmp/a", "/tmp/b"]);
+    assert!(
+        matches!(result, Err(SimError::Usage(_))),
+        "{:?}",
+        result.err()
+    );
+}
+
+#[test]
+fn bounds_times_and_rates() {
+    assert!(parse(&["--stats-interval", "60"]).is_ok());
+    assert!(parse(&["--stats-interval", "1e20"]).is_err());
+    assert!(parse(&["--stats-interval", "0"]).is_err());
+    assert!(parse(&["generate", "--rate", "10", "-n", "1"]).is_ok());
+    assert!(parse(&["generate", "--rate", "1e-300", "-n", "1"]).is_err());
+    assert!(parse(&["generate", "--rate", "1e300", "-n", "1"]).is_err());
+}
+
+#[test]
+fn rejects_oversized_durations() {
+    assert!(parse(&["--duration", "31536000"]).is_ok());
+    assert!(parse(&["--duration", "1e20"]).is_err());
+    assert!(parse(&["generate", "--duration", "1e20"]).is_err());
+    assert!(parse(&["generate", "--duration", "inf"]).is_err());
+}
+
+#[test]
+fn bounds_latency() {
+    assert!(parse(&["--latency", "0.5"]).is_ok());
+    assert!(parse(&["--latency", "60"]).is_ok());
+    assert!(parse(&["--latency", "61"]).is_err());
+    assert!(parse(&["--latency", "1e30"]).is_err());
+}
@@ -0,0 +1,42 @@
+// tests/common/mod.rs
+
+// What the integration tests share. Each test file uses only some of it.
+#![allow(dead_code)]
+
+use nmea_simulator::OutputSink;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+
+// Output that keeps everything written to it, by the name "capture" unless
+// it has one of its own
+#[derive(Clone, Default)]
+pub struct Capture {
+    pub name: Option<&'static str>,
+    pub data: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Capture {
+    // For outputs told apart by name, e.g. by filters
+    pub fn named(name: &'static str) -> Self {
+        Capture {
+            name: Some(name),
+            ..Capture::default()
+        }
+    }
+
+    // Everything written so far
+    pub fn output(&self) -> String {
+        String::from_utf8(self.data.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl OutputSink for Capture {
+    fn name(&self) -> String {
+        self.name.unwrap_or("capture").to_string()
+    }
+
+    fn write_all(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.data.lock().unwrap().extend_from_slice(data);
+        Ok(())
+    }
+}
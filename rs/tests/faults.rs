@@ -0,0 +1,290 @@
+// tests/faults.rs
+
+// Faults applied to generated epochs, checked sentence by sentence.
+
+use nmea_simulator::checksum::verify;
+use nmea_simulator::faults::{Fault, FaultInjector};
+use nmea_simulator::nmea_generator::NmeaGenerator;
+use nmea_simulator::position::Position;
+use std::thread;
+use std::time::Duration;
+
+fn epochs(count: usize) -> Vec<String> {
+    NmeaGenerator::with_seed(3).iter().take(count).collect()
+}
+
+// For faults that leave the epoch text
+fn apply(faults: &mut FaultInjector, epoch: &str) -> String {
+    String::from_utf8(faults.apply(epoch)).unwrap()
+}
+
+// Whether a line ends in the uppercase checksum of its body
+fn intact(line: &str) -> bool {
+    verify(line).is_ok()
+}
+
+#[test]
+fn damages_checksums_at_the_rate() {
+    let mut faults = FaultInjector::new(vec![Fault::Checksum(0.5)], 1);
+    let mut damaged = 0;
+    let mut total = 0;
+    for epoch in epochs(50) {
+        let out = apply(&mut faults, &epoch);
+        assert_eq!(out.matches("\r\n").count(), epoch.matches("\r\n").count());
+        for line in out.lines() {
+            total += 1;
+            if !intact(line) {
+                damaged += 1;
+            }
+        }
+    }
+    assert!(
+        (total / 3..total * 2 / 3).contains(&damaged),
+        "{} of {}",
+        damaged,
+        total
+    );
+
+    // No damage means the epoch goes out as generated
+    let mut faults = FaultInjector::new(vec![Fault::Checksum(0.0)], 1);
+    for epoch in epochs(5) {
+        assert_eq!(apply(&mut faults, &epoch), epoch);
+    }
+}
+
+#[test]
+fn truncates_and_merges_sentences() {
+    let epoch = &epochs(1)[0];
+    let sentences = epoch.lines().count();
+
+    // Every sentence glued to the next one
+    let mut faults = FaultInjector::new(vec![Fault::Merge(1.0)], 1);
+    let out = apply(&mut faults, epoch);
+    assert_eq!(out, epoch.replace("\r\n", ""));
+
+    // Every sentence cut short, somewhere in its fields
+    let mut faults = FaultInjector::new(vec![Fault::Truncate(1.0)], 1);
+    let out = apply(&mut faults, epoch);
+    assert!(!out.contains('*') && !out.contains('\n'), "{:?}", out);
+    let cut: Vec<_> = out.split('$').skip(1).collect();
+    assert_eq!(cut.len(), sentences);
+    for (cut, line) in cut.iter().zip(epoch.lines()) {
+        assert!(line[1..].starts_with(cut) && cut.contains(','), "{:?}", cut);
+    }
+}
+
+#[test]
+fn inserts_garbage_between_sentences() {
+    let epoch = &epochs(1)[0];
+    let mut faults = FaultInjector::new(
+        vec![Fault::Garbage {
+            rate: 1.0,
+            max_length: 4,
+        }],
+        1,
+    );
+    let out = faults.apply(epoch);
+
+    // Taking out the bytes that are not ASCII leaves the epoch intact
+    let ascii: Vec<u8> = out.iter().copied().filter(u8::is_ascii).collect();
+    assert_eq!(ascii, epoch.as_bytes());
+    let sentences = epoch.lines().count();
+    assert!((sentences..=sentences * 4).contains(&(out.len() - ascii.len())));
+    for burst in out.split(|&b| b == b'\n').skip(1) {
+        assert!(!burst.is_empty() && !burst[0].is_ascii());
+    }
+}
+
+#[test]
+fn drops_and_repeats_sentences() {
+    let epochs = epochs(2);
+
+    // What is left of an epoch is a part of it, in order
+    let mut faults = FaultInjector::new(vec![Fault::Drop(0.5)], 1);
+    let out = apply(&mut faults, &epochs[0]);
+    let mut lines = epochs[0].lines();
+    for line in out.lines() {
+        assert!(lines.any(|kept| kept == line), "{:?}", line);
+    }
+    assert!(out.lines().count() < epochs[0].lines().count());
+
+    let mut faults = FaultInjector::new(vec![Fault::DropEpoch(1.0)], 1);
+    assert!(faults.apply(&epochs[0]).is_empty());
+
+    // The first epoch has nothing to repeat; in the second every sentence
+    // is followed by one of the first
+    let mut faults = FaultInjector::new(vec![Fault::Repeat(1.0)], 1);
+    assert_eq!(apply(&mut faults, &epochs[0]), epochs[0]);
+    let out = apply(&mut faults, &epochs[1]);
+    let lines: Vec<_> = out.lines().collect();
+    let expected: Vec<_> = epochs[1].lines().collect();
+    assert_eq!(lines.len(), expected.len() * 2);
+    for (pair, line) in lines.chunks(2).zip(expected) {
+        assert_eq!(pair[0], line);
+        assert!(epochs[0].lines().any(|stale| stale == pair[1]));
+    }
+}
+
+#[test]
+fn delays_and_stalls_epochs() {
+    let interval = Duration::from_secs(1);
+    let mut faults = FaultInjector::new(vec![Fault::Jitter(0.25)], 1);
+    let delays: Vec<_> = (0..20).map(|_| faults.jitter(interval)).collect();
+    assert!(delays.iter().all(|&delay| delay <= interval / 4));
+    assert!(delays.iter().any(|&delay| delay > Duration::ZERO));
+
+    // Epochs during a stall come out together with the one after it
+    let mut faults = FaultInjector::new(
+        vec![Fault::Stall {
+            rate: 1.0,
+            duration: Duration::from_millis(50),
+        }],
+        1,
+    );
+    assert!(faults.release(b"a".to_vec()).is_empty());
+    assert!(faults.release(b"b".to_vec()).is_empty());
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(faults.release(b"c".to_vec()), b"abc");
+    assert!(faults.release(b"d".to_vec()).is_empty());
+    assert_eq!(faults.jitter(interval), Duration::ZERO);
+
+    let mut faults = FaultInjector::new(
+        vec![Fault::Stall {
+            rate: 0.0,
+            duration: Duration::from_millis(50),
+        }],
+        1,
+    );
+    assert_eq!(faults.release(b"a".to_vec()), b"a");
+}
+
+#[test]
+fn freezes_the_reported_fix() {
+    let mut generator = NmeaGenerator::with_seed(3);
+    let mut faults = FaultInjector::new(
+        vec![Fault::Freeze {
+            rate: 1.0,
+            duration: Duration::from_millis(50),
+        }],
+        1,
+    );
+    let first = generator.generate_fix();
+    assert_eq!(faults.report(&first).latitude, first.latitude);
+
+    // The truth moves on, the receiver doesn't
+    thread::sleep(Duration::from_millis(5));
+    let second = generator.generate_fix();
+    assert_ne!(second.latitude, first.latitude);
+    let reported = faults.report(&second);
+    assert_eq!(reported.latitude, first.latitude);
+    assert_eq!(reported.time, first.time);
+
+    thread::sleep(Duration::from_millis(60));
+    let third = generator.generate_fix();
+    assert_eq!(faults.report(&third).time, third.time);
+}
+
+#[test]
+fn displaces_outliers() {
+    let mut generator = NmeaGenerator::with_seed(3);
+    generator.position = Some(Position::new(48.1173, 11.5167, 545.4));
+    let mut faults = FaultInjector::new(
+        vec![Fault::Outlier {
+            rate: 1.0,
+            max_distance_m: 5000.0,
+        }],
+        1,
+    );
+    for _ in 0..20 {
+        let truth = generator.generate_fix();
+        let reported = faults.report(&truth);
+        let north = (reported.latitude - truth.latitude).to_radians() * 6378137.0;
+        let east = (reported.longitude - truth.longitude).to_radians()
+            * 6378137.0
+            * truth.latitude.to_radians().cos();
+        let distance = north.hypot(east);
+        assert!((499.0..=5001.0).contains(&distance), "{}", distance);
+        assert_eq!(reported.hdop, truth.hdop);
+        assert_eq!(reported.altitude, truth.altitude);
+    }
+}
+
+#[test]
+fn varies_framing() {
+    let epoch = &epochs(1)[0];
+    let mut faults = FaultInjector::new(vec![Fault::LineFeed(1.0), Fault::NoDollar(1.0)], 1);
+    let out = apply(&mut faults, epoch);
+    assert_eq!(out, epoch.replace("\r\n", "\n").replace('$', ""));
+
+    // The pieces of a split epoch put together are the epoch
+    let mut faults = FaultInjector::new(vec![Fault::Split(1.0)], 1);
+    for _ in 0..10 {
+        let pieces = faults.pieces(epoch.as_bytes());
+        assert!((2..=4).contains(&pieces.len()), "{:?}", pieces);
+        assert!(pieces.iter().all(|piece| !piece.is_empty()));
+        assert_eq!(pieces.concat(), epoch.as_bytes());
+    }
+    let mut faults = FaultInjector::new(vec![Fault::Split(0.0)], 1);
+    assert_eq!(faults.pieces(epoch.as_bytes()), [epoch.as_bytes()]);
+}
+
+#[test]
+fn parses_fault_specs() {
+    assert_eq!(
+        Fault::parse("checksum=0.02").unwrap(),
+        Fault::Checksum(0.02)
+    );
+    assert_eq!(Fault::parse("merge=1").unwrap(), Fault::Merge(1.0));
+    assert_eq!(
+        Fault::parse("garbage=0.1:64").unwrap(),
+        Fault::Garbage {
+            rate: 0.1,
+            max_length: 64
+        }
+    );
+    assert!(matches!(
+        Fault::parse("garbage=0.1").unwrap(),
+        Fault::Garbage { max_length: 16, .. }
+    ));
+    assert_eq!(
+        Fault::parse("drop-epoch=0.1").unwrap(),
+        Fault::DropEpoch(0.1)
+    );
+    assert_eq!(
+        Fault::parse("stall=0.01:2.5").unwrap(),
+        Fault::Stall {
+            rate: 0.01,
+            duration: Duration::from_millis(2500)
+        }
+    );
+    assert!(matches!(
+        Fault::parse("freeze=0.01").unwrap(),
+        Fault::Freeze { duration, .. } if duration == Duration::from_secs(10)
+    ));
+    assert_eq!(
+        Fault::parse("outlier=0.05:300").unwrap(),
+        Fault::Outlier {
+            rate: 0.05,
+            max_distance_m: 300.0
+        }
+    );
+    assert_eq!(Fault::parse("lf=1").unwrap(), Fault::LineFeed(1.0));
+    assert_eq!(
+        Fault::parse("seq-gap=0.1:3").unwrap(),
+        Fault::SequenceGap {
+            rate: 0.1,
+            max_length: 3
+        }
+    );
+    assert!(Fault::parse("seq-gap=0.1:0").is_err());
+    assert!(Fault::parse("garbage=0.1:0").is_err());
+    assert!(Fault::parse("stall=0.1:3600").is_ok());
+    assert!(Fault::parse("stall=0.1:1e30").is_err());
+    assert!(Fault::parse("freeze=0.1:3600").is_ok());
+    assert!(Fault::parse("freeze=0.1:1e30").is_err());
+    assert!(Fault::parse("freeze=0.1:0").is_err());
+    assert!(Fault::parse("merge=0.1:4").is_err());
+    assert!(Fault::parse("checksum").is_err());
+    assert!(Fault::parse("checksum=1.5").is_err());
+    assert!(Fault::parse("gremlins=0.1").is_err());
+}
@@ -0,0 +1,38 @@
+// tests/ffi.rs
+
+// The C API as a C caller uses it, including the retry with a larger
+// buffer.
+
+use nmea_simulator::ffi::*;
+use std::ptr;
+
+#[test]
+fn pulls_epochs_into_caller_buffers() {
+    unsafe {
+        let sim = nmea_sim_new(42);
+        assert_eq!(nmea_sim_set_position(sim, 48.1173, 11.5167, 545.4), 0);
+        assert_eq!(nmea_sim_set_position(sim, 91.0, 0.0, 0.0), -1);
+
+        // Too small: nothing is copied and the epoch is kept
+        let size = nmea_sim_next(sim, ptr::null_mut(), 0);
+        let mut small = [0xAAu8; 16];
+        assert_eq!(nmea_sim_next(sim, small.as_mut_ptr(), small.len()), size);
+        assert!(small.iter().all(|&b| b == 0xAA));
+
+        let mut buf = vec![0u8; size + 1];
+        assert_eq!(nmea_sim_next(sim, buf.as_mut_ptr(), buf.len()), size);
+        assert_eq!(buf[size], 0);
+        let epoch = std::str::from_utf8(&buf[..size]).unwrap();
+        // Satellites of every system, so the combined talker
+        assert!(epoch.starts_with("$GNRMC,"), "{:?}", epoch);
+        assert!(epoch.contains(",4807.0380,N,01131.0020,E,"), "{:?}", epoch);
+
+        // The next call generates a new epoch
+        let next = nmea_sim_next(sim, buf.as_mut_ptr(), 0);
+        assert!(next > 0);
+        nmea_sim_free(sim);
+
+        assert_eq!(nmea_sim_next(ptr::null_mut(), ptr::null_mut(), 0), 0);
+        nmea_sim_free(ptr::null_mut());
+    }
+}
@@ -0,0 +1,100 @@
+// tests/filters.rs
+
+// Outputs that only get some of the sentences, as set in the config file.
+
+mod common;
+
+use common::Capture;
+use nmea_simulator::config::Config;
+use nmea_simulator::mux::Instrument;
+use nmea_simulator::output::SentenceFilter;
+use nmea_simulator::Simulator;
+
+impl Capture {
+    // Addresses of the sentences it got, e.g. "GPGGA"
+    fn addresses(&self) -> Vec<String> {
+        self.output()
+            .lines()
+            .map(|line| line[1..6].to_string())
+            .collect()
+    }
+}
+
+#[test]
+fn filters_by_output_and_sentence() {
+    let filter = SentenceFilter {
+        output: "udp".to_string(),
+        sentences: vec!["GGA".to_string(), "AIVDM".to_string()],
+    };
+    assert!(filter.matches("udp"));
+    assert!(filter.matches("udp:127.0.0.1:10110"));
+    assert!(filter.matches("udp@4800"));
+    assert!(!filter.matches("udpx:127.0.0.1:10110"));
+    assert!(!filter.matches("file:udp.nmea"));
+
+    let data =
+        b"$GPGGA,1*00\r\n$GPRMC,1*00\r\n!AIVDM,1,1,,A,1,0*00\r\n$GNGGA*00\r\n\xb5\x62\x01\x07";
+    assert_eq!(
+        filter.apply(data).as_ref(),
+        b"$GPGGA,1*00\r\n!AIVDM,1,1,,A,1,0*00\r\n$GNGGA*00\r\n"
+    );
+    // The address must match whole
+    let filter = SentenceFilter {
+        output: "udp".to_string(),
+        sentences: vec!["GPVDM".to_string()],
+    };
+    assert!(filter.apply(b"!AIVDM,1,1,,A,1,0*00\r\n").is_empty());
+}
+
+#[test]
+fn applies_config_filters() {
+    let dir = std::env::temp_dir().join(format!("nmea_filters_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sim.toml");
+    std::fs::write(
+        &path,
+        "rate = 10.0\n\n[filters]\nposition = [\"RMC\", \"GGA\"]\nais = [\"VDM\"]\n",
+    )
+    .unwrap();
+
+    let (all, position, ais) = (
+        Capture::named("all"),
+        Capture::named("position"),
+        Capture::named("ais"),
+    );
+    Simulator::builder()
+        .count(5)
+        .options(|options| {
+            options.config_path = Some(path.to_str().unwrap().to_string());
+            options.mux = vec![Instrument::Gyro(20.0), Instrument::Ais(20.0)];
+        })
+        .sink(Box::new(all.clone()))
+        .sink(Box::new(position.clone()))
+        .sink(Box::new(ais.clone()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    // Sentences go by formatter or address
+    std::fs::write(&path, "[filters]\nudp = [\"gga\"]\n").unwrap();
+    assert!(Config::load(path.to_str().unwrap()).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let all = all.addresses();
+    assert!(all.iter().any(|address| address.ends_with("GSV")));
+    assert!(all.iter().any(|address| address == "HEHDT"));
+    let position = position.addresses();
+    assert_eq!(position.len(), 10, "{:?}", position);
+    assert!(position
+        .iter()
+        .all(|address| address.ends_with("RMC") || address.ends_with("GGA")));
+    let ais = ais.addresses();
+    assert!(!ais.is_empty());
+    assert!(ais.iter().all(|address| address == "AIVDM"), "{:?}", ais);
+    assert_eq!(
+        ais.len(),
+        all.iter().filter(|address| *address == "AIVDM").count()
+    );
+}
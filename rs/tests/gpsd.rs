@@ -0,0 +1,253 @@
+// tests/gpsd.rs
+
+// gpsd reading the simulator's PTY, as a check that the sentences decode
+// the same in a real client: what gpsd reports as TPV has to match the
+// truth the simulator had for the same epoch. Needs gpsd installed, so
+// it only runs when asked for:
+//
+//   cargo test --test gpsd -- --ignored
+//
+// NMEA_SIM_GPSD picks another gpsd binary than the one on the PATH.
+
+#![cfg(unix)]
+
+use chrono::{DateTime, Utc};
+use nmea_simulator::nmea_generator::Fix;
+use nmea_simulator::{MotionProfile, Simulator};
+use serde_json::Value;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Epochs gpsd has to agree on before the test passes
+const MATCHES: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+// Degrees, about a meter; the sentences carry 1e-4 minutes
+const POSITION_TOLERANCE: f64 = 1e-5;
+const ALTITUDE_TOLERANCE: f64 = 0.2;
+const SPEED_TOLERANCE_KNOTS: f64 = 0.2;
+const COURSE_TOLERANCE: f64 = 0.2;
+const KNOTS_PER_METER_PER_SECOND: f64 = 3600.0 / 1852.0;
+
+struct Gpsd(Child);
+
+impl Drop for Gpsd {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+struct TempDir(PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn time(value: &Value) -> Option<DateTime<Utc>> {
+    let time = DateTime::parse_from_rfc3339(value.as_str()?).ok()?;
+    Some(time.with_timezone(&Utc))
+}
+
+fn number(record: &Value, key: &str) -> Option<f64> {
+    record.get(key).and_then(Value::as_f64)
+}
+
+// The truth of every epoch so far, as the simulator's state had it
+type History = Arc<Mutex<Vec<Fix>>>;
+
+// The sentences carry whole seconds, and so does what gpsd reports. gpsd
+// may be quicker than the recorder, so the truth gets a moment to show up.
+fn truth_at(history: &History, at: DateTime<Utc>) -> Option<Fix> {
+    let deadline = Instant::now() + Duration::from_millis(500);
+    loop {
+        let truth = history
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|fix| fix.time.timestamp() == at.timestamp())
+            .cloned();
+        if truth.is_some() || Instant::now() > deadline {
+            return truth;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn assert_close(field: &str, reported: f64, truth: f64, tolerance: f64, tpv: &Value) {
+    assert!(
+        (reported - truth).abs() <= tolerance,
+        "{}: gpsd reported {}, the truth was {}\n{}",
+        field,
+        reported,
+        truth,
+        tpv
+    );
+}
+
+// Compares what gpsd reported with the truth; false if the TPV has no fix
+// yet or its epoch has no truth record
+fn check_tpv(tpv: &Value, history: &History) -> bool {
+    if tpv["mode"].as_u64().unwrap_or(0) < 2 {
+        return false;
+    }
+    let Some(at) = time(&tpv["time"]) else {
+        return false;
+    };
+    let Some(truth) = truth_at(history, at) else {
+        return false;
+    };
+    let (Some(lat), Some(lon)) = (number(tpv, "lat"), number(tpv, "lon")) else {
+        return false;
+    };
+    assert_close("lat", lat, truth.latitude, POSITION_TOLERANCE, tpv);
+    assert_close("lon", lon, truth.longitude, POSITION_TOLERANCE, tpv);
+    // altMSL since gpsd 3.20, alt before
+    if let Some(alt) = number(tpv, "altMSL").or_else(|| number(tpv, "alt")) {
+        assert_close("altMSL", alt, truth.altitude, ALTITUDE_TOLERANCE, tpv);
+    }
+    if let Some(speed) = number(tpv, "speed") {
+        assert_close(
+            "speed",
+            speed * KNOTS_PER_METER_PER_SECOND,
+            truth.speed_knots,
+            SPEED_TOLERANCE_KNOTS,
+            tpv,
+        );
+    }
+    if let Some(track) = number(tpv, "track") {
+        // Across north, 359.9 and 0.1 are close
+        let difference = (track - truth.course).rem_euclid(360.0);
+        assert_close(
+            "track",
+            difference.min(360.0 - difference),
+            0.0,
+            COURSE_TOLERANCE,
+            tpv,
+        );
+    }
+    true
+}
+
+fn connect(port: u16, deadline: Instant) -> TcpStream {
+    loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => return stream,
+            Err(e) => {
+                assert!(Instant::now() < deadline, "gpsd never listened: {}", e);
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs gpsd; run with --ignored"]
+fn gpsd_reports_the_truth() {
+    let dir = std::env::temp_dir().join(format!("nmea_gpsd_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let dir = TempDir(dir);
+    let path = |file: &str| dir.0.join(file).to_str().unwrap().to_string();
+    let (input, output) = (path("gps_input"), path("gps_output"));
+
+    // Moving, so that speed and track are compared too
+    let simulator = Simulator::builder()
+        .profile(MotionProfile::Boat)
+        .seed(1)
+        .linked_ptys(&input, &output)
+        .build()
+        .unwrap();
+    let state = simulator.state();
+    let shutdown = simulator.shutdown_handle();
+    let run = thread::spawn(move || simulator.run());
+
+    // Epochs are a second apart, plenty for catching each one
+    let history = History::default();
+    let recorder = {
+        let (history, shutdown) = (history.clone(), shutdown.clone());
+        thread::spawn(move || {
+            while !shutdown.load(Ordering::SeqCst) {
+                let truth = state.lock().unwrap().truth.clone();
+                let mut history = history.lock().unwrap();
+                if let Some(fix) =
+                    truth.filter(|fix| history.last().map(|f| f.time) != Some(fix.time))
+                {
+                    history.push(fix);
+                }
+                drop(history);
+                thread::sleep(Duration::from_millis(20));
+            }
+        })
+    };
+
+    let deadline = Instant::now() + TIMEOUT;
+    while fs::symlink_metadata(&output).is_err() {
+        assert!(Instant::now() < deadline, "the PTYs never came up");
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // Read-only, so that gpsd does not try to reconfigure the receiver
+    let port = free_port();
+    let gpsd = std::env::var("NMEA_SIM_GPSD").unwrap_or_else(|_| "gpsd".to_string());
+    let gpsd = Gpsd(
+        Command::new(&gpsd)
+            .args(["-N", "-n", "-b", "-S", &port.to_string(), &output])
+            .spawn()
+            .unwrap_or_else(|e| panic!("failed to start {}: {}", gpsd, e)),
+    );
+
+    let mut stream = connect(port, deadline);
+    stream
+        .write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")
+        .unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+
+    let mut matches = 0;
+    let mut line = String::new();
+    while matches < MATCHES {
+        assert!(
+            Instant::now() < deadline,
+            "gpsd matched only {} of {} epochs",
+            matches,
+            MATCHES
+        );
+        // A line cut off by the timeout is completed by the next read
+        match reader.read_line(&mut line) {
+            Ok(0) => panic!("gpsd closed the connection"),
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => panic!("{}", e),
+        }
+        let report = serde_json::from_str::<Value>(&line).unwrap_or_default();
+        if report["class"] == "TPV" && check_tpv(&report, &history) {
+            matches += 1;
+        }
+        line.clear();
+    }
+
+    drop(gpsd);
+    shutdown.store(true, Ordering::SeqCst);
+    run.join().unwrap().unwrap();
+    recorder.join().unwrap();
+}
@@ -0,0 +1,111 @@
+// tests/inject.rs
+
+// Sentences of our own in the stream: right away, at a time into the run,
+// or again and again, from the control commands and the config file.
+
+mod common;
+
+use common::Capture;
+use nmea_simulator::checksum::verify;
+use nmea_simulator::config::Config;
+use nmea_simulator::control::ControlCommand;
+use nmea_simulator::Simulator;
+
+impl Capture {
+    // How often a sentence with this body went out, checking its checksum
+    fn count(&self, body: &str) -> usize {
+        self.output()
+            .lines()
+            .filter(|line| line.contains(body))
+            .inspect(|line| assert_eq!(verify(line).unwrap(), body))
+            .count()
+    }
+}
+
+fn inject(sentence: &str, time: Option<f64>, every: Option<f64>) -> ControlCommand {
+    ControlCommand::InjectSentence {
+        sentence: sentence.to_string(),
+        port: None,
+        time,
+        every,
+    }
+}
+
+#[test]
+fn schedules_from_control_commands() {
+    let capture = Capture::default();
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .count(10)
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap();
+    let controller = simulator.controller();
+    controller
+        .apply(inject("PXYZ,CLEARED", None, Some(0.25)))
+        .unwrap();
+    controller
+        .apply(ControlCommand::ClearInjections { port: Some(0) })
+        .unwrap();
+    controller
+        .apply(inject("PXYZ,STATUS,1", None, Some(0.25)))
+        .unwrap();
+    controller
+        .apply(inject("$GPTXT,01,01,02,later", Some(0.5), None))
+        .unwrap();
+
+    assert!(controller.apply(inject("PXYZ", None, Some(0.01))).is_err());
+    assert!(controller.apply(inject("PXYZ", Some(-1.0), None)).is_err());
+    assert!(controller.apply(inject("PXYZ", Some(0.0), None)).is_err());
+    assert!(controller.apply(inject("PXYZ", Some(1e30), None)).is_err());
+    assert!(controller.apply(inject("PXYZ", None, Some(1e30))).is_err());
+    let port = ControlCommand::ClearInjections { port: Some(1) };
+    assert!(controller.apply(port).is_err());
+    simulator.run().unwrap();
+
+    // At 0, 0.25, 0.5 and 0.75 s into the run of 0.9 s
+    let repeats = capture.count("PXYZ,STATUS,1");
+    assert!((2..=5).contains(&repeats), "{}", repeats);
+    assert_eq!(capture.count("GPTXT,01,01,02,later"), 1);
+    assert_eq!(capture.count("PXYZ,CLEARED"), 0);
+}
+
+#[test]
+fn schedules_from_the_config_file() {
+    let dir = std::env::temp_dir().join(format!("nmea_inject_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sim.toml");
+    std::fs::write(
+        &path,
+        "rate = 10.0\n\n\
+         [[inject]]\nsentence = \"PXYZ,STATUS,1\"\nevery = 0.25\n\n\
+         [[inject]]\nsentence = \"GPTXT,01,01,02,later\"\nat = 0.5\nport = 0\n\n\
+         [[inject]]\nsentence = \"PXYZ,NOWHERE\"\nat = 0.5\nport = 3\n",
+    )
+    .unwrap();
+
+    let capture = Capture::default();
+    Simulator::builder()
+        .count(10)
+        .options(|options| options.config_path = Some(path.to_str().unwrap().to_string()))
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    // A single one needs its time, or it would go out again on every reload
+    std::fs::write(&path, "[[inject]]\nsentence = \"PXYZ\"\n").unwrap();
+    assert!(Config::load(path.to_str().unwrap()).is_err());
+    std::fs::write(&path, "[[inject]]\nsentence = \"PXYZ\"\nevery = 0\n").unwrap();
+    assert!(Config::load(path.to_str().unwrap()).is_err());
+    std::fs::write(&path, "[[inject]]\nsentence = \"PXYZ\"\nat = 1e30\n").unwrap();
+    assert!(Config::load(path.to_str().unwrap()).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let repeats = capture.count("PXYZ,STATUS,1");
+    assert!((2..=5).contains(&repeats), "{}", repeats);
+    assert_eq!(capture.count("GPTXT,01,01,02,later"), 1);
+    assert_eq!(capture.count("PXYZ,NOWHERE"), 0);
+}
@@ -0,0 +1,143 @@
+// tests/mux.rs
+
+// The instruments of a multiplexer, what they send, and their sentences
+// going out whole in between the receiver's.
+
+mod common;
+
+use common::Capture;
+use nmea_simulator::ais::PositionReport;
+use nmea_simulator::checksum::verify;
+use nmea_simulator::mux::Instrument;
+use nmea_simulator::position::Position;
+use nmea_simulator::sentences::{Dbt, Dpt, Sentence, Vdm};
+use nmea_simulator::Simulator;
+
+// The bits of an armored AIS payload, most significant first
+fn unarmor(payload: &str, fill_bits: u8) -> Vec<bool> {
+    let mut bits: Vec<bool> = payload
+        .bytes()
+        .flat_map(|c| {
+            let value = if c < 96 { c - 48 } else { c - 56 };
+            (0..6).rev().map(move |i| (value >> i) & 1 == 1)
+        })
+        .collect();
+    bits.truncate(bits.len() - fill_bits as usize);
+    bits
+}
+
+fn field(bits: &[bool], start: usize, width: usize) -> u64 {
+    bits[start..start + width]
+        .iter()
+        .fold(0, |value, &bit| value << 1 | bit as u64)
+}
+
+fn signed(bits: &[bool], start: usize, width: usize) -> i64 {
+    let value = field(bits, start, width) as i64;
+    if value >> (width - 1) == 1 {
+        value - (1 << width)
+    } else {
+        value
+    }
+}
+
+#[test]
+fn parses_instruments() {
+    assert_eq!(Instrument::parse("gyro").unwrap(), Instrument::Gyro(10.0));
+    assert_eq!(Instrument::parse("ais=0.5").unwrap(), Instrument::Ais(0.5));
+    assert_eq!(Instrument::parse("depth=2").unwrap().to_string(), "depth=2");
+    assert!(Instrument::parse("radar").is_err());
+    assert!(Instrument::parse("compass=0").is_err());
+    assert!(Instrument::parse("compass=fast").is_err());
+}
+
+#[test]
+fn encodes_depth_and_ais() {
+    let dpt = Dpt {
+        talker: "SD".to_string(),
+        depth_m: 12.34,
+        offset_m: -1.5,
+    };
+    assert_eq!(dpt.to_nmea(), "$SDDPT,12.3,-1.5,*62\r\n");
+    let dbt = Dbt {
+        talker: "SD".to_string(),
+        depth_m: 18.288,
+    };
+    assert_eq!(dbt.fields(), ["60.0", "f", "18.3", "M", "10.0", "F"]);
+
+    let report = PositionReport {
+        mmsi: 366_123_456,
+        position: Position::new(37.80, -122.42, 0.0),
+        speed_knots: 12.3,
+        course: 271.4,
+        heading: Some(270.0),
+        second: 42,
+    };
+    let (payload, fill_bits) = report.encode();
+    assert_eq!((payload.len(), fill_bits), (28, 0));
+    let bits = unarmor(&payload, fill_bits);
+    assert_eq!(bits.len(), 168);
+    assert_eq!(field(&bits, 0, 6), 1);
+    assert_eq!(field(&bits, 8, 30), 366_123_456);
+    assert_eq!(signed(&bits, 42, 8), -128);
+    assert_eq!(field(&bits, 50, 10), 123);
+    assert_eq!(signed(&bits, 61, 28), -73_452_000);
+    assert_eq!(signed(&bits, 89, 27), 22_680_000);
+    assert_eq!(field(&bits, 116, 12), 2714);
+    assert_eq!(field(&bits, 128, 9), 270);
+    assert_eq!(field(&bits, 137, 6), 42);
+
+    let vdm = Vdm {
+        talker: "AI".to_string(),
+        channel: 'A',
+        payload,
+        fill_bits,
+    };
+    let line = vdm.to_nmea();
+    assert!(line.starts_with("!AIVDM,1,1,,A,"), "{}", line);
+    verify(&line).unwrap();
+}
+
+#[test]
+fn interleaves_instruments() {
+    let capture = Capture::default();
+    Simulator::builder()
+        .rate_hz(5.0)
+        .seed(2)
+        .count(5)
+        .options(|options| {
+            options.mux = vec![
+                Instrument::Gyro(50.0),
+                Instrument::Depth(5.0),
+                Instrument::Ais(5.0),
+            ]
+        })
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+    let output = capture.output();
+
+    // Every sentence whole, whoever sent it
+    let mut addresses = Vec::new();
+    for line in output.split_inclusive('\n') {
+        assert!(line.ends_with("\r\n"), "{:?}", line);
+        verify(line).unwrap();
+        addresses.push(&line[1..6]);
+    }
+    let count = |address: &str| addresses.iter().filter(|a| **a == address).count();
+    assert_eq!(count("GNGGA") + count("GPGGA"), 5);
+    assert!(count("HEHDT") >= 20, "{:?}", addresses);
+    assert!(count("SDDPT") >= 2 && count("SDDPT") == count("SDDBT"));
+    assert!(count("AIVDM") >= 2);
+
+    // Some of the receiver's epochs have instrument sentences in them, not
+    // only before their RMC
+    let receiver = |address: &str| !["HEHDT", "SDDPT", "SDDBT", "AIVDM"].contains(&address);
+    let within = addresses
+        .windows(3)
+        .filter(|w| receiver(w[0]) && !receiver(w[1]) && receiver(w[2]) && !w[2].ends_with("RMC"))
+        .count();
+    assert!(within > 0, "{:?}", addresses);
+}
@@ -0,0 +1,106 @@
+// tests/pps.rs
+
+// Where the pulse per second goes, and what it refuses.
+
+#![cfg(unix)]
+
+use nix::fcntl::OFlag;
+use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt};
+use nmea_simulator::pps::PpsSpec;
+use nmea_simulator::{ControlCommand, OutputSink, SimError, Simulator};
+use std::error::Error;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+struct Discard;
+
+impl OutputSink for Discard {
+    fn name(&self) -> String {
+        "discard".to_string()
+    }
+
+    fn write_all(&mut self, _data: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+fn simulator(pps: PpsSpec) -> Simulator {
+    Simulator::builder()
+        .options(|options| options.pps = Some(pps))
+        .sink(Box::new(Discard))
+        .build()
+        .unwrap()
+}
+
+fn field(sample: &[u8], offset: usize) -> i32 {
+    i32::from_ne_bytes(sample[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn parses_pps_outputs() {
+    assert_eq!(
+        PpsSpec::parse("rts:/dev/ttyUSB0").unwrap(),
+        PpsSpec::Rts("/dev/ttyUSB0".to_string())
+    );
+    assert_eq!(
+        PpsSpec::parse("dtr:/dev/ttyS0").unwrap(),
+        PpsSpec::Dtr("/dev/ttyS0".to_string())
+    );
+    let chrony = PpsSpec::parse("chrony:/run/chrony.ttyS0.sock").unwrap();
+    assert_eq!(chrony.to_string(), "chrony:/run/chrony.ttyS0.sock");
+    for invalid in ["rts:", "dcd:/dev/ttyS0", "/dev/ttyS0"] {
+        assert!(PpsSpec::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn sends_pulses_to_chrony() {
+    let dir = std::env::temp_dir().join(format!("nmea_pps_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("chrony.sock");
+    // As chrony listens for SOCK refclock samples
+    let chrony = UnixDatagram::bind(&path).unwrap();
+    chrony
+        .set_read_timeout(Some(Duration::from_secs(3)))
+        .unwrap();
+
+    let simulator = simulator(PpsSpec::Chrony(path.to_str().unwrap().to_string()));
+    // Without a fix there are no pulses
+    simulator
+        .controller()
+        .apply(ControlCommand::SetFixQuality {
+            fix_quality: Some(1),
+        })
+        .unwrap();
+    let shutdown = simulator.shutdown_handle();
+    let run = std::thread::spawn(move || simulator.run());
+
+    let mut sample = [0u8; 64];
+    let len = chrony.recv(&mut sample).unwrap();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    shutdown.store(true, Ordering::SeqCst);
+    run.join().unwrap().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // struct sock_sample: timeval, offset, pulse, leap, padding, magic
+    assert_eq!(len, 40);
+    let second = i64::from_ne_bytes(sample[..8].try_into().unwrap());
+    assert!((now.as_secs() as i64 - second).abs() <= 1, "{}", second);
+    assert_eq!(i64::from_ne_bytes(sample[8..16].try_into().unwrap()), 0);
+    assert_eq!(f64::from_ne_bytes(sample[16..24].try_into().unwrap()), 0.0);
+    assert_eq!(field(&sample, 24), 1);
+    assert_eq!(field(&sample, 36), 0x534f_434b);
+}
+
+#[test]
+fn rejects_ports_without_modem_lines() {
+    let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY).unwrap();
+    grantpt(&master).unwrap();
+    unlockpt(&master).unwrap();
+    let slave = ptsname_r(&master).unwrap();
+
+    let result = simulator(PpsSpec::Rts(slave)).run();
+    assert!(matches!(result, Err(SimError::Io { .. })), "{:?}", result);
+}
@@ -0,0 +1,350 @@
+// tests/pty.rs
+
+// The symlinks of the PTYs: what a run may take over from an earlier one,
+// what it must leave alone, and what it removes again. And where data
+// written on either side goes in each mode, and the selftest that checks
+// it all on a system.
+
+#![cfg(unix)]
+
+use nmea_simulator::cli::{self, SelftestArgs};
+use nmea_simulator::error::SimError;
+use nmea_simulator::output::{OutputSink, PtyMode};
+use nmea_simulator::pty_handler::{PtyHandler, ReaderTap, ReceiveTap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+struct Paths {
+    dir: PathBuf,
+    input: String,
+    output: String,
+}
+
+impl Paths {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("nmea_pty_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = |file: &str| dir.join(file).to_str().unwrap().to_string();
+        Paths {
+            input: path("gps_input"),
+            output: path("gps_output"),
+            dir,
+        }
+    }
+}
+
+impl Drop for Paths {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn pty_handler(runtime: &Runtime, lock_links: bool) -> PtyHandler {
+    let mut handler = PtyHandler::new(Arc::new(AtomicBool::new(false)), runtime.handle().clone());
+    handler.lock_links = lock_links;
+    handler
+}
+
+fn target(link: &str) -> PathBuf {
+    fs::read_link(link).unwrap()
+}
+
+// What clients write and whether one is connected, as the handler reports
+// them to the simulator
+#[derive(Clone, Default)]
+struct Taps {
+    received: Arc<Mutex<Vec<u8>>>,
+    connected: Arc<AtomicBool>,
+}
+
+impl Taps {
+    fn on_receive(&self) -> Option<ReceiveTap> {
+        let received = self.received.clone();
+        Some(Box::new(move |data: &[u8]| {
+            received.lock().unwrap().extend_from_slice(data)
+        }))
+    }
+
+    fn on_reader(&self) -> Option<ReaderTap> {
+        let connected = self.connected.clone();
+        Some(Box::new(move |reader: bool| {
+            connected.store(reader, Ordering::SeqCst)
+        }))
+    }
+
+    fn received(&self) -> Vec<u8> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+// Opens a PTY the way a client does, without blocking reads
+fn open_client(path: &str) -> File {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+        .open(path)
+        .unwrap()
+}
+
+fn wait_for(mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Reads until `expected` bytes came in
+fn read_exactly(file: &mut File, expected: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 256];
+    wait_for(|| {
+        match file.read(&mut buf) {
+            Ok(n) => data.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => panic!("{}", e),
+        }
+        data.len() >= expected
+    });
+    data
+}
+
+// Nothing arrives within a while
+fn assert_silent(file: &mut File) {
+    thread::sleep(Duration::from_millis(300));
+    let mut buf = [0u8; 256];
+    match file.read(&mut buf) {
+        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+        other => panic!("expected nothing, got {:?}", other),
+    }
+}
+
+// Sets up a linked pair, connects a client to its output side and returns
+// the simulator's and the client's ends
+fn connected_pair(handler: &mut PtyHandler, paths: &Paths, taps: &Taps) -> (File, File) {
+    handler
+        .setup_linked_ptys(
+            &paths.input,
+            &paths.output,
+            taps.on_receive(),
+            taps.on_reader(),
+        )
+        .unwrap();
+    handler.start_forwarding().unwrap();
+    let simulator = open_client(&paths.input);
+    let client = open_client(&paths.output);
+    wait_for(|| taps.connected.load(Ordering::SeqCst));
+    (simulator, client)
+}
+
+#[test]
+fn replaces_stale_links() {
+    let runtime = Runtime::new().unwrap();
+    let paths = Paths::new("stale");
+    // Left by a run that crashed; its PTY is gone
+    symlink("/dev/pts/999999", &paths.input).unwrap();
+    symlink("/dev/pts/999998", &paths.output).unwrap();
+
+    let mut handler = pty_handler(&runtime, false);
+    handler
+        .setup_linked_ptys(&paths.input, &paths.output, None, None)
+        .unwrap();
+    assert!(Path::new(&paths.input).exists());
+    assert_ne!(target(&paths.input), Path::new("/dev/pts/999999"));
+
+    handler.cleanup().unwrap();
+    assert!(fs::symlink_metadata(&paths.input).is_err());
+    assert!(fs::symlink_metadata(&paths.output).is_err());
+}
+
+#[test]
+fn leaves_links_in_use() {
+    let runtime = Runtime::new().unwrap();
+    let paths = Paths::new("in_use");
+    // As if another simulator served it
+    let other = paths.dir.join("other");
+    fs::write(&other, "").unwrap();
+    symlink(&other, &paths.input).unwrap();
+
+    let mut handler = pty_handler(&runtime, false);
+    let result = handler.setup_linked_ptys(&paths.input, &paths.output, None, None);
+    assert!(
+        matches!(result, Err(SimError::Symlink { .. })),
+        "{:?}",
+        result
+    );
+    handler.cleanup().unwrap();
+    assert_eq!(target(&paths.input), other);
+
+    // Neither are files that are not links
+    fs::remove_file(&paths.input).unwrap();
+    fs::write(&paths.input, "data").unwrap();
+    let mut handler = pty_handler(&runtime, false);
+    let result = handler.setup_linked_ptys(&paths.input, &paths.output, None, None);
+    assert!(
+        matches!(result, Err(SimError::Symlink { .. })),
+        "{:?}",
+        result
+    );
+    handler.cleanup().unwrap();
+    assert_eq!(fs::read_to_string(&paths.input).unwrap(), "data");
+}
+
+#[test]
+fn lock_keeps_second_run_out() {
+    let runtime = Runtime::new().unwrap();
+    let paths = Paths::new("lock");
+    let lock = format!("{}.lock", paths.input);
+
+    let mut first = pty_handler(&runtime, true);
+    first
+        .setup_linked_ptys(&paths.input, &paths.output, None, None)
+        .unwrap();
+    let served = target(&paths.input);
+
+    let mut second = pty_handler(&runtime, true);
+    let result = second.setup_linked_ptys(&paths.input, &paths.output, None, None);
+    assert!(matches!(result, Err(SimError::Io { .. })), "{:?}", result);
+    second.cleanup().unwrap();
+    assert_eq!(target(&paths.input), served);
+
+    first.cleanup().unwrap();
+    assert!(!Path::new(&lock).exists());
+}
+
+#[test]
+fn lock_takes_over_links_of_ended_runs() {
+    let runtime = Runtime::new().unwrap();
+    let paths = Paths::new("take_over");
+    // Pointing at something that exists, e.g. a PTY number reused since
+    let other = paths.dir.join("other");
+    fs::write(&other, "").unwrap();
+    symlink(&other, &paths.input).unwrap();
+
+    let mut handler = pty_handler(&runtime, true);
+    handler
+        .setup_linked_ptys(&paths.input, &paths.output, None, None)
+        .unwrap();
+    assert_ne!(target(&paths.input), other);
+    handler.cleanup().unwrap();
+}
+
+#[test]
+fn cleanup_leaves_links_taken_over() {
+    let runtime = Runtime::new().unwrap();
+    let paths = Paths::new("cleanup");
+    let mut handler = pty_handler(&runtime, false);
+    handler
+        .setup_linked_ptys(&paths.input, &paths.output, None, None)
+        .unwrap();
+
+    // Another run pointed the link at its own PTY meanwhile
+    let other = paths.dir.join("other");
+    fs::write(&other, "").unwrap();
+    fs::remove_file(&paths.input).unwrap();
+    symlink(&other, &paths.input).unwrap();
+
+    handler.cleanup().unwrap();
+    assert_eq!(target(&paths.input), other);
+    assert!(fs::symlink_metadata(&paths.output).is_err());
+}
+
+#[test]
+fn linked_pair_keeps_client_writes_to_the_tap() {
+    let runtime = Runtime::new().unwrap();
+    let paths = Paths::new("linked_pair");
+    let taps = Taps::default();
+    let mut handler = pty_handler(&runtime, false);
+    let (mut simulator, mut client) = connected_pair(&mut handler, &paths, &taps);
+
+    // Input to output
+    simulator.write_all(b"$GPGGA\r\n").unwrap();
+    assert_eq!(read_exactly(&mut client, 8), b"$GPGGA\r\n");
+
+    // Client writes reach the tap only
+    client.write_all(b"$PMTK220,100\r\n").unwrap();
+    wait_for(|| taps.received() == b"$PMTK220,100\r\n");
+    assert_silent(&mut simulator);
+
+    handler.cleanup().unwrap();
+}
+
+#[test]
+fn loopback_forwards_client_writes_to_the_input() {
+    let runtime = Runtime::new().unwrap();
+    let paths = Paths::new("loopback");
+    let taps = Taps::default();
+    let mut handler = pty_handler(&runtime, false);
+    handler.loopback = true;
+    let (mut simulator, mut client) = connected_pair(&mut handler, &paths, &taps);
+
+    simulator.write_all(b"$GPGGA\r\n").unwrap();
+    assert_eq!(read_exactly(&mut client, 8), b"$GPGGA\r\n");
+
+    // Client writes reach both the tap and the input side
+    client.write_all(b"$PMTK220,100\r\n").unwrap();
+    assert_eq!(read_exactly(&mut simulator, 14), b"$PMTK220,100\r\n");
+    assert_eq!(taps.received(), b"$PMTK220,100\r\n");
+
+    handler.cleanup().unwrap();
+}
+
+#[test]
+fn single_port_writes_to_its_client() {
+    let runtime = Runtime::new().unwrap();
+    let paths = Paths::new("single_port");
+    let taps = Taps::default();
+    let mut handler = pty_handler(&runtime, false);
+    let mut sink = handler
+        .setup_single_pty(&paths.input, taps.on_receive(), taps.on_reader())
+        .unwrap();
+    handler.start_forwarding().unwrap();
+
+    // Dropped while nobody listens, instead of waiting for the next client
+    sink.write_all(b"$GPGLL\r\n").unwrap();
+    let mut client = open_client(&paths.input);
+    wait_for(|| taps.connected.load(Ordering::SeqCst));
+
+    sink.write_all(b"$GPGGA\r\n").unwrap();
+    assert_eq!(read_exactly(&mut client, 8), b"$GPGGA\r\n");
+
+    // Client writes reach the tap
+    client.write_all(b"$PMTK220,100\r\n").unwrap();
+    wait_for(|| taps.received() == b"$PMTK220,100\r\n");
+
+    // The port pauses again once the client is gone
+    drop(client);
+    wait_for(|| !taps.connected.load(Ordering::SeqCst));
+
+    handler.cleanup().unwrap();
+    assert!(fs::symlink_metadata(&paths.input).is_err());
+}
+
+fn selftest(name: &str, mode: PtyMode) -> Result<(), SimError> {
+    let paths = Paths::new(name);
+    let args = SelftestArgs {
+        mode: Some(mode),
+        duration: Duration::from_secs(2),
+        dir: Some(paths.dir.to_str().unwrap().to_string()),
+    };
+    cli::selftest(args, &AtomicBool::new(false))
+}
+
+#[test]
+fn selftest_passes() {
+    selftest("selftest_pair", PtyMode::LinkedPair).unwrap();
+}
+
+#[test]
+fn selftest_passes_on_a_single_port() {
+    selftest("selftest_single", PtyMode::SinglePort).unwrap();
+}
@@ -0,0 +1,74 @@
+// tests/replay.rs
+
+// How a recorded log splits into epochs, and how a looped one moves on in
+// time instead of starting over.
+
+use chrono::TimeDelta;
+use nmea_simulator::checksum::wrap_sentence;
+use nmea_simulator::replay::{self, Replay};
+use std::time::Duration;
+
+fn log(sentences: &[&str]) -> String {
+    sentences.iter().map(|s| wrap_sentence(s)).collect()
+}
+
+#[test]
+fn loops_after_the_last_interval() {
+    let replay = Replay::from_log(&log(&[
+        "GPRMC,235958.50,A,4807.038,N,01131.000,E,022.4,084.4,311224,003.1,W",
+        "GPGSA,A,3,04,05,,,,,,,,,,,2.5,1.3,2.1",
+        "GPRMC,235959.00,A,4807.038,N,01131.000,E,022.4,084.4,311224,003.1,W",
+        "GPRMC,000001.00,A,4807.038,N,01131.000,E,022.4,084.4,010125,003.1,W",
+    ]));
+    assert_eq!(replay.epochs.len(), 3);
+    assert_eq!(replay.interval_after(0), Duration::from_millis(500));
+    // Across midnight, then a second after the last
+    assert_eq!(replay.interval_after(1), Duration::from_secs(2));
+    assert_eq!(replay.duration(), Duration::from_millis(3500));
+}
+
+#[test]
+fn rebases_times_and_dates() {
+    let epoch = log(&[
+        "GPRMC,235958.50,A,4807.038,N,01131.000,E,022.4,084.4,311224,003.1,W",
+        "GPZDA,235958.50,31,12,2024,00,00",
+        "GPGGA,235958.50,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,",
+        "GNGLL,4807.038,N,01131.000,E,235958.50,A,A",
+        "GPGSA,A,3,04,05,,,,,,,,,,,2.5,1.3,2.1",
+        "PUBX,04,235958.50,311224",
+    ]);
+    let rebased = replay::rebase(&epoch, TimeDelta::milliseconds(3500));
+    assert_eq!(
+        rebased,
+        log(&[
+            "GPRMC,000002.00,A,4807.038,N,01131.000,E,022.4,084.4,010125,003.1,W",
+            "GPZDA,000002.00,01,01,2025,00,00",
+            "GPGGA,000002.00,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,",
+            "GNGLL,4807.038,N,01131.000,E,000002.00,A,A",
+            "GPGSA,A,3,04,05,,,,,,,,,,,2.5,1.3,2.1",
+            "PUBX,04,235958.50,311224",
+        ])
+    );
+
+    // Whole seconds stay whole, and a long drive moves on by days
+    let rebased = replay::rebase(
+        &log(&["GPRMC,120000,A,4807.038,N,01131.000,E,022.4,084.4,150324,003.1,W"]),
+        TimeDelta::hours(8) * 5,
+    );
+    assert_eq!(
+        rebased,
+        log(&["GPRMC,040000,A,4807.038,N,01131.000,E,022.4,084.4,170324,003.1,W"])
+    );
+
+    // Lines with a wrong checksum are kept as they are; those without one
+    // get none
+    let broken = "$GPGGA,120000,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*00\r\n\
+                  $GPGGA,120000,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,\r\n";
+    let rebased = replay::rebase(broken, TimeDelta::seconds(1));
+    let lines: Vec<_> = rebased.lines().collect();
+    assert_eq!(lines[0], broken.lines().next().unwrap());
+    assert_eq!(
+        lines[1],
+        "$GPGGA,120001,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,"
+    );
+}
@@ -0,0 +1,148 @@
+// tests/ros2.rs
+
+// The CDR encoding of the ROS 2 messages, and the topics a simulator
+// publishes them on. Only with the ros2 feature:
+//
+//   cargo test --features ros2 --test ros2
+
+#![cfg(feature = "ros2")]
+
+use chrono::{TimeZone, Utc};
+use nmea_simulator::nmea_generator::{Constellation, Fix, Satellite};
+use nmea_simulator::ros2::{self, FIX_TOPIC, SENTENCE_TOPIC};
+use nmea_simulator::{ControlCommand, OutputSink, Simulator};
+use std::error::Error;
+use std::net::TcpListener;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use zenoh::Wait;
+
+struct Discard;
+
+impl OutputSink for Discard {
+    fn name(&self) -> String {
+        "discard".to_string()
+    }
+
+    fn write_all(&mut self, _data: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+fn fix() -> Fix {
+    Fix {
+        time: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+        latitude: 48.1173,
+        longitude: 11.5167,
+        altitude: 545.4,
+        geoid_height: 46.9,
+        speed_knots: 12.5,
+        course: 84.4,
+        heading: 84.4,
+        water_speed_knots: 12.5,
+        fix_quality: 2,
+        hdop: 0.5,
+        satellites: vec![
+            Satellite::new(Constellation::GPS, 4),
+            Satellite::new(Constellation::GALILEO, 11),
+        ],
+        authenticated: None,
+    }
+}
+
+fn f64_at(data: &[u8], offset: usize) -> f64 {
+    f64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[test]
+fn encodes_nav_sat_fix() {
+    let data = ros2::encode_nav_sat_fix(&fix(), false, "gps");
+    // Encapsulation, then the header: stamp and frame_id with its NUL
+    assert_eq!(data[..4], [0x00, 0x01, 0x00, 0x00]);
+    assert_eq!(data[4..8], 1709294400i32.to_le_bytes());
+    assert_eq!(data[8..12], 0u32.to_le_bytes());
+    assert_eq!(data[12..16], 4u32.to_le_bytes());
+    assert_eq!(&data[16..20], b"gps\0");
+    // Status SBAS for DGPS, services GPS and Galileo
+    assert_eq!(data[20] as i8, 1);
+    assert_eq!(data[22..24], 9u16.to_le_bytes());
+    // Coordinates aligned to 8 past the encapsulation
+    assert_eq!(f64_at(&data, 28), 48.1173);
+    assert_eq!(f64_at(&data, 36), 11.5167);
+    assert!((f64_at(&data, 44) - 592.3).abs() < 1e-9);
+    // Covariance: 2 m squared horizontally, twice that vertically
+    assert_eq!(f64_at(&data, 52), 4.0);
+    assert_eq!(f64_at(&data, 60), 0.0);
+    assert_eq!(f64_at(&data, 84), 4.0);
+    assert_eq!(f64_at(&data, 116), 16.0);
+    assert_eq!(data[124..], [1]);
+
+    // Reported as no fix when the receiver lost it
+    let data = ros2::encode_nav_sat_fix(&fix(), true, "gps");
+    assert_eq!(data[20] as i8, -1);
+}
+
+#[test]
+fn encodes_sentences() {
+    let stamp = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+    let data = ros2::encode_sentence("$GPGGA,1*00", stamp, "gps");
+    assert_eq!(data.len(), 20 + 4 + 12);
+    assert_eq!(data[20..24], 12u32.to_le_bytes());
+    assert_eq!(&data[24..], b"$GPGGA,1*00\0");
+}
+
+#[test]
+fn publishes_fixes_and_sentences() {
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    // As the bridge does, listening where the simulator connects to
+    let endpoint = format!("tcp/127.0.0.1:{}", port);
+    let mut config = zenoh::Config::default();
+    config
+        .insert_json5("listen/endpoints", &format!("[\"{}\"]", endpoint))
+        .unwrap();
+    config
+        .insert_json5("scouting/multicast/enabled", "false")
+        .unwrap();
+    let bridge = zenoh::open(config).wait().unwrap();
+    let fixes = bridge.declare_subscriber(FIX_TOPIC).wait().unwrap();
+    let sentences = bridge.declare_subscriber(SENTENCE_TOPIC).wait().unwrap();
+
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("GGA")
+        .options(|options| {
+            options.ros2 = true;
+            options.ros2_connect = vec![endpoint];
+            options.ros2_frame_id = "gnss".to_string();
+        })
+        .sink(Box::new(Discard))
+        .build()
+        .unwrap();
+    simulator
+        .controller()
+        .apply(ControlCommand::SetFixQuality {
+            fix_quality: Some(1),
+        })
+        .unwrap();
+    let shutdown = simulator.shutdown_handle();
+    let run = std::thread::spawn(move || simulator.run());
+
+    let timeout = Duration::from_secs(10);
+    let fix = fixes.recv_timeout(timeout).unwrap().expect("no fix");
+    let fix = fix.payload().to_bytes();
+    assert_eq!(&fix[16..21], b"gnss\0");
+    assert_eq!(fix[24] as i8, 0);
+    let sentence = sentences
+        .recv_timeout(timeout)
+        .unwrap()
+        .expect("no sentence");
+    let sentence = sentence.payload().to_bytes();
+    assert!(sentence[28..].starts_with(b"$GNGGA,") || sentence[28..].starts_with(b"$GPGGA,"));
+
+    shutdown.store(true, Ordering::SeqCst);
+    run.join().unwrap().unwrap();
+}
@@ -0,0 +1,220 @@
+// tests/scenario.rs
+
+// A run recorded as a scenario, and the scenario replaying it.
+
+mod common;
+
+use common::Capture;
+use nmea_simulator::parser::{ParsedSentence, Parser};
+use nmea_simulator::scenario::{Action, Scenario};
+use nmea_simulator::{ControlCommand, Simulator};
+use std::time::Duration;
+
+// Latitude and longitude of every GGA with a position
+fn positions(capture: &Capture) -> Vec<(f64, f64)> {
+    let output = capture.output();
+    let mut parser = Parser::new();
+    output
+        .lines()
+        .filter_map(|line| match parser.parse(line) {
+            Ok(ParsedSentence::Gga(gga)) => gga.position,
+            _ => None,
+        })
+        .map(|position| (position.lat_deg, position.lon_deg))
+        .collect()
+}
+
+#[test]
+fn records_and_replays_runs() {
+    let dir = std::env::temp_dir().join(format!("nmea_scenario_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("run.scenario").to_str().unwrap().to_string();
+
+    // Unpinned, so every epoch is somewhere else
+    let recorded = Capture::default();
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("GGA")
+        .seed(3)
+        .count(10)
+        .options(|options| options.scenario_record_path = Some(path.clone()))
+        .sink(Box::new(recorded.clone()))
+        .build()
+        .unwrap();
+    let controller = simulator.controller();
+    let run = std::thread::spawn(move || simulator.run());
+    std::thread::sleep(Duration::from_millis(300));
+    controller
+        .apply(ControlCommand::CorruptSentences {
+            count: 1,
+            port: None,
+        })
+        .unwrap();
+    controller.apply(ControlCommand::GetState).unwrap();
+    run.join().unwrap().unwrap();
+
+    // A set-truth per epoch, the commands in between and a stop at the end
+    let scenario = Scenario::load(&path).unwrap();
+    let truths: Vec<_> = scenario
+        .events
+        .iter()
+        .filter_map(|(_, action)| match action {
+            Action::Command(ControlCommand::SetTruth {
+                latitude,
+                longitude,
+                ..
+            }) => Some((*latitude, *longitude)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(truths.len(), 10);
+    let commands: Vec<_> = scenario
+        .events
+        .iter()
+        .filter(|(_, action)| !matches!(action, Action::Command(ControlCommand::SetTruth { .. })))
+        .collect();
+    assert_eq!(commands.len(), 2, "{:?}", commands);
+    let (corrupt_at, corrupt) = commands[0];
+    assert!(
+        matches!(
+            corrupt,
+            Action::Command(ControlCommand::CorruptSentences { count: 1, .. })
+        ),
+        "{:?}",
+        corrupt
+    );
+    assert!((0.2..1.0).contains(corrupt_at), "{}", corrupt_at);
+    assert!(matches!(commands[1].1, Action::Stop), "{:?}", commands[1]);
+    // The GGA of the corrupted epoch does not parse
+    let recorded = positions(&recorded);
+    assert_eq!(recorded.len(), 9);
+    let near =
+        |a: &(f64, f64), b: &(f64, f64)| (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4;
+    assert!(recorded.iter().all(|p| truths.iter().any(|t| near(p, t))));
+
+    // The replay goes where the run went, once its first event is in, and
+    // stops where it did
+    let replayed = Capture::default();
+    Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("GGA")
+        .seed(3)
+        .options(|options| options.scenario_path = Some(path.clone()))
+        .sink(Box::new(replayed.clone()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let replayed = positions(&replayed);
+    assert!((8..=11).contains(&replayed.len()), "{:?}", replayed);
+    for position in &replayed[1..] {
+        assert!(
+            truths.iter().any(|truth| near(position, truth)),
+            "{:?} not in {:?}",
+            position,
+            truths
+        );
+    }
+}
+
+// A marginal baseline: RTK fixed and float by turns, with the GST accuracy
+// of each, centimetres and decimetres
+#[test]
+fn oscillates_between_rtk_fixed_and_float() {
+    let dir = std::env::temp_dir().join(format!("nmea_rtk_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("rtk.scenario");
+    std::fs::write(
+        &path,
+        "[[event]]\nat = 0\ncommand = \"set-hdop\"\nhdop = 1.0\n\n\
+         [[event]]\nat = 0\ncommand = \"set-rtk-oscillation\"\nfixed = 0.3\nfloat = 0.3\n\n\
+         [[event]]\nat = 2\ncommand = \"stop\"\n",
+    )
+    .unwrap();
+
+    let capture = Capture::default();
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("RMC")
+        .sentence("GGA")
+        .sentence("GST")
+        .options(|options| options.scenario_path = Some(path.to_str().unwrap().to_string()))
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap();
+    let controller = simulator.controller();
+    let oscillation = |fixed, float| ControlCommand::SetRtkOscillation { fixed, float };
+    assert!(controller.apply(oscillation(Some(1.0), None)).is_err());
+    assert!(controller.apply(oscillation(Some(0.0), Some(1.0))).is_err());
+    assert!(controller
+        .apply(oscillation(Some(1.0), Some(f64::NAN)))
+        .is_err());
+    assert!(controller
+        .apply(oscillation(Some(1e300), Some(1.0)))
+        .is_err());
+    simulator.run().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // The quality of each GGA with the RMC mode and the GST accuracy of its epoch
+    let output = capture.output();
+    let mut parser = Parser::new();
+    let mut mode = None;
+    let mut quality = None;
+    let mut epochs = Vec::new();
+    for line in output.lines() {
+        match parser.parse(line) {
+            Ok(ParsedSentence::Rmc(rmc)) => mode = rmc.mode,
+            Ok(ParsedSentence::Gga(gga)) => quality = Some(gga.fix_quality),
+            Ok(ParsedSentence::Gst(gst)) => epochs.push((
+                quality.take().unwrap(),
+                mode.take(),
+                gst.accuracy.unwrap().semi_major,
+            )),
+            _ => {}
+        }
+    }
+    let mut qualities: Vec<u8> = epochs.iter().map(|(quality, _, _)| *quality).collect();
+    qualities.dedup();
+    assert!(
+        qualities.windows(4).any(|turns| turns == [4, 5, 4, 5]),
+        "{:?}",
+        qualities
+    );
+    for (quality, mode, semi_major) in epochs {
+        match quality {
+            4 => {
+                assert_eq!(mode, Some('R'));
+                assert!(semi_major < 0.05, "{}", semi_major)
+            }
+            5 => {
+                assert_eq!(mode, Some('F'));
+                assert!((0.1..1.0).contains(&semi_major), "{}", semi_major)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn rejects_event_times_past_a_year() {
+    let dir = std::env::temp_dir().join(format!("nmea_scenario_times_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("times.scenario");
+    let load = |events: &str| {
+        std::fs::write(&path, events).unwrap();
+        Scenario::load(path.to_str().unwrap())
+    };
+    assert!(load("[[event]]\nat = 31536000\ncommand = \"stop\"\n").is_ok());
+    assert!(load("[[event]]\nat = 1e30\ncommand = \"stop\"\n").is_err());
+    assert!(load(
+        "[[event]]\nat = 31536000\ncommand = \"pause\"\n\n\
+         [[event]]\nafter = 1\ncommand = \"stop\"\n"
+    )
+    .is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
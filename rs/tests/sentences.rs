@@ -0,0 +1,1240 @@
+// tests/sentences.rs
+
+// Properties every encoded sentence has, whatever the seed and the pinned
+// values: a valid checksum, the field count of its formatter, and a parse
+// that encodes back to the same line.
+
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use nmea_simulator::checksum::{verify, wrap_sentence};
+use nmea_simulator::geoid;
+use nmea_simulator::nmea_generator::{
+    Constellation, Crab, DgpsNetwork, EncodingMode, Environment, Satellite, TRACKING_THRESHOLD,
+};
+use nmea_simulator::parser::{ParsedSentence, Parser};
+use nmea_simulator::position::Position;
+use nmea_simulator::sentences::{
+    check_length, Gsa, Gsv, GsvSatellite, Sentence, SentenceBuffer, GSA_SLOTS, MAX_MINUTE_DECIMALS,
+    MAX_SENTENCE_LENGTH, MINUTE_DECIMALS,
+};
+use nmea_simulator::validate::{self, KINEMATICS_TOLERANCE};
+use nmea_simulator::{NmeaGenerator, TalkerPolicy};
+use quickcheck::{quickcheck, Arbitrary, Gen};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const EPOCHS: usize = 3;
+
+#[derive(Debug, Clone)]
+struct Settings {
+    seed: u64,
+    position: Option<Position>,
+    speed_knots: Option<f64>,
+    course: Option<f64>,
+    crab: Crab,
+    // VTG, VHW, HDT, HDG and GST in every epoch too
+    optional_sentences: bool,
+    fix_quality: Option<u8>,
+    satellites: Option<usize>,
+    hdop: Option<f64>,
+    constellations: Option<Vec<Constellation>>,
+    sbas: bool,
+    environment: Environment,
+    no_fix: bool,
+    data_age: Option<f64>,
+    authenticated: Option<bool>,
+    minute_decimals: usize,
+    talker_policy: TalkerPolicy,
+    encoding: EncodingMode,
+}
+
+// Values from u32s scaled into range, as arbitrary floats are mostly NaN,
+// infinite or huge
+fn in_range(g: &mut Gen, min: f64, max: f64) -> f64 {
+    min + (max - min) * (u32::arbitrary(g) as f64 / u32::MAX as f64)
+}
+
+fn maybe<T>(g: &mut Gen, value: impl FnOnce(&mut Gen) -> T) -> Option<T> {
+    if bool::arbitrary(g) {
+        Some(value(g))
+    } else {
+        None
+    }
+}
+
+impl Arbitrary for Settings {
+    fn arbitrary(g: &mut Gen) -> Self {
+        Settings {
+            seed: u64::arbitrary(g),
+            position: maybe(g, |g| {
+                Position::new(
+                    in_range(g, -90.0, 90.0),
+                    in_range(g, -180.0, 180.0),
+                    in_range(g, -100.0, 10000.0),
+                )
+            }),
+            speed_knots: maybe(g, |g| in_range(g, 0.0, 1000.0)),
+            course: maybe(g, |g| in_range(g, 0.0, 360.0)),
+            crab: match u8::arbitrary(g) % 3 {
+                0 => Crab::None,
+                1 => Crab::Offset {
+                    degrees: in_range(g, -90.0, 90.0),
+                },
+                _ => Crab::Current {
+                    knots: in_range(g, 0.0, 10.0),
+                    set: in_range(g, 0.0, 360.0),
+                },
+            },
+            optional_sentences: bool::arbitrary(g),
+            fix_quality: maybe(g, |g| u8::arbitrary(g) % 9),
+            // Also more than the 12 one GSA can list
+            satellites: maybe(g, |g| usize::arbitrary(g) % 21),
+            hdop: maybe(g, |g| in_range(g, 0.5, 99.0)),
+            constellations: maybe(g, |g| {
+                Constellation::ALL
+                    .into_iter()
+                    .filter(|_| bool::arbitrary(g))
+                    .collect()
+            }),
+            sbas: bool::arbitrary(g),
+            environment: *g
+                .choose(&[
+                    Environment::OpenSky,
+                    Environment::Suburban,
+                    Environment::Urban,
+                    Environment::Indoor,
+                ])
+                .unwrap(),
+            no_fix: bool::arbitrary(g),
+            data_age: maybe(g, |g| in_range(g, 0.0, 99.0)),
+            authenticated: maybe(g, bool::arbitrary),
+            minute_decimals: MINUTE_DECIMALS + usize::arbitrary(g) % 5,
+            talker_policy: *g
+                .choose(&[
+                    TalkerPolicy::Gps,
+                    TalkerPolicy::Combined,
+                    TalkerPolicy::Dominant,
+                ])
+                .unwrap(),
+            encoding: *g
+                .choose(&[EncodingMode::Strict, EncodingMode::Permissive])
+                .unwrap(),
+        }
+    }
+}
+
+impl Settings {
+    fn sentences(&self) -> Vec<String> {
+        self.epochs()
+            .iter()
+            .flat_map(|burst| {
+                burst
+                    .split_inclusive("\r\n")
+                    .map(str::to_string)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn epochs(&self) -> Vec<String> {
+        self.generator().iter().take(EPOCHS).collect()
+    }
+
+    fn generator(&self) -> NmeaGenerator {
+        let mut generator = NmeaGenerator::with_seed(self.seed);
+        // The sky moves with the clock, so a seed only repeats with this
+        generator.time = Some(Utc.with_ymd_and_hms(2024, 3, 15, 12, 34, 56).unwrap());
+        generator.position = self.position;
+        generator.speed_knots = self.speed_knots;
+        generator.course = self.course;
+        generator.crab = self.crab;
+        if self.optional_sentences {
+            for formatter in ["VTG", "VHW", "HDT", "HDG", "GST"] {
+                *generator.sentence_rates.get_mut(formatter).unwrap() = 1;
+            }
+        }
+        generator.fix_quality = self.fix_quality;
+        generator.satellites = self.satellites;
+        generator.hdop = self.hdop;
+        generator.constellations = self.constellations.clone();
+        generator.sbas = self.sbas;
+        generator.environment = self.environment;
+        generator.no_fix = self.no_fix;
+        generator.data_age = self.data_age;
+        generator.authenticated = self.authenticated;
+        generator.minute_decimals = self.minute_decimals;
+        generator.talker_policy = self.talker_policy;
+        generator.encoding = self.encoding;
+        generator
+    }
+}
+
+fn checksums_are_valid(settings: Settings) -> Result<(), String> {
+    for line in settings.sentences() {
+        if !line.starts_with('$') || !line.ends_with("\r\n") {
+            return Err(format!("Malformed {:?}", line));
+        }
+        verify(&line).map_err(|e| format!("{} in {:?}", e, line))?;
+    }
+    Ok(())
+}
+
+fn field_counts_match_formatter(settings: Settings) -> Result<(), String> {
+    let mut satellites_used = 0;
+    let mut satellites_in_gsa = 0;
+    let mut satellites_in_gsv = 0;
+    // Fields NMEA 4.11 adds to RMC, GLL, GSA and GSV
+    let added = (settings.encoding == EncodingMode::Strict) as usize;
+    for line in settings.sentences() {
+        let body = &line[1..line.find('*').unwrap()];
+        let fields: Vec<&str> = body.split(',').skip(1).collect();
+        let expected = match &body[2..5] {
+            "GGA" => {
+                satellites_used += fields[6].parse::<usize>().unwrap();
+                14
+            }
+            "RMC" => 12 + added,
+            "GLL" => 6 + added,
+            "GSA" => {
+                satellites_in_gsa += fields[2..14].iter().filter(|id| !id.is_empty()).count();
+                17 + added
+            }
+            // Groups of ID, elevation, azimuth and SNR
+            "GSV" => {
+                let satellites = fields.len().saturating_sub(3 + added) / 4;
+                if !(1..=4).contains(&satellites) {
+                    return Err(format!("{} satellites in {:?}", satellites, line));
+                }
+                satellites_in_gsv += satellites;
+                3 + 4 * satellites + added
+            }
+            "TXT" => 4,
+            "VTG" => 9,
+            "VHW" => 8,
+            "HDT" => 2,
+            "HDG" => 5,
+            "GST" => 8,
+            other => return Err(format!("Unexpected {}", other)),
+        };
+        if fields.len() != expected {
+            return Err(format!(
+                "{} fields instead of {} in {:?}",
+                fields.len(),
+                expected,
+                line
+            ));
+        }
+    }
+    // GGA counts the satellites GSA lists as used, out of those in view
+    if satellites_in_gsa != satellites_used || satellites_in_gsv < satellites_used {
+        return Err(format!(
+            "{} satellites in GSA and {} in GSV, {} in GGA",
+            satellites_in_gsa, satellites_in_gsv, satellites_used
+        ));
+    }
+    Ok(())
+}
+
+// ddmm.mmmm or dddmm.mmmm with the hemisphere in the next field, as
+// NMEA 0183 lays it out, or both empty without a fix
+fn check_coordinate(
+    value: &str,
+    hemisphere: &str,
+    degree_digits: usize,
+    hemispheres: &str,
+) -> bool {
+    if value.is_empty() {
+        return hemisphere.is_empty();
+    }
+    let Some((whole, decimals)) = value.split_once('.') else {
+        return false;
+    };
+    whole.len() == degree_digits + 2
+        && whole[degree_digits..] < *"60"
+        && !decimals.is_empty()
+        && whole
+            .chars()
+            .chain(decimals.chars())
+            .all(|c| c.is_ascii_digit())
+        && hemisphere.len() == 1
+        && hemispheres.contains(hemisphere)
+}
+
+fn rmc_matches_layout(settings: Settings) -> Result<(), String> {
+    for line in settings.sentences() {
+        if &line[3..7] != "RMC," {
+            continue;
+        }
+        let fields: Vec<&str> = line[..line.find('*').unwrap()].split(',').skip(1).collect();
+        let digits = |field: &str, len: usize| {
+            field.len() >= len && field[..len].chars().all(|c| c.is_ascii_digit())
+        };
+        let valid = digits(fields[0], 6)
+            && matches!(fields[1], "A" | "V")
+            && check_coordinate(fields[2], fields[3], 2, "NS")
+            && check_coordinate(fields[4], fields[5], 3, "EW")
+            && fields[6].parse::<f64>().is_ok()
+            && fields[7].parse::<f64>().is_ok()
+            && digits(fields[8], 6)
+            && fields[8].len() == 6
+            && fields[9].is_empty()
+            && fields[10].is_empty()
+            && matches!(fields[11], "A" | "D" | "R" | "F" | "E" | "N")
+            // No position or a void status come with mode N
+            && (fields[1] == "A") == (fields[11] != "N")
+            && (fields[2].is_empty() == (fields[1] == "V"))
+            // Strictly also the navigational status, safe with a fix
+            && match settings.encoding {
+                EncodingMode::Strict => fields.get(12) == Some(if fields[1] == "A" { &"S" } else { &"V" }),
+                EncodingMode::Permissive => fields.len() == 12,
+            };
+        if !valid {
+            return Err(format!("Malformed {:?}", line));
+        }
+    }
+    Ok(())
+}
+
+// Strict sentences fit in 82 characters and carry their times to the
+// hundredth of a second; GSV goes by system, Galileo with its E1 signal
+fn strict_sentences_follow_standard(settings: Settings) -> Result<(), String> {
+    let strict = settings.encoding == EncodingMode::Strict;
+    for line in settings.sentences() {
+        if strict && line.len() > MAX_SENTENCE_LENGTH {
+            return Err(format!("{} characters in {:?}", line.len(), line));
+        }
+        let fields: Vec<&str> = line[..line.find('*').unwrap()].split(',').collect();
+        let time = match &fields[0][3..] {
+            "GGA" | "RMC" => fields[1],
+            "GLL" => fields[5],
+            "GSV" if strict => {
+                let signal = if &fields[0][1..3] == "GA" { "7" } else { "1" };
+                if fields.len() % 4 != 1 || fields[fields.len() - 1] != signal {
+                    return Err(format!("Signal of {:?}", line));
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let decimals = time
+            .split_once('.')
+            .map_or(0, |(_, decimals)| decimals.len());
+        if decimals != if strict { 2 } else { 0 } {
+            return Err(format!("{:?} in {:?}", settings.encoding, line));
+        }
+    }
+    Ok(())
+}
+
+// Encoding into one buffer cleared every epoch gives the same epochs
+fn reused_buffer_matches(settings: Settings) -> Result<(), String> {
+    let mut generator = settings.generator();
+    let mut out = SentenceBuffer::new();
+    for expected in settings.epochs() {
+        let fix = generator.generate_fix();
+        out.clear();
+        generator.encode_epoch(&fix, &mut out);
+        if out.as_str() != expected {
+            return Err(format!("{:?} instead of {:?}", out.as_str(), expected));
+        }
+    }
+    Ok(())
+}
+
+// Every coordinate has the configured digits of minutes, or fewer where
+// strict sentences would get too long with them
+fn coordinates_have_minute_decimals(settings: Settings) -> Result<(), String> {
+    for line in settings.sentences() {
+        let fields: Vec<&str> = line[..line.find('*').unwrap()].split(',').collect();
+        let latitude = match &fields[0][3..] {
+            "GGA" => 2,
+            "RMC" => 3,
+            "GLL" => 1,
+            _ => continue,
+        };
+        for (value, hemisphere, degree_digits, hemispheres) in [
+            (fields[latitude], fields[latitude + 1], 2, "NS"),
+            (fields[latitude + 2], fields[latitude + 3], 3, "EW"),
+        ] {
+            let decimals = value
+                .split_once('.')
+                .map_or(0, |(_, decimals)| decimals.len());
+            let expected = match settings.encoding {
+                EncodingMode::Strict => decimals <= settings.minute_decimals,
+                EncodingMode::Permissive => decimals == settings.minute_decimals,
+            };
+            let valid = check_coordinate(value, hemisphere, degree_digits, hemispheres)
+                && (value.is_empty() || expected);
+            if !valid {
+                return Err(format!("Coordinate {:?} in {:?}", value, line));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Age and station of the corrections come with DGPS and RTK fixes only
+fn gga_has_differential_fields(settings: Settings) -> Result<(), String> {
+    for line in settings.sentences() {
+        if &line[3..7] != "GGA," {
+            continue;
+        }
+        let fields: Vec<&str> = line[..line.find('*').unwrap()].split(',').skip(1).collect();
+        let differential = matches!(fields[5], "2" | "4" | "5");
+        let age_expected = differential || settings.data_age.is_some();
+        let station_valid = if differential {
+            fields[13].len() == 4 && fields[13].chars().all(|c| c.is_ascii_digit())
+        } else {
+            fields[13].is_empty()
+        };
+        if fields[12].is_empty() == age_expected || !station_valid {
+            return Err(format!("Differential fields of {:?}", line));
+        }
+    }
+    Ok(())
+}
+
+// The position, course and heading sentences of an epoch share the talker
+// the policy picks for its systems, which each have a GSA of their own
+fn position_talkers_follow_policy(settings: Settings) -> Result<(), String> {
+    for epoch in settings.epochs() {
+        let talkers = |formatters: &[&str]| {
+            epoch
+                .lines()
+                .filter(|line| formatters.contains(&&line[3..6]))
+                .map(|line| line[1..3].to_string())
+                .collect::<Vec<_>>()
+        };
+        let systems = talkers(&["GSA"]);
+        let positions = talkers(&["GGA", "RMC", "GLL", "VTG", "HDT"]);
+        let expected = match settings.talker_policy {
+            _ if systems.is_empty() => vec!["GP".to_string()],
+            TalkerPolicy::Gps => vec!["GP".to_string()],
+            TalkerPolicy::Combined if systems.len() > 1 => vec!["GN".to_string()],
+            TalkerPolicy::Combined | TalkerPolicy::Dominant => systems,
+        };
+        if positions.iter().any(|talker| !expected.contains(talker))
+            || positions.windows(2).any(|pair| pair[0] != pair[1])
+        {
+            return Err(format!("{:?} with {:?}", settings.talker_policy, epoch));
+        }
+    }
+    Ok(())
+}
+
+// Speeds, courses and headings in every sentence and unit are the fix's
+fn kinematics_match_fix(settings: Settings) -> Result<(), String> {
+    let mut generator = settings.generator();
+    for _ in 0..EPOCHS {
+        let fix = generator.generate_fix();
+        let epoch = generator.encode_sentences(&fix);
+        validate::check_kinematics(&fix, &epoch, KINEMATICS_TOLERANCE)
+            .map_err(|e| format!("{} in {:?}", e, epoch))?;
+    }
+    Ok(())
+}
+
+fn parse_encodes_back(settings: Settings) -> Result<(), String> {
+    let mut parser = Parser::new();
+    for line in settings.sentences() {
+        let sentence = parser
+            .parse(&line)
+            .map_err(|e| format!("{:?}: {}", line, e))?;
+        if sentence.to_nmea() != line {
+            return Err(format!("{:?} encodes as {:?}", line, sentence.to_nmea()));
+        }
+    }
+    Ok(())
+}
+
+// Receivers leave out what they do not know yet, e.g. after a cold start:
+// the time, the date, speed and course, and where satellites are
+#[test]
+fn null_fields() {
+    let mut parser = Parser::new();
+    let parse = |parser: &mut Parser, body: &str| {
+        let line = wrap_sentence(body);
+        let sentence = parser.parse(&line).unwrap();
+        (sentence.to_nmea() == line, sentence)
+    };
+
+    let (same, sentence) = parse(&mut parser, "GPRMC,,V,,,,,,,,,,N");
+    let ParsedSentence::Rmc(rmc) = sentence else {
+        panic!("{:?}", sentence);
+    };
+    assert!(same);
+    assert_eq!((rmc.time, rmc.date), (None, None));
+    assert_eq!((rmc.speed_knots, rmc.course), (None, None));
+    // The time of day comes before the date
+    let (same, sentence) = parse(&mut parser, "GPRMC,083559.00,V,,,,,,,,,,N");
+    let ParsedSentence::Rmc(rmc) = sentence else {
+        panic!("{:?}", sentence);
+    };
+    assert!(same);
+    assert!(rmc.time.is_some() && rmc.date.is_none());
+
+    let (_, sentence) = parse(&mut parser, "GPGGA,,,,,,0,00,99.99,,,,,,");
+    let ParsedSentence::Gga(gga) = sentence else {
+        panic!("{:?}", sentence);
+    };
+    assert_eq!(
+        (gga.time, gga.position, gga.geoid_height),
+        (None, None, None)
+    );
+    assert_eq!(sentence_time(&mut parser, "GPGLL,,,,,,V,N"), None);
+    assert_eq!(sentence_time(&mut parser, "GPGST,,,,,,,,"), None);
+
+    // Without the units, which our encoder always sends
+    let (_, sentence) = parse(&mut parser, "GPVTG,,,,,,,,,N");
+    let ParsedSentence::Vtg(vtg) = sentence else {
+        panic!("{:?}", sentence);
+    };
+    assert_eq!(
+        (vtg.course, vtg.speed_knots, vtg.speed_kmh),
+        (None, None, None)
+    );
+
+    let (same, sentence) = parse(&mut parser, "GPGSV,1,1,1,25,,,27");
+    let ParsedSentence::Gsv(gsv) = sentence else {
+        panic!("{:?}", sentence);
+    };
+    assert!(same);
+    let satellite = &gsv.satellites[0];
+    assert_eq!((satellite.elevation, satellite.azimuth), (None, None));
+    assert_eq!(satellite.snr, Some(27));
+}
+
+fn sentence_time(parser: &mut Parser, body: &str) -> Option<DateTime<Utc>> {
+    parser.parse(&wrap_sentence(body)).unwrap().time()
+}
+
+// GSA always has 12 ID slots, the first satellites in them and the rest
+// empty, also with more satellites of one system than that
+#[test]
+fn gsa_slots() {
+    let mut parser = Parser::new();
+    for count in 1..=20u16 {
+        let gsa = Gsa {
+            talker: "GP".to_string(),
+            mode: 'A',
+            fix_type: 3,
+            satellite_ids: (1..=count).collect(),
+            pdop: 1.5,
+            hdop: 0.9,
+            vdop: 1.2,
+            system_id: None,
+        };
+        let listed = (count as usize).min(GSA_SLOTS);
+        let line = gsa.to_nmea();
+        let fields: Vec<&str> = line[..line.find('*').unwrap()].split(',').collect();
+        assert_eq!(fields.len(), 3 + GSA_SLOTS + 3, "{:?}", line);
+        for (slot, field) in fields[3..3 + GSA_SLOTS].iter().enumerate() {
+            let expected = if slot < listed {
+                (slot + 1).to_string()
+            } else {
+                String::new()
+            };
+            assert_eq!(*field, expected, "{:?}", line);
+        }
+        let ParsedSentence::Gsa(parsed) = parser.parse(&line).unwrap() else {
+            panic!("{:?}", line);
+        };
+        assert_eq!(parsed.satellite_ids, gsa.satellite_ids[..listed]);
+
+        // The generator lists as many of one system as fit
+        let mut generator = NmeaGenerator::with_seed(count as u64);
+        generator.satellites = Some(count as usize);
+        generator.constellations = Some(vec![Constellation::GPS]);
+        let epoch = generator.iter().next().unwrap();
+        let line = epoch
+            .lines()
+            .find(|line| line.starts_with("$GPGSA"))
+            .unwrap();
+        let ParsedSentence::Gsa(parsed) = parser.parse(line).unwrap() else {
+            panic!("{:?}", line);
+        };
+        assert_eq!(parsed.satellite_ids.len(), listed, "{:?}", line);
+        let used = gga_satellites(&mut parser, &epoch);
+        assert_eq!(used, listed, "{}", epoch);
+
+        // Each system of a mixed sky has as many of its own, so together
+        // they may use more than one GSA lists
+        let mut generator = NmeaGenerator::with_seed(count as u64);
+        generator.satellites = Some(count as usize);
+        generator.constellations = Some(vec![
+            Constellation::GPS,
+            Constellation::GLONASS,
+            Constellation::GALILEO,
+        ]);
+        let epoch = generator.iter().next().unwrap();
+        let mut listed_by_system = HashMap::new();
+        for line in epoch.lines().filter(|line| &line[3..6] == "GSA") {
+            let ParsedSentence::Gsa(parsed) = parser.parse(line).unwrap() else {
+                panic!("{:?}", line);
+            };
+            *listed_by_system.entry(parsed.talker).or_insert(0) += parsed.satellite_ids.len();
+        }
+        assert!(listed_by_system.values().all(|&listed| listed <= GSA_SLOTS));
+        let listed: usize = listed_by_system.values().sum();
+        // Under open sky every satellite of the first fix is tracked
+        assert_eq!(listed, count as usize, "{}", epoch);
+        assert_eq!(gga_satellites(&mut parser, &epoch), listed, "{}", epoch);
+
+        // More go on in the next GSA, each with the same DOPs
+        let split = gsa.split();
+        assert_eq!(split.len(), (count as usize).div_ceil(GSA_SLOTS));
+        let ids: Vec<u16> = split
+            .iter()
+            .flat_map(|part| part.satellite_ids.clone())
+            .collect();
+        assert_eq!(ids, gsa.satellite_ids);
+        assert!(split.iter().all(|part| part.pdop == gsa.pdop));
+    }
+}
+
+// GSA reports the HDOP of GGA, pinned or not, with the other DOPs
+// following from it
+#[test]
+fn dops_agree() {
+    let mut parser = Parser::new();
+    for hdop in [None, Some(1.2), Some(8.0)] {
+        let mut generator = NmeaGenerator::with_seed(6);
+        generator.hdop = hdop;
+        for epoch in generator.iter().take(5) {
+            let mut gga_hdop = None;
+            for line in epoch.lines() {
+                match parser.parse(line).unwrap() {
+                    ParsedSentence::Gga(gga) => gga_hdop = Some(gga.hdop),
+                    ParsedSentence::Gsa(gsa) => {
+                        assert_eq!(Some(gsa.hdop), gga_hdop, "{}", epoch);
+                        assert!(gsa.vdop > gsa.hdop, "{}", line);
+                        let pdop = gsa.hdop.hypot(gsa.vdop);
+                        assert!((gsa.pdop - pdop).abs() <= 0.1, "{}", line);
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(hdop) = hdop {
+                assert_eq!(gga_hdop, Some(hdop));
+            }
+        }
+    }
+}
+
+// Receivers talking GP list the satellites of every system in one GSA,
+// going on in another past 12
+#[test]
+fn gsa_split() {
+    let mut parser = Parser::new();
+    let mut generator = NmeaGenerator::with_seed(2);
+    generator.satellites = Some(20);
+    generator.constellations = Some(vec![
+        Constellation::GPS,
+        Constellation::GLONASS,
+        Constellation::GALILEO,
+    ]);
+    generator.talker_policy = TalkerPolicy::Gps;
+    let epoch = generator.iter().next().unwrap();
+    let gsas: Vec<Gsa> = epoch
+        .lines()
+        .filter(|line| &line[3..6] == "GSA")
+        .map(|line| match parser.parse(line).unwrap() {
+            ParsedSentence::Gsa(gsa) => gsa,
+            other => panic!("{:?}", other),
+        })
+        .collect();
+    let listed: Vec<usize> = gsas.iter().map(|gsa| gsa.satellite_ids.len()).collect();
+    assert_eq!(listed, [GSA_SLOTS, 20 - GSA_SLOTS], "{}", epoch);
+    assert!(gsas.iter().all(|gsa| gsa.talker == "GP"));
+    assert_eq!(gsas[0].hdop, gsas[1].hdop);
+    assert_eq!(gga_satellites(&mut parser, &epoch), 20);
+
+    // Strictly one system to each, with its system ID
+    generator.encoding = EncodingMode::Strict;
+    let epoch = generator.iter().next().unwrap();
+    let talkers: Vec<&str> = epoch
+        .lines()
+        .filter(|line| &line[3..6] == "GSA")
+        .map(|line| &line[1..3])
+        .collect();
+    assert_eq!(talkers, ["GP", "GL", "GA"], "{}", epoch);
+}
+
+// Satellites used by the GGA of an epoch
+fn gga_satellites(parser: &mut Parser, epoch: &str) -> usize {
+    let line = epoch.lines().find(|line| &line[3..6] == "GGA").unwrap();
+    match parser.parse(line).unwrap() {
+        ParsedSentence::Gga(gga) => gga.satellites,
+        other => panic!("{:?}", other),
+    }
+}
+
+// Sentences longer than NMEA 0183 allows, with their line ending
+#[test]
+fn sentence_length() {
+    let line = |length: usize| format!("$GPTXT,{}*00\r\n", "x".repeat(length - 12));
+    assert_eq!(line(82).len(), MAX_SENTENCE_LENGTH);
+    assert!(check_length(&line(82)).is_ok());
+    assert!(check_length(line(82).trim_end()).is_ok());
+    let error = check_length(&line(83)).unwrap_err();
+    assert!(error.contains("83 characters"), "{}", error);
+}
+
+// Every system of the default mix shows up, NavIC only once enabled
+#[test]
+fn constellations() {
+    for enabled in [None, Some(Constellation::ALL.to_vec())] {
+        let mut generator = NmeaGenerator::with_seed(3);
+        generator.constellations = enabled.clone();
+        let mut talkers: Vec<String> = generator
+            .iter()
+            .take(50)
+            .flat_map(|epoch| {
+                epoch
+                    .lines()
+                    .filter(|line| &line[3..6] == "GSA")
+                    .map(|line| line[1..3].to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        talkers.sort();
+        talkers.dedup();
+        let expected = enabled.unwrap_or(Constellation::DEFAULT.to_vec());
+        let mut expected: Vec<String> = expected.iter().map(Constellation::to_code).collect();
+        expected.sort();
+        assert_eq!(talkers, expected);
+    }
+}
+
+// Every count of satellites, also more than one GSA can list, goes into
+// full messages of four and one with the rest
+#[test]
+fn gsv_messages() {
+    let mut parser = Parser::new();
+    for count in 1..=32 {
+        let satellites: Vec<_> = (1..=count)
+            .map(|id| GsvSatellite {
+                id,
+                elevation: Some(45),
+                azimuth: Some(180),
+                snr: Some(40),
+            })
+            .collect();
+        let messages = Gsv::messages("GP", &satellites);
+        assert_eq!(messages.len(), (count as usize).div_ceil(4));
+
+        let mut listed = Vec::new();
+        for (i, message) in messages.iter().enumerate() {
+            let line = message.to_nmea();
+            let fields: Vec<&str> = line[..line.find('*').unwrap()].split(',').collect();
+            assert_eq!(fields[1], messages.len().to_string(), "{:?}", line);
+            assert_eq!(fields[2], (i + 1).to_string(), "{:?}", line);
+            assert_eq!(fields[3], count.to_string(), "{:?}", line);
+            let expected = if i + 1 < messages.len() {
+                4
+            } else {
+                (count as usize - 1) % 4 + 1
+            };
+            assert_eq!(fields.len(), 4 + 4 * expected, "{:?}", line);
+
+            let ParsedSentence::Gsv(gsv) = parser.parse(&line).unwrap() else {
+                panic!("{:?}", line);
+            };
+            assert_eq!(&gsv, message);
+            listed.extend(gsv.satellites);
+        }
+        assert_eq!(listed, satellites);
+    }
+}
+
+// With SBAS corrections the last one or two satellites in view are SBAS
+// ones, GSV lists them, and GGA reports DGPS from one of them
+#[test]
+fn sbas() {
+    let mut parser = Parser::new();
+    for seed in 0..20 {
+        let mut generator = NmeaGenerator::with_seed(seed);
+        generator.sbas = true;
+        generator.satellites = Some(8);
+        let fix = generator.generate_fix();
+        let sbas: Vec<u16> = fix
+            .satellites
+            .iter()
+            .skip_while(|sat| sat.constellation != Constellation::SBAS)
+            .map(|sat| {
+                assert_eq!(sat.constellation, Constellation::SBAS, "{:?}", fix);
+                sat.id
+            })
+            .collect();
+        assert!((1..=2).contains(&sbas.len()), "{:?}", fix);
+        assert!(
+            sbas.iter().all(|prn| (120..=158).contains(prn)),
+            "{:?}",
+            sbas
+        );
+
+        let mut listed = Vec::new();
+        for line in generator.encode_sentences(&fix).lines() {
+            match parser.parse(line).unwrap() {
+                ParsedSentence::Gga(gga) => {
+                    assert_eq!(gga.fix_quality, 2, "{:?}", line);
+                    assert_eq!(gga.station_id, Some(sbas[0]), "{:?}", line);
+                }
+                ParsedSentence::Gsv(gsv) => listed.extend(gsv.satellites.iter().map(|s| s.id)),
+                // GSA lists the satellites of the systems in the solution
+                ParsedSentence::Gsa(gsa) if gsa.talker == "GP" => {
+                    assert!(gsa.satellite_ids.iter().all(|id| *id <= 32), "{:?}", line)
+                }
+                _ => {}
+            }
+        }
+        assert!(listed.ends_with(&sbas), "{:?}", listed);
+
+        // A fix quality set otherwise, e.g. RTK, wins
+        generator.fix_quality = Some(4);
+        assert_eq!(generator.generate_fix().fix_quality, 4);
+    }
+}
+
+// A DGPS network hands over between its stations, and the age of its
+// corrections runs up to 10 s and back, unless real corrections come in
+#[test]
+fn dgps_network() {
+    let network = DgpsNetwork::parse("101,102,103@30").unwrap();
+    assert_eq!(network.stations, [101, 102, 103]);
+    assert_eq!(network.to_string(), "101,102,103@30");
+    assert_eq!(
+        DgpsNetwork::parse("7").unwrap().handover,
+        Duration::from_secs(60)
+    );
+    assert!(DgpsNetwork::parse("101@86400").is_ok());
+    for invalid in ["", "101,", "1024", "101@0", "101@0.5", "101@x", "101@1e30"] {
+        assert!(DgpsNetwork::parse(invalid).is_err(), "{}", invalid);
+    }
+
+    let mut parser = Parser::new();
+    let mut generator = NmeaGenerator::with_seed(3);
+    generator.fix_quality = Some(2);
+    generator.dgps_network = Some(network);
+    let start = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+    let mut reported = Vec::new();
+    for seconds in (0..90).step_by(3) {
+        generator.time = Some(start + TimeDelta::seconds(seconds));
+        let fix = generator.generate_fix();
+        let gga = generator.encode_sentence("GGA", &fix).unwrap();
+        let ParsedSentence::Gga(gga) = parser.parse(gga.trim_end()).unwrap() else {
+            panic!("{}", gga);
+        };
+        let age = gga.age.unwrap();
+        assert_eq!(age, (seconds % 10) as f64, "{:?}", gga);
+        reported.push(gga.station_id.unwrap());
+    }
+    reported.dedup();
+    assert_eq!(reported, [101, 102, 103]);
+
+    generator.correction_age = Some(2.5);
+    generator.station_id = Some(9);
+    let fix = generator.generate_fix();
+    let gga = generator.encode_sentence("GGA", &fix).unwrap();
+    assert!(gga.contains(",2.5,0009*"), "{}", gga);
+}
+
+// GST reports centimetres with RTK fixed, decimetres with float and metres
+// without corrections, and nothing without a fix
+#[test]
+fn gst_accuracy() {
+    let mut parser = Parser::new();
+    let mut generator = NmeaGenerator::with_seed(4);
+    generator.hdop = Some(1.0);
+    let mut semi_major = |generator: &mut NmeaGenerator, fix_quality| {
+        generator.fix_quality = Some(fix_quality);
+        let fix = generator.generate_fix();
+        let line = generator.encode_sentence("GST", &fix).unwrap();
+        let ParsedSentence::Gst(gst) = parser.parse(line.trim_end()).unwrap() else {
+            panic!("{}", line);
+        };
+        gst.accuracy.map(|accuracy| {
+            assert!(accuracy.semi_minor <= accuracy.semi_major, "{}", line);
+            assert!(accuracy.altitude > accuracy.semi_major, "{}", line);
+            accuracy.semi_major
+        })
+    };
+    for _ in 0..20 {
+        let fixed = semi_major(&mut generator, 4).unwrap();
+        let float = semi_major(&mut generator, 5).unwrap();
+        let gps = semi_major(&mut generator, 1).unwrap();
+        assert!(
+            fixed < 0.05 && fixed < float && float < gps,
+            "{} {} {}",
+            fixed,
+            float,
+            gps
+        );
+    }
+    generator.no_fix = true;
+    assert_eq!(semi_major(&mut generator, 4), None);
+}
+
+// Course over the ground goes into RMC and VTG, the heading the crab puts
+// next to it into VHW, HDT and HDG, with the speed through the water
+#[test]
+fn heading_and_course() {
+    let mut parser = Parser::new();
+    let mut generator = NmeaGenerator::with_seed(5);
+    generator.speed_knots = Some(10.0);
+    generator.course = Some(0.0);
+    generator.fix_quality = Some(1);
+    for formatter in ["VTG", "VHW", "HDT", "HDG"] {
+        *generator.sentence_rates.get_mut(formatter).unwrap() = 1;
+    }
+    // Against a current setting east the bow points a little west of north
+    // and makes more way through the water
+    let crabs = [
+        (Crab::None, 0.0, (10.0, 18.5)),
+        (Crab::Offset { degrees: -15.0 }, 345.0, (10.0, 18.5)),
+        (
+            Crab::Current {
+                knots: 2.0,
+                set: 90.0,
+            },
+            348.7,
+            (10.2, 18.9),
+        ),
+    ];
+    for (crab, heading, water_speed) in crabs {
+        generator.crab = crab;
+        let fix = generator.generate_fix();
+        assert!((fix.heading - heading).abs() < 0.05, "{:?}", fix);
+        let mut formatters = Vec::new();
+        for line in generator.encode_sentences(&fix).lines() {
+            let sentence = parser.parse(line).unwrap();
+            match &sentence {
+                ParsedSentence::Rmc(rmc) => assert_eq!(rmc.course, Some(0.0), "{:?}", line),
+                ParsedSentence::Vtg(vtg) => {
+                    assert_eq!(
+                        (vtg.course, vtg.speed_knots),
+                        (Some(0.0), Some(10.0)),
+                        "{:?}",
+                        line
+                    );
+                    assert_eq!(vtg.speed_kmh, Some(18.5), "{:?}", line);
+                    assert_eq!(vtg.mode, Some('A'), "{:?}", line);
+                }
+                ParsedSentence::Vhw(vhw) => {
+                    assert_eq!(vhw.talker, "VW", "{:?}", line);
+                    assert_eq!(vhw.heading, Some(heading), "{:?}", line);
+                    assert_eq!((vhw.speed_knots, vhw.speed_kmh), water_speed, "{:?}", line);
+                }
+                ParsedSentence::Hdt(hdt) => assert_eq!(hdt.heading, Some(heading), "{:?}", line),
+                ParsedSentence::Hdg(hdg) => {
+                    assert_eq!(hdg.talker, "HC", "{:?}", line);
+                    assert_eq!(hdg.heading, heading, "{:?}", line);
+                }
+                _ => continue,
+            }
+            formatters.push(sentence.address()[2..].to_string());
+        }
+        assert_eq!(formatters, ["RMC", "VTG", "VHW", "HDT", "HDG"]);
+    }
+
+    // A dual antenna receiver has no heading without a fix
+    generator.no_fix = true;
+    let fix = generator.generate_fix();
+    let hdt = generator.encode_sentence("HDT", &fix).unwrap();
+    assert!(
+        hdt.starts_with("$GPHDT,,T*") || hdt.starts_with("$GNHDT,,T*"),
+        "{:?}",
+        hdt
+    );
+
+    assert_eq!(Crab::parse("none").unwrap(), Crab::None);
+    assert_eq!(
+        Crab::parse("offset:-8.5").unwrap(),
+        Crab::Offset { degrees: -8.5 }
+    );
+    let current = Crab::parse("current:1.5@400").unwrap();
+    assert_eq!(
+        current,
+        Crab::Current {
+            knots: 1.5,
+            set: 40.0
+        }
+    );
+    assert_eq!(Crab::parse(&current.to_string()).unwrap(), current);
+    for invalid in [
+        "",
+        "offset:",
+        "offset:x",
+        "current:2",
+        "current:-1@90",
+        "wind:5",
+    ] {
+        assert!(Crab::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+// The separation GGA reports is the geoid's where the fix is, rather than
+// a new draw every epoch
+#[test]
+fn geoid_separation() {
+    // Near the full model where it is flat, and smooth in between
+    assert!((geoid::separation(48.1, 11.5) - 47.0).abs() < 3.0);
+    assert!(geoid::separation(0.0, 80.0) < -90.0);
+    assert!(geoid::separation(-10.0, 150.0) > 60.0);
+    assert_eq!(
+        geoid::separation(90.0, 0.0),
+        geoid::separation(90.0, -120.0)
+    );
+    assert_eq!(geoid::separation(-90.0, 45.0), -30.0);
+    assert_eq!(
+        geoid::separation(35.0, 180.0),
+        geoid::separation(35.0, -180.0)
+    );
+    assert!((geoid::separation(35.0, 179.999) - geoid::separation(35.0, -179.999)).abs() < 0.01);
+    assert!((geoid::separation(35.0, 12.0) - geoid::separation(35.001, 12.001)).abs() < 0.01);
+
+    let mut generator = NmeaGenerator::with_seed(5);
+    generator.position = Some(Position::new(-6.0, 147.0, 20.0));
+    generator.fix_quality = Some(1);
+    for _ in 0..EPOCHS {
+        let fix = generator.generate_fix();
+        let separation = geoid::separation(fix.latitude, fix.longitude);
+        assert_eq!(fix.geoid_height, separation);
+        assert!(separation > 60.0, "{}", separation);
+
+        let line = generator.encode_sentence("GGA", &fix).unwrap();
+        let Ok(ParsedSentence::Gga(gga)) = Parser::new().parse(line.trim_end()) else {
+            panic!("{:?}", line);
+        };
+        assert_eq!(gga.geoid_height, Some((separation * 10.0).round() / 10.0));
+    }
+}
+
+// Bursts take no time of their own: their clock moves on an interval per
+// epoch, and the next burst carries on where one ended
+#[test]
+fn bursts() {
+    let start = Utc.with_ymd_and_hms(2024, 3, 15, 12, 0, 0).unwrap();
+    let mut generator = NmeaGenerator::with_seed(8);
+    generator.time = Some(start);
+    let burst = generator.generate_duration(Duration::from_secs(300), Duration::from_secs(1));
+    assert_eq!(burst.epochs.len(), 300);
+    let mut parser = Parser::new();
+    let mut rmcs = 0;
+    for line in burst.to_string().lines() {
+        if let ParsedSentence::Rmc(rmc) = parser.parse(line).unwrap() {
+            assert_eq!(
+                rmc.time,
+                Some(start + TimeDelta::seconds(rmcs)),
+                "{:?}",
+                line
+            );
+            rmcs += 1;
+        }
+    }
+    assert_eq!(rmcs, 300);
+    assert_eq!(generator.time, Some(start + TimeDelta::seconds(300)));
+
+    let next = generator.generate_epochs(3, Duration::from_millis(100));
+    let times: Vec<_> = next.epochs.iter().map(|epoch| epoch.fix.time).collect();
+    let millis = |ms| start + TimeDelta::seconds(300) + TimeDelta::milliseconds(ms);
+    assert_eq!(times, [millis(0), millis(100), millis(200)]);
+    for epoch in &next.epochs {
+        assert!(epoch.sentences.starts_with('$'), "{:?}", epoch.sentences);
+    }
+
+    // The same seed and start make the same burst
+    let mut again = NmeaGenerator::with_seed(8);
+    again.time = Some(start);
+    let repeated = again.generate_epochs(300, Duration::from_secs(1));
+    assert_eq!(repeated.to_string(), burst.to_string());
+}
+
+// Signals get stronger with elevation and weaker with what is around the
+// antenna; those too weak to track have no SNR
+#[test]
+fn signal_strengths() {
+    let mut mean_snrs = Vec::new();
+    for environment in [
+        Environment::OpenSky,
+        Environment::Suburban,
+        Environment::Urban,
+        Environment::Indoor,
+    ] {
+        // Untracked satellites count as the threshold
+        let (mut low, mut high) = (Vec::new(), Vec::new());
+        for seed in 0..50 {
+            let mut generator = NmeaGenerator::with_seed(seed);
+            generator.environment = environment;
+            generator.satellites = Some(12);
+            for sat in generator.generate_fix().satellites {
+                assert!((5..=90).contains(&sat.elevation), "{:?}", sat);
+                assert!(sat.azimuth < 360, "{:?}", sat);
+                let snr = sat.snr.map_or(TRACKING_THRESHOLD, |snr| {
+                    assert!((TRACKING_THRESHOLD..=50.0).contains(&(snr as f64)));
+                    snr as f64
+                });
+                match sat.elevation {
+                    0..=20 => low.push(snr),
+                    60.. => high.push(snr),
+                    _ => {}
+                }
+            }
+        }
+        let mean = |snrs: &[f64]| snrs.iter().sum::<f64>() / snrs.len() as f64;
+        assert!(mean(&high) > mean(&low) + 5.0, "{:?}", environment);
+        mean_snrs.push(mean(&[low, high].concat()));
+    }
+    assert!(
+        mean_snrs.windows(2).all(|pair| pair[0] > pair[1]),
+        "{:?}",
+        mean_snrs
+    );
+}
+
+// The satellites in view stay from epoch to epoch. Those that set are
+// replaced by new ones rising from the mask, which GSV lists with a signal
+// for a while before GSA and GGA count them as used.
+#[test]
+fn satellites_rise_and_set() {
+    let mut generator = NmeaGenerator::with_seed(11);
+    // A pinned clock moves the sky on a second per epoch
+    generator.time = Some(Utc::now());
+    let mut parser = Parser::new();
+    let mut previous: Vec<Satellite> = Vec::new();
+    // When each satellite that rose showed up, and whether it was used then
+    let mut risen: HashMap<(String, u16), (usize, Option<usize>)> = HashMap::new();
+    let mut set = 0;
+    // Four hours
+    for epoch in 0..4 * 3600 {
+        let fix = generator.generate_fix();
+        let key = |sat: &Satellite| (sat.constellation.to_string(), sat.id);
+        for sat in &fix.satellites {
+            match previous.iter().find(|old| key(old) == key(sat)) {
+                Some(old) => assert!(sat.elevation.abs_diff(old.elevation) <= 1, "{:?}", sat),
+                None if epoch > 0 => {
+                    assert_eq!(sat.elevation, 5, "{:?}", sat);
+                    assert!(!sat.used, "{:?}", sat);
+                    risen.insert(key(sat), (epoch, None));
+                }
+                None => {}
+            }
+            if let Some((_, used @ None)) = risen.get_mut(&key(sat)) {
+                if sat.used {
+                    *used = Some(epoch);
+                }
+            }
+        }
+        let gone = previous
+            .iter()
+            .filter(|old| !fix.satellites.iter().any(|sat| key(sat) == key(old)))
+            .count();
+        assert!(gone <= 1, "{} satellites gone at once", gone);
+        set += gone;
+
+        // GGA counts exactly the satellites GSA lists
+        if epoch % 600 == 0 {
+            let (mut in_gga, mut in_gsa) = (0, 0);
+            for line in generator.encode_sentences(&fix).lines() {
+                match parser.parse(line).unwrap() {
+                    ParsedSentence::Gga(gga) => in_gga = gga.satellites,
+                    ParsedSentence::Gsa(gsa) => in_gsa += gsa.satellite_ids.len(),
+                    _ => {}
+                }
+            }
+            assert_eq!(in_gga, in_gsa, "{:?}", fix);
+            assert_eq!(in_gga, fix.satellites_used());
+        }
+        previous = fix.satellites;
+    }
+    assert!(
+        set > 0 && !risen.is_empty(),
+        "{} set, {:?} rose",
+        set,
+        risen
+    );
+    // Acquired a while after rising, if high enough by the end
+    for (rose, used) in risen.values() {
+        if let Some(used) = used {
+            assert!(used - rose >= 6, "used {} s after rising", used - rose);
+        }
+    }
+    assert!(risen.values().any(|(_, used)| used.is_some()));
+}
+
+// Minutes that round up to 60 carry into the degrees at every precision
+#[test]
+fn minutes_round_into_degrees() {
+    for decimals in MINUTE_DECIMALS..=MAX_MINUTE_DECIMALS {
+        let position = Position::new(-48.999999999999, 11.999999999999, 0.0);
+        let zeros = "0".repeat(decimals);
+        assert_eq!(
+            position.nmea_latitude(decimals),
+            (format!("4900.{}", zeros), 'S')
+        );
+        assert_eq!(
+            position.nmea_longitude(decimals),
+            (format!("01200.{}", zeros), 'E')
+        );
+        let position = Position::new(5.5, 0.25, 0.0);
+        assert_eq!(
+            position.nmea_latitude(decimals).0,
+            format!("0530.{}", zeros)
+        );
+        assert_eq!(
+            position.nmea_longitude(decimals).0,
+            format!("00015.{}", zeros)
+        );
+    }
+}
+
+#[test]
+fn checksums() {
+    quickcheck(checksums_are_valid as fn(Settings) -> Result<(), String>);
+}
+
+#[test]
+fn field_counts() {
+    quickcheck(field_counts_match_formatter as fn(Settings) -> Result<(), String>);
+}
+
+#[test]
+fn rmc_layout() {
+    quickcheck(rmc_matches_layout as fn(Settings) -> Result<(), String>);
+}
+
+#[test]
+fn strict_encoding() {
+    quickcheck(strict_sentences_follow_standard as fn(Settings) -> Result<(), String>);
+    assert_eq!(EncodingMode::parse("strict").unwrap(), EncodingMode::Strict);
+    assert!(EncodingMode::parse("legacy").is_err());
+}
+
+#[test]
+fn reused_buffer() {
+    quickcheck(reused_buffer_matches as fn(Settings) -> Result<(), String>);
+}
+
+#[test]
+fn minute_decimals() {
+    quickcheck(coordinates_have_minute_decimals as fn(Settings) -> Result<(), String>);
+}
+
+#[test]
+fn gga_differential_fields() {
+    quickcheck(gga_has_differential_fields as fn(Settings) -> Result<(), String>);
+}
+
+#[test]
+fn position_talkers() {
+    quickcheck(position_talkers_follow_policy as fn(Settings) -> Result<(), String>);
+}
+
+#[test]
+fn kinematics() {
+    quickcheck(kinematics_match_fix as fn(Settings) -> Result<(), String>);
+}
+
+#[test]
+fn round_trip() {
+    quickcheck(parse_encodes_back as fn(Settings) -> Result<(), String>);
+}
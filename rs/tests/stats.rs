@@ -0,0 +1,158 @@
+// tests/stats.rs
+
+// What the stats count of the outputs of a port, and how they and the
+// simulated truth are reported.
+
+use chrono::{TimeZone, Utc};
+use nmea_simulator::nmea_generator::{Constellation, Fix, Satellite};
+use nmea_simulator::output::MultiSink;
+use nmea_simulator::state::SimState;
+use nmea_simulator::stats::{self, Stats};
+use nmea_simulator::{OutputSink, Simulator};
+use std::error::Error;
+
+struct Discard;
+
+impl OutputSink for Discard {
+    fn name(&self) -> String {
+        "discard".to_string()
+    }
+
+    fn write_all(&mut self, _data: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+struct Broken;
+
+impl OutputSink for Broken {
+    fn name(&self) -> String {
+        "broken".to_string()
+    }
+
+    fn write_all(&mut self, _data: &[u8]) -> Result<(), Box<dyn Error>> {
+        Err("gone".into())
+    }
+}
+
+#[test]
+fn counts_sentences_by_formatter() {
+    let stats = Stats::new();
+    stats.count_output(b"$GPGGA,1*00\r\n$GNGSA,A*00\r\n$GLGSA,A*00\r\n");
+    stats.count_output(b"$PMTK001,220,3*30\r\n");
+    // UBX frames are only bytes
+    stats.count_output(&[0xb5, 0x62, 0x01, 0x07]);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.sentences, 4);
+    assert_eq!(snapshot.bytes, 39 + 19 + 4);
+    let counts: Vec<_> = snapshot
+        .sentence_counts
+        .iter()
+        .map(|(kind, count)| (kind.as_str(), *count))
+        .collect();
+    assert_eq!(counts, [("GGA", 1), ("GSA", 2), ("PMTK001", 1)]);
+}
+
+#[test]
+fn outputs_count_once_and_errors_each() {
+    let stats = Stats::new();
+    let mut outputs = MultiSink::new();
+    outputs.set_stats(stats.clone());
+    outputs.add(Box::new(Discard));
+    outputs.add(Box::new(Discard));
+    outputs.add(Box::new(Broken));
+
+    outputs.write_all(b"$GPRMC,1*00\r\n");
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.sentences, 1);
+    assert_eq!(snapshot.bytes, 13);
+    assert_eq!(snapshot.write_errors, 1);
+}
+
+#[test]
+fn reports_in_prometheus_format() {
+    let stats = Stats::new();
+    stats.count_output(b"$GPGGA,1*00\r\n$GPGGA,2*00\r\n");
+    stats.count_clients(1, 0);
+
+    let text = stats.snapshot().prometheus();
+    assert!(text.contains("# TYPE nmea_simulator_sentences_total counter\n"));
+    assert!(text.contains("nmea_simulator_sentences_total{formatter=\"GGA\"} 2\n"));
+    assert!(text.contains("nmea_simulator_bytes_total 26\n"));
+    assert!(text.contains("nmea_simulator_client_connects_total 1\n"));
+    assert!(text.contains("nmea_simulator_write_errors_total 0\n"));
+    assert_eq!(
+        stats.snapshot().summary(),
+        "2 sentences (GGA 2), 26 bytes, 1 client connects, 0 disconnects, 0 write errors"
+    );
+}
+
+#[test]
+fn simulator_counts_its_epochs() {
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("GGA")
+        .seed(3)
+        .count(3)
+        .sink(Box::new(Discard))
+        .build()
+        .unwrap();
+    let stats = simulator.stats();
+    simulator.run().unwrap();
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.sentences, 3);
+    assert_eq!(snapshot.sentence_counts.get("GGA"), Some(&3));
+}
+
+#[test]
+fn reports_truth_as_gauges() {
+    let mut state = SimState::default();
+    // Only the state of the simulation before the first fix
+    assert_eq!(
+        stats::truth_gauges(&state),
+        "# HELP nmea_simulator_paused Whether epochs are held back\n\
+         # TYPE nmea_simulator_paused gauge\n\
+         nmea_simulator_paused 0\n"
+    );
+
+    state.truth = Some(Fix {
+        time: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+        latitude: 48.1173,
+        longitude: 11.5167,
+        altitude: 545.4,
+        geoid_height: 46.9,
+        speed_knots: 12.5,
+        course: 84.4,
+        heading: 84.4,
+        water_speed_knots: 12.5,
+        fix_quality: 1,
+        hdop: 0.9,
+        satellites: vec![
+            Satellite::new(Constellation::GPS, 4),
+            Satellite::new(Constellation::GPS, 9),
+            Satellite::new(Constellation::GALILEO, 11),
+        ],
+        authenticated: None,
+    });
+    let text = stats::truth_gauges(&state);
+    for line in [
+        "# TYPE nmea_simulator_speed_knots gauge",
+        "nmea_simulator_fix 1",
+        "nmea_simulator_latitude_degrees 48.1173",
+        "nmea_simulator_altitude_meters 545.4",
+        "nmea_simulator_speed_knots 12.5",
+        "nmea_simulator_satellites 3",
+        "nmea_simulator_fix_timestamp_seconds 1709294400",
+        "nmea_simulator_constellation_satellites{constellation=\"GALILEO\"} 1",
+        "nmea_simulator_constellation_satellites{constellation=\"GPS\"} 2",
+    ] {
+        assert!(
+            text.lines().any(|l| l == line),
+            "{} missing in\n{}",
+            line,
+            text
+        );
+    }
+}
@@ -0,0 +1,86 @@
+// tests/tag_block.rs
+
+// NMEA 4.0 TAG blocks in front of the sentences, and sentences with them
+// still parsing.
+
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::Capture;
+use nmea_simulator::checksum::checksum;
+use nmea_simulator::parser::{ParseError, Parser};
+use nmea_simulator::tag_block::{self, TagBlocks};
+use nmea_simulator::Simulator;
+
+#[test]
+fn parses_parameters() {
+    let tag_blocks = TagBlocks::parse("s:gps1,c,n").unwrap();
+    assert_eq!(tag_blocks.source.as_deref(), Some("gps1"));
+    assert!(tag_blocks.time && tag_blocks.line_count);
+    assert_eq!(tag_blocks.to_string(), "s:gps1,c,n");
+    assert!(!TagBlocks::parse("n").unwrap().time);
+    assert!(TagBlocks::parse("s:").is_err());
+    assert!(TagBlocks::parse("s:a*b").is_err());
+    assert!(TagBlocks::parse("s:seventeen-letters").is_err());
+    assert!(TagBlocks::parse("d:shore").is_err());
+}
+
+#[test]
+fn tags_every_sentence() {
+    let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+    let mut tag_blocks = TagBlocks::parse("s:gps1,c,n").unwrap();
+    let tagged = tag_blocks.apply(b"$GPGGA,1*00\r\n!AIVDM,1*00\r\n", now);
+    let tagged = String::from_utf8(tagged).unwrap();
+    let lines: Vec<&str> = tagged.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "\\s:gps1,c:1709294400,n:1*24\\$GPGGA,1*00",
+            "\\s:gps1,c:1709294400,n:2*27\\!AIVDM,1*00",
+        ]
+    );
+    assert_eq!(checksum("s:gps1,c:1709294400,n:1"), 0x24);
+
+    // A sentence written in pieces gets one TAG block, and UBX frames none
+    let mut tagged = tag_blocks.apply(b"$GPRMC,", now);
+    tagged.extend(tag_blocks.apply(b"1*00\r\n$GP", now));
+    tagged.extend(tag_blocks.apply(b"VTG*00\r\n\xb5\x62\x01\x07", now));
+    assert_eq!(
+        String::from_utf8_lossy(&tagged),
+        "\\s:gps1,c:1709294400,n:3*26\\$GPRMC,1*00\r\n\
+         \\s:gps1,c:1709294400,n:4*21\\$GPVTG*00\r\n\u{fffd}b\u{1}\u{7}"
+    );
+}
+
+#[test]
+fn parses_tagged_sentences() {
+    let capture = Capture::default();
+    Simulator::builder()
+        .rate_hz(10.0)
+        .seed(3)
+        .count(3)
+        .options(|options| options.tag_blocks = Some(TagBlocks::parse("s:gps1,n").unwrap()))
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+    let output = capture.output();
+
+    let mut parser = Parser::new();
+    let mut count = 0;
+    for (n, line) in output.lines().enumerate() {
+        let prefix = format!("\\s:gps1,n:{}*", n + 1);
+        assert!(line.starts_with(&prefix), "{}", line);
+        parser.parse(line).unwrap();
+        count += 1;
+    }
+    assert!(count > 3);
+
+    assert_eq!(tag_block::strip("$GPGGA*00").unwrap(), "$GPGGA*00");
+    let line = "\\s:gps1*00\\$GPRMC,,V,,,,,,,,,,N*53";
+    assert!(matches!(
+        parser.parse(line),
+        Err(ParseError::Checksum { expected, .. }) if expected == checksum("s:gps1")
+    ));
+}
@@ -0,0 +1,264 @@
+// tests/template.rs
+
+// Sentences of our own defined by templates, with the placeholders filled
+// from each fix.
+
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::Capture;
+use nmea_simulator::checksum::verify;
+use nmea_simulator::config::Config;
+use nmea_simulator::control::ControlCommand;
+use nmea_simulator::faults::{Fault, FaultInjector};
+use nmea_simulator::nmea_generator::{NmeaGenerator, SentenceRates};
+use nmea_simulator::template::SentenceTemplate;
+use nmea_simulator::{EncodingMode, Simulator};
+use std::sync::Arc;
+
+#[test]
+fn parses_placeholders() {
+    let valid = [
+        "PXYZ,{time},{date},{lat},{lon},{seq}",
+        "$PXYZ,{alt:.2},{speed},{course:.0},{heading},{hdop},{quality},{sats}",
+        "!AIXYZ,{{braces}},{rand:-5..5},{rand:0..100:.1},{time:.3},{lat:.6}",
+    ];
+    for text in valid {
+        assert!(SentenceTemplate::parse(text).is_ok(), "{}", text);
+    }
+    let invalid = [
+        "",
+        "{seq},PXYZ",
+        "pxyz,1",
+        "PXYZ,{seq",
+        "PXYZ,{unknown}",
+        "PXYZ,{seq:.1}",
+        "PXYZ,{lat:2}",
+        "PXYZ,{rand}",
+        "PXYZ,{rand:5..5}",
+        "PXYZ,{rand:0..x}",
+        "PXYZ,{seq:3..1}",
+        "PXYZ,{seq:0..1.5}",
+        "PXYZ,1}",
+        "PXYZ,1*00",
+        "PXYZ,1\r\n",
+    ];
+    for text in invalid {
+        assert!(SentenceTemplate::parse(text).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn fills_templates_each_epoch() {
+    let mut generator = NmeaGenerator::with_seed(5);
+    generator.time = Some(Utc.with_ymd_and_hms(2024, 3, 1, 12, 34, 56).unwrap());
+    generator.sentence_rates = SentenceRates {
+        rmc: 0,
+        gga: 0,
+        gll: 0,
+        gsa: 0,
+        gsv: 0,
+        ..SentenceRates::default()
+    };
+    let mut every_other =
+        SentenceTemplate::parse("PXYZ,{time},{date},{lat},{lon},{rand:0..100:.1},{seq}").unwrap();
+    every_other.every = 2;
+    generator.templates = Arc::new(vec![
+        every_other,
+        SentenceTemplate::parse("PABC,{{{seq}}},{quality},{rand:1..2}").unwrap(),
+    ]);
+
+    let mut lines = Vec::new();
+    for _ in 0..3 {
+        let fix = generator.generate_fix();
+        lines.extend(
+            generator
+                .encode_sentences(&fix)
+                .lines()
+                .map(|line| verify(line).unwrap().to_string()),
+        );
+    }
+    assert_eq!(lines.len(), 5, "{:?}", lines);
+
+    let fields: Vec<&str> = lines[0].split(',').collect();
+    assert_eq!(fields[..3], ["PXYZ", "123456", "010324"]);
+    assert_eq!(fields[4].len(), 1);
+    assert!(fields[3].contains('.') && fields[5].contains('.'));
+    let random: f64 = fields[7].parse().unwrap();
+    assert!((0.0..100.0).contains(&random));
+    assert_eq!(fields[7].split('.').nth(1).unwrap().len(), 1);
+    assert_eq!(fields[8], "0");
+    assert!(lines[1].starts_with("PABC,{0},"));
+    assert!(lines[1].ends_with(",1") || lines[1].ends_with(",2"));
+    assert!(lines[2].starts_with("PABC,{1},"));
+    assert!(lines[3].starts_with("PXYZ,") && lines[3].ends_with(",1"));
+    assert!(lines[4].starts_with("PABC,{2},"));
+}
+
+#[test]
+fn numbers_sentences_with_gaps() {
+    let mut generator = NmeaGenerator::with_seed(5);
+    generator.sentence_rates = SentenceRates {
+        rmc: 0,
+        gga: 0,
+        gll: 0,
+        gsa: 0,
+        gsv: 0,
+        ..SentenceRates::default()
+    };
+    generator.templates = Arc::new(vec![
+        SentenceTemplate::parse("PXYZ,{seq}").unwrap(),
+        SentenceTemplate::parse("PABC,{seq:1..3}").unwrap(),
+        SentenceTemplate::parse("PNUM,1").unwrap(),
+    ]);
+    let epoch = |generator: &mut NmeaGenerator| {
+        let fix = generator.generate_fix();
+        generator
+            .encode_sentences(&fix)
+            .lines()
+            .map(|line| verify(line).unwrap().split(',').nth(1).unwrap().to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    assert_eq!(epoch(&mut generator), "0 1 1");
+    assert_eq!(epoch(&mut generator), "1 2 1");
+    generator.skip_sequence(2);
+    assert_eq!(epoch(&mut generator), "4 2 1");
+    assert_eq!(epoch(&mut generator), "5 3 1");
+
+    // Up to the largest number there is, and round again
+    let full = format!("PXYZ,{{seq:1..{}}}", u64::MAX);
+    generator.templates = Arc::new(vec![SentenceTemplate::parse(&full).unwrap()]);
+    assert_eq!(epoch(&mut generator), "1");
+    generator.skip_sequence(u64::MAX - 2);
+    assert_eq!(epoch(&mut generator), u64::MAX.to_string());
+    assert_eq!(epoch(&mut generator), "1");
+
+    // From the control command, and as a fault
+    let mut faults = FaultInjector::new(vec![Fault::parse("seq-gap=1:3").unwrap()], 1);
+    assert!((1..=3).contains(&faults.sequence_gap()));
+    let capture = Capture::default();
+    let dir = std::env::temp_dir().join(format!("nmea_sequence_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sim.toml");
+    std::fs::write(
+        &path,
+        "rate = 10.0\n\n[[template]]\nsentence = \"PXYZ,{seq}\"\n",
+    )
+    .unwrap();
+    let simulator = Simulator::builder()
+        .count(3)
+        .options(|options| options.config_path = Some(path.to_str().unwrap().to_string()))
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap();
+    let skip = ControlCommand::SkipSequence {
+        count: 5,
+        port: None,
+    };
+    simulator.controller().apply(skip).unwrap();
+    simulator.run().unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    let output = capture.output();
+    let sequence: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.strip_prefix("$PXYZ,"))
+        .map(|line| line.split('*').next().unwrap())
+        .collect();
+    assert_eq!(sequence, ["5", "6", "7"]);
+}
+
+#[test]
+fn reads_templates_from_the_config_file() {
+    let dir = std::env::temp_dir().join(format!("nmea_template_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sim.toml");
+    std::fs::write(
+        &path,
+        "rate = 10.0\n\n\
+         [[template]]\nsentence = \"PXYZ,{sats},{seq}\"\n",
+    )
+    .unwrap();
+
+    let capture = Capture::default();
+    Simulator::builder()
+        .count(3)
+        .options(|options| options.config_path = Some(path.to_str().unwrap().to_string()))
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    std::fs::write(&path, "[[template]]\nsentence = \"PXYZ,{nope}\"\n").unwrap();
+    assert!(Config::load(path.to_str().unwrap()).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let output = capture.output();
+    let sequence: Vec<&str> = output
+        .lines()
+        .filter(|line| line.starts_with("$PXYZ,"))
+        .map(|line| verify(line).unwrap().rsplit(',').next().unwrap())
+        .collect();
+    assert_eq!(sequence, ["0", "1", "2"]);
+}
+
+#[test]
+fn checks_the_length_of_templates() {
+    // Too long once filled: sent anyway unless strict
+    let filled = format!("PXYZ{}", ",{rand:0..1:.9}".repeat(8));
+    let epoch = |encoding| {
+        let mut generator = NmeaGenerator::with_seed(5);
+        generator.encoding = encoding;
+        generator.sentence_rates = SentenceRates {
+            rmc: 0,
+            gga: 0,
+            gll: 0,
+            gsa: 0,
+            gsv: 0,
+            ..SentenceRates::default()
+        };
+        generator.templates = Arc::new(vec![SentenceTemplate::parse(&filled).unwrap()]);
+        let fix = generator.generate_fix();
+        generator.encode_sentences(&fix)
+    };
+    let line = epoch(EncodingMode::Permissive);
+    assert!(line.starts_with("$PXYZ,") && line.len() > 82, "{}", line);
+    assert_eq!(epoch(EncodingMode::Strict), "");
+
+    // Too long without its placeholders: refused by the config when strict
+    let dir = std::env::temp_dir().join(format!("nmea_template_length_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sim.toml");
+    std::fs::write(
+        &path,
+        format!(
+            "rate = 10.0\n\n[[template]]\nsentence = \"PXYZ,{}\"\n",
+            "x".repeat(80)
+        ),
+    )
+    .unwrap();
+    let run = |encoding| {
+        let capture = Capture::default();
+        Simulator::builder()
+            .encoding(encoding)
+            .count(2)
+            .options(|options| options.config_path = Some(path.to_str().unwrap().to_string()))
+            .sink(Box::new(capture.clone()))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        let output = capture.output();
+        output
+            .lines()
+            .filter(|line| line.starts_with("$PXYZ,"))
+            .count()
+    };
+    assert_eq!(run(EncodingMode::Permissive), 2);
+    assert_eq!(run(EncodingMode::Strict), 0);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,317 @@
+// tests/trajectory.rs
+
+// Walks and routes, and switching between them and external positions
+// without the receiver jumping.
+
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::Capture;
+use nmea_simulator::config::Config;
+use nmea_simulator::nmea_generator::Fix;
+use nmea_simulator::parser::{ParsedSentence, Parser};
+use nmea_simulator::position::Position;
+use nmea_simulator::state::SharedState;
+use nmea_simulator::trajectory::{self, Motion, Trajectory, TrajectorySource, BLEND_TIME};
+use nmea_simulator::{ControlCommand, Simulator};
+use std::time::{Duration, Instant};
+
+const STEP: Duration = Duration::from_millis(100);
+
+fn fix_at(position: Position) -> Fix {
+    Fix {
+        time: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+        latitude: position.lat_deg,
+        longitude: position.lon_deg,
+        altitude: position.alt_m,
+        geoid_height: 0.0,
+        speed_knots: 0.0,
+        course: 90.0,
+        heading: 90.0,
+        water_speed_knots: 0.0,
+        fix_quality: 1,
+        hdop: 1.0,
+        satellites: Vec::new(),
+        authenticated: None,
+    }
+}
+
+// Positions reported every STEP for this long
+fn run(
+    trajectory: &mut Trajectory,
+    start: Instant,
+    duration: Duration,
+    pinned: Motion,
+) -> Vec<Position> {
+    let steps = (duration.as_millis() / STEP.as_millis()) as u32;
+    (0..=steps)
+        .map(|i| {
+            trajectory
+                .advance(start + STEP * i, STEP, pinned)
+                .position
+                .unwrap()
+        })
+        .collect()
+}
+
+// A square of about 1.1 km sides from the equator and the meridian
+fn square() -> Vec<Position> {
+    trajectory::parse_route("# square\n0,0\n0.01,0,10\n\n0.01,0.01 # corner\n0,0.01\n").unwrap()
+}
+
+#[test]
+fn parses_sources_and_routes() {
+    for source in ["external", "walk", "route:drive.csv"] {
+        assert_eq!(TrajectorySource::parse(source).unwrap().to_string(), source);
+    }
+    assert!(TrajectorySource::parse("route:").is_err());
+    assert!(TrajectorySource::parse("drive").is_err());
+
+    let waypoints = square();
+    assert_eq!(waypoints.len(), 4);
+    assert_eq!(waypoints[1], Position::new(0.01, 0.0, 10.0));
+    assert!(trajectory::parse_route("# nothing\n").is_err());
+    assert!(trajectory::parse_route("0,0\n1\n").is_err());
+    assert!(trajectory::parse_route("91,0\n").is_err());
+    assert!(TrajectorySource::parse("route:/nonexistent/drive.csv")
+        .unwrap()
+        .waypoints()
+        .is_err());
+}
+
+#[test]
+fn follows_routes_and_walks() {
+    let waypoints = square();
+    let mut route = Trajectory::default();
+    route.switch(
+        TrajectorySource::Route {
+            path: "square.csv".to_string(),
+        },
+        waypoints.clone(),
+        None,
+        true,
+    );
+    let pinned = Motion {
+        speed_knots: Some(100.0),
+        ..Motion::default()
+    };
+    let start = Instant::now();
+    let motion = route.advance(start, STEP, pinned);
+    assert_eq!(motion.position, Some(waypoints[0]));
+    // North up the first leg at the pinned speed, climbing with it
+    let motion = route.advance(
+        start + Duration::from_secs(10),
+        Duration::from_secs(10),
+        pinned,
+    );
+    let position = motion.position.unwrap();
+    assert!(
+        (waypoints[0].distance_m(&position) - 514.4).abs() < 1.0,
+        "{:?}",
+        position
+    );
+    assert!(position.lon_deg.abs() < 1e-9);
+    assert!((position.alt_m - 10.0 * 514.4 / waypoints[0].distance_m(&waypoints[1])).abs() < 0.1);
+    assert!(motion.course.unwrap() < 1e-6 || motion.course.unwrap() > 359.999);
+    assert_eq!(motion.speed_knots, Some(100.0));
+    // Round the corner onto the second leg, east
+    let motion = route.advance(
+        start + Duration::from_secs(25),
+        Duration::from_secs(15),
+        pinned,
+    );
+    assert!((motion.course.unwrap() - 90.0).abs() < 0.01, "{:?}", motion);
+    assert!(motion.position.unwrap().lon_deg > 0.0);
+
+    // A walk goes on from the fix at the pinned speed, turning a little
+    let mut walk = Trajectory::default();
+    walk.seed(1);
+    let from = Position::new(48.0, 11.0, 500.0);
+    walk.switch(
+        TrajectorySource::Walk,
+        Vec::new(),
+        Some(&fix_at(from)),
+        true,
+    );
+    let positions = run(&mut walk, start, Duration::from_secs(10), pinned);
+    assert_eq!(positions[0], from);
+    for pair in positions.windows(2) {
+        let distance = pair[0].distance_m(&pair[1]);
+        assert!((distance - 5.144).abs() < 0.01, "{}", distance);
+    }
+    assert!(positions[100].lon_deg > from.lon_deg);
+}
+
+#[test]
+fn blends_between_sources() {
+    let start = Instant::now();
+    let waypoints = square();
+    let from = Position::new(0.005, -0.005, 0.0);
+    let route = TrajectorySource::Route {
+        path: "square.csv".to_string(),
+    };
+    let pinned = Motion {
+        speed_knots: Some(10.0),
+        ..Motion::default()
+    };
+
+    // Gliding from the fix over 780 m onto the route, in steps of no more
+    // than the blend's fastest and the route's speed allow
+    let mut trajectory = Trajectory::default();
+    trajectory.switch(route.clone(), waypoints.clone(), Some(&fix_at(from)), false);
+    let positions = run(
+        &mut trajectory,
+        start,
+        BLEND_TIME + Duration::from_secs(1),
+        pinned,
+    );
+    assert!(positions[0].distance_m(&from) < 1e-6);
+    let gap = from.distance_m(&waypoints[0]);
+    let fastest = 1.5 * gap / BLEND_TIME.as_secs_f64() + 5.2;
+    for pair in positions.windows(2) {
+        assert!(pair[0].distance_m(&pair[1]) < fastest * STEP.as_secs_f64());
+    }
+    // On the route after the blend, up its first leg
+    let last = positions.last().unwrap();
+    assert!(last.lon_deg.abs() < 1e-9, "{:?}", last);
+    assert!((last.distance_m(&waypoints[0]) - 6.0 * 5.144).abs() < 0.5);
+
+    // Unless asked to jump
+    let mut trajectory = Trajectory::default();
+    trajectory.switch(route, waypoints.clone(), Some(&fix_at(from)), true);
+    assert_eq!(
+        trajectory.advance(start, STEP, pinned).position,
+        Some(waypoints[0])
+    );
+
+    // Back to a pinned position the same way
+    let mut trajectory = Trajectory::default();
+    trajectory.switch(
+        TrajectorySource::External,
+        Vec::new(),
+        Some(&fix_at(from)),
+        false,
+    );
+    let pinned = Motion {
+        position: Some(waypoints[2]),
+        ..Motion::default()
+    };
+    let positions = run(&mut trajectory, start, BLEND_TIME, pinned);
+    assert!(positions[0].distance_m(&from) < 1e-6);
+    assert!(positions[25].distance_m(&from) > 100.0);
+    assert!(positions[25].distance_m(&waypoints[2]) > 100.0);
+    assert_eq!(*positions.last().unwrap(), waypoints[2]);
+}
+
+#[test]
+fn switches_while_running() {
+    let capture = Capture::default();
+    let simulator = Simulator::builder()
+        .rate_hz(10.0)
+        .sentence("GGA")
+        .seed(5)
+        .count(20)
+        .options(|options| options.trajectory = Some(TrajectorySource::Walk))
+        .sink(Box::new(capture.clone()))
+        .build()
+        .unwrap();
+    let controller = simulator.controller();
+    controller
+        .apply(ControlCommand::SetPosition {
+            latitude: 48.0,
+            longitude: 11.0,
+            altitude: 0.0,
+        })
+        .unwrap();
+    let run = std::thread::spawn(move || simulator.run());
+    std::thread::sleep(Duration::from_millis(1000));
+    // A route that is not there leaves the walk going
+    assert!(controller
+        .apply(ControlCommand::SetTrajectory {
+            trajectory: TrajectorySource::parse("route:/nonexistent/drive.csv").unwrap(),
+            jump: false,
+        })
+        .is_err());
+    controller
+        .apply(ControlCommand::SetTrajectory {
+            trajectory: TrajectorySource::External,
+            jump: false,
+        })
+        .unwrap();
+    let state = controller.apply(ControlCommand::GetState).unwrap();
+    assert_eq!(state["trajectory"]["source"], "external");
+    run.join().unwrap().unwrap();
+
+    // Walking off the pinned position, and staying where the walk got to
+    // once back on external
+    let output = capture.output();
+    let mut parser = Parser::new();
+    let positions: Vec<Position> = output
+        .lines()
+        .filter_map(|line| match parser.parse(line) {
+            Ok(ParsedSentence::Gga(gga)) => gga.position,
+            _ => None,
+        })
+        .map(|position| Position::new(position.lat_deg, position.lon_deg, 0.0))
+        .collect();
+    assert_eq!(positions.len(), 20);
+    let start = Position::new(48.0, 11.0, 0.0);
+    assert!(positions[0].distance_m(&start) < 1.0);
+    for pair in positions.windows(2) {
+        assert!(pair[0].distance_m(&pair[1]) < 1.0, "{:?}", pair);
+    }
+    assert!(positions[19].distance_m(&start) > 0.5);
+    assert!(positions[18].distance_m(&positions[19]) < 1e-3);
+}
+
+#[test]
+fn follows_routes_from_the_config_file() {
+    let dir = std::env::temp_dir().join(format!("nmea_config_route_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let route = dir.join("square.csv");
+    std::fs::write(&route, "0,0\n0.01,0,10\n0.01,0.01\n0,0.01\n").unwrap();
+    let path = dir.join("sim.toml");
+    let config = |source: &str| {
+        std::fs::write(
+            &path,
+            format!(
+                "[trajectory]\nsource = \"{}\"\npath = \"{}\"\n",
+                source,
+                route.display()
+            ),
+        )
+        .unwrap();
+        Config::load(path.to_str().unwrap())
+    };
+
+    let state = SharedState::default();
+    let loaded = config("route").unwrap();
+    loaded.apply(&state, &[]);
+    let start = Instant::now();
+    let pinned = Motion {
+        speed_knots: Some(100.0),
+        ..Motion::default()
+    };
+    let advance = |at: u64| {
+        let mut state = state.lock().unwrap();
+        let step = Duration::from_secs(10);
+        state
+            .trajectory
+            .advance(start + Duration::from_secs(at), step, pinned)
+            .position
+            .unwrap()
+    };
+    assert_eq!(advance(0), Position::new(0.0, 0.0, 0.0));
+    let moved = advance(10);
+    assert!((moved.distance_m(&Position::new(0.0, 0.0, 0.0)) - 514.4).abs() < 1.0);
+    // Reloading the same route leaves it where it got to
+    loaded.apply(&state, &[]);
+    assert!(advance(20).lat_deg > moved.lat_deg);
+
+    // A route that is not there, or a source there is not, is refused
+    std::fs::remove_file(&route).unwrap();
+    assert!(config("route").is_err());
+    assert!(config("drive").is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+}
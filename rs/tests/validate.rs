@@ -0,0 +1,157 @@
+// tests/validate.rs
+
+// Violations the validator finds in hand-made logs, and none in ours.
+
+use nmea_simulator::checksum::wrap_sentence;
+use nmea_simulator::nmea_generator::Crab;
+use nmea_simulator::sentences::{Sentence, Vtg};
+use nmea_simulator::validate::{self, Validator, Violation, KINEMATICS_TOLERANCE};
+use nmea_simulator::NmeaGenerator;
+
+fn check(validator: &mut Validator, sentence: &str) -> Result<(), Violation> {
+    validator.check(&wrap_sentence(sentence))
+}
+
+#[test]
+fn reports_values_out_of_range() {
+    let mut validator = Validator::new(false);
+    let cases = [
+        (
+            "GPGGA,120000,9130.0000,N,01130.0000,E,1,8,0.9,545.4,M,46.9,M,,",
+            "latitude",
+        ),
+        (
+            "GPGGA,120000,4807.0380,N,18130.0000,E,1,8,0.9,545.4,M,46.9,M,,",
+            "longitude",
+        ),
+        (
+            "GPGGA,120000,4807.0380,N,01131.0000,E,9,8,0.9,545.4,M,46.9,M,,",
+            "fix quality",
+        ),
+        (
+            "GPRMC,120000,A,4807.038,N,01131.000,E,022.4,400.0,230394,,,",
+            "course",
+        ),
+        ("GPGSA,A,4,04,05,,09,12,,,24,,,,,2.5,1.3,2.1", "fix type"),
+        ("GPGSV,2,3,08,01,40,083,46", "message number"),
+        ("GPGSV,1,1,01,01,95,083,46", "elevation"),
+    ];
+    for (sentence, field) in cases {
+        match check(&mut validator, sentence) {
+            Err(Violation::Range { name, .. }) => assert_eq!(name, field, "{}", sentence),
+            other => panic!("{:?} for {}", other, sentence),
+        }
+    }
+}
+
+// What a receiver sends after a cold start, before it knows the time or
+// where the satellites are
+#[test]
+fn accepts_null_fields() {
+    let mut validator = Validator::new(true);
+    let lines = [
+        "$GPRMC,,V,,,,,,,,,,N*53",
+        "$GPGGA,,,,,,0,00,99.99,,,,,,*48",
+        "$GPVTG,,,,,,,,,N*30",
+        "$GPGLL,,,,,,V,N*64",
+        "$GPGSV,1,1,01,25,,,27*7A",
+    ];
+    for line in lines {
+        assert_eq!(validator.check(line), Ok(()), "{}", line);
+    }
+    // Time still only goes forward once there is one
+    check(&mut validator, "GPGLL,,,,,120001,V,N").unwrap();
+    check(&mut validator, "GPGLL,,,,,,V,N").unwrap();
+    let result = check(&mut validator, "GPGLL,,,,,120000,V,N");
+    assert!(
+        matches!(result, Err(Violation::TimeBackwards { .. })),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn reports_time_going_back() {
+    let mut validator = Validator::new(false);
+    check(
+        &mut validator,
+        "GPRMC,120001,A,4807.038,N,01131.000,E,022.4,084.4,230394,,,",
+    )
+    .unwrap();
+    let result = check(&mut validator, "GPGLL,4807.038,N,01131.000,E,120000,A");
+    assert!(
+        matches!(result, Err(Violation::TimeBackwards { .. })),
+        "{:?}",
+        result
+    );
+
+    // Passing midnight before the RMC of the new day is fine
+    let mut validator = Validator::new(false);
+    check(
+        &mut validator,
+        "GPRMC,235959,A,4807.038,N,01131.000,E,022.4,084.4,230394,,,",
+    )
+    .unwrap();
+    check(&mut validator, "GPGLL,4807.038,N,01131.000,E,000000,A").unwrap();
+}
+
+#[test]
+fn unsupported_sentences_only_count_when_strict() {
+    assert!(check(&mut Validator::new(false), "PUBX,00,foo").is_ok());
+    assert!(matches!(
+        check(&mut Validator::new(true), "PUBX,00,foo"),
+        Err(Violation::Parse(_))
+    ));
+}
+
+#[test]
+fn generated_logs_are_valid() {
+    let mut generator = NmeaGenerator::with_seed(11);
+    let mut validator = Validator::new(true);
+    for epoch in generator.iter().take(50) {
+        for line in epoch.lines() {
+            validator
+                .check(line)
+                .unwrap_or_else(|e| panic!("{}: {}", line, e));
+        }
+    }
+}
+
+// Speeds in every unit, courses and headings are those of the fix, also
+// against a current; km/h taken for knots is caught
+#[test]
+fn kinematics_agree_with_the_fix() {
+    let mut generator = NmeaGenerator::with_seed(3);
+    generator.crab = Crab::Current {
+        knots: 1.5,
+        set: 200.0,
+    };
+    for formatter in ["VTG", "VHW", "HDT", "HDG"] {
+        *generator.sentence_rates.get_mut(formatter).unwrap() = 1;
+    }
+    for _ in 0..50 {
+        let fix = generator.generate_fix();
+        let epoch = generator.encode_sentences(&fix);
+        validate::check_kinematics(&fix, &epoch, KINEMATICS_TOLERANCE)
+            .unwrap_or_else(|e| panic!("{}: {:?}", e, epoch));
+    }
+
+    generator.speed_knots = Some(20.0);
+    generator.course = Some(359.97);
+    let fix = generator.generate_fix();
+    let rmc = generator.encode_sentence("RMC", &fix).unwrap();
+    assert!(rmc.contains(",20.0,360.0,"), "{:?}", rmc);
+    validate::check_kinematics(&fix, &rmc, KINEMATICS_TOLERANCE).unwrap();
+
+    let vtg = Vtg {
+        talker: "GP".to_string(),
+        course: Some(fix.course),
+        speed_knots: Some(fix.speed_knots),
+        speed_kmh: Some(fix.speed_knots),
+        mode: Some('A'),
+    };
+    match validate::check_kinematics(&fix, &vtg.to_nmea(), KINEMATICS_TOLERANCE) {
+        Err(Violation::Disagrees { name, .. }) => assert_eq!(name, "VTG km/h"),
+        other => panic!("{:?}", other),
+    }
+}